@@ -0,0 +1,302 @@
+use circ::ir::term::*;
+use circ::target::aby::assignment::{ShareType, SharingMap, SHARE_TYPES};
+use circ::target::aby::trans::to_aby_bytecode_with_sharing;
+use circ::term;
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "aby_costs",
+    about = "Benchmark ABY operations, writing a cost model for MPC share assignment"
+)]
+struct Options {
+    /// Command that runs an ABY program, as `<runner> <bytecode> <share map>`. If the last line
+    /// it prints is `<rounds> <bytes>`, the cost model records that communication, and the
+    /// runner's time is taken to be local computation.
+    #[structopt(parse(from_os_str))]
+    runner: PathBuf,
+
+    /// Output cost model (.json)
+    #[structopt(parse(from_os_str))]
+    output: PathBuf,
+
+    /// Bit-widths to benchmark
+    #[structopt(long, default_value = "8,16,32,64", use_delimiter = true)]
+    widths: Vec<usize>,
+
+    /// Runs per program (their times are averaged)
+    #[structopt(long, default_value = "5")]
+    runs: usize,
+
+    /// Latency of the deployment network (time per round, in ms)
+    #[structopt(long)]
+    latency: Option<f64>,
+
+    /// Bandwidth of the deployment network (bytes per ms)
+    #[structopt(long)]
+    bandwidth: Option<f64>,
+}
+
+/// The operators of a cost model, and the sharings that ABY supports them in.
+const OPS: &[(&str, &[ShareType])] = &[
+    ("&&", &[ShareType::Boolean, ShareType::Yao]),
+    ("||", &[ShareType::Boolean, ShareType::Yao]),
+    ("add", &SHARE_TYPES),
+    ("and", &[ShareType::Boolean, ShareType::Yao]),
+    ("div", &[ShareType::Boolean, ShareType::Yao]),
+    ("eq", &[ShareType::Boolean, ShareType::Yao]),
+    ("ge", &[ShareType::Boolean, ShareType::Yao]),
+    ("gt", &[ShareType::Boolean, ShareType::Yao]),
+    ("le", &[ShareType::Boolean, ShareType::Yao]),
+    ("lt", &[ShareType::Boolean, ShareType::Yao]),
+    ("mul", &SHARE_TYPES),
+    ("mux", &[ShareType::Boolean, ShareType::Yao]),
+    ("ne", &[ShareType::Boolean, ShareType::Yao]),
+    ("or", &[ShareType::Boolean, ShareType::Yao]),
+    ("rem", &[ShareType::Boolean, ShareType::Yao]),
+    ("shl", &[ShareType::Boolean, ShareType::Yao]),
+    ("shr", &[ShareType::Boolean, ShareType::Yao]),
+    ("sub", &SHARE_TYPES),
+    ("xor", &[ShareType::Boolean, ShareType::Yao]),
+];
+
+/// A computation with one operator: `name` on `width`-bit inputs.
+fn op_computation(name: &str, width: usize) -> Computation {
+    let mut cs = Computation::new();
+    let (sort, width) = match name {
+        "&&" | "||" => (Sort::Bool, 1),
+        _ => (Sort::BitVector(width), width),
+    };
+    let a = cs.new_var("a", sort.clone(), Some(0), 0, false, None);
+    let b = cs.new_var("b", sort, Some(1), 0, false, None);
+    let out = match name {
+        "&&" => term![AND; a, b],
+        "||" => term![OR; a, b],
+        "add" => term![BV_ADD; a, b],
+        "and" => term![BV_AND; a, b],
+        "div" => term![BV_UDIV; a, b],
+        "eq" => term![EQ; a, b],
+        "ge" => term![BV_UGE; a, b],
+        "gt" => term![BV_UGT; a, b],
+        "le" => term![BV_ULE; a, b],
+        "lt" => term![BV_ULT; a, b],
+        "mul" => term![BV_MUL; a, b],
+        "mux" => {
+            let c = cs.new_var("c", Sort::Bool, Some(0), 0, false, None);
+            term![ITE; c, a, b]
+        }
+        "ne" => term![NOT; term![EQ; a, b]],
+        "or" => term![BV_OR; a, b],
+        "rem" => term![BV_UREM; a, b],
+        "shl" => term![BV_SHL; a, bv_lit(1, width)],
+        "shr" => term![BV_LSHR; a, bv_lit(1, width)],
+        "sub" => term![BV_SUB; a, b],
+        "xor" => term![BV_XOR; a, b],
+        _ => unreachable!(),
+    };
+    cs.outputs.push(out);
+    cs
+}
+
+/// A computation that only outputs a `width`-bit input.
+fn baseline_computation(width: usize) -> Computation {
+    let mut cs = Computation::new();
+    let a = cs.new_var("a", Sort::BitVector(width), Some(0), 0, false, None);
+    cs.outputs.push(a);
+    cs
+}
+
+/// Assign `ty` to each term of `cs`, except the inputs, which get `input_ty`.
+fn sharing(cs: &Computation, ty: ShareType, input_ty: ShareType) -> SharingMap {
+    cs.outputs
+        .iter()
+        .flat_map(|o| PostOrderIter::new(o.clone()))
+        .map(|t| {
+            let t_ty = if matches!(t.op, Op::Var(..)) {
+                input_ty
+            } else {
+                ty
+            };
+            (t, t_ty)
+        })
+        .collect()
+}
+
+/// A measurement: time (in ms), and (if the runner reports it) rounds and bytes.
+struct Measurement {
+    time: f64,
+    comm: Option<(f64, f64)>,
+}
+
+impl Measurement {
+    fn minus(&self, other: &Measurement) -> Measurement {
+        let comm = match (self.comm, other.comm) {
+            (Some((r0, b0)), Some((r1, b1))) => Some(((r0 - r1).max(0.0), (b0 - b1).max(0.0))),
+            (c, _) => c,
+        };
+        Measurement {
+            time: (self.time - other.time).max(0.0),
+            comm,
+        }
+    }
+
+    fn scaled(&self, k: f64) -> Measurement {
+        Measurement {
+            time: self.time * k,
+            comm: self.comm.map(|(rounds, bytes)| (rounds * k, bytes * k)),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self.comm {
+            Some((rounds, bytes)) => json!({ "time": self.time, "rounds": rounds, "bytes": bytes }),
+            None => json!(self.time),
+        }
+    }
+}
+
+struct Bench {
+    runner: PathBuf,
+    runs: usize,
+    dir: PathBuf,
+}
+
+impl Bench {
+    fn run(&self, cs: Computation, s_map: SharingMap) -> Measurement {
+        let (bytecode, share_map) = to_aby_bytecode_with_sharing(cs, s_map);
+        let bytecode_path = self.dir.join("bytecode.txt");
+        let share_map_path = self.dir.join("share_map.txt");
+        std::fs::write(&bytecode_path, bytecode.concat()).unwrap();
+        std::fs::write(&share_map_path, share_map.concat()).unwrap();
+        let mut total = 0.0;
+        let mut comm = None;
+        for _ in 0..self.runs {
+            let start = Instant::now();
+            let out = Command::new(&self.runner)
+                .arg(&bytecode_path)
+                .arg(&share_map_path)
+                .output()
+                .unwrap_or_else(|e| panic!("Could not run {}: {}", self.runner.display(), e));
+            total += start.elapsed().as_secs_f64() * 1000.0;
+            if !out.status.success() {
+                panic!(
+                    "{} failed on {}:\n{}",
+                    self.runner.display(),
+                    bytecode_path.display(),
+                    String::from_utf8_lossy(&out.stderr)
+                );
+            }
+            comm = parse_comm(&String::from_utf8_lossy(&out.stdout));
+        }
+        Measurement {
+            time: total / self.runs as f64,
+            comm,
+        }
+    }
+}
+
+/// Parse `<rounds> <bytes>` from the last line of `out`.
+fn parse_comm(out: &str) -> Option<(f64, f64)> {
+    let toks: Vec<&str> = out.lines().last()?.split_whitespace().collect();
+    match toks[..] {
+        [rounds, bytes] => Some((rounds.parse().ok()?, bytes.parse().ok()?)),
+        _ => None,
+    }
+}
+
+fn main() {
+    env_logger::Builder::from_default_env()
+        .format_level(false)
+        .format_timestamp(None)
+        .init();
+    let options = Options::from_args();
+    let dir = std::env::temp_dir().join(format!("aby_costs_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let bench = Bench {
+        runner: options.runner.clone(),
+        runs: options.runs,
+        dir: dir.clone(),
+    };
+    let mut model = Map::new();
+
+    for width in &options.widths {
+        println!("Benchmarking {}-bit operations", width);
+        let baselines: Vec<(ShareType, Measurement)> = SHARE_TYPES
+            .iter()
+            .map(|ty| {
+                let cs = baseline_computation(*width);
+                let s_map = sharing(&cs, *ty, *ty);
+                (*ty, bench.run(cs, s_map))
+            })
+            .collect();
+        let baseline = |ty: ShareType| &baselines.iter().find(|(t, _)| *t == ty).unwrap().1;
+        for (name, types) in OPS {
+            // boolean operators have one width
+            let op_width = match *name {
+                "&&" | "||" if *width != options.widths[0] => continue,
+                "&&" | "||" => 1,
+                _ => *width,
+            };
+            for ty in *types {
+                let cs = op_computation(name, *width);
+                let s_map = sharing(&cs, *ty, *ty);
+                let cost = bench.run(cs, s_map).minus(baseline(*ty));
+                let share = ty.char().to_string();
+                set_cost(&mut model, &[*name, share.as_str()], op_width, &cost);
+            }
+        }
+        // a conversion costs half the difference between computing on two converted inputs, and on
+        // two unconverted ones
+        for from in &SHARE_TYPES {
+            for to in &SHARE_TYPES {
+                if from != to {
+                    let op = if *to == ShareType::Arithmetic {
+                        "add"
+                    } else {
+                        "xor"
+                    };
+                    let cs = op_computation(op, *width);
+                    let converted = bench.run(cs.clone(), sharing(&cs, *to, *from));
+                    let unconverted = bench.run(cs.clone(), sharing(&cs, *to, *to));
+                    let name = format!("{}2{}", from.char(), to.char());
+                    let cost = converted.minus(&unconverted).scaled(0.5);
+                    set_cost(&mut model, &[&name], *width, &cost);
+                }
+            }
+        }
+    }
+    if options.latency.is_some() || options.bandwidth.is_some() {
+        model.insert(
+            "network".to_owned(),
+            json!({
+                "latency": options.latency.unwrap_or(0.0),
+                "bandwidth": options.bandwidth.unwrap_or(f64::MAX),
+            }),
+        );
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+    write_model(&options.output, &Value::Object(model));
+}
+
+/// Set the cost at `width` under the keys `path` of `model`.
+fn set_cost(model: &mut Map<String, Value>, path: &[&str], width: usize, cost: &Measurement) {
+    let mut entry = model;
+    for k in path {
+        entry = entry
+            .entry(k.to_string())
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+            .unwrap();
+    }
+    entry.insert(width.to_string(), cost.to_json());
+}
+
+fn write_model(path: &Path, model: &Value) {
+    let f = std::fs::File::create(path).unwrap();
+    serde_json::to_writer_pretty(f, model).unwrap();
+    println!("Wrote cost model to {}", path.display());
+}
@@ -15,9 +15,12 @@ use circ::front::datalog::{self, Datalog};
 #[cfg(all(feature = "smt", feature = "zok"))]
 use circ::front::zsharp::{self, ZSharpFE};
 use circ::front::{FrontEnd, Mode};
-use circ::ir::term::{Op, BV_LSHR, BV_SHL};
+use circ::ir::term::{Op, Value, BV_LSHR, BV_SHL};
 use circ::ir::{
-    opt::{opt, Opt},
+    opt::{
+        opt, opt_validated, opt_with_report, opts_to_spec, parse_opts, stats::OptReport, validate,
+        Opt,
+    },
     term::{
         check,
         extras::Letified,
@@ -25,12 +28,20 @@ use circ::ir::{
     },
 };
 use circ::target::aby::trans::to_aby;
+use circ::target::arith::to_arith;
+use circ::target::bristol::to_bristol;
 #[cfg(feature = "lp")]
 use circ::target::ilp::{assignment_to_values, trans::to_ilp};
 #[cfg(feature = "r1cs")]
 use circ::target::r1cs::bellman::{gen_params, prove, verify};
+use circ::target::r1cs::export;
+use circ::target::r1cs::import::{parse_circom_sym, read_circom_r1cs_with_names};
 use circ::target::r1cs::opt::reduce_linearities;
 use circ::target::r1cs::trans::to_r1cs;
+#[cfg(feature = "smt")]
+use circ::target::r1cs::uniq;
+use circ::target::r1cs::wit_gen::WitnessGenerator;
+use circ::target::r1cs::{ProverData, R1cs, VerifierData};
 
 #[cfg(feature = "marlin")]
 use ark_bls12_381::{Bls12_381, Fr as BlsFr};
@@ -50,8 +61,13 @@ use sha2::Sha256;
 #[cfg(feature = "mirage")]
 use circ::target::r1cs::mirage;
 
+#[cfg(feature = "spartan")]
+use bls12_381::G1Projective;
+#[cfg(feature = "spartan")]
+use circ::target::r1cs::spartan;
+
 #[cfg(feature = "smt")]
-use circ::target::smt::find_model;
+use circ::target::smt::{find_counterexample, find_model, find_witness};
 use circ::util::field::DFL_T;
 use circ_fields::FieldT;
 use fxhash::FxHashMap as HashMap;
@@ -78,6 +94,29 @@ struct Options {
     #[structopt(long, default_value = "2", name = "PARTIES")]
     parties: u8,
 
+    /// IR optimization passes to run, replacing the default pipeline for the backend.
+    ///
+    /// A comma-separated list, e.g., "scalarize,flatten,cfold(bvshl bvlshr),obliv".
+    #[structopt(long, name = "SPEC", conflicts_with = "passes-file")]
+    passes: Option<String>,
+
+    /// A file containing IR optimization passes to run (see --passes); one or more per line.
+    #[structopt(long, parse(from_os_str))]
+    passes_file: Option<PathBuf>,
+
+    /// Write per-pass optimization statistics (time, term counts) to this file, as JSON.
+    #[structopt(long, parse(from_os_str))]
+    opt_report: Option<PathBuf>,
+
+    /// After each optimization pass, check that the computation's meaning is unchanged, using an
+    /// SMT solver or random inputs. Reports the first pass that changes it.
+    #[structopt(long)]
+    validate_passes: Option<Validation>,
+
+    /// The number of random inputs to try when validating passes by sampling
+    #[structopt(long, default_value = "64")]
+    validation_samples: usize,
+
     #[structopt(subcommand)]
     backend: Backend,
 }
@@ -107,6 +146,10 @@ struct FrontendOptions {
     /// [ZoKrates](https://zokrates.github.io/language/control_flow.html).
     #[structopt(long)]
     z_isolate_asserts: bool,
+
+    /// A circom .sym file, naming the signals of a circom .r1cs input
+    #[structopt(long, parse(from_os_str))]
+    sym: Option<PathBuf>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -117,6 +160,19 @@ enum Backend {
         prover_key: PathBuf,
         #[structopt(long, default_value = "V", parse(from_os_str))]
         verifier_key: PathBuf,
+        #[structopt(long, default_value = "pi", parse(from_os_str))]
+        proof: PathBuf,
+        /// Value map for the prover (for `prove`) or the verifier (for `verify`)
+        #[structopt(long, parse(from_os_str))]
+        inputs: Option<PathBuf>,
+        /// Path prefix for the files written by `export` (.r1cs, .json, .wgen, and, given
+        /// --inputs, .wtns)
+        #[structopt(long, default_value = "circuit", parse(from_os_str))]
+        export_prefix: PathBuf,
+        /// Also compile the witness computation, and write the generator here (whatever the
+        /// action); run it with the `wit_gen` example
+        #[structopt(long, parse(from_os_str))]
+        wit_gen: Option<PathBuf>,
         #[structopt(long, default_value = "50")]
         /// linear combination constraints up to this size will be eliminated
         lc_elimination_thresh: usize,
@@ -125,14 +181,37 @@ enum Backend {
         #[structopt(long, default_value = "groth")]
         proof_system: ProofSystem,
     },
-    Smt {},
+    Smt {
+        /// Search for a witness that satisfies the assertions, or check for a counterexample
+        /// that violates one
+        #[structopt(long, default_value = "witness")]
+        action: SmtAction,
+        /// Write the witness or counterexample to this file, as a value map
+        #[structopt(long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
     Ilp {},
     Mpc {
+        /// The cost model for share assignment: `opa`, `hycc`, or the path of a cost model file
+        /// (e.g., as written by the `aby_costs` example)
         #[structopt(long, default_value = "hycc", name = "cost_model")]
         cost_model: String,
+        /// The share assignment: `b`, `y`, `a+b`, `a+y`, `greedy`, `lp`, or (for large
+        /// computations) `plp` or `plp:<region size>`, which solve an ILP per region of terms
         #[structopt(long, default_value = "lp", name = "selection_scheme")]
         selection_scheme: String,
     },
+    /// A Bristol Fashion boolean circuit, for MPC engines other than ABY
+    Bristol {
+        /// Path prefix for the circuit (.bristol) and its input/output description (.io)
+        #[structopt(long, default_value = "circuit", parse(from_os_str))]
+        output_prefix: PathBuf,
+    },
+    /// An arithmetic circuit over the default field, as a JSON netlist, for N-party MPC engines
+    Arith {
+        #[structopt(long, default_value = "circuit.json", parse(from_os_str))]
+        output: PathBuf,
+    },
 }
 
 arg_enum! {
@@ -141,6 +220,7 @@ arg_enum! {
         Zsharp,
         Datalog,
         C,
+        R1cs,
         Auto,
     }
 }
@@ -150,6 +230,7 @@ pub enum DeterminedLanguage {
     Zsharp,
     Datalog,
     C,
+    R1cs,
 }
 
 #[derive(PartialEq, Debug)]
@@ -158,11 +239,32 @@ pub enum CostModelType {
     Hycc,
 }
 
+arg_enum! {
+    #[derive(PartialEq, Debug, Clone, Copy)]
+    enum Validation {
+        Smt,
+        Sample,
+    }
+}
+
 arg_enum! {
     #[derive(PartialEq, Debug)]
     enum ProofAction {
         Count,
+        Profile,
+        Underconstrained,
         Setup,
+        Prove,
+        Verify,
+        Export,
+    }
+}
+
+arg_enum! {
+    #[derive(PartialEq, Debug, Clone, Copy)]
+    enum SmtAction {
+        Witness,
+        Check,
     }
 }
 
@@ -172,6 +274,7 @@ arg_enum! {
         Groth,
         Marlin,
         Mirage,
+        Spartan,
     }
 }
 
@@ -180,6 +283,7 @@ fn determine_language(l: &Language, input_path: &Path) -> DeterminedLanguage {
         Language::Datalog => DeterminedLanguage::Datalog,
         Language::Zsharp => DeterminedLanguage::Zsharp,
         Language::C => DeterminedLanguage::C,
+        Language::R1cs => DeterminedLanguage::R1cs,
         Language::Auto => {
             let p = input_path.to_str().unwrap();
             if p.ends_with(".zok") {
@@ -188,6 +292,8 @@ fn determine_language(l: &Language, input_path: &Path) -> DeterminedLanguage {
                 DeterminedLanguage::Datalog
             } else if p.ends_with(".c") || p.ends_with(".cpp") || p.ends_with(".cc") {
                 DeterminedLanguage::C
+            } else if p.ends_with(".r1cs") {
+                DeterminedLanguage::R1cs
             } else {
                 println!("Could not deduce the input language from path '{}', please set the language manually", p);
                 std::process::exit(2)
@@ -196,6 +302,226 @@ fn determine_language(l: &Language, input_path: &Path) -> DeterminedLanguage {
     }
 }
 
+#[cfg(feature = "r1cs")]
+/// Print how many constraints each source function and line is responsible for.
+fn print_profile(r1cs: &R1cs<String>) {
+    let counts = r1cs.src_loc_counts();
+    let total = r1cs.constraints().len().max(1) as f64;
+    let mut by_fn: HashMap<Option<(&str, &str)>, usize> = HashMap::default();
+    for (loc, n) in &counts {
+        let f = loc.map(|l| (l.file.as_str(), l.function.as_str()));
+        *by_fn.entry(f).or_insert(0) += n;
+    }
+    let mut by_fn: Vec<_> = by_fn.into_iter().collect();
+    by_fn.sort_by(|(f0, n0), (f1, n1)| n1.cmp(n0).then_with(|| f0.cmp(f1)));
+    println!("Constraints by function:");
+    for (f, n) in by_fn {
+        let name = match f {
+            Some((file, function)) => format!("{} ({})", function, file),
+            None => "<unknown>".to_owned(),
+        };
+        println!("{:>10} {:>6.2}%  {}", n, 100.0 * n as f64 / total, name);
+    }
+    println!("Constraints by line:");
+    for (loc, n) in counts {
+        let name = match loc {
+            Some(l) => format!("{}", l),
+            None => "<unknown>".to_owned(),
+        };
+        println!("{:>10} {:>6.2}%  {}", n, 100.0 * n as f64 / total, name);
+    }
+}
+
+#[cfg(feature = "r1cs")]
+/// Generate keys for `proof_system`, writing them to `pk_path` and `vk_path`.
+fn setup(
+    proof_system: &ProofSystem,
+    pk_path: &Path,
+    vk_path: &Path,
+    prover_data: &ProverData,
+    verifier_data: &VerifierData,
+) {
+    match proof_system {
+        ProofSystem::Groth => {
+            gen_params::<Bls12, _, _>(pk_path, vk_path, prover_data, verifier_data).unwrap();
+        }
+        #[cfg(feature = "marlin")]
+        ProofSystem::Marlin => {
+            marlin::gen_params::<
+                BlsFr,
+                MarlinKZG10<Bls12_381, DensePolynomial<BlsFr>>,
+                SimpleHashFiatShamirRng<Sha256, ChaChaRng>,
+                _,
+                _,
+            >(pk_path, vk_path, prover_data, verifier_data)
+            .unwrap();
+        }
+        #[cfg(not(feature = "marlin"))]
+        ProofSystem::Marlin => {
+            panic!("Missing feature: marlin");
+        }
+        #[cfg(feature = "mirage")]
+        ProofSystem::Mirage => {
+            mirage::gen_params::<Bls12, _, _>(pk_path, vk_path, prover_data, verifier_data)
+                .unwrap();
+        }
+        #[cfg(not(feature = "mirage"))]
+        ProofSystem::Mirage => {
+            panic!("Missing feature: mirage");
+        }
+        #[cfg(feature = "spartan")]
+        ProofSystem::Spartan => {
+            spartan::gen_params::<G1Projective, _, _>(pk_path, vk_path, prover_data, verifier_data)
+                .unwrap();
+        }
+        #[cfg(not(feature = "spartan"))]
+        ProofSystem::Spartan => {
+            panic!("Missing feature: spartan");
+        }
+    }
+}
+
+#[cfg(feature = "r1cs")]
+/// Prove with `proof_system`, using the key at `pk_path`, writing the proof to `pf_path`.
+fn prove_with(
+    proof_system: &ProofSystem,
+    pk_path: &Path,
+    pf_path: &Path,
+    input_map: &HashMap<String, Value>,
+) {
+    match proof_system {
+        ProofSystem::Groth => {
+            prove::<Bls12, _, _>(pk_path, pf_path, input_map).unwrap();
+        }
+        #[cfg(feature = "marlin")]
+        ProofSystem::Marlin => {
+            marlin::prove::<
+                BlsFr,
+                MarlinKZG10<Bls12_381, DensePolynomial<BlsFr>>,
+                SimpleHashFiatShamirRng<Sha256, ChaChaRng>,
+                _,
+                _,
+            >(pk_path, pf_path, input_map)
+            .unwrap();
+        }
+        #[cfg(not(feature = "marlin"))]
+        ProofSystem::Marlin => {
+            panic!("Missing feature: marlin");
+        }
+        #[cfg(feature = "mirage")]
+        ProofSystem::Mirage => {
+            mirage::prove::<Bls12, _, _>(pk_path, pf_path, input_map).unwrap();
+        }
+        #[cfg(not(feature = "mirage"))]
+        ProofSystem::Mirage => {
+            panic!("Missing feature: mirage");
+        }
+        #[cfg(feature = "spartan")]
+        ProofSystem::Spartan => {
+            spartan::prove::<G1Projective, _, _>(pk_path, pf_path, input_map).unwrap();
+        }
+        #[cfg(not(feature = "spartan"))]
+        ProofSystem::Spartan => {
+            panic!("Missing feature: spartan");
+        }
+    }
+}
+
+#[cfg(feature = "r1cs")]
+/// Verify the proof at `pf_path` with `proof_system`, using the key at `vk_path`.
+fn verify_with(
+    proof_system: &ProofSystem,
+    vk_path: &Path,
+    pf_path: &Path,
+    input_map: &HashMap<String, Value>,
+) {
+    match proof_system {
+        ProofSystem::Groth => {
+            verify::<Bls12, _, _>(vk_path, pf_path, input_map).unwrap();
+        }
+        #[cfg(feature = "marlin")]
+        ProofSystem::Marlin => {
+            marlin::verify::<
+                BlsFr,
+                MarlinKZG10<Bls12_381, DensePolynomial<BlsFr>>,
+                SimpleHashFiatShamirRng<Sha256, ChaChaRng>,
+                _,
+                _,
+            >(vk_path, pf_path, input_map)
+            .unwrap();
+        }
+        #[cfg(not(feature = "marlin"))]
+        ProofSystem::Marlin => {
+            panic!("Missing feature: marlin");
+        }
+        #[cfg(feature = "mirage")]
+        ProofSystem::Mirage => {
+            mirage::verify::<Bls12, _, _>(vk_path, pf_path, input_map).unwrap();
+        }
+        #[cfg(not(feature = "mirage"))]
+        ProofSystem::Mirage => {
+            panic!("Missing feature: mirage");
+        }
+        #[cfg(feature = "spartan")]
+        ProofSystem::Spartan => {
+            spartan::verify::<G1Projective, _, _>(vk_path, pf_path, input_map).unwrap();
+        }
+        #[cfg(not(feature = "spartan"))]
+        ProofSystem::Spartan => {
+            panic!("Missing feature: spartan");
+        }
+    }
+}
+
+/// The IR optimization pipeline for `mode`, used when no pass spec is given.
+fn default_passes(mode: Mode) -> Vec<Opt> {
+    match mode {
+        Mode::Opt => vec![Opt::ScalarizeVars, Opt::ConstantFold(Box::new([]))],
+        Mode::Mpc(_) => {
+            let ignore = [BV_LSHR, BV_SHL];
+            vec![
+                Opt::ScalarizeVars,
+                Opt::Flatten,
+                Opt::Sha,
+                Opt::ConstantFold(Box::new(ignore.clone())),
+                Opt::Flatten,
+                Opt::Obliv,
+                // The obliv elim pass produces more tuples, that must be eliminated
+                Opt::Tuple,
+                Opt::LinearScan,
+                // The linear scan pass produces more tuples, that must be eliminated
+                Opt::Tuple,
+                Opt::ConstantFold(Box::new(ignore)),
+                // Binarize nary terms
+                Opt::Binarize,
+            ]
+        }
+        Mode::Proof | Mode::ProofOfHighValue(_) => vec![
+            Opt::RamExt(DFL_T.clone()),
+            Opt::ScalarizeVars,
+            Opt::Flatten,
+            Opt::Sha,
+            Opt::ConstantFold(Box::new([])),
+            Opt::Flatten,
+            Opt::Inline,
+            // Tuples must be eliminated before oblivious array elim
+            Opt::Tuple,
+            Opt::ConstantFold(Box::new([])),
+            Opt::Obliv,
+            // The obliv elim pass produces more tuples, that must be eliminated
+            Opt::Tuple,
+            Opt::LinearScan,
+            // The linear scan pass produces more tuples, that must be eliminated
+            Opt::Tuple,
+            // Floats must be scalar to be lowered to soft-float circuits
+            Opt::Fp,
+            Opt::Flatten,
+            Opt::ConstantFold(Box::new([])),
+            Opt::Inline,
+        ],
+    }
+}
+
 fn main() {
     env_logger::Builder::from_default_env()
         .format_level(false)
@@ -210,7 +536,9 @@ fn main() {
             None => Mode::Proof,
         },
         Backend::Ilp { .. } => Mode::Opt,
-        Backend::Mpc { .. } => Mode::Mpc(options.parties),
+        Backend::Mpc { .. } | Backend::Bristol { .. } | Backend::Arith { .. } => {
+            Mode::Mpc(options.parties)
+        }
         Backend::Smt { .. } => Mode::Proof,
     };
     let language = determine_language(&options.frontend.language, &options.path);
@@ -248,60 +576,55 @@ fn main() {
         DeterminedLanguage::C => {
             panic!("Missing feature: c");
         }
+        DeterminedLanguage::R1cs => {
+            let names = match &options.frontend.sym {
+                Some(sym) => parse_circom_sym(&std::fs::read_to_string(sym).unwrap()).unwrap(),
+                None => Default::default(),
+            };
+            let file = File::open(&options.path).unwrap();
+            read_circom_r1cs_with_names(file, &names).unwrap().lift()
+        }
     };
-    let cs = match mode {
-        Mode::Opt => opt(
-            cs,
-            vec![Opt::ScalarizeVars, Opt::ConstantFold(Box::new([]))],
-        ),
-        Mode::Mpc(_) => {
-            let ignore = [BV_LSHR, BV_SHL];
-            opt(
-                cs,
-                vec![
-                    Opt::ScalarizeVars,
-                    Opt::Flatten,
-                    Opt::Sha,
-                    Opt::ConstantFold(Box::new(ignore.clone())),
-                    Opt::Flatten,
-                    Opt::Obliv,
-                    // The obliv elim pass produces more tuples, that must be eliminated
-                    Opt::Tuple,
-                    Opt::LinearScan,
-                    // The linear scan pass produces more tuples, that must be eliminated
-                    Opt::Tuple,
-                    Opt::ConstantFold(Box::new(ignore)),
-                    // Binarize nary terms
-                    Opt::Binarize,
-                ],
-                // vec![Opt::Sha, Opt::ConstantFold, Opt::Mem, Opt::ConstantFold],
-            )
-        }
-        Mode::Proof | Mode::ProofOfHighValue(_) => opt(
-            cs,
-            vec![
-                Opt::RamExt,
-                Opt::ScalarizeVars,
-                Opt::Flatten,
-                Opt::Sha,
-                Opt::ConstantFold(Box::new([])),
-                Opt::Flatten,
-                Opt::Inline,
-                // Tuples must be eliminated before oblivious array elim
-                Opt::Tuple,
-                Opt::ConstantFold(Box::new([])),
-                Opt::Obliv,
-                // The obliv elim pass produces more tuples, that must be eliminated
-                Opt::Tuple,
-                Opt::LinearScan,
-                // The linear scan pass produces more tuples, that must be eliminated
-                Opt::Tuple,
-                Opt::Flatten,
-                Opt::ConstantFold(Box::new([])),
-                Opt::Inline,
-            ],
-        ),
+    let passes = if let Some(spec) = &options.passes {
+        parse_opts(spec).unwrap_or_else(|e| panic!("Bad --passes: {}", e))
+    } else if let Some(path) = &options.passes_file {
+        let spec = std::fs::read_to_string(path).unwrap();
+        parse_opts(&spec).unwrap_or_else(|e| panic!("Bad pass spec in {}: {}", path.display(), e))
+    } else {
+        default_passes(mode)
     };
+    println!("Optimization passes: {}", opts_to_spec(&passes));
+    let mut report = OptReport::default();
+    let cs = match options.validate_passes {
+        Some(validation) => {
+            let method = match validation {
+                Validation::Smt => validate::Method::Smt,
+                Validation::Sample => validate::Method::Sample(options.validation_samples),
+            };
+            let report = options.opt_report.as_ref().map(|_| &mut report);
+            match opt_validated(cs, passes, method, report) {
+                Ok(cs) => cs,
+                Err(miscompile) => {
+                    eprintln!("{}", miscompile);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None if options.opt_report.is_some() => {
+            let (cs, r) = opt_with_report(cs, passes);
+            report = r;
+            cs
+        }
+        None => opt(cs, passes),
+    };
+    if let Some(report_path) = &options.opt_report {
+        std::fs::write(report_path, report.to_json()).unwrap();
+        println!(
+            "Wrote optimization report ({:.3}s total) to {}",
+            report.total_seconds(),
+            report_path.display()
+        );
+    }
     println!("Done with IR optimization");
 
     match options.backend {
@@ -310,9 +633,12 @@ fn main() {
             action,
             prover_key,
             verifier_key,
+            proof,
+            inputs,
+            export_prefix,
+            wit_gen,
             lc_elimination_thresh,
             proof_system,
-            ..
         } => {
             println!("Converting to r1cs");
             let (r1cs, mut prover_data, verifier_data) = to_r1cs(cs, FieldT::from(DFL_T.modulus()));
@@ -321,51 +647,85 @@ fn main() {
             println!("Final R1cs size: {}", r1cs.constraints().len());
             // save the optimized r1cs: the prover needs it to synthesize.
             prover_data.r1cs = r1cs;
+            if let Some(path) = wit_gen {
+                println!("Writing the witness generator to {}", path.display());
+                WitnessGenerator::new(&prover_data)
+                    .write(File::create(path).unwrap())
+                    .unwrap();
+            }
             match action {
                 ProofAction::Count => (),
+                ProofAction::Profile => print_profile(&prover_data.r1cs),
+                #[cfg(feature = "smt")]
+                ProofAction::Underconstrained => {
+                    println!("Searching for under-constrained signals");
+                    let ambiguities = uniq::find_ambiguities(&prover_data.r1cs);
+                    if ambiguities.is_empty() {
+                        println!("All private signals are determined by the public ones");
+                    } else {
+                        for a in &ambiguities {
+                            println!("Two witnesses differ on:");
+                            print!("{}", a);
+                        }
+                        std::process::exit(1)
+                    }
+                }
+                #[cfg(not(feature = "smt"))]
+                ProofAction::Underconstrained => {
+                    panic!("Missing feature: smt");
+                }
                 ProofAction::Setup => {
                     println!("Generating Parameters for proof system {}", proof_system);
-                    match proof_system {
-                        ProofSystem::Groth => {
-                            gen_params::<Bls12, _, _>(
-                                prover_key,
-                                verifier_key,
-                                &prover_data,
-                                &verifier_data,
-                            )
-                            .unwrap();
-                        }
-                        #[cfg(feature = "marlin")]
-                        ProofSystem::Marlin => {
-                            marlin::gen_params::<
-                                BlsFr,
-                                MarlinKZG10<Bls12_381, DensePolynomial<BlsFr>>,
-                                SimpleHashFiatShamirRng<Sha256, ChaChaRng>,
-                                _,
-                                _,
-                            >(
-                                prover_key, verifier_key, &prover_data, &verifier_data
-                            )
-                            .unwrap();
-                        }
-                        #[cfg(not(feature = "marlin"))]
-                        ProofSystem::Marlin => {
-                            panic!("Missing feature: marlin");
-                        }
-                        #[cfg(feature = "mirage")]
-                        ProofSystem::Mirage => {
-                            mirage::gen_params::<Bls12, _, _>(
-                                prover_key,
-                                verifier_key,
-                                &prover_data,
-                                &verifier_data,
-                            )
-                            .unwrap();
-                        }
-                        #[cfg(not(feature = "mirage"))]
-                        ProofSystem::Mirage => {
-                            panic!("Missing feature: mirage");
+                    setup(
+                        &proof_system,
+                        &prover_key,
+                        &verifier_key,
+                        &prover_data,
+                        &verifier_data,
+                    );
+                }
+                ProofAction::Prove => {
+                    let inputs = inputs.expect("The prove action requires --inputs");
+                    let input_map = parse_value_map(&std::fs::read(inputs).unwrap());
+                    if !prover_key.exists() {
+                        eprintln!(
+                            "No proving key at {}; run the setup action first",
+                            prover_key.display()
+                        );
+                        std::process::exit(1);
+                    }
+                    println!("Proving ({})", proof_system);
+                    prove_with(&proof_system, &prover_key, &proof, &input_map);
+                }
+                ProofAction::Verify => {
+                    let inputs = inputs.expect("The verify action requires --inputs");
+                    let input_map = parse_value_map(&std::fs::read(inputs).unwrap());
+                    println!("Verifying ({})", proof_system);
+                    verify_with(&proof_system, &verifier_key, &proof, &input_map);
+                }
+                ProofAction::Export => {
+                    let r1cs = &prover_data.r1cs;
+                    let path = |ext: &str| export_prefix.with_extension(ext);
+                    println!("Exporting to {}", path("r1cs").display());
+                    export::write_circom_r1cs(r1cs, File::create(path("r1cs")).unwrap()).unwrap();
+                    export::write_json(r1cs, File::create(path("json")).unwrap()).unwrap();
+                    let generator = WitnessGenerator::new(&prover_data);
+                    generator
+                        .write(File::create(path("wgen")).unwrap())
+                        .unwrap();
+                    if let Some(inputs) = inputs {
+                        if generator.has_challenges() {
+                            println!(
+                                "Not exporting a witness: it depends on verifier challenges, \
+                                 which a proof system must draw"
+                            );
+                            return;
                         }
+                        let input_map = parse_value_map(&std::fs::read(inputs).unwrap());
+                        let values = generator.eval(&input_map);
+                        r1cs.check_all(&values);
+                        let wtns = File::create(path("wtns")).unwrap();
+                        export::write_circom_wtns(r1cs, &values, wtns).unwrap();
                     }
                 }
             }
@@ -388,6 +748,24 @@ fn main() {
             println!("Selection scheme: {}", selection_scheme);
             to_aby(cs, &path_buf, &lang_str, &cost_model, &selection_scheme);
         }
+        Backend::Bristol { output_prefix } => {
+            println!("Converting to a Bristol Fashion circuit");
+            let circuit = to_bristol(cs);
+            println!("AND gates: {}", circuit.and_count());
+            let path = |ext: &str| output_prefix.with_extension(ext);
+            println!("Writing to {}", path("bristol").display());
+            circuit
+                .write(File::create(path("bristol")).unwrap())
+                .unwrap();
+            circuit.write_io(File::create(path("io")).unwrap()).unwrap();
+        }
+        Backend::Arith { output } => {
+            println!("Converting to an arithmetic circuit");
+            let circuit = to_arith(cs, FieldT::from(DFL_T.modulus()));
+            println!("Multiplication gates: {}", circuit.mul_count());
+            println!("Writing to {}", output.display());
+            circuit.write_json(File::create(&output).unwrap()).unwrap();
+        }
         #[cfg(feature = "lp")]
         Backend::Ilp { .. } => {
             println!("Converting to ilp");
@@ -414,7 +792,7 @@ fn main() {
             panic!("Missing feature: lp");
         }
         #[cfg(feature = "smt")]
-        Backend::Smt { .. } => {
+        Backend::Smt { action, output } => {
             if options.frontend.lint_prim_rec {
                 assert_eq!(cs.outputs.len(), 1);
                 match find_model(&cs.outputs[0]) {
@@ -430,7 +808,41 @@ fn main() {
                     }
                 }
             } else {
-                todo!()
+                let model = match action {
+                    SmtAction::Witness => {
+                        println!("Searching for a witness");
+                        find_witness(&cs)
+                    }
+                    SmtAction::Check => {
+                        println!("Searching for a counterexample");
+                        find_counterexample(&cs)
+                    }
+                };
+                match model {
+                    Some(m) => {
+                        let values = serialize_value_map(&m.into_iter().collect());
+                        match action {
+                            SmtAction::Witness => println!("Witness:"),
+                            SmtAction::Check => println!("Counterexample:"),
+                        }
+                        print!("{}", values);
+                        if let Some(path) = output {
+                            std::fs::write(path, values).unwrap();
+                        }
+                        if action == SmtAction::Check {
+                            std::process::exit(1)
+                        }
+                    }
+                    None => match action {
+                        SmtAction::Witness => {
+                            println!("No witness: the assertions are unsatisfiable");
+                            std::process::exit(1)
+                        }
+                        SmtAction::Check => {
+                            println!("No counterexample: the assertions always hold");
+                        }
+                    },
+                }
             }
         }
         #[cfg(not(feature = "smt"))]
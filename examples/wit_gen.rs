@@ -0,0 +1,41 @@
+use circ::ir::term::text::parse_value_map;
+use circ::target::r1cs::wit_gen::WitnessGenerator;
+use std::fs::File;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "wit_gen",
+    about = "Run a compiled witness generator, writing a circom witness"
+)]
+struct Options {
+    /// Witness generator (as written by `circ ... r1cs --action export`)
+    #[structopt(parse(from_os_str))]
+    generator: PathBuf,
+
+    /// Value map for the prover
+    #[structopt(parse(from_os_str))]
+    inputs: PathBuf,
+
+    /// Output witness (.wtns)
+    #[structopt(parse(from_os_str))]
+    output: PathBuf,
+}
+
+fn main() {
+    env_logger::Builder::from_default_env()
+        .format_level(false)
+        .format_timestamp(None)
+        .init();
+    let options = Options::from_args();
+    let generator = WitnessGenerator::read(File::open(&options.generator).unwrap()).unwrap();
+    if generator.has_challenges() {
+        eprintln!("The witness depends on verifier challenges; prove it with a proof system");
+        std::process::exit(1);
+    }
+    let input_map = parse_value_map(&std::fs::read(&options.inputs).unwrap());
+    generator
+        .write_circom_wtns(&input_map, File::create(&options.output).unwrap())
+        .unwrap();
+}
@@ -1,6 +1,6 @@
 use bls12_381::Bls12;
 use circ::ir::term::text::parse_value_map;
-use circ::target::r1cs::bellman::{prove, verify};
+use circ::target::r1cs::bellman::{add_to_batch, batch_verify, prove, verify};
 use std::path::PathBuf;
 use structopt::clap::arg_enum;
 use structopt::StructOpt;
@@ -34,6 +34,9 @@ struct Options {
     proof: PathBuf,
     #[structopt(long, default_value = "in", parse(from_os_str))]
     inputs: PathBuf,
+    /// Batch of proofs and their inputs (for `batchadd` and `batchverify`)
+    #[structopt(long, default_value = "batch", parse(from_os_str))]
+    batch: PathBuf,
     #[structopt(long)]
     action: ProofAction,
     #[structopt(long, default_value = "groth")]
@@ -45,6 +48,8 @@ arg_enum! {
     enum ProofAction {
         Prove,
         Verify,
+        BatchAdd,
+        BatchVerify,
     }
 }
 
@@ -63,6 +68,16 @@ fn main() {
         .format_timestamp(None)
         .init();
     let opts = Options::from_args();
+    if opts.action == ProofAction::BatchVerify {
+        assert_eq!(
+            opts.proof_system,
+            ProofSystem::Groth,
+            "Batch verification is only supported for groth"
+        );
+        println!("Batch verifying ({})", opts.proof_system);
+        batch_verify::<Bls12, _, _>(opts.verifier_key, opts.batch).unwrap();
+        return;
+    }
     let input_map = parse_value_map(&std::fs::read(opts.inputs).unwrap());
     match opts.action {
         ProofAction::Prove => {
@@ -128,5 +143,14 @@ fn main() {
                 }
             }
         }
+        ProofAction::BatchAdd => {
+            assert_eq!(
+                opts.proof_system,
+                ProofSystem::Groth,
+                "Batch verification is only supported for groth"
+            );
+            add_to_batch(opts.batch, opts.proof, &input_map).unwrap();
+        }
+        ProofAction::BatchVerify => unreachable!(),
     }
 }
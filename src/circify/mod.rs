@@ -1,5 +1,6 @@
 //! A library for building front-ends
 use crate::circify::mem::AllocId;
+use crate::ir::term::src_loc::SrcLoc;
 use crate::ir::term::*;
 
 use std::cell::RefCell;
@@ -216,6 +217,8 @@ struct FnFrame<Ty> {
     prefix: String,
     name: String,
     has_return: bool,
+    /// The source location of the statement being embedded
+    src_loc: Option<SrcLoc>,
 }
 
 impl<Ty: Display> FnFrame<Ty> {
@@ -226,6 +229,7 @@ impl<Ty: Display> FnFrame<Ty> {
             prefix,
             name,
             has_return,
+            src_loc: None,
         };
         this.enter_scope();
         this.enter_breakable(RET_BREAK_NAME.to_owned());
@@ -379,6 +383,13 @@ pub trait Embeddable {
     // Because the type alias may change.
     #[allow(clippy::ptr_arg)]
     fn initialize_return(&self, ty: &Self::Ty, ssa_name: &SsaName) -> Self::T;
+
+    /// The IR terms of a language value, for attributing them to source locations.
+    ///
+    /// By default, values are not attributed.
+    fn terms(&self, _ctx: &CirCtx, _t: &Self::T) -> Vec<Term> {
+        Vec::new()
+    }
 }
 
 /// Manager for circuit-embedded state.
@@ -392,6 +403,8 @@ pub struct Circify<E: Embeddable> {
     cir_ctx: CirCtx,
     condition: Term,
     typedefs: HashMap<String, E::Ty>,
+    /// The source location of global statements
+    src_loc: Option<SrcLoc>,
 }
 
 impl<E: Embeddable> Debug for Circify<E> {
@@ -444,6 +457,7 @@ impl<E: Embeddable> Circify<E> {
             },
             condition: leaf_term(Op::Const(Value::Bool(true))),
             typedefs: HashMap::default(),
+            src_loc: None,
         }
     }
 
@@ -452,6 +466,49 @@ impl<E: Embeddable> Circify<E> {
         &self.cir_ctx
     }
 
+    /// Set the source location of the statement being embedded, in the current function.
+    ///
+    /// Terms that are subsequently assigned, stored, or asserted are attributed to it.
+    pub fn set_src_loc(&mut self, file: &str, line: usize) {
+        if let Some(back) = self.fn_stack.last_mut() {
+            back.src_loc = Some(SrcLoc {
+                file: file.to_owned(),
+                function: back.name.clone(),
+                line,
+            });
+        } else {
+            self.src_loc = Some(SrcLoc {
+                file: file.to_owned(),
+                function: String::new(),
+                line,
+            });
+        }
+    }
+
+    /// Attribute `t` (and any new sub-terms) to the current source location, if any.
+    pub fn locate(&self, t: &Term) {
+        let loc = match self.fn_stack.last() {
+            Some(back) => back.src_loc.as_ref(),
+            None => self.src_loc.as_ref(),
+        };
+        if let Some(loc) = loc {
+            self.cir_ctx
+                .cs
+                .borrow_mut()
+                .metadata
+                .src_locs
+                .record(t, loc);
+        }
+    }
+
+    fn locate_val(&self, v: &Val<E::T>) {
+        if let Val::Term(t) = v {
+            for t in self.e.terms(&self.cir_ctx, t) {
+                self.locate(&t);
+            }
+        }
+    }
+
     /// Initialize environment entry binding `name` to `ty`.
     fn declare_env_name(&mut self, name: VarName, ty: &E::Ty) -> Result<&SsaName> {
         if let Some(back) = self.fn_stack.last_mut() {
@@ -495,7 +552,9 @@ impl<E: Embeddable> Circify<E> {
             random,
             precomputed_value,
         );
-        assert!(self.vals.insert(ssa_name, Val::Term(t.clone())).is_none());
+        let val = Val::Term(t.clone());
+        self.locate_val(&val);
+        assert!(self.vals.insert(ssa_name, val).is_none());
         Ok(t)
     }
 
@@ -580,6 +639,7 @@ impl<E: Embeddable> Circify<E> {
     pub fn declare_init(&mut self, name: VarName, ty: E::Ty, val: Val<E::T>) -> Result<Val<E::T>> {
         let ssa_name = self.declare_env_name(name, &ty)?.clone();
         // TODO: add language-specific coersion here if needed
+        self.locate_val(&val);
         assert!(self.vals.insert(ssa_name, val.clone()).is_none());
         Ok(val)
     }
@@ -617,6 +677,7 @@ impl<E: Embeddable> Circify<E> {
                     None => self.e.ite(&mut self.cir_ctx, guard, new, (*old).clone()),
                 };
                 let ite_val = Val::Term(ite);
+                self.locate_val(&ite_val);
                 // TODO: add language-specific coersion here if needed
                 assert!(self.vals.insert(new_name, ite_val.clone()).is_none());
                 Ok(ite_val)
@@ -700,8 +761,14 @@ impl<E: Embeddable> Circify<E> {
     pub fn enter_fn(&mut self, name: String, ret_ty: Option<E::Ty>) {
         let prefix = format!("{}_f{}", name, self.fn_ctr);
         self.fn_ctr += 1;
-        self.fn_stack
-            .push(FnFrame::new(name, prefix, ret_ty.is_some()));
+        // until the first statement, attribute terms (e.g., arguments) to the call site
+        let caller_src_loc = match self.fn_stack.last() {
+            Some(back) => back.src_loc.clone(),
+            None => self.src_loc.clone(),
+        };
+        let mut frame = FnFrame::new(name, prefix, ret_ty.is_some());
+        frame.src_loc = caller_src_loc;
+        self.fn_stack.push(frame);
         if let Some(ty) = ret_ty {
             let ssa_name = self
                 .declare_env_name(RET_NAME.to_owned(), &ty)
@@ -739,6 +806,7 @@ impl<E: Embeddable> Circify<E> {
 
     /// Assert something
     pub fn assert(&mut self, t: Term) {
+        self.locate(&t);
         self.cir_ctx.cs.borrow_mut().assert(t);
     }
 
@@ -826,6 +894,7 @@ impl<E: Embeddable> Circify<E> {
     /// Conditional store to an AllocId based on current path condition
     pub fn store(&mut self, id: AllocId, offset: Term, val: Term) {
         let cond = self.condition();
+        self.locate(&val);
         self.cir_ctx.mem.borrow_mut().store(id, offset, val, cond);
     }
 
@@ -40,7 +40,8 @@ impl FrontEnd for C {
     fn gen(i: Inputs) -> Computation {
         let parser = parser::CParser::new();
         let p = parser.parse_file(&i.file).unwrap();
-        let mut g = CGen::new(i.mode, p.unit);
+        let lines = parser::LineMap::new(&i.file, &p.source);
+        let mut g = CGen::new(i.mode, p.unit, lines);
         g.visit_files();
         g.entry_fn("main");
         g.circ.consume().borrow().clone()
@@ -77,17 +78,19 @@ struct CGen {
     circ: Circify<Ct>,
     mode: Mode,
     tu: TranslationUnit,
+    lines: parser::LineMap,
     structs: HashMap<String, Ty>,
     functions: HashMap<String, FnInfo>,
     typedefs: HashMap<String, Ty>,
 }
 
 impl CGen {
-    fn new(mode: Mode, tu: TranslationUnit) -> Self {
+    fn new(mode: Mode, tu: TranslationUnit, lines: parser::LineMap) -> Self {
         let this = Self {
             circ: Circify::new(Ct::new()),
             mode,
             tu,
+            lines,
             structs: HashMap::default(),
             functions: HashMap::default(),
             typedefs: HashMap::default(),
@@ -958,6 +961,8 @@ impl CGen {
         match stmt {
             Statement::Compound(nodes) => {
                 for node in nodes {
+                    let (file, line) = self.lines.get(node.span.start);
+                    self.circ.set_src_loc(file, line);
                     match node.node {
                         BlockItem::Declaration(decl) => {
                             self.gen_decl(decl.node);
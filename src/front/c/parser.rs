@@ -19,3 +19,48 @@ impl CParser {
         parse(&self.config, path)
     }
 }
+
+/// Maps byte offsets in preprocessed source to (file, line) pairs, honoring linemarkers.
+pub struct LineMap {
+    /// For each line of the preprocessed source: its starting offset, original file, and
+    /// original line.
+    lines: Vec<(usize, String, usize)>,
+}
+
+impl LineMap {
+    pub fn new(path: &Path, source: &str) -> Self {
+        let mut lines = Vec::new();
+        let mut file = path.display().to_string();
+        let mut line = 1;
+        let mut start = 0;
+        for l in source.split_inclusive('\n') {
+            lines.push((start, file.clone(), line));
+            start += l.len();
+            line += 1;
+            // `# 12 "file.c" flags` or `#line 12 "file.c"`
+            if let Some(marker) = l.trim_start().strip_prefix('#') {
+                let mut toks = marker.split_whitespace().peekable();
+                if toks.peek() == Some(&"line") {
+                    toks.next();
+                }
+                if let Some(n) = toks.next().and_then(|n| n.parse().ok()) {
+                    line = n;
+                    if let Some(f) = toks.next() {
+                        file = f.trim_matches('"').to_owned();
+                    }
+                }
+            }
+        }
+        Self { lines }
+    }
+
+    /// The file and line of this byte offset.
+    pub fn get(&self, offset: usize) -> (&str, usize) {
+        let i = match self.lines.binary_search_by_key(&offset, |(s, _, _)| *s) {
+            Ok(i) => i,
+            Err(i) => i.saturating_sub(1),
+        };
+        let (_, file, line) = &self.lines[i];
+        (file, *line)
+    }
+}
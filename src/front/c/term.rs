@@ -704,4 +704,17 @@ impl Embeddable for Ct {
             },
         }
     }
+
+    fn terms(&self, ctx: &CirCtx, t: &Self::T) -> Vec<Term> {
+        // arrays live in memory, and are located when stored to
+        match &t.term {
+            CTermData::CBool(t) | CTermData::CInt(_, _, t) | CTermData::CStackPtr(_, t, _) => {
+                vec![t.clone()]
+            }
+            CTermData::CArray(..) => Vec::new(),
+            CTermData::CStruct(_, fs) => {
+                fs.fields().flat_map(|(_, f)| self.terms(ctx, f)).collect()
+            }
+        }
+    }
 }
@@ -40,13 +40,15 @@ struct Gen<'ast> {
     stack_by_fn: FxHashMap<&'ast str, Vec<Option<Integer>>>,
     rec_limit: usize,
     circ: Circify<term::Datalog>,
+    file: String,
 }
 
 impl<'ast> Gen<'ast> {
-    fn new(rec_limit: usize) -> Self {
+    fn new(rec_limit: usize, file: String) -> Self {
         Self {
             rules: FxHashMap::default(),
             rec_limit,
+            file,
             stack_by_fn: FxHashMap::default(),
             // TODO: values !?
             circ: Circify::new(term::Datalog::new()),
@@ -134,11 +136,20 @@ impl<'ast> Gen<'ast> {
         Ok(())
     }
 
+    /// Attribute subsequent terms to the start of `span`.
+    fn set_src_loc(&mut self, span: &ast::Span) {
+        let (line, _) = span.start_pos().line_col();
+        self.circ.set_src_loc(&self.file, line);
+    }
+
     fn rule_cases(&mut self, rule: &'ast ast::Rule_) -> Result<'ast, term::T> {
-        rule.conds.iter().try_fold(term::bool_lit(false), |x, y| {
+        let r = rule.conds.iter().try_fold(term::bool_lit(false), |x, y| {
             let cond = self.condition(y)?;
             term::or(&x, &cond).map_err(|e| Error::from(e).with_span(rule.span.clone()))
-        })
+        })?;
+        self.set_src_loc(&rule.span);
+        self.circ.locate(&r.ir);
+        Ok(r)
     }
 
     fn condition(&mut self, c: &'ast ast::Condition) -> Result<'ast, term::T> {
@@ -157,7 +168,9 @@ impl<'ast> Gen<'ast> {
             }
         }
         c.exprs.iter().try_fold(term::bool_lit(true), |x, y| {
+            self.set_src_loc(y.span());
             let cond = self.expr(y, true)?;
+            self.circ.locate(&cond.ir);
             term::and(&x, &cond).map_err(|e| Error::from(e).with_span(y.span().clone()))
         })
     }
@@ -408,7 +421,7 @@ impl FrontEnd for Datalog {
                 panic!("parse error!")
             }
         };
-        let mut g = Gen::new(i.rec_limit);
+        let mut g = Gen::new(i.rec_limit, i.file.display().to_string());
         g.register_rules(&ast);
         let r = if i.lint_prim_rec {
             g.lint_rules()
@@ -386,6 +386,10 @@ impl Embeddable for Datalog {
     fn initialize_return(&self, ty: &Self::Ty, _ssa_name: &String) -> Self::T {
         ty.default()
     }
+
+    fn terms(&self, _ctx: &CirCtx, t: &Self::T) -> Vec<Term> {
+        vec![t.ir.clone()]
+    }
 }
 
 impl Default for Datalog {
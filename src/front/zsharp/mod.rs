@@ -1132,6 +1132,7 @@ impl<'ast> ZGen<'ast> {
             debug!("Const stmt: {}", s.span().as_str());
         } else {
             debug!("Stmt: {}", s.span().as_str());
+            self.circ_set_src_loc(s.span());
         }
 
         match s {
@@ -1850,6 +1851,7 @@ impl<'ast> ZGen<'ast> {
 
     fn assert(&self, asrt: Term) {
         debug_assert!(matches!(check(&asrt), Sort::Bool));
+        self.circ_locate(&asrt);
         if self.isolate_asserts {
             let path = self.circ_condition();
             self.assertions
@@ -1927,6 +1929,16 @@ impl<'ast> ZGen<'ast> {
     fn circ_assign(&self, loc: Loc, val: Val<T>) -> Result<Val<T>, CircError> {
         self.circ.borrow_mut().assign(loc, val)
     }
+
+    fn circ_set_src_loc(&self, span: &ast::Span) {
+        let file = self.file_stack.borrow().last().unwrap().display().to_string();
+        let (line, _) = span.start_pos().line_col();
+        self.circ.borrow_mut().set_src_loc(&file, line);
+    }
+
+    fn circ_locate(&self, t: &Term) {
+        self.circ.borrow().locate(t)
+    }
 }
 
 fn span_to_string(span: &ast::Span) -> String {
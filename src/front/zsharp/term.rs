@@ -992,4 +992,8 @@ impl Embeddable for ZSharp {
     fn initialize_return(&self, ty: &Self::Ty, _ssa_name: &String) -> Self::T {
         ty.default()
     }
+
+    fn terms(&self, _ctx: &CirCtx, t: &Self::T) -> Vec<Term> {
+        vec![t.term.clone()]
+    }
 }
@@ -0,0 +1,291 @@
+//! Known-bits analysis for bit-vectors
+//!
+//! Assigns each bit-vector term the bits that are the same in every value it may take. This
+//! complements [range analysis](super::range): bitwise operators fix bits that an interval can't
+//! describe, e.g., the top bit of `(bvxor (bvor x #x80) #x80)`.
+
+use super::{analyze, Domain};
+use crate::ir::term::*;
+
+use rug::Integer;
+use std::cmp::{max, min};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The known bits of a bit-vector of some width
+pub struct KnownBits {
+    /// The bit-vector width
+    pub width: usize,
+    /// A mask of the bits known to be zero
+    pub zeros: Integer,
+    /// A mask of the bits known to be one
+    pub ones: Integer,
+}
+
+impl KnownBits {
+    /// No known bits.
+    pub fn unknown(width: usize) -> Self {
+        KnownBits {
+            width,
+            zeros: Integer::from(0),
+            ones: Integer::from(0),
+        }
+    }
+    /// Exactly `bv`.
+    pub fn exact(bv: &BitVector) -> Self {
+        let mask = mask(bv.width());
+        KnownBits {
+            width: bv.width(),
+            zeros: mask ^ bv.uint(),
+            ones: bv.uint().clone(),
+        }
+    }
+    /// The number of low bits that may be set. At least one.
+    pub fn bits(&self) -> usize {
+        let maybe_set = mask(self.width) & !Integer::from(&self.zeros);
+        max(maybe_set.significant_bits() as usize, 1)
+    }
+    /// The bits known in both `self` and `other`.
+    pub fn join(&self, other: &Self) -> Self {
+        assert_eq!(self.width, other.width);
+        KnownBits {
+            width: self.width,
+            zeros: Integer::from(&self.zeros & &other.zeros),
+            ones: Integer::from(&self.ones & &other.ones),
+        }
+    }
+    /// The number of low bits known to be zero.
+    fn trailing_zeros(&self) -> usize {
+        min(self.zeros.find_zero(0).unwrap() as usize, self.width)
+    }
+    /// Whether the sign bit is known, and if so, its value.
+    fn sign(&self) -> Option<bool> {
+        let top = self.width as u32 - 1;
+        if self.ones.get_bit(top) {
+            Some(true)
+        } else if self.zeros.get_bit(top) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// The low `width` bits.
+fn mask(width: usize) -> Integer {
+    (Integer::from(1) << width as u32) - 1
+}
+
+/// The shift amount `b`, if it is known exactly.
+fn known_shift(b: &KnownBits) -> Option<usize> {
+    if Integer::from(&b.zeros | &b.ones) == mask(b.width) {
+        Some(b.ones.to_usize().unwrap_or(usize::MAX))
+    } else {
+        None
+    }
+}
+
+fn bv_transfer(t: &Term, width: usize, cs: &[&KnownBits]) -> KnownBits {
+    let m = mask(width);
+    let unknown = || KnownBits::unknown(width);
+    let low_zeros = |n: usize| KnownBits {
+        width,
+        zeros: mask(min(n, width)),
+        ones: Integer::from(0),
+    };
+    match &t.op {
+        Op::Const(Value::BitVector(bv)) => KnownBits::exact(bv),
+        Op::BvNaryOp(o) => match o {
+            BvNaryOp::And => KnownBits {
+                width,
+                zeros: cs.iter().fold(Integer::from(0), |acc, c| acc | &c.zeros),
+                ones: cs.iter().fold(m, |acc, c| acc & &c.ones),
+            },
+            BvNaryOp::Or => KnownBits {
+                width,
+                zeros: cs.iter().fold(m, |acc, c| acc & &c.zeros),
+                ones: cs.iter().fold(Integer::from(0), |acc, c| acc | &c.ones),
+            },
+            BvNaryOp::Xor => {
+                let known = cs
+                    .iter()
+                    .fold(m, |acc, c| acc & Integer::from(&c.zeros | &c.ones));
+                let ones = cs.iter().fold(Integer::from(0), |acc, c| acc ^ &c.ones);
+                KnownBits {
+                    width,
+                    zeros: Integer::from(!&ones) & &known,
+                    ones: known & ones,
+                }
+            }
+            // no carry comes out of the low bits that are zero in every summand
+            BvNaryOp::Add => low_zeros(cs.iter().map(|c| c.trailing_zeros()).min().unwrap()),
+            BvNaryOp::Mul => low_zeros(cs.iter().map(|c| c.trailing_zeros()).sum()),
+        },
+        Op::BvBinOp(o) => {
+            let (a, b) = (cs[0], cs[1]);
+            match (o, known_shift(b)) {
+                (BvBinOp::Sub, _) => low_zeros(min(a.trailing_zeros(), b.trailing_zeros())),
+                (BvBinOp::Shl, Some(s)) if s < width => KnownBits {
+                    width,
+                    zeros: (Integer::from(&a.zeros << s as u32) | mask(s)) & &m,
+                    ones: Integer::from(&a.ones << s as u32) & &m,
+                },
+                (BvBinOp::Lshr, Some(s)) if s < width => KnownBits {
+                    width,
+                    zeros: Integer::from(&a.zeros >> s as u32) | (mask(s) << (width - s) as u32),
+                    ones: Integer::from(&a.ones >> s as u32),
+                },
+                (BvBinOp::Shl, Some(_)) | (BvBinOp::Lshr, Some(_)) => low_zeros(width),
+                _ => unknown(),
+            }
+        }
+        Op::BvUnOp(BvUnOp::Not) => KnownBits {
+            width,
+            zeros: cs[0].ones.clone(),
+            ones: cs[0].zeros.clone(),
+        },
+        Op::BvUext(n) => KnownBits {
+            width,
+            zeros: cs[0].zeros.clone() | (mask(*n) << cs[0].width as u32),
+            ones: cs[0].ones.clone(),
+        },
+        Op::BvSext(n) => {
+            let high = mask(*n) << cs[0].width as u32;
+            match cs[0].sign() {
+                Some(true) => KnownBits {
+                    width,
+                    zeros: cs[0].zeros.clone(),
+                    ones: cs[0].ones.clone() | high,
+                },
+                Some(false) => KnownBits {
+                    width,
+                    zeros: cs[0].zeros.clone() | high,
+                    ones: cs[0].ones.clone(),
+                },
+                None => KnownBits {
+                    width,
+                    zeros: cs[0].zeros.clone(),
+                    ones: cs[0].ones.clone(),
+                },
+            }
+        }
+        Op::BvExtract(_, l) => KnownBits {
+            width,
+            zeros: Integer::from(&cs[0].zeros >> *l as u32) & &m,
+            ones: Integer::from(&cs[0].ones >> *l as u32) & &m,
+        },
+        Op::BvConcat => {
+            let mut zeros = Integer::from(0);
+            let mut ones = Integer::from(0);
+            for c in cs {
+                zeros = (zeros << c.width as u32) | &c.zeros;
+                ones = (ones << c.width as u32) | &c.ones;
+            }
+            KnownBits { width, zeros, ones }
+        }
+        _ => unknown(),
+    }
+}
+
+impl Domain for Option<KnownBits> {
+    fn transfer(t: &Term, children: &[&Self]) -> Self {
+        let width = match check(t) {
+            Sort::BitVector(w) => w,
+            _ => return None,
+        };
+        Some(match &t.op {
+            Op::Ite => match (children[1], children[2]) {
+                (Some(a), Some(b)) => a.join(b),
+                _ => unreachable!("bit-vector ite with non-bit-vector branches"),
+            },
+            _ => match children
+                .iter()
+                .map(|c| c.as_ref())
+                .collect::<Option<Vec<_>>>()
+            {
+                Some(cs) => bv_transfer(t, width, &cs),
+                // e.g., bool2bv, pf2bv, select
+                None => KnownBits::unknown(width),
+            },
+        })
+    }
+}
+
+/// Compute the known bits of every bit-vector term in `cs`.
+pub fn known_bits(cs: &Computation) -> TermMap<Option<KnownBits>> {
+    analyze(cs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bits_of(t: Term) -> KnownBits {
+        let mut cs = Computation::new();
+        cs.outputs.push(t.clone());
+        known_bits(&cs).get(&t).unwrap().clone().unwrap()
+    }
+
+    fn k(width: usize, zeros: u32, ones: u32) -> KnownBits {
+        KnownBits {
+            width,
+            zeros: Integer::from(zeros),
+            ones: Integer::from(ones),
+        }
+    }
+
+    #[test]
+    fn masks() {
+        let x = leaf_term(Op::Var("x".into(), Sort::BitVector(8)));
+        let c = leaf_term(Op::Var("c".into(), Sort::Bool));
+        let low = term![BV_AND; x.clone(), bv_lit(0x0f, 8)];
+        assert_eq!(bits_of(low.clone()), k(8, 0xf0, 0));
+        assert_eq!(bits_of(low.clone()).bits(), 4);
+        let set = term![BV_OR; low.clone(), bv_lit(0x80, 8)];
+        assert_eq!(bits_of(set.clone()), k(8, 0x70, 0x80));
+        assert_eq!(bits_of(term![BV_NOT; set.clone()]), k(8, 0x80, 0x70));
+        assert_eq!(
+            bits_of(term![BV_XOR; set, bv_lit(0xc0, 8)]),
+            k(8, 0xb0, 0x40)
+        );
+        let ite = term![ITE; c, low, bv_lit(0x10, 8)];
+        assert_eq!(bits_of(ite), k(8, 0xe0, 0));
+        assert_eq!(bits_of(x), k(8, 0, 0));
+    }
+
+    #[test]
+    fn shifts_and_widths() {
+        let x = leaf_term(Op::Var("x".into(), Sort::BitVector(8)));
+        assert_eq!(
+            bits_of(term![BV_SHL; x.clone(), bv_lit(3, 8)]),
+            k(8, 0x07, 0)
+        );
+        assert_eq!(
+            bits_of(term![BV_LSHR; x.clone(), bv_lit(3, 8)]),
+            k(8, 0xe0, 0)
+        );
+        assert_eq!(
+            bits_of(term![BV_SHL; x.clone(), bv_lit(9, 8)]),
+            k(8, 0xff, 0)
+        );
+        let even = term![BV_SHL; x.clone(), bv_lit(1, 8)];
+        assert_eq!(
+            bits_of(term![BV_ADD; even.clone(), bv_lit(4, 8)]),
+            k(8, 0x01, 0)
+        );
+        assert_eq!(
+            bits_of(term![BV_MUL; even.clone(), even.clone()]),
+            k(8, 0x03, 0)
+        );
+        let neg = term![BV_OR; x.clone(), bv_lit(0x80, 8)];
+        assert_eq!(bits_of(term![Op::BvSext(4); neg]), k(12, 0, 0xf80));
+        assert_eq!(bits_of(term![Op::BvUext(4); x.clone()]), k(12, 0xf00, 0));
+        assert_eq!(
+            bits_of(term![Op::BvExtract(7, 4); term![BV_AND; x.clone(), bv_lit(0x3f, 8)]]),
+            k(4, 0xc, 0)
+        );
+        assert_eq!(
+            bits_of(term![BV_CONCAT; bv_lit(1, 2), x]),
+            k(10, 0x200, 0x100)
+        );
+    }
+}
@@ -0,0 +1,28 @@
+//! Analyses of IR computations
+//!
+//! An analysis is an abstract interpretation: it assigns each term a value in some abstract
+//! [Domain], computed from the abstract values of its children.
+
+use crate::ir::term::*;
+
+pub mod bits;
+pub mod range;
+
+/// An abstract domain: a set of facts about terms, computable bottom-up.
+pub trait Domain: Sized + Clone {
+    /// The abstract value of `t`, given the abstract values of its children.
+    fn transfer(t: &Term, children: &[&Self]) -> Self;
+}
+
+/// Compute the abstract value of every term in `cs`.
+pub fn analyze<D: Domain>(cs: &Computation) -> TermMap<D> {
+    let mut values = TermMap::<D>::new();
+    for t in cs.terms_postorder() {
+        let v = {
+            let children: Vec<&D> = t.cs.iter().map(|c| values.get(c).unwrap()).collect();
+            D::transfer(&t, &children)
+        };
+        values.insert(t, v);
+    }
+    values
+}
@@ -0,0 +1,239 @@
+//! Unsigned range analysis for bit-vectors
+//!
+//! Assigns each bit-vector term an interval `[lo, hi]` that contains every value it may take.
+//! Equivalently, all bits above the [BvRange::bits] low bits of a term are known to be zero.
+
+use super::{analyze, Domain};
+use crate::ir::term::*;
+
+use rug::Integer;
+use std::cmp::{max, min};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// An interval of unsigned values for a bit-vector of some width
+pub struct BvRange {
+    /// The bit-vector width
+    pub width: usize,
+    /// Least possible value
+    pub lo: Integer,
+    /// Greatest possible value
+    pub hi: Integer,
+}
+
+impl BvRange {
+    /// The range `[lo, hi]`, or all values if `hi` does not fit in `width` bits.
+    pub fn new(width: usize, lo: Integer, hi: Integer) -> Self {
+        if hi.significant_bits() as usize > width || lo > hi {
+            Self::full(width)
+        } else {
+            BvRange { width, lo, hi }
+        }
+    }
+    /// All values of this width.
+    pub fn full(width: usize) -> Self {
+        BvRange {
+            width,
+            lo: Integer::from(0),
+            hi: (Integer::from(1) << width as u32) - 1,
+        }
+    }
+    /// Exactly `bv`.
+    pub fn exact(bv: &BitVector) -> Self {
+        BvRange {
+            width: bv.width(),
+            lo: bv.uint().clone(),
+            hi: bv.uint().clone(),
+        }
+    }
+    /// The number of low bits that may be set. At least one.
+    pub fn bits(&self) -> usize {
+        max(self.hi.significant_bits() as usize, 1)
+    }
+    /// Whether the sign bit is always zero.
+    pub fn non_negative(&self) -> bool {
+        self.bits() < self.width
+    }
+    /// The smallest range containing `self` and `other`.
+    pub fn union(&self, other: &Self) -> Self {
+        assert_eq!(self.width, other.width);
+        BvRange {
+            width: self.width,
+            lo: min(&self.lo, &other.lo).clone(),
+            hi: max(&self.hi, &other.hi).clone(),
+        }
+    }
+    /// The largest value of this width.
+    fn max_value(&self) -> Integer {
+        (Integer::from(1) << self.width as u32) - 1
+    }
+}
+
+fn bv_transfer(t: &Term, width: usize, cs: &[&BvRange]) -> BvRange {
+    let new = |lo, hi| BvRange::new(width, lo, hi);
+    let full = || BvRange::full(width);
+    match &t.op {
+        Op::Const(Value::BitVector(bv)) => BvRange::exact(bv),
+        Op::BvNaryOp(o) => match o {
+            BvNaryOp::Add => new(
+                cs.iter().map(|c| &c.lo).sum(),
+                cs.iter().map(|c| &c.hi).sum(),
+            ),
+            BvNaryOp::Mul => new(
+                cs.iter().map(|c| &c.lo).product(),
+                cs.iter().map(|c| &c.hi).product(),
+            ),
+            BvNaryOp::And => new(
+                Integer::from(0),
+                cs.iter().map(|c| &c.hi).min().unwrap().clone(),
+            ),
+            BvNaryOp::Or => {
+                let bits = cs.iter().map(|c| c.bits()).max().unwrap();
+                new(
+                    cs.iter().map(|c| &c.lo).max().unwrap().clone(),
+                    (Integer::from(1) << bits as u32) - 1,
+                )
+            }
+            BvNaryOp::Xor => {
+                let bits = cs.iter().map(|c| c.bits()).max().unwrap();
+                new(Integer::from(0), (Integer::from(1) << bits as u32) - 1)
+            }
+        },
+        Op::BvBinOp(o) => {
+            let (a, b) = (cs[0], cs[1]);
+            match o {
+                BvBinOp::Sub if a.lo >= b.hi => {
+                    new(Integer::from(&a.lo - &b.hi), Integer::from(&a.hi - &b.lo))
+                }
+                // division by zero gives all ones
+                BvBinOp::Udiv if b.lo > 0 => {
+                    new(Integer::from(&a.lo / &b.hi), Integer::from(&a.hi / &b.lo))
+                }
+                // remainder by zero gives the dividend
+                BvBinOp::Urem if b.lo > 0 => new(
+                    Integer::from(0),
+                    min(a.hi.clone(), Integer::from(&b.hi - 1)),
+                ),
+                BvBinOp::Urem => new(Integer::from(0), a.hi.clone()),
+                BvBinOp::Lshr => {
+                    let shift = |s: &Integer| s.to_u32().filter(|s| (*s as usize) < width);
+                    let lo = match shift(&b.hi) {
+                        Some(s) => Integer::from(&a.lo >> s),
+                        None => Integer::from(0),
+                    };
+                    let hi = match shift(&b.lo) {
+                        Some(s) => Integer::from(&a.hi >> s),
+                        None => Integer::from(0),
+                    };
+                    new(lo, hi)
+                }
+                BvBinOp::Shl => match (b.lo.to_u32(), b.hi.to_u32()) {
+                    (Some(l), Some(h)) if (h as usize) < width => {
+                        new(Integer::from(&a.lo << l), Integer::from(&a.hi << h))
+                    }
+                    _ => full(),
+                },
+                _ => full(),
+            }
+        }
+        Op::BvUnOp(BvUnOp::Not) => {
+            let m = cs[0].max_value();
+            new(Integer::from(&m - &cs[0].hi), m - &cs[0].lo)
+        }
+        Op::BvUext(_) => new(cs[0].lo.clone(), cs[0].hi.clone()),
+        Op::BvSext(_) if cs[0].non_negative() => new(cs[0].lo.clone(), cs[0].hi.clone()),
+        Op::BvExtract(_, l) if cs[0].bits() <= width + *l => new(
+            Integer::from(&cs[0].lo >> *l as u32),
+            Integer::from(&cs[0].hi >> *l as u32),
+        ),
+        Op::BvConcat => {
+            let mut lo = Integer::from(0);
+            let mut hi = Integer::from(0);
+            for c in cs {
+                lo = (lo << c.width as u32) + &c.lo;
+                hi = (hi << c.width as u32) + &c.hi;
+            }
+            new(lo, hi)
+        }
+        _ => full(),
+    }
+}
+
+impl Domain for Option<BvRange> {
+    fn transfer(t: &Term, children: &[&Self]) -> Self {
+        let width = match check(t) {
+            Sort::BitVector(w) => w,
+            _ => return None,
+        };
+        Some(match &t.op {
+            Op::Ite => match (children[1], children[2]) {
+                (Some(a), Some(b)) => a.union(b),
+                _ => unreachable!("bit-vector ite with non-bit-vector branches"),
+            },
+            Op::BoolToBv => BvRange::new(width, Integer::from(0), Integer::from(1)),
+            _ => match children
+                .iter()
+                .map(|c| c.as_ref())
+                .collect::<Option<Vec<_>>>()
+            {
+                Some(cs) => bv_transfer(t, width, &cs),
+                // e.g., pf2bv, select
+                None => BvRange::full(width),
+            },
+        })
+    }
+}
+
+/// Compute the range of every bit-vector term in `cs`.
+pub fn bv_ranges(cs: &Computation) -> TermMap<Option<BvRange>> {
+    analyze(cs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn range_of(t: Term) -> BvRange {
+        let mut cs = Computation::new();
+        cs.outputs.push(t.clone());
+        bv_ranges(&cs).get(&t).unwrap().clone().unwrap()
+    }
+
+    fn r(width: usize, lo: u32, hi: u32) -> BvRange {
+        BvRange::new(width, Integer::from(lo), Integer::from(hi))
+    }
+
+    #[test]
+    fn counter() {
+        let i = leaf_term(Op::Var("i".into(), Sort::BitVector(32)));
+        let small = term![BV_UREM; i.clone(), bv_lit(16, 32)];
+        assert_eq!(range_of(small.clone()), r(32, 0, 15));
+        assert_eq!(range_of(small.clone()).bits(), 4);
+        let sum = term![BV_ADD; small.clone(), small.clone(), bv_lit(1, 32)];
+        assert_eq!(range_of(sum), r(32, 1, 31));
+        let prod = term![BV_MUL; small.clone(), i.clone()];
+        assert_eq!(range_of(prod), BvRange::full(32));
+        assert_eq!(range_of(i), BvRange::full(32));
+    }
+
+    #[test]
+    fn bit_ops() {
+        let x = leaf_term(Op::Var("x".into(), Sort::BitVector(8)));
+        let b = leaf_term(Op::Var("b".into(), Sort::Bool));
+        let low = term![BV_AND; x.clone(), bv_lit(7, 8)];
+        assert_eq!(range_of(low.clone()), r(8, 0, 7));
+        assert_eq!(
+            range_of(term![BV_LSHR; x.clone(), bv_lit(4, 8)]),
+            r(8, 0, 15)
+        );
+        assert_eq!(range_of(term![BV_NOT; low.clone()]), r(8, 248, 255));
+        assert_eq!(
+            range_of(term![ITE; b.clone(), low.clone(), bv_lit(12, 8)]),
+            r(8, 0, 12)
+        );
+        assert_eq!(
+            range_of(term![Op::BvUext(8); term![Op::BoolToBv; b]]),
+            r(9, 0, 1)
+        );
+        assert_eq!(range_of(term![Op::BvExtract(7, 2); low]), r(6, 0, 1));
+    }
+}
@@ -2,5 +2,6 @@
 
 #[macro_use]
 pub mod term;
+pub mod analysis;
 pub mod opt;
 pub mod proof;
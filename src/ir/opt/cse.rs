@@ -0,0 +1,354 @@
+//! Common sub-expression grouping for commutative, associative n-ary operators
+//!
+//! Hash-consing only shares syntactically identical terms, so `(* a b c)` and `(* c a b d)` share
+//! nothing. This pass:
+//!
+//! 1. sorts the arguments of commutative, associative operators, so that terms that differ only
+//!    in argument order become identical, and
+//! 2. greedily factors pairs of arguments that occur together in many field sums or products,
+//!    until no pair is shared. In the example above, `(* a b c)` becomes a child of
+//!    `(* d (* a b c))`.
+//!
+//! Factoring is limited to field operators. In R1CS, field additions are free, and each shared
+//! field product saves a constraint per owner. Bit-vector and boolean operators are not factored:
+//! nesting them adds intermediate results, each of which costs a bit decomposition.
+//!
+//! It should run after [super::flat] (which would undo the grouping within a single output).
+
+use super::visit::RewritePass;
+use crate::ir::term::*;
+
+use fxhash::{FxHashMap, FxHashSet};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Terms with more arguments than this are canonicalized, but not considered for factoring
+/// (the number of argument pairs is quadratic).
+const MAX_FACTOR_ARITY: usize = 64;
+
+fn is_ac(op: &Op) -> bool {
+    matches!(op, Op::BvNaryOp(_) | Op::PfNaryOp(_) | Op::BoolNaryOp(_))
+}
+
+/// Whether we factor common arguments of `op`.
+fn is_factored(op: &Op) -> bool {
+    matches!(op, Op::PfNaryOp(_))
+}
+
+/// Sort the arguments of commutative, associative operators.
+struct Canonicalize;
+
+impl RewritePass for Canonicalize {
+    fn visit<F: Fn() -> Vec<Term>>(
+        &mut self,
+        _computation: &mut Computation,
+        orig: &Term,
+        rewritten_children: F,
+    ) -> Option<Term> {
+        if is_ac(&orig.op) {
+            let mut cs = rewritten_children();
+            cs.sort_by_key(|c| c.uid());
+            Some(term(orig.op.clone(), cs))
+        } else {
+            None
+        }
+    }
+}
+
+/// An (unordered) pair of arguments to an operator.
+type Pair = (Op, Term, Term);
+
+/// The argument pairs in a (uid-sorted) argument multiset.
+fn pairs(op: &Op, args: &[Term]) -> FxHashSet<Pair> {
+    let mut ps = FxHashSet::default();
+    for i in 0..args.len() {
+        for j in i + 1..args.len() {
+            ps.insert((op.clone(), args[i].clone(), args[j].clone()));
+        }
+    }
+    ps
+}
+
+/// The argument pairs in a (uid-sorted) argument multiset that include one of `ts`.
+fn pairs_with(op: &Op, args: &[Term], ts: &[&Term]) -> FxHashSet<Pair> {
+    let mut ps = FxHashSet::default();
+    for (i, x) in args.iter().enumerate() {
+        if ts.contains(&x) {
+            for (j, y) in args.iter().enumerate() {
+                if i < j {
+                    ps.insert((op.clone(), x.clone(), y.clone()));
+                } else if j < i {
+                    ps.insert((op.clone(), y.clone(), x.clone()));
+                }
+            }
+        }
+    }
+    ps
+}
+
+/// Pairs with at least two owners, most owners first, then oldest arguments (for determinism).
+///
+/// Entries are not updated when owner counts change; instead, the pair is pushed again. So an
+/// entry is stale unless its pair still has the entry's number of owners.
+#[derive(Default)]
+struct Queue {
+    heap: BinaryHeap<(usize, Reverse<(u64, u64)>, Reverse<usize>)>,
+    pairs: Vec<Pair>,
+}
+
+impl Queue {
+    fn push(&mut self, pair: &Pair, owners: usize) {
+        let (_, a, b) = pair;
+        let order = Reverse((a.uid(), b.uid()));
+        self.heap.push((owners, order, Reverse(self.pairs.len())));
+        self.pairs.push(pair.clone());
+    }
+
+    fn pop(&mut self, occurs: &FxHashMap<Pair, TermSet>) -> Option<Pair> {
+        while let Some((owners, _, Reverse(i))) = self.heap.pop() {
+            let pair = &self.pairs[i];
+            if occurs.get(pair).map(|os| os.len()) == Some(owners) {
+                return Some(pair.clone());
+            }
+        }
+        None
+    }
+}
+
+/// Remove one instance of `a` and one of `b` from `args`, add `p`, and re-sort.
+fn replace_pair(args: &mut Vec<Term>, a: &Term, b: &Term, p: Term) {
+    let i = args.iter().position(|x| x == a).unwrap();
+    args.remove(i);
+    let j = args.iter().position(|x| x == b).unwrap();
+    args.remove(j);
+    args.push(p);
+    args.sort_by_key(|c| c.uid());
+}
+
+/// Choose groupings: a new argument list for each n-ary term whose arguments change, and the
+/// set of new pair terms.
+fn factor(cs: &Computation) -> (TermMap<Vec<Term>>, TermSet) {
+    let mut groups = TermMap::new();
+    let mut occurs: FxHashMap<Pair, TermSet> = FxHashMap::default();
+    for t in cs.terms_postorder() {
+        if is_factored(&t.op) && t.cs.len() >= 2 && t.cs.len() <= MAX_FACTOR_ARITY {
+            for p in pairs(&t.op, &t.cs) {
+                occurs.entry(p).or_default().insert(t.clone());
+            }
+            groups.insert(t.clone(), t.cs.clone());
+        }
+    }
+    // A pair with one owner can only gain another if it includes a new pair term, and all of
+    // that term's owners are known when it is made. So, we drop such pairs.
+    occurs.retain(|_, owners| owners.len() >= 2);
+    let mut queue = Queue::default();
+    for (k, owners) in &occurs {
+        queue.push(k, owners.len());
+    }
+    let mut new_pairs = TermSet::new();
+    while let Some(k) = queue.pop(&occurs) {
+        let (op, a, b) = k.clone();
+        let owners = occurs.get(&k).unwrap().clone();
+        let p = term(op.clone(), vec![a.clone(), b.clone()]);
+        new_pairs.insert(p.clone());
+        let mut changed = FxHashSet::default();
+        for owner in owners {
+            // only pairs with `a`, `b`, or `p` change
+            let args = groups.get_mut(&owner).unwrap();
+            let old = pairs_with(&op, args, &[&a, &b]);
+            replace_pair(args, &a, &b, p.clone());
+            let new = pairs_with(&op, args, &[&a, &b, &p]);
+            for k in old.difference(&new) {
+                if let Some(os) = occurs.get_mut(k) {
+                    os.remove(&owner);
+                    changed.insert(k.clone());
+                }
+            }
+            for k in new.difference(&old) {
+                occurs.entry(k.clone()).or_default().insert(owner.clone());
+                changed.insert(k.clone());
+            }
+        }
+        for k in changed {
+            let owners = occurs.get(&k).unwrap().len();
+            if owners >= 2 {
+                queue.push(&k, owners);
+            } else {
+                occurs.remove(&k);
+            }
+        }
+    }
+    groups.retain(|t, args| t.cs[..] != args[..]);
+    (groups, new_pairs)
+}
+
+/// Rebuild the computation with the chosen groupings.
+struct Regroup {
+    groups: TermMap<Vec<Term>>,
+    new_pairs: TermSet,
+    /// Original (or pair) terms to their rewrites
+    rewritten: TermMap<Term>,
+}
+
+impl Regroup {
+    fn get(&mut self, t: &Term) -> Term {
+        if let Some(r) = self.rewritten.get(t) {
+            return r.clone();
+        }
+        assert!(self.new_pairs.contains(t), "{} was not rewritten", t);
+        let cs = t.cs.iter().map(|c| self.get(c)).collect();
+        let r = term(t.op.clone(), cs);
+        self.rewritten.insert(t.clone(), r.clone());
+        r
+    }
+}
+
+impl RewritePass for Regroup {
+    fn visit<F: Fn() -> Vec<Term>>(
+        &mut self,
+        _computation: &mut Computation,
+        orig: &Term,
+        rewritten_children: F,
+    ) -> Option<Term> {
+        for (c, r) in orig.cs.iter().zip(rewritten_children()) {
+            self.rewritten.insert(c.clone(), r);
+        }
+        let new = self.groups.get(orig).cloned().map(|args| {
+            if args.len() == 1 {
+                self.get(&args[0])
+            } else {
+                term(orig.op.clone(), args.iter().map(|a| self.get(a)).collect())
+            }
+        });
+        // record the rewrite, in case `orig` is an argument of a new pair.
+        self.rewritten.insert(
+            orig.clone(),
+            new.clone()
+                .unwrap_or_else(|| term(orig.op.clone(), rewritten_children())),
+        );
+        new
+    }
+}
+
+/// Canonicalize commutative, associative n-ary terms in `cs`, and group the common arguments of
+/// field sums and products.
+pub fn group_common_subexprs(cs: &mut Computation) {
+    Canonicalize.traverse(cs);
+    let (groups, new_pairs) = factor(cs);
+    if !groups.is_empty() {
+        let mut pass = Regroup {
+            groups,
+            new_pairs,
+            rewritten: TermMap::new(),
+        };
+        pass.traverse(cs);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::target::r1cs::trans::to_r1cs;
+    use crate::util::field::DFL_T;
+
+    fn bv(n: &str) -> Term {
+        leaf_term(Op::Var(n.into(), Sort::BitVector(8)))
+    }
+
+    fn pf(n: &str) -> Term {
+        leaf_term(Op::Var(n.into(), Sort::Field(DFL_T.clone())))
+    }
+
+    fn pf_env(vs: &[(&str, u64)]) -> FxHashMap<String, Value> {
+        vs.iter()
+            .map(|(n, v)| (n.to_string(), Value::Field(DFL_T.new_v(*v))))
+            .collect()
+    }
+
+    #[test]
+    fn canonical_order() {
+        let (a, b, c) = (bv("a"), bv("b"), bv("c"));
+        let mut cs = Computation::new();
+        cs.outputs = vec![
+            term![BV_ADD; a.clone(), b.clone(), c.clone()],
+            term![BV_ADD; c, a, b],
+        ];
+        group_common_subexprs(&mut cs);
+        assert_eq!(cs.outputs[0], cs.outputs[1]);
+    }
+
+    #[test]
+    fn shared_sub_multiset() {
+        let (a, b, c, d) = (pf("a"), pf("b"), pf("c"), pf("d"));
+        let mut cs = Computation::new();
+        let orig = vec![
+            term![PF_MUL; a.clone(), b.clone(), c.clone()],
+            term![PF_MUL; c.clone(), a.clone(), b.clone(), d.clone()],
+            term![PF_ADD; a.clone(), b.clone(), c.clone(), d.clone()],
+        ];
+        cs.outputs = orig.clone();
+        group_common_subexprs(&mut cs);
+        // the first product is re-used in the second
+        assert_eq!(cs.outputs[1].cs.len(), 2);
+        assert!(cs.outputs[1].cs.contains(&cs.outputs[0]));
+        // the sum has no company
+        assert_eq!(cs.outputs[2].cs.len(), 4);
+        let env = pf_env(&[("a", 3), ("b", 5), ("c", 7), ("d", 11)]);
+        for (o, n) in orig.iter().zip(&cs.outputs) {
+            assert_eq!(eval(o, &env), eval(n, &env));
+        }
+    }
+
+    #[test]
+    fn repeated_argument() {
+        let (a, b) = (pf("a"), pf("b"));
+        let mut cs = Computation::new();
+        cs.outputs = vec![
+            term![PF_MUL; a.clone(), a.clone(), b.clone()],
+            term![PF_MUL; b.clone(), a.clone(), a.clone(), b.clone()],
+        ];
+        group_common_subexprs(&mut cs);
+        assert!(cs.outputs[1].cs.contains(&cs.outputs[0]));
+    }
+
+    #[test]
+    fn bit_vectors_not_factored() {
+        let (a, b, c, d) = (bv("a"), bv("b"), bv("c"), bv("d"));
+        let mut cs = Computation::new();
+        cs.outputs = vec![
+            term![BV_ADD; a.clone(), b.clone(), c.clone()],
+            term![BV_ADD; c.clone(), a.clone(), b.clone(), d.clone()],
+        ];
+        group_common_subexprs(&mut cs);
+        assert_eq!(cs.outputs[1].cs.len(), 4);
+    }
+
+    #[test]
+    fn fewer_constraints() {
+        let prover = Some(crate::ir::proof::PROVER_ID);
+        let mut cs = Computation::new();
+        let f = Sort::Field(DFL_T.clone());
+        let vs: Vec<Term> = ["a", "b", "c", "d", "x", "y"]
+            .iter()
+            .map(|n| cs.new_var(n, f.clone(), prover, 0, false, None))
+            .collect();
+        let (a, b, c, d) = (&vs[0], &vs[1], &vs[2], &vs[3]);
+        cs.assert(term![EQ; term![PF_MUL; a.clone(), b.clone(), c.clone()], vs[4].clone()]);
+        cs.assert(
+            term![EQ; term![PF_MUL; d.clone(), c.clone(), b.clone(), a.clone()], vs[5].clone()],
+        );
+        let (before, _, _) = to_r1cs(cs.clone(), DFL_T.clone());
+        group_common_subexprs(&mut cs);
+        let (after, pd, _) = to_r1cs(cs, DFL_T.clone());
+        assert!(after.constraints().len() < before.constraints().len());
+        let env = pf_env(&[
+            ("a", 3),
+            ("b", 5),
+            ("c", 7),
+            ("d", 11),
+            ("x", 105),
+            ("y", 1155),
+        ]);
+        after.check_all(&pd.precompute.eval(&env));
+    }
+}
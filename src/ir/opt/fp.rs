@@ -0,0 +1,1045 @@
+//! Floating-point elimination
+//!
+//! Replaces each floating-point term with an IEEE-754 soft-float circuit over its bits, for
+//! back-ends (like R1CS) that only understand booleans, bit-vectors, and fields.
+//!
+//! A float-sorted term `t` is rewritten to a bit-vector term equal to `(fp2bv t)`. Floating-point
+//! variables remain, but only as the argument of `fp2bv`.
+//!
+//! Arithmetic and conversions round to nearest, ties to even. [FpUnOp::Round] rounds ties away
+//! from zero. [FpBinOp::Rem] is the IEEE-754 remainder, whose quotient also rounds to nearest,
+//! ties to even. Arithmetic on NaNs produces the canonical quiet NaN, rather than propagating
+//! payloads.
+
+use crate::ir::term::*;
+
+use rug::Integer;
+
+/// Replace the floating-point terms of `cs` with soft-float circuits.
+///
+/// Float-sorted outputs are converted back from their bits.
+pub fn lower_fp(cs: &mut Computation) {
+    let mut cache = TermMap::new();
+    for o in &mut cs.outputs {
+        let new = lower_cached(o, &mut cache);
+        *o = if is_fp(o) {
+            term![Op::BvToFp; new]
+        } else {
+            new
+        };
+    }
+}
+
+fn lower_cached(t: &Term, cache: &mut TermMap<Term>) -> Term {
+    for c in PostOrderIter::new(t.clone()) {
+        if !cache.contains_key(&c) {
+            let cs: Vec<Term> = c.cs.iter().map(|c| cache.get(c).unwrap().clone()).collect();
+            let new = lower_term(&c, cs);
+            cache.insert(c, new);
+        }
+    }
+    cache.get(t).unwrap().clone()
+}
+
+/// Lower `t`, whose children have been lowered to `cs`.
+fn lower_term(t: &Term, cs: Vec<Term>) -> Term {
+    match &t.op {
+        Op::Var(_, Sort::F32) | Op::Var(_, Sort::F64) => term![Op::FpToBv; t.clone()],
+        Op::Const(Value::F32(f)) => bv_lit(f.to_bits(), 32),
+        Op::Const(Value::F64(f)) => bv_lit(f.to_bits(), 64),
+        Op::BvToFp | Op::FpToBv => cs[0].clone(),
+        Op::Eq if is_fp(&t.cs[0]) => Format::of(&t.cs[0]).eq(&cs[0], &cs[1]),
+        Op::FpBinPred(o) => {
+            let f = Format::of(&t.cs[0]);
+            let (a, b) = (&cs[0], &cs[1]);
+            match o {
+                FpBinPred::Le => f.le(a, b),
+                FpBinPred::Lt => f.lt(a, b),
+                FpBinPred::Eq => f.eq(a, b),
+                FpBinPred::Ge => f.le(b, a),
+                FpBinPred::Gt => f.lt(b, a),
+            }
+        }
+        Op::FpUnPred(o) => {
+            let f = Format::of(&t.cs[0]);
+            let a = &cs[0];
+            match o {
+                FpUnPred::Normal => {
+                    let e = f.exp(a);
+                    term![AND; term![NOT; is_zero(&e)], term![NOT; is_ones(&e)]]
+                }
+                FpUnPred::Subnormal => {
+                    term![AND; is_zero(&f.exp(a)), term![NOT; is_zero(&f.frac(a))]]
+                }
+                FpUnPred::Zero => f.is_zero(a),
+                FpUnPred::Infinite => f.is_inf(a),
+                FpUnPred::Nan => f.is_nan(a),
+                FpUnPred::Negative => term![AND; f.sign(a), term![NOT; f.is_nan(a)]],
+                FpUnPred::Positive => {
+                    term![AND; term![NOT; f.sign(a)], term![NOT; f.is_nan(a)]]
+                }
+            }
+        }
+        Op::FpUnOp(o) => {
+            let f = Format::of(t);
+            let a = &cs[0];
+            match o {
+                FpUnOp::Neg => f.neg(a),
+                FpUnOp::Abs => uext(f.mag(a), 1),
+                FpUnOp::Round => f.round(a),
+                FpUnOp::Sqrt => f.sqrt(a),
+            }
+        }
+        Op::FpBinOp(o) => {
+            let f = Format::of(t);
+            let (a, b) = (&cs[0], &cs[1]);
+            match o {
+                FpBinOp::Add => f.add(a, b),
+                FpBinOp::Sub => f.add(a, &f.neg(b)),
+                FpBinOp::Mul => f.mul(a, b),
+                FpBinOp::Div => f.div(a, b),
+                FpBinOp::Max => f.max_min(a, b, true),
+                FpBinOp::Min => f.max_min(a, b, false),
+                FpBinOp::Rem => f.rem(a, b),
+            }
+        }
+        Op::UbvToFp(_) => Format::of(t).convert_uint(&cs[0], bool_lit(false)),
+        Op::SbvToFp(_) => {
+            let x = &cs[0];
+            let sign = term![Op::BvBit(width(x) - 1); x.clone()];
+            let mag = ite(sign.clone(), term![BV_NEG; x.clone()], x.clone());
+            Format::of(t).convert_uint(&mag, sign)
+        }
+        Op::FpToFp(_) => Format::of(t).convert(Format::of(&t.cs[0]), &cs[0]),
+        Op::Ite => term(Op::Ite, cs),
+        _ => {
+            if is_fp(t) || t.cs.iter().any(is_fp) {
+                panic!("{} is not supported by floating-point elimination", t.op)
+            }
+            term(t.op.clone(), cs)
+        }
+    }
+}
+
+fn is_fp(t: &Term) -> bool {
+    matches!(check(t), Sort::F32 | Sort::F64)
+}
+
+fn width(t: &Term) -> usize {
+    check(t).as_bv()
+}
+
+fn extract(t: &Term, high: usize, low: usize) -> Term {
+    term![Op::BvExtract(high, low); t.clone()]
+}
+
+fn concat(high: Term, low: Term) -> Term {
+    term![Op::BvConcat; high, low]
+}
+
+fn uext(t: Term, n: usize) -> Term {
+    if n == 0 {
+        t
+    } else {
+        term![Op::BvUext(n); t]
+    }
+}
+
+fn zeros(w: usize) -> Term {
+    bv_lit(0, w)
+}
+
+/// A `w`-bit two's complement constant.
+fn int_lit(i: i64, w: usize) -> Term {
+    bv_lit(Integer::from(i).keep_bits(w as u32), w)
+}
+
+fn from_bool(b: Term) -> Term {
+    term![BOOL_TO_BV; b]
+}
+
+fn is_zero(t: &Term) -> Term {
+    term![EQ; t.clone(), zeros(width(t))]
+}
+
+fn is_ones(t: &Term) -> Term {
+    let w = width(t);
+    term![EQ; t.clone(), bv_lit((Integer::from(1) << w as u32) - 1, w)]
+}
+
+fn ite(c: Term, t: Term, f: Term) -> Term {
+    term![ITE; c, t, f]
+}
+
+/// The largest power of two that is at most `n`, which must be positive.
+fn prev_pow2(n: usize) -> usize {
+    1 << (usize::BITS - 1 - n.leading_zeros())
+}
+
+/// Shift `x` left until its top bit is set, returning the result and the shift (`ew` bits).
+///
+/// If `x` is zero, the results are unspecified.
+fn normalize(x: &Term, ew: usize) -> (Term, Term) {
+    let k = width(x);
+    let mut x = x.clone();
+    // the bits of the shift, most significant first
+    let mut shift = Vec::new();
+    let mut s = if k > 1 { prev_pow2(k - 1) } else { 0 };
+    while s > 0 {
+        let top_zero = is_zero(&extract(&x, k - 1, k - s));
+        x = ite(
+            top_zero.clone(),
+            concat(extract(&x, k - s - 1, 0), zeros(s)),
+            x,
+        );
+        shift.push(from_bool(top_zero));
+        s /= 2;
+    }
+    let shift = match shift.len() {
+        0 => zeros(ew),
+        n => {
+            assert!(n < ew);
+            let bits = if n == 1 {
+                shift.pop().unwrap()
+            } else {
+                term(Op::BvConcat, shift)
+            };
+            uext(bits, ew - n)
+        }
+    };
+    (x, shift)
+}
+
+/// The number of bits of a shift amount that can shift some, but not all, of `k` bits out.
+fn shift_stages(k: usize, amt: &Term) -> usize {
+    (0..width(amt)).take_while(|j| (1 << *j) < k).count()
+}
+
+/// Shift `x` left by the unsigned `amt`.
+fn shl(x: &Term, amt: &Term) -> Term {
+    let k = width(x);
+    let n = width(amt);
+    let stages = shift_stages(k, amt);
+    let x = (0..stages).fold(x.clone(), |x, j| {
+        let s = 1 << j;
+        let shifted = concat(extract(&x, k - s - 1, 0), zeros(s));
+        ite(term![Op::BvBit(j); amt.clone()], shifted, x)
+    });
+    if stages < n {
+        let all = term![NOT; is_zero(&extract(amt, n - 1, stages))];
+        ite(all, zeros(k), x)
+    } else {
+        x
+    }
+}
+
+/// Shift `x` right by the unsigned `amt`, OR-ing the bits shifted out into the lowest bit.
+fn shr_sticky(x: &Term, amt: &Term) -> Term {
+    let k = width(x);
+    let n = width(amt);
+    let stages = shift_stages(k, amt);
+    let mut sticky = bool_lit(false);
+    let mut x = x.clone();
+    for j in 0..stages {
+        let s = 1 << j;
+        let b = term![Op::BvBit(j); amt.clone()];
+        let lost = term![NOT; is_zero(&extract(&x, s - 1, 0))];
+        sticky = term![OR; sticky, term![AND; b.clone(), lost]];
+        x = ite(b, uext(extract(&x, k - 1, s), s), x);
+    }
+    if stages < n {
+        let all = term![NOT; is_zero(&extract(amt, n - 1, stages))];
+        let lost = term![NOT; is_zero(&x)];
+        sticky = term![OR; sticky, term![AND; all.clone(), lost]];
+        x = ite(all, zeros(k), x);
+    }
+    term![BV_OR; x, uext(from_bool(sticky), k - 1)]
+}
+
+/// The integer square root of `n`, which has even width, and the remainder `n - root^2`.
+///
+/// This is the digit-by-digit method, which fixes one bit of the root per step.
+fn isqrt(n: &Term) -> (Term, Term) {
+    let w = width(n);
+    let mut root = zeros(w);
+    let mut rem = n.clone();
+    for j in (0..w / 2).rev() {
+        let bit = bv_lit(Integer::from(1) << (2 * j) as u32, w);
+        let trial = term![BV_ADD; root.clone(), bit.clone()];
+        let fits = term![BV_UGE; rem.clone(), trial.clone()];
+        let half = uext(extract(&root, w - 1, 1), 1);
+        rem = ite(fits.clone(), term![BV_SUB; rem.clone(), trial], rem);
+        root = ite(fits, term![BV_ADD; half.clone(), bit], half);
+    }
+    (root, rem)
+}
+
+#[derive(Clone, Copy)]
+/// An IEEE-754 binary interchange format.
+struct Format {
+    /// Exponent bits
+    e: usize,
+    /// Fraction bits (the significand, without its hidden bit)
+    m: usize,
+}
+
+impl Format {
+    /// The format of a float-sorted term.
+    fn of(t: &Term) -> Self {
+        match check(t) {
+            Sort::F32 => Format { e: 8, m: 23 },
+            Sort::F64 => Format { e: 11, m: 52 },
+            s => panic!("No floating-point format for sort {}", s),
+        }
+    }
+
+    /// Total width
+    fn w(&self) -> usize {
+        1 + self.e + self.m
+    }
+
+    fn bias(&self) -> i64 {
+        (1 << (self.e - 1)) - 1
+    }
+
+    /// The width of signed exponents in intermediate results: enough for products and quotients.
+    fn ew(&self) -> usize {
+        self.e + 3
+    }
+
+    fn sign(&self, x: &Term) -> Term {
+        term![Op::BvBit(self.w() - 1); x.clone()]
+    }
+
+    fn exp(&self, x: &Term) -> Term {
+        extract(x, self.w() - 2, self.m)
+    }
+
+    fn frac(&self, x: &Term) -> Term {
+        extract(x, self.m - 1, 0)
+    }
+
+    /// Everything but the sign.
+    fn mag(&self, x: &Term) -> Term {
+        extract(x, self.w() - 2, 0)
+    }
+
+    fn is_nan(&self, x: &Term) -> Term {
+        term![AND; is_ones(&self.exp(x)), term![NOT; is_zero(&self.frac(x))]]
+    }
+
+    fn is_inf(&self, x: &Term) -> Term {
+        term![AND; is_ones(&self.exp(x)), is_zero(&self.frac(x))]
+    }
+
+    fn is_zero(&self, x: &Term) -> Term {
+        is_zero(&self.mag(x))
+    }
+
+    fn nan(&self) -> Term {
+        bv_lit(
+            ((Integer::from(1) << (self.e + 1) as u32) - 1) << (self.m - 1) as u32,
+            self.w(),
+        )
+    }
+
+    fn inf(&self, sign: Term) -> Term {
+        concat(from_bool(sign), self.inf_mag())
+    }
+
+    fn inf_mag(&self) -> Term {
+        bv_lit(
+            ((Integer::from(1) << self.e as u32) - 1) << self.m as u32,
+            self.w() - 1,
+        )
+    }
+
+    fn zero(&self, sign: Term) -> Term {
+        concat(from_bool(sign), zeros(self.w() - 1))
+    }
+
+    fn neg(&self, x: &Term) -> Term {
+        concat(from_bool(term![NOT; self.sign(x)]), self.mag(x))
+    }
+
+    /// The sign, exponent, and significand of a finite, nonzero `x`.
+    ///
+    /// `x` is `(-1)^sign * sig * 2^(exp - m)`, where `sig` has `m + 1` bits, and its top bit set.
+    /// The exponent is signed, with `ew` bits.
+    fn unpack(&self, x: &Term, ew: usize) -> (Term, Term, Term) {
+        let exp = self.exp(x);
+        let normal = term![NOT; is_zero(&exp)];
+        let sig = concat(from_bool(normal.clone()), self.frac(x));
+        // subnormals have the least normal exponent
+        let biased = ite(normal, uext(exp, ew - self.e), int_lit(1, ew));
+        let (sig, shift) = normalize(&sig, ew);
+        let exp = term![BV_SUB; term![BV_SUB; biased, int_lit(self.bias(), ew)], shift];
+        (self.sign(x), exp, sig)
+    }
+
+    /// Round `(-1)^sign * sig * 2^(exp - (k - 1))` to this format.
+    ///
+    /// `sig` has `k >= m + 3` bits, and its top bit set. Its lowest bit may be sticky: i.e., it
+    /// may stand for any nonzero value below the bit above it. The exponent is signed, and may
+    /// have any width of at least `e + 3`.
+    fn round_pack(&self, sign: Term, exp: Term, sig: Term) -> Term {
+        let (e, m) = (self.e, self.m);
+        let k = width(&sig);
+        let ew = width(&exp);
+        assert!(k >= m + 3);
+        assert!(ew >= e + 3);
+        let biased = term![BV_ADD; exp, int_lit(self.bias(), ew)];
+        // shift tiny results down to the least normal exponent
+        let tiny = term![BV_SLT; biased.clone(), int_lit(1, ew)];
+        let shift = term![BV_SUB; int_lit(1, ew), biased.clone()];
+        let sig = ite(tiny.clone(), shr_sticky(&sig, &shift), sig);
+        let biased = ite(tiny, int_lit(1, ew), biased);
+
+        // round to nearest, ties to even
+        let lsb = term![Op::BvBit(k - m - 1); sig.clone()];
+        let guard = term![Op::BvBit(k - m - 2); sig.clone()];
+        let sticky = term![NOT; is_zero(&extract(&sig, k - m - 3, 0))];
+        let up = term![AND; guard, term![OR; sticky, lsb]];
+        let rounded = term![BV_ADD;
+            uext(extract(&sig, k - 1, k - m - 1), 1),
+            uext(from_bool(up), m + 1)
+        ];
+
+        // Add the significand, hidden bit and all, to the exponent field less one. A subnormal
+        // (hidden bit 0) gets exponent field 0, and a carry out of the significand increments it.
+        let exp_field = extract(&term![BV_SUB; biased.clone(), int_lit(1, ew)], e - 1, 0);
+        let packed = term![BV_ADD;
+            concat(uext(exp_field, 1), zeros(m)),
+            uext(rounded, e - 1)
+        ];
+        let max_exp = (1 << e) - 1;
+        let overflow = term![OR;
+            term![BV_SGE; biased, int_lit(max_exp, ew)],
+            term![BV_UGE; extract(&packed, e + m, m), bv_lit(max_exp, e + 1)]
+        ];
+        let mag = ite(overflow, self.inf_mag(), extract(&packed, e + m - 1, 0));
+        concat(from_bool(sign), mag)
+    }
+
+    /// Pick a result, given the result for finite, nonzero arguments, and the special cases.
+    fn special(
+        &self,
+        nan: Term,
+        inf: Option<(Term, Term)>,
+        zero: Option<(Term, Term)>,
+        res: Term,
+    ) -> Term {
+        let res = match zero {
+            Some((c, sign)) => ite(c, self.zero(sign), res),
+            None => res,
+        };
+        let res = match inf {
+            Some((c, sign)) => ite(c, self.inf(sign), res),
+            None => res,
+        };
+        ite(nan, self.nan(), res)
+    }
+
+    fn both_zero(&self, a: &Term, b: &Term) -> Term {
+        term![AND; self.is_zero(a), self.is_zero(b)]
+    }
+
+    fn lt(&self, a: &Term, b: &Term) -> Term {
+        let (sa, sb) = (self.sign(a), self.sign(b));
+        let (ma, mb) = (self.mag(a), self.mag(b));
+        let ordered = ite(
+            term![XOR; sa.clone(), sb],
+            sa.clone(),
+            ite(
+                sa,
+                term![BV_UGT; ma.clone(), mb.clone()],
+                term![BV_ULT; ma, mb],
+            ),
+        );
+        term![AND;
+            term![NOT; self.is_nan(a)],
+            term![NOT; self.is_nan(b)],
+            term![NOT; self.both_zero(a, b)],
+            ordered
+        ]
+    }
+
+    fn eq(&self, a: &Term, b: &Term) -> Term {
+        term![AND;
+            term![NOT; self.is_nan(a)],
+            term![NOT; self.is_nan(b)],
+            term![OR; term![EQ; a.clone(), b.clone()], self.both_zero(a, b)]
+        ]
+    }
+
+    fn le(&self, a: &Term, b: &Term) -> Term {
+        term![OR; self.lt(a, b), self.eq(a, b)]
+    }
+
+    /// The larger (or smaller) argument, ignoring NaNs.
+    fn max_min(&self, a: &Term, b: &Term, max: bool) -> Term {
+        let pick_b = if max { self.lt(a, b) } else { self.lt(b, a) };
+        ite(
+            self.is_nan(a),
+            b.clone(),
+            ite(self.is_nan(b), a.clone(), ite(pick_b, b.clone(), a.clone())),
+        )
+    }
+
+    fn add(&self, a: &Term, b: &Term) -> Term {
+        let ew = self.ew();
+        // order the arguments by magnitude
+        let swap = term![BV_ULT; self.mag(a), self.mag(b)];
+        let x = ite(swap.clone(), b.clone(), a.clone());
+        let y = ite(swap, a.clone(), b.clone());
+        let (sx, ex, sigx) = self.unpack(&x, ew);
+        let (sy, ey, sigy) = self.unpack(&y, ew);
+        // align, with guard, round, and sticky bits, and room for a carry
+        let bx = uext(concat(sigx, zeros(3)), 1);
+        let by = uext(
+            shr_sticky(&concat(sigy, zeros(3)), &term![BV_SUB; ex.clone(), ey]),
+            1,
+        );
+        let sum = ite(
+            term![XOR; sx.clone(), sy],
+            term![BV_SUB; bx.clone(), by.clone()],
+            term![BV_ADD; bx, by],
+        );
+        let (sig, shift) = normalize(&sum, ew);
+        let exp = term![BV_SUB; term![BV_ADD; ex, int_lit(1, ew)], shift];
+        // exact cancellation gives +0
+        let res = ite(
+            is_zero(&sum),
+            self.zero(bool_lit(false)),
+            self.round_pack(sx, exp, sig),
+        );
+
+        let nan = term![OR;
+            self.is_nan(a),
+            self.is_nan(b),
+            term![AND; self.is_inf(a), self.is_inf(b), term![XOR; self.sign(a), self.sign(b)]]
+        ];
+        let zero_sum = ite(
+            self.is_zero(b),
+            self.zero(term![AND; self.sign(a), self.sign(b)]),
+            b.clone(),
+        );
+        ite(
+            nan,
+            self.nan(),
+            ite(
+                self.is_inf(a),
+                a.clone(),
+                ite(
+                    self.is_inf(b),
+                    b.clone(),
+                    ite(
+                        self.is_zero(a),
+                        zero_sum,
+                        ite(self.is_zero(b), a.clone(), res),
+                    ),
+                ),
+            ),
+        )
+    }
+
+    fn mul(&self, a: &Term, b: &Term) -> Term {
+        let (m, ew) = (self.m, self.ew());
+        let sign = term![XOR; self.sign(a), self.sign(b)];
+        let (_, ea, siga) = self.unpack(a, ew);
+        let (_, eb, sigb) = self.unpack(b, ew);
+        let n = 2 * m + 2;
+        let prod = term![BV_MUL; uext(siga, m + 1), uext(sigb, m + 1)];
+        // the product of significands in [1, 2) is in [1, 4)
+        let top = term![Op::BvBit(n - 1); prod.clone()];
+        let sig = ite(
+            top.clone(),
+            prod.clone(),
+            concat(extract(&prod, n - 2, 0), zeros(1)),
+        );
+        let exp = term![BV_ADD; ea, eb, uext(from_bool(top), ew - 1)];
+        let res = self.round_pack(sign.clone(), exp, sig);
+
+        let nan = term![OR;
+            self.is_nan(a),
+            self.is_nan(b),
+            term![AND; self.is_inf(a), self.is_zero(b)],
+            term![AND; self.is_zero(a), self.is_inf(b)]
+        ];
+        let inf = term![OR; self.is_inf(a), self.is_inf(b)];
+        let zero = term![OR; self.is_zero(a), self.is_zero(b)];
+        self.special(nan, Some((inf, sign.clone())), Some((zero, sign)), res)
+    }
+
+    fn div(&self, a: &Term, b: &Term) -> Term {
+        let (m, ew) = (self.m, self.ew());
+        let sign = term![XOR; self.sign(a), self.sign(b)];
+        let (_, ea, siga) = self.unpack(a, ew);
+        let (_, eb, sigb) = self.unpack(b, ew);
+        let num = concat(siga, zeros(m + 2));
+        let den = uext(sigb, m + 2);
+        // the quotient of significands in [1, 2) is in (1/2, 2)
+        let q = extract(&term![BV_UDIV; num.clone(), den.clone()], m + 2, 0);
+        let inexact = term![NOT; is_zero(&term![BV_UREM; num, den])];
+        let sig = concat(q, from_bool(inexact));
+        let top = term![Op::BvBit(m + 3); sig.clone()];
+        let sig = ite(
+            top.clone(),
+            sig.clone(),
+            concat(extract(&sig, m + 2, 0), zeros(1)),
+        );
+        let exp = term![BV_SUB;
+            term![BV_SUB; ea, eb],
+            uext(from_bool(term![NOT; top]), ew - 1)
+        ];
+        let res = self.round_pack(sign.clone(), exp, sig);
+
+        let nan = term![OR;
+            self.is_nan(a),
+            self.is_nan(b),
+            term![AND; self.is_inf(a), self.is_inf(b)],
+            term![AND; self.is_zero(a), self.is_zero(b)]
+        ];
+        let inf = term![OR; self.is_inf(a), self.is_zero(b)];
+        let zero = term![OR; self.is_zero(a), self.is_inf(b)];
+        self.special(nan, Some((inf, sign.clone())), Some((zero, sign)), res)
+    }
+
+    fn sqrt(&self, x: &Term) -> Term {
+        let (m, ew) = (self.m, self.ew());
+        let (_, exp, sig) = self.unpack(x, ew);
+        // Make the exponent even, so that it halves exactly. The radicand, scaled by 2^(2m + 2),
+        // is in [2^(2m + 2), 2^(2m + 4)), so its root has m + 2 bits, and its top bit set.
+        let odd = term![Op::BvBit(0); exp.clone()];
+        let n = ite(
+            odd,
+            concat(sig.clone(), zeros(m + 3)),
+            uext(concat(sig, zeros(m + 2)), 1),
+        );
+        let (root, rem) = isqrt(&n);
+        let sig = concat(
+            extract(&root, m + 1, 0),
+            from_bool(term![NOT; is_zero(&rem)]),
+        );
+        let half = concat(
+            from_bool(term![Op::BvBit(ew - 1); exp.clone()]),
+            extract(&exp, ew - 1, 1),
+        );
+        let res = self.round_pack(bool_lit(false), half, sig);
+
+        // the root of -0 is -0
+        let nan = term![OR;
+            self.is_nan(x),
+            term![AND; self.sign(x), term![NOT; self.is_zero(x)]]
+        ];
+        self.special(
+            nan,
+            Some((self.is_inf(x), bool_lit(false))),
+            Some((self.is_zero(x), self.sign(x))),
+            res,
+        )
+    }
+
+    /// The IEEE-754 remainder, `a - n * b`, where `n` is the integer nearest `a / b`.
+    ///
+    /// The result is exact, and at most `|b| / 2` in magnitude.
+    fn rem(&self, a: &Term, b: &Term) -> Term {
+        let (m, ew) = (self.m, self.ew());
+        let w = m + 4;
+        let (sa, ea, siga) = self.unpack(a, ew);
+        let (_, eb, sigb) = self.unpack(b, ew);
+        // If |a| < 2^(eb - 1) <= |b| / 2, then the result is `a`. Otherwise, in units of
+        // 2^(eb - m - 1), |b| is p = 2 * sigb, and |a| is siga * 2^d, for d = ea - eb + 1 >= 0.
+        let d = term![BV_SUB; term![BV_ADD; ea, int_lit(1, ew)], eb.clone()];
+        let small = term![BV_SLT; d.clone(), int_lit(0, ew)];
+        // r = |a| mod 2p, by square-and-multiply for 2^d mod 2p
+        let modulus = concat(sigb, zeros(2));
+        let mul_mod = |x: Term, y: Term| {
+            let n = 2 * (m + 3);
+            let wx = width(&x);
+            let prod = term![BV_MUL; uext(x, n - wx), uext(y, m + 3)];
+            extract(
+                &term![BV_UREM; prod, uext(modulus.clone(), m + 3)],
+                m + 2,
+                0,
+            )
+        };
+        let mut pow = bv_lit(1, m + 3);
+        for j in (0..ew - 1).rev() {
+            pow = mul_mod(pow.clone(), pow);
+            let double = concat(pow.clone(), zeros(1));
+            let double = ite(
+                term![BV_UGE; double.clone(), uext(modulus.clone(), 1)],
+                term![BV_SUB; double.clone(), uext(modulus.clone(), 1)],
+                double,
+            );
+            pow = ite(
+                term![Op::BvBit(j); d.clone()],
+                extract(&double, m + 2, 0),
+                pow,
+            );
+        }
+        let r = uext(mul_mod(siga, pow), 1);
+        // n is even below p / 2, odd up to 3p / 2, and even again above; ties go to even
+        let p = uext(extract(&modulus, m + 2, 1), 2);
+        let two_r = concat(extract(&r, w - 2, 0), zeros(1));
+        let two_p = concat(extract(&p, w - 2, 0), zeros(1));
+        let three_p = term![BV_ADD; p.clone(), two_p.clone()];
+        let above = term![BV_UGT; two_r.clone(), p.clone()];
+        let below_p = term![BV_ULT; r.clone(), p.clone()];
+        let high = term![BV_UGE; two_r, three_p];
+        let flip = term![AND; above.clone(), term![OR; below_p.clone(), high.clone()]];
+        let mag = ite(
+            term![NOT; above],
+            r.clone(),
+            ite(
+                below_p,
+                term![BV_SUB; p.clone(), r.clone()],
+                ite(high, term![BV_SUB; two_p, r.clone()], term![BV_SUB; r, p]),
+            ),
+        );
+        let (sig, shift) = normalize(&mag, ew);
+        let exp = term![BV_SUB; term![BV_ADD; eb, int_lit(2, ew)], shift];
+        // a zero result has the sign of `a`
+        let res = ite(
+            is_zero(&mag),
+            self.zero(sa.clone()),
+            self.round_pack(term![XOR; sa, flip], exp, sig),
+        );
+
+        let nan = term![OR;
+            self.is_nan(a),
+            self.is_nan(b),
+            self.is_inf(a),
+            self.is_zero(b)
+        ];
+        let keep = term![OR; self.is_inf(b), self.is_zero(a), small];
+        ite(nan, self.nan(), ite(keep, a.clone(), res))
+    }
+
+    /// Round to an integer, with ties away from zero.
+    fn round(&self, x: &Term) -> Term {
+        let (e, m, w) = (self.e, self.m, self.w());
+        let bias = self.bias();
+        let exp = self.exp(x);
+        let sign = self.sign(x);
+        // infinities, NaNs, and numbers of magnitude at least 2^m are integers
+        let integral = term![BV_UGE; exp.clone(), bv_lit(bias + m as i64, e)];
+        let below_half = term![BV_ULT; exp.clone(), bv_lit(bias - 1, e)];
+        let half_to_one = term![EQ; exp.clone(), bv_lit(bias - 1, e)];
+        // Otherwise, there are 1 to m fractional bits. Add half of the unit in the last integral
+        // place, and clear the fractional bits. A carry may increment the exponent.
+        let frac_bits = term![BV_SUB; bv_lit(bias + m as i64, e), exp];
+        let half = shl(&bv_lit(1, w - 1), &term![BV_SUB; frac_bits, bv_lit(1, e)]);
+        let frac_mask = term![BV_SUB; concat(extract(&half, w - 3, 0), zeros(1)), bv_lit(1, w - 1)];
+        let mag = term![BV_AND;
+            term![BV_ADD; self.mag(x), half],
+            term![BV_NOT; frac_mask]
+        ];
+        let one = bv_lit(Integer::from(bias) << m as u32, w - 1);
+        ite(
+            integral,
+            x.clone(),
+            ite(
+                below_half,
+                self.zero(sign.clone()),
+                concat(from_bool(sign), ite(half_to_one, one, mag)),
+            ),
+        )
+    }
+
+    /// Convert the unsigned integer `x` to this format, and give it `sign`.
+    fn convert_uint(&self, x: &Term, sign: Term) -> Term {
+        let n = width(x);
+        let ew = std::cmp::max(self.ew(), (usize::BITS - n.leading_zeros()) as usize + 2);
+        let pad = (self.m + 3).saturating_sub(n);
+        let sig = if pad > 0 {
+            concat(x.clone(), zeros(pad))
+        } else {
+            x.clone()
+        };
+        let (sig, shift) = normalize(&sig, ew);
+        let exp = term![BV_SUB; int_lit(n as i64 - 1, ew), shift];
+        ite(
+            is_zero(x),
+            self.zero(bool_lit(false)),
+            self.round_pack(sign, exp, sig),
+        )
+    }
+
+    /// Convert `x`, in format `from`, to this format.
+    fn convert(&self, from: Format, x: &Term) -> Term {
+        if from.e == self.e && from.m == self.m {
+            return x.clone();
+        }
+        let ew = std::cmp::max(self.ew(), from.ew());
+        let (sign, exp, sig) = from.unpack(x, ew);
+        let pad = (self.m + 3).saturating_sub(from.m + 1);
+        let sig = if pad > 0 {
+            concat(sig, zeros(pad))
+        } else {
+            sig
+        };
+        let res = self.round_pack(sign.clone(), exp, sig);
+        self.special(
+            from.is_nan(x),
+            Some((from.is_inf(x), sign.clone())),
+            Some((from.is_zero(x), sign)),
+            res,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fxhash::FxHashMap;
+    use rand::{Rng, SeedableRng};
+
+    fn var(name: &str, sort: Sort) -> Term {
+        leaf_term(Op::Var(name.into(), sort))
+    }
+
+    fn env(vals: &[(&str, Value)]) -> FxHashMap<String, Value> {
+        vals.iter()
+            .map(|(n, v)| (n.to_string(), v.clone()))
+            .collect()
+    }
+
+    /// Check that the lowering of `t` agrees with `t` on `vals`.
+    ///
+    /// Floats agree if they have the same bits, or are both NaN.
+    fn check_lowering(t: &Term, vals: &FxHashMap<String, Value>) {
+        let lowered = lower_cached(t, &mut TermMap::new());
+        let expected = eval(t, vals);
+        let actual = eval(&lowered, vals);
+        let ok = match &expected {
+            Value::F32(f) => {
+                let g = f32::from_bits(actual.as_bv().uint().to_u32().unwrap());
+                f.to_bits() == g.to_bits() || (f.is_nan() && g.is_nan())
+            }
+            Value::F64(f) => {
+                let g = f64::from_bits(actual.as_bv().uint().to_u64().unwrap());
+                f.to_bits() == g.to_bits() || (f.is_nan() && g.is_nan())
+            }
+            v => v == &actual,
+        };
+        assert!(
+            ok,
+            "{} on {:?}\nexpected {}, got {}",
+            t, vals, expected, actual
+        );
+    }
+
+    fn f32_samples() -> Vec<f32> {
+        let mut xs = vec![
+            0.0,
+            -0.0,
+            1.0,
+            -1.0,
+            0.5,
+            -2.5,
+            0.1,
+            3.0,
+            1.0e30,
+            -3.4028235e38,
+            f32::MIN_POSITIVE,
+            1.1754942e-38,
+            1.0e-45,
+            -7.0e-45,
+            4194304.5,
+            16777215.0,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NAN,
+        ];
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        for _ in 0..8 {
+            xs.push(f32::from_bits(rng.gen()));
+            xs.push(rng.gen::<f32>() * 100.0 - 50.0);
+        }
+        xs
+    }
+
+    fn f64_samples() -> Vec<f64> {
+        let mut xs = vec![
+            0.0,
+            -0.0,
+            1.0,
+            -1.5,
+            0.1,
+            1.0e300,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            5.0e-324,
+            -2.225073858507201e-308,
+            4503599627370495.5,
+            f64::INFINITY,
+            f64::NAN,
+        ];
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        for _ in 0..6 {
+            xs.push(f64::from_bits(rng.gen()));
+            xs.push(rng.gen::<f64>() * 100.0 - 50.0);
+        }
+        xs
+    }
+
+    fn binary_terms(sort: Sort) -> Vec<Term> {
+        let a = var("a", sort.clone());
+        let b = var("b", sort);
+        let mut ts: Vec<Term> = vec![
+            FpBinOp::Add,
+            FpBinOp::Sub,
+            FpBinOp::Mul,
+            FpBinOp::Div,
+            FpBinOp::Rem,
+        ]
+        .into_iter()
+        .map(|o| term![Op::FpBinOp(o); a.clone(), b.clone()])
+        .collect();
+        ts.extend(
+            vec![
+                FpBinPred::Le,
+                FpBinPred::Lt,
+                FpBinPred::Eq,
+                FpBinPred::Ge,
+                FpBinPred::Gt,
+            ]
+            .into_iter()
+            .map(|o| term![Op::FpBinPred(o); a.clone(), b.clone()]),
+        );
+        ts.push(term![EQ; a, b]);
+        ts
+    }
+
+    fn unary_terms(sort: Sort) -> Vec<Term> {
+        let a = var("a", sort);
+        let mut ts: Vec<Term> = vec![FpUnOp::Neg, FpUnOp::Abs, FpUnOp::Round, FpUnOp::Sqrt]
+            .into_iter()
+            .map(|o| term![Op::FpUnOp(o); a.clone()])
+            .collect();
+        ts.extend(
+            vec![
+                FpUnPred::Normal,
+                FpUnPred::Subnormal,
+                FpUnPred::Zero,
+                FpUnPred::Infinite,
+                FpUnPred::Nan,
+                FpUnPred::Negative,
+                FpUnPred::Positive,
+            ]
+            .into_iter()
+            .map(|o| term![Op::FpUnPred(o); a.clone()]),
+        );
+        ts.push(term![Op::FpToFp(32); a.clone()]);
+        ts.push(term![Op::FpToFp(64); a]);
+        ts
+    }
+
+    #[test]
+    fn f32_ops() {
+        let xs = f32_samples();
+        let bin = binary_terms(Sort::F32);
+        let un = unary_terms(Sort::F32);
+        for x in &xs {
+            for t in &un {
+                check_lowering(t, &env(&[("a", Value::F32(*x))]));
+            }
+            for y in &xs {
+                let vals = env(&[("a", Value::F32(*x)), ("b", Value::F32(*y))]);
+                for t in &bin {
+                    check_lowering(t, &vals);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn f64_ops() {
+        let xs = f64_samples();
+        let bin = binary_terms(Sort::F64);
+        let un = unary_terms(Sort::F64);
+        for x in &xs {
+            for t in &un {
+                check_lowering(t, &env(&[("a", Value::F64(*x))]));
+            }
+            for y in &xs {
+                let vals = env(&[("a", Value::F64(*x)), ("b", Value::F64(*y))]);
+                for t in &bin {
+                    check_lowering(t, &vals);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn max_min() {
+        let a = var("a", Sort::F32);
+        let b = var("b", Sort::F32);
+        let ts = vec![
+            term![Op::FpBinOp(FpBinOp::Max); a.clone(), b.clone()],
+            term![Op::FpBinOp(FpBinOp::Min); a, b],
+        ];
+        let xs = f32_samples();
+        for x in &xs {
+            for y in &xs {
+                // the sign of a zero result is unspecified
+                if *x == 0.0 && *y == 0.0 {
+                    continue;
+                }
+                let vals = env(&[("a", Value::F32(*x)), ("b", Value::F32(*y))]);
+                for t in &ts {
+                    check_lowering(t, &vals);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn int_conversions() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+        let mut xs: Vec<u64> = vec![0, 1, 3, u64::MAX, 1 << 63, (1 << 24) + 1, (1 << 53) + 1];
+        xs.extend((0..16).map(|i| rng.gen::<u64>() >> (i * 4)));
+        let ts: Vec<(Term, usize)> = vec![(8, 32), (32, 32), (64, 64), (64, 32)]
+            .into_iter()
+            .flat_map(|(n, w)| {
+                let x = var("x", Sort::BitVector(n));
+                vec![
+                    (term![Op::UbvToFp(w); x.clone()], n),
+                    (term![Op::SbvToFp(w); x], n),
+                ]
+            })
+            .collect();
+        for x in xs {
+            for (t, n) in &ts {
+                let bv = BitVector::new(Integer::from(x).keep_bits(*n as u32), *n);
+                check_lowering(t, &env(&[("x", Value::BitVector(bv))]));
+            }
+        }
+    }
+
+    #[test]
+    fn computation() {
+        let a = var("a", Sort::F32);
+        let b = var("b", Sort::F32);
+        let t = term![Op::FpBinPred(FpBinPred::Lt);
+            term![Op::FpBinOp(FpBinOp::Mul); a.clone(), b.clone()],
+            term![Op::FpBinOp(FpBinOp::Add); a, leaf_term(Op::Const(Value::F32(1.0)))]
+        ];
+        let mut cs = Computation::from_constraint_system_parts(vec![t], Vec::new());
+        lower_fp(&mut cs);
+        for c in PostOrderIter::new(cs.outputs[0].clone()) {
+            assert!(
+                !is_fp(&c) || matches!(c.op, Op::Var(..)),
+                "{} remains",
+                c.op
+            );
+        }
+        let vals = env(&[("a", Value::F32(2.0)), ("b", Value::F32(1.25))]);
+        assert_eq!(eval(&cs.outputs[0], &vals), Value::Bool(true));
+        let vals = env(&[("a", Value::F32(2.0)), ("b", Value::F32(1.75))]);
+        assert_eq!(eval(&cs.outputs[0], &vals), Value::Bool(false));
+    }
+}
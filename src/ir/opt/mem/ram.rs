@@ -6,6 +6,7 @@ use log::debug;
 use crate::ir::opt::visit::RewritePass;
 use crate::ir::term::extras::Letified;
 use crate::ir::term::*;
+use circ_fields::FieldT;
 
 #[derive(Debug)]
 /// An access to a RAM
@@ -16,21 +17,28 @@ pub struct Access {
     pub idx: Term,
     /// The value written or read.
     pub val: Term,
+    /// When the access happens.
+    ///
+    /// The RAM is initialized at time 0. The k-th write is at time 2k, and reads that follow it
+    /// (but precede the next write) are at time 2k+1.
+    pub time: usize,
 }
 
 impl Access {
-    fn new_read(idx: Term, val: Term) -> Self {
+    fn new_read(idx: Term, val: Term, time: usize) -> Self {
         Self {
             idx,
             val,
             is_write: bool_lit(false),
+            time,
         }
     }
-    fn new_write(idx: Term, val: Term, guard: Term) -> Self {
+    fn new_write(idx: Term, val: Term, guard: Term, time: usize) -> Self {
         Self {
             idx,
             val,
             is_write: guard,
+            time,
         }
     }
 }
@@ -104,21 +112,27 @@ fn universal_hash(terms: impl IntoIterator<Item = Term>, beta: &Term) -> Term {
     term(PF_ADD, results)
 }
 
-fn cast_to_field(term: &Term, field: &circ_fields::FieldT) -> Term {
+fn cast_to_field(term: &Term, field: &FieldT) -> Term {
     match check(term) {
         Sort::Field(_) => term.clone(),
         Sort::BitVector(_) => term![Op::UbvToPf(field.clone()); term.clone()],
+        Sort::Bool => term![ITE; term.clone(), pf_lit(field.new_v(1)), pf_lit(field.new_v(0))],
         _ => panic!("Cannot cast term of type {:?} to field!", check(term)),
     }
 }
 
+/// The number of bits needed to represent `n` (at least one).
+fn bits(n: usize) -> usize {
+    (std::mem::size_of::<usize>() * 8 - n.leading_zeros() as usize).max(1)
+}
+
 #[derive(Debug)]
 /// A RAM transcript
 pub struct Ram {
     /// The unique id of this RAM
     pub id: usize,
-    /// The starting value of all cells
-    pub init_val: Value,
+    /// The initial contents (an array term)
+    pub init: Term,
     /// The size
     pub size: usize,
     /// The list of accesses (in access order)
@@ -130,254 +144,239 @@ pub struct Ram {
 }
 
 impl Ram {
-    fn new(id: usize, const_: Array) -> Self {
-        assert_eq!(const_.map.len(), 0);
-        Ram {
-            id,
-            val_sort: const_.default.sort(),
-            idx_sort: const_.key_sort,
-            accesses: vec![],
-            size: const_.size,
-            init_val: *const_.default,
+    fn new(id: usize, init: Term) -> Self {
+        match check(&init) {
+            Sort::Array(idx_sort, val_sort, size) => Ram {
+                id,
+                init,
+                size,
+                accesses: vec![],
+                idx_sort: *idx_sort,
+                val_sort: *val_sort,
+            },
+            s => panic!("Cannot start a RAM from {}, which is a {}", init, s),
         }
     }
-    fn new_read(&mut self, idx: Term, computation: &mut Computation, read_value: Term) -> Term {
+    fn new_read(
+        &mut self,
+        idx: Term,
+        computation: &mut Computation,
+        read_value: Term,
+        time: usize,
+    ) -> Term {
         let val_name = format!("__ram_{}_{}", self.id, self.accesses.len());
         debug_assert_eq!(&check(&idx), &self.idx_sort);
         let var = computation.new_var(
             &val_name,
             check(&read_value),
             Some(crate::ir::proof::PROVER_ID),
-            0, // TODO: correct?
+            0,
             false,
             Some(read_value),
         );
-        self.accesses.push(Access::new_read(idx, var.clone()));
+        self.accesses.push(Access::new_read(idx, var.clone(), time));
         var
     }
 
-    fn new_write(&mut self, idx: Term, val: Term, guard: Term) {
+    fn new_write(&mut self, idx: Term, val: Term, guard: Term, time: usize) {
         debug_assert_eq!(&check(&idx), &self.idx_sort);
         debug_assert_eq!(&check(&val), &self.val_sort);
         debug_assert_eq!(&check(&guard), &Sort::Bool);
-        self.accesses.push(Access::new_write(idx, val, guard));
+        self.accesses.push(Access::new_write(idx, val, guard, time));
     }
 
-    fn sorted_by_index(&self, computation: &mut Computation) -> Ram {
-        let idx_terms: Vec<Term> = self
-            .accesses
+    /// The number of writes
+    fn writes(&self) -> usize {
+        self.accesses
             .iter()
-            .map(|access| access.idx.clone())
-            .collect();
-        // create array from idx->val to precompute correct values from sorted idxs
-        let empty_arr = leaf_term(Op::Const(Value::Array(Array::default(
-            self.idx_sort.clone(),
-            &self.val_sort,
-            self.accesses.len(),
-        ))));
-        let val_array_term = self.accesses.iter().fold(
-            empty_arr,
-            |arr, access| term![Op::Store; arr, access.idx.clone(), access.val.clone()],
-        );
-
-        // construct a term that ensures __ram_srows are a witness to the ordering
-        // constraints on __ram values
-        let mut sorted_accesses = Vec::new();
-        for i in 0..self.accesses.len() {
-            // create an input for each field
-            // TODO: create a check + stronger opt where we elide all writes
-            //       when we have a pattern of "all writes" -> "all reads",
-            //       where val is the value from the last write
-            //let is_write_name = format!("__ram_srow_{}_{}.is_write", ramid, i);
-            let idx_name = format!("__ram_srow_{}_{}.idx", self.id, i);
-            let val_name = format!("__ram_srow_{}_{}.val", self.id, i);
-            //let is_write = cs.new_var(
-            //    &is_write_name,
-            //    ram.idx_sort.clone(),
-            //    Some(crate::ir::proof::PROVER_ID),
-            //    None,
-            //);
-            let idx = computation.new_var(
-                &idx_name,
-                self.idx_sort.clone(),
-                Some(crate::ir::proof::PROVER_ID),
-                0, // TODO
-                false,
-                Some(term(Op::NthSmallest(i), idx_terms.clone())),
-            );
-            let val = computation.new_var(
-                &val_name,
-                self.val_sort.clone(),
-                Some(crate::ir::proof::PROVER_ID),
-                0, // TODO
-                false,
-                Some(
-                    term![Op::Select; val_array_term.clone(), term(Op::NthSmallest(i), idx_terms.clone())],
-                ),
-            );
-            let access = Access {
-                is_write: bool_lit(false),
-                idx,
-                val,
-            };
-            sorted_accesses.push(access);
-        }
+            .filter(|a| a.is_write != bool_lit(false))
+            .count()
+    }
+}
 
-        Ram {
-            id: self.id,
-            init_val: self.init_val.clone(),
-            size: self.size,
-            accesses: sorted_accesses,
-            idx_sort: self.idx_sort.clone(),
-            val_sort: self.val_sort.clone(),
-        }
+/// If `t` is a projection out of a tuple literal, the projected term. Otherwise, `t`.
+fn resolve(t: &Term) -> Term {
+    match &t.op {
+        Op::Field(i) if t.cs[0].op == Op::Tuple => resolve(&t.cs[0].cs[*i]),
+        _ => t.clone(),
     }
 }
 
-/// Parse `ite` as a conditional store (arr, idx, val, guard)
-fn parse_cond_store(ite: &Term) -> Option<ConditionalStore> {
-    if ite.op == Op::Ite && ite.cs[1].op == Op::Store && ite.cs[1].cs[0] == ite.cs[2] {
-        Some(ConditionalStore {
-            arr: ite.cs[2].clone(),
-            idx: ite.cs[1].cs[1].clone(),
-            val: ite.cs[1].cs[2].clone(),
-            guard: ite.cs[0].clone(),
-            store: ite.cs[1].clone(),
-        })
+fn is_array(t: &Term) -> bool {
+    matches!(check(t), Sort::Array(..))
+}
+
+/// `(and c g)`, but `c` if `g` is true.
+fn guard_and(c: Term, g: &Term) -> Term {
+    if g == &bool_lit(true) {
+        c
     } else {
-        None
+        term![AND; c, g.clone()]
     }
 }
 
-#[derive(Debug)]
-/// Data about a conditional store: term = (ite guard (store arr idx val) arr)
-///
-/// The store field is (store arr idx val)
-struct ConditionalStore {
-    arr: Term,
+#[derive(Debug, Clone)]
+/// A conditional write: (ite guard (store _ idx val) _)
+struct Write {
     idx: Term,
     val: Term,
     guard: Term,
-    store: Term,
 }
 
 /// Graph of the *arrays* in the computation.
 ///
-/// Nodes are *most* of the array-sorted terms (see "subsumed store" for the exception).
-///
-/// Edges go from dependent arrays to their dependencies and represent stores,
-/// ITEs, and conditional stores.
-///
-/// A conditional store has form T = (ite C (store A I V) A).
+/// Nodes are the *RAM terms*: stores, and some array-valued ITEs. Each has one edge, to the array
+/// that it updates (its *base*). Other array terms (constants, inputs, ...) are *roots*: RAMs
+/// start from them.
 ///
-/// It is regarded as a single edge from T to A, so long as the interior store
-/// (store A I V) has no other dependents. In this case, the interior store is
-/// called a "subsumed store", and is not part of the array graph itself.
-///
-/// The "constant-free" graph has constant arrays removed.
-///
-/// An array term in the graph is non-RAM if is is connected (undirectedly) in
-/// the constant-free graph to any node with multiple parents or children in the
-/// constant-free graph.
+/// An ITE (ite C T F) is a RAM term if T and F are each a (possibly empty) sequence of RAM terms
+/// applied to the same base, A, and each RAM term in those sequences has just one parent. The ITE
+/// is then regarded as a single edge from the ITE to A, which makes T's writes (guarded by C) and
+/// then F's writes (guarded by (not C)). The RAM terms in T and F are "subsumed", and are not
+/// part of the graph. A conditional store, (ite C (store A I V) A), is the simplest such ITE.
 ///
+/// A RAM term is non-RAM if it is connected (undirectedly, through RAM terms) to a RAM term with
+/// multiple parents in the graph, or to one that is used by an array term other than a select or
+/// a RAM term (e.g., an ITE that is not a RAM term).
 #[derive(Debug)]
 struct ArrayGraph {
-    /// Map from array terms to their children (dependencies)
-    children: TermMap<Vec<Term>>,
-    /// Set of all array terms.
-    array_terms: TermSet,
+    /// Map from RAM terms to their bases
+    base: TermMap<Term>,
+    /// Map from RAM terms to their writes (in order)
+    writes: TermMap<Vec<Write>>,
+    /// Subsumed RAM terms
+    subsumed: TermSet,
     /// Set of non-RAM array terms.
     non_ram: TermSet,
+    /// Roots that are read at non-constant indices
+    read_roots: TermSet,
 }
 
 impl ArrayGraph {
     fn new(c: &Computation) -> Self {
         let term_parents = extras::parents_map(c);
-        let mut c_stores = TermMap::default();
-        let mut ps = TermMap::default();
-        let mut cs = TermMap::default();
-        let mut arrs = TermSet::default();
+        let mut base: TermMap<Term> = TermMap::default();
+        let mut writes: TermMap<Vec<Write>> = TermMap::default();
         let mut subsumed = TermSet::default();
-        // We go parent->children to see conditional stores before their subsumed stores.
-        // clippy thinks this is needless, but we need it for the reverse iteration.
-        #[allow(clippy::needless_collect)]
-        let terms: Vec<_> = c.terms_postorder().collect();
-        for t in terms.into_iter().rev() {
-            let mut is_c_store = false;
-            if let Some(c_store) = parse_cond_store(&t) {
-                if term_parents.get(&c_store.store).unwrap().len() == 1 {
-                    debug!("cond store: {}", Letified(t.clone()));
-                    subsumed.insert(c_store.store.clone());
-                    ps.entry(c_store.arr.clone())
-                        .or_insert_with(Vec::new)
-                        .push(t.clone());
-                    cs.entry(t.clone())
-                        .or_insert_with(Vec::new)
-                        .push(c_store.arr.clone());
-                    arrs.insert(t.clone());
-                    c_stores.insert(t.clone(), c_store);
-                    is_c_store = true;
+        let mut escaped = TermSet::default();
+        let mut reads = Vec::new();
+        // Children come first, so the branches of an ITE are parsed before the ITE itself.
+        for t in c.terms_postorder() {
+            match &t.op {
+                Op::Select => reads.push(t.clone()),
+                Op::Store => {
+                    base.insert(t.clone(), resolve(&t.cs[0]));
+                    writes.insert(
+                        t.clone(),
+                        vec![Write {
+                            idx: t.cs[1].clone(),
+                            val: t.cs[2].clone(),
+                            guard: bool_lit(true),
+                        }],
+                    );
                 }
-            }
-            if !is_c_store && !subsumed.contains(&t) {
-                if let Sort::Array(..) = check(&t) {
-                    for c in &t.cs {
-                        if let Sort::Array(..) = check(c) {
-                            arrs.insert(t.clone());
-                            ps.entry(c.clone()).or_insert_with(Vec::new).push(t.clone());
-                            cs.entry(t.clone()).or_insert_with(Vec::new).push(c.clone());
+                Op::Ite if is_array(&t) => {
+                    // Walk down a branch, through RAM terms with just one parent.
+                    let branch = |mut a: Term| {
+                        let mut interior = Vec::new();
+                        while let Some(b) = base.get(&a) {
+                            if term_parents.get(&a).unwrap().len() != 1 {
+                                break;
+                            }
+                            interior.push(a.clone());
+                            a = b.clone();
+                        }
+                        (a, interior)
+                    };
+                    let (t_base, t_interior) = branch(t.cs[1].clone());
+                    let (f_base, f_interior) = branch(t.cs[2].clone());
+                    if t.cs[1] != t.cs[2] && t_base == f_base {
+                        debug!("RAM ite: {}", Letified(t.clone()));
+                        let c = &t.cs[0];
+                        let not_c = term![NOT; c.clone()];
+                        let mut ws = Vec::new();
+                        for (cond, interior) in &[(c, &t_interior), (&not_c, &f_interior)] {
+                            for a in interior.iter().rev() {
+                                ws.extend(writes.get(a).unwrap().iter().map(|w| Write {
+                                    guard: guard_and((*cond).clone(), &w.guard),
+                                    ..w.clone()
+                                }));
+                            }
                         }
+                        subsumed.extend(t_interior);
+                        subsumed.extend(f_interior);
+                        base.insert(t.clone(), t_base);
+                        writes.insert(t.clone(), ws);
+                    } else {
+                        escaped.extend(t.cs[1..].iter().map(resolve));
                     }
                 }
+                _ if is_array(&t) => {
+                    escaped.extend(t.cs.iter().filter(|c| is_array(c)).map(resolve));
+                }
+                _ => {}
+            }
+        }
+        let mut ps: TermMap<Vec<Term>> = TermMap::default();
+        for (t, b) in base.iter() {
+            if !subsumed.contains(t) {
+                ps.entry(b.clone()).or_insert_with(Vec::new).push(t.clone());
             }
         }
+        let is_node = |t: &Term| base.contains_key(t) && !subsumed.contains(t);
         let mut non_ram: TermSet = TermSet::default();
         {
-            let mut stack: Vec<Term> = arrs
-                .iter()
+            let mut stack: Vec<Term> = base
+                .keys()
                 .filter(|a| {
-                    !a.is_const()
-                        && (ps.get(a).map(|ps| ps.len() > 1).unwrap_or(false)
-                            || cs.get(a).map(|cs| cs.len() > 1).unwrap_or(false))
+                    is_node(a)
+                        && (escaped.contains(a)
+                            || ps.get(a).map(|ps| ps.len() > 1).unwrap_or(false))
                 })
                 .cloned()
                 .collect();
             while let Some(t) = stack.pop() {
-                if !t.is_const() && !non_ram.contains(&t) {
+                if is_node(&t) && !non_ram.contains(&t) {
                     non_ram.insert(t.clone());
                     for t in ps.get(&t).into_iter().flatten() {
                         stack.push(t.clone());
                     }
-                    for t in cs.get(&t).into_iter().flatten() {
-                        stack.push(t.clone());
-                    }
+                    stack.push(base.get(&t).unwrap().clone());
                 }
             }
         }
+        // Roots that are read at some non-constant index are worth a RAM of their own. We skip
+        // roots that are themselves read from some other array.
+        let mut read_roots = TermSet::default();
+        read_roots.extend(
+            reads
+                .iter()
+                .filter(|r| !r.cs[1].is_const())
+                .map(|r| resolve(&r.cs[0]))
+                .filter(|a| !base.contains_key(a) && a.op != Op::Select),
+        );
         Self {
-            children: cs,
-            array_terms: arrs,
+            base,
+            writes,
+            subsumed,
             non_ram,
+            read_roots,
         }
     }
     fn is_ram(&self, t: &Term) -> bool {
-        !t.is_const() && self.array_terms.contains(t) && !self.non_ram.contains(t)
+        self.base.contains_key(t) && !self.subsumed.contains(t) && !self.non_ram.contains(t)
     }
     fn is_ram_read(&self, t: &Term) -> bool {
-        match t.op {
-            Op::Field(idx) => {
-                // recursively check if the field of t is a ram
-                self.is_ram_read(&t.cs[0].get().cs[idx])
-            }
-            _ => !t.is_const() && self.array_terms.contains(t) && !self.non_ram.contains(t),
-        }
+        let a = resolve(&t.cs[0]);
+        self.is_ram(&a) || self.read_roots.contains(&a)
     }
 }
 
 #[derive(Debug)]
 struct Extactor {
     rams: Vec<Ram>,
-    term_ram: TermMap<RamId>,
+    /// Map from arrays to their RAM, and the number of writes to it so far
+    term_ram: TermMap<(RamId, usize)>,
     read_terms: TermMap<Term>,
     graph: ArrayGraph,
 }
@@ -394,19 +393,27 @@ impl Extactor {
             graph,
         }
     }
-    // If this term is a constant array, start a new RAM. Otherwise, look this term up.
-    fn get_or_start(&mut self, t: &Term) -> RamId {
-        match &t.op {
-            Op::Const(Value::Array(a)) => {
-                let id = self.rams.len();
-                self.rams.push(Ram::new(id, a.clone()));
-                id
-            }
-            Op::Field(idx) => self.get_or_start(&t.cs[0].get().cs[*idx]),
-            _ => *self
-                .term_ram
-                .get(t)
-                .unwrap_or_else(|| panic!("No RAM for term {}", extras::Letified(t.clone()))),
+
+    // Start a new RAM, whose initial contents are `init`.
+    fn start(&mut self, init: &Term) -> RamId {
+        let id = self.rams.len();
+        self.rams.push(Ram::new(id, init.clone()));
+        id
+    }
+
+    // If this term is a root, start a new RAM for reads from it. Otherwise, look this term up.
+    fn get_or_start(&mut self, t: &Term) -> (RamId, usize) {
+        if let Some(ram) = self.term_ram.get(t) {
+            *ram
+        } else {
+            assert!(
+                self.graph.read_roots.contains(t),
+                "No RAM for term {}",
+                Letified(t.clone())
+            );
+            let ram = (self.start(t), 0);
+            self.term_ram.insert(t.clone(), ram);
+            ram
         }
     }
 
@@ -417,6 +424,8 @@ impl Extactor {
         for ram in &mut rams {
             for access in &mut ram.accesses {
                 access.idx = self.rewrite_index_term(access.idx.clone(), &mut cache);
+                access.val = self.rewrite_index_term(access.val.clone(), &mut cache);
+                access.is_write = self.rewrite_index_term(access.is_write.clone(), &mut cache);
             }
         }
         self.rams = rams;
@@ -425,8 +434,8 @@ impl Extactor {
     /// Rewrite a single index term recursivly. Uses the cache if we have already
     /// seen this term before
     fn rewrite_index_term(&self, t: Term, cache: &mut TermMap<Term>) -> Term {
-        if self.read_terms.contains_key(&t) {
-            return self.read_terms.get(&t).unwrap().clone();
+        if let Some(r) = self.read_terms.get(&t).or_else(|| cache.get(&t)) {
+            return r.clone();
         }
 
         // rewrite all children
@@ -445,46 +454,33 @@ impl RewritePass for Extactor {
         &mut self,
         computation: &mut Computation,
         t: &Term,
-        rewritten_children: F,
+        _rewritten_children: F,
     ) -> Option<Term> {
         // First, we rewrite RAM terms.
         if self.graph.is_ram(t) {
-            // Since this is a "RAM" term, it is non-constant and either a c-store or a store.
-            assert!(matches!(t.op, Op::Store | Op::Ite));
-            debug_assert!(self.graph.children.get(t).is_some());
-            debug_assert_eq!(1, self.graph.children.get(t).unwrap().len());
-            // Get dependency's RAM
-            let child = self.graph.children.get(t).unwrap()[0].clone();
-            let ram_id = self.get_or_start(&child);
+            // Get dependency's RAM. Each chain of writes from a root is a new RAM.
+            let base = self.graph.base.get(t).unwrap().clone();
+            let (ram_id, mut version) = if self.graph.is_ram(&base) {
+                *self.term_ram.get(&base).unwrap()
+            } else {
+                (self.start(&base), 0)
+            };
+            // Add the writes to the RAM. Their terms are rewritten later.
             let ram = &mut self.rams[ram_id];
-            // Build new term, and parse as a c-store
-            let new = term(t.op.clone(), rewritten_children());
-            let c_store = parse_cond_store(&new).unwrap_or_else(|| {
-                // this is a plain store then, map it to a c-store
-                assert_eq!(Op::Store, new.op);
-                ConditionalStore {
-                    arr: new.cs[0].clone(),
-                    idx: new.cs[1].clone(),
-                    val: new.cs[2].clone(),
-                    guard: bool_lit(true),
-                    store: bool_lit(false), // dummy; unused.
-                }
-            });
-            // Add the write to the RAM
-            ram.new_write(c_store.idx, c_store.val, c_store.guard);
-            let id = ram.id;
-            self.term_ram.insert(t.clone(), id);
+            for w in self.graph.writes.get(t).unwrap() {
+                version += 1;
+                ram.new_write(w.idx.clone(), w.val.clone(), w.guard.clone(), 2 * version);
+            }
+            self.term_ram.insert(t.clone(), (ram_id, version));
             None
         } else {
             match &t.op {
                 // Rewrite select's whose array is a RAM term
-                Op::Select if self.graph.is_ram_read(&t.cs[0]) => {
-                    if let Op::Field(_) = t.op {
-                        println!("Field op in ram!: {:?}", t);
-                    }
-                    let ram_id = self.get_or_start(&t.cs[0]);
+                Op::Select if self.graph.is_ram_read(t) => {
+                    let (ram_id, version) = self.get_or_start(&resolve(&t.cs[0]));
                     let ram = &mut self.rams[ram_id];
-                    let read_value = ram.new_read(t.cs[1].clone(), computation, t.clone());
+                    let read_value =
+                        ram.new_read(t.cs[1].clone(), computation, t.clone(), 2 * version + 1);
                     self.read_terms.insert(t.clone(), read_value.clone());
                     Some(read_value)
                 }
@@ -494,66 +490,185 @@ impl RewritePass for Extactor {
     }
 }
 
+/// An entry in a RAM transcript
+struct Entry {
+    time: Term,
+    idx: Term,
+    val: Term,
+    is_write: Term,
+}
+
+impl Entry {
+    fn hash(&self, beta: &Term) -> Term {
+        universal_hash(
+            vec![
+                self.time.clone(),
+                self.idx.clone(),
+                self.val.clone(),
+                self.is_write.clone(),
+            ],
+            beta,
+        )
+    }
+}
+
 struct Encoder {
     rams: Vec<Ram>,
+    field: FieldT,
 }
 
 impl Encoder {
-    fn new(rams: Vec<Ram>) -> Encoder {
-        Encoder { rams }
+    fn new(rams: Vec<Ram>, field: FieldT) -> Encoder {
+        Encoder { rams, field }
     }
 
-    fn construct_permutation_check(ram1: &Ram, ram2: &Ram, alpha: Term, beta: Term) -> Term {
-        // TODO: support non-field indices?
-        //       Just do a cast on permuation check, and have correct type
-        //       in sorted check
-
-        // construct a term to check the __ram_srow values are the same as the
-        // __ram values
-        let orig_ms_hash = multiset_hash(
-            ram1.accesses
-                .iter()
-                .map(|access| universal_hash(vec![access.idx.clone(), access.val.clone()], &beta)),
-            &alpha,
-        );
+    /// The transcript of `ram`, in time order: a write to each cell (at time 0) of its initial
+    /// value, and then the accesses.
+    ///
+    /// A write whose guard is false does not change the RAM. We regard it as a read; so, its value
+    /// in the transcript is the value that it would have replaced.
+    fn transcript(ram: &Ram, computation: &mut Computation, time_width: usize) -> Vec<Entry> {
+        let time = |t: usize| bv_lit(t, time_width);
+        let mut entries: Vec<Entry> = ram
+            .idx_sort
+            .elems_iter_values()
+            .take(ram.size)
+            .map(|k| Entry {
+                time: time(0),
+                val: match &ram.init.op {
+                    Op::Const(Value::Array(a)) => leaf_term(Op::Const(a.select(&k))),
+                    _ => term![Op::Select; ram.init.clone(), leaf_term(Op::Const(k.clone()))],
+                },
+                idx: leaf_term(Op::Const(k)),
+                is_write: bool_lit(true),
+            })
+            .collect();
+        // The RAM's contents, for precomputing the values that conditional writes replace.
+        let mut state = ram.init.clone();
+        for (i, access) in ram.accesses.iter().enumerate() {
+            let mut val = access.val.clone();
+            if access.is_write != bool_lit(false) {
+                let store = term![Op::Store; state.clone(), access.idx.clone(), access.val.clone()];
+                if access.is_write != bool_lit(true) {
+                    let old = computation.new_var(
+                        &format!("__ram_{}_{}.old", ram.id, i),
+                        ram.val_sort.clone(),
+                        Some(crate::ir::proof::PROVER_ID),
+                        0,
+                        false,
+                        Some(term![Op::Select; state.clone(), access.idx.clone()]),
+                    );
+                    val = term![ITE; access.is_write.clone(), val, old];
+                    state = term![ITE; access.is_write.clone(), store, state];
+                } else {
+                    state = store;
+                }
+            }
+            entries.push(Entry {
+                time: time(access.time),
+                idx: access.idx.clone(),
+                val,
+                is_write: access.is_write.clone(),
+            });
+        }
+        entries
+    }
 
-        let perm_ms_hash = multiset_hash(
-            ram2.accesses
-                .iter()
-                .map(|access| universal_hash(vec![access.idx.clone(), access.val.clone()], &beta)),
-            &alpha,
-        );
+    /// The transcript `entries`, sorted by index and then by time.
+    fn sorted(
+        ram: &Ram,
+        entries: &[Entry],
+        computation: &mut Computation,
+        time_width: usize,
+    ) -> Vec<Entry> {
+        let pos_width = bits(entries.len() - 1);
+        let pos_sort = Sort::BitVector(pos_width);
+        // we sort by (idx, time, position) to get each entry's position in the transcript
+        let keys: Vec<Term> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| {
+                let idx = match &ram.idx_sort {
+                    Sort::Field(_) => term![Op::PfToBv(bits(ram.size - 1)); e.idx.clone()],
+                    Sort::BitVector(_) => e.idx.clone(),
+                    s => panic!("Cannot use RAM with {} indices", s),
+                };
+                term![BV_CONCAT; idx, e.time.clone(), bv_lit(i, pos_width)]
+            })
+            .collect();
+        let by_pos = |sort: Sort, f: &dyn Fn(&Entry) -> Term| {
+            make_array(pos_sort.clone(), sort, entries.iter().map(f).collect())
+        };
+        let idxs = by_pos(ram.idx_sort.clone(), &|e| e.idx.clone());
+        let vals = by_pos(ram.val_sort.clone(), &|e| e.val.clone());
+        let times = by_pos(Sort::BitVector(time_width), &|e| e.time.clone());
+        let is_writes = by_pos(Sort::Bool, &|e| e.is_write.clone());
+        let mut sorted_entries = Vec::new();
+        for i in 0..entries.len() {
+            // create an input for each field
+            let pos =
+                term![Op::BvExtract(pos_width - 1, 0); term(Op::NthSmallest(i), keys.clone())];
+            let mut field = |name: &str, array: &Term| {
+                let elem = term![Op::Select; array.clone(), pos.clone()];
+                computation.new_var(
+                    &format!("__ram_srow_{}_{}.{}", ram.id, i, name),
+                    check(&elem),
+                    Some(crate::ir::proof::PROVER_ID),
+                    0,
+                    false,
+                    Some(elem),
+                )
+            };
+            sorted_entries.push(Entry {
+                time: field("time", &times),
+                idx: field("idx", &idxs),
+                val: field("val", &vals),
+                is_write: field("is_write", &is_writes),
+            });
+        }
+        sorted_entries
+    }
 
+    fn construct_permutation_check(
+        entries: &[Entry],
+        sorted_entries: &[Entry],
+        alpha: &Term,
+        beta: &Term,
+    ) -> Term {
+        // construct a term to check the __ram_srow values are a permutation of the transcript
+        let orig_ms_hash = multiset_hash(entries.iter().map(|e| e.hash(beta)), alpha);
+        let perm_ms_hash = multiset_hash(sorted_entries.iter().map(|e| e.hash(beta)), alpha);
         term![EQ; orig_ms_hash, perm_ms_hash]
     }
 
-    fn construct_sorted_check(sorted_ram: &Ram) -> Term {
-        // TODO: support non-field indices?
-        //       Just do a cast on permuation check, and have correct type
-        //       in sorted check
-        let idx_field = match &sorted_ram.idx_sort {
-            Sort::Field(field) => field,
-            _ => panic!("Cannot use RAM on non-field index type!"),
+    fn construct_sorted_check(sorted_entries: &[Entry], idx_sort: &Sort) -> Term {
+        let (add, one) = match idx_sort {
+            Sort::Field(field) => (PF_ADD, pf_lit(field.new_v(1))),
+            Sort::BitVector(width) => (BV_ADD, bv_lit(1, *width)),
+            s => panic!("Cannot use RAM with {} indices", s),
+        };
+        let time_zero = match check(&sorted_entries[0].time) {
+            Sort::BitVector(width) => bv_lit(0, width),
+            _ => unreachable!(),
         };
 
-        let mut check_terms = Vec::new();
-        for i in 1..sorted_ram.accesses.len() {
-            // if idx == idx' then v == v' else idx == idx' + 1
+        // The first access to each cell is its initialization.
+        let mut check_terms = vec![term![EQ; sorted_entries[0].time.clone(), time_zero.clone()]];
+        for pair in sorted_entries.windows(2) {
+            let (prev, next) = (&pair[0], &pair[1]);
+            let prev_idx_plus_one = term(add.clone(), vec![prev.idx.clone(), one.clone()]);
+            // if idx == idx' then time <= time' and (v == v' or the access is a write)
+            // else idx' == idx + 1 and time' == 0
             let check_term = term![
                 ITE;
-                term![
-                    EQ;
-                    sorted_ram.accesses[i].idx.clone(),
-                    sorted_ram.accesses[i - 1].idx.clone()
-                ],
-                term![
-                    Op::Eq;
-                    sorted_ram.accesses[i].val.clone(),
-                    sorted_ram.accesses[i - 1].val.clone()
+                term![EQ; next.idx.clone(), prev.idx.clone()],
+                term![AND;
+                    term![BV_ULE; prev.time.clone(), next.time.clone()],
+                    term![OR; next.is_write.clone(), term![EQ; next.val.clone(), prev.val.clone()]]
                 ],
-                term![Op::Eq;
-                    sorted_ram.accesses[i].idx.clone(),
-                    term![PF_ADD; sorted_ram.accesses[i-1].idx.clone(), pf_lit(idx_field.new_v::<u32>(1))]
+                term![AND;
+                    term![EQ; next.idx.clone(), prev_idx_plus_one],
+                    term![EQ; next.time.clone(), time_zero.clone()]
                 ]
             ];
             check_terms.push(check_term);
@@ -562,7 +677,7 @@ impl Encoder {
     }
 
     fn encode(&mut self, computation: &mut Computation) {
-        if self.rams.len() < 2 {
+        if self.rams.is_empty() {
             return;
         }
         assert_eq!(
@@ -576,54 +691,49 @@ impl Encoder {
             "Proofs using RAMs must have a boolean output!"
         );
 
-        // remove ram writes
-        // TODO: This ONLY works for read-only rams.
-        for ram in self.rams.iter_mut() {
-            while !ram.accesses.is_empty()
-                && eval(&ram.accesses[0].is_write, &fxhash::FxHashMap::default()).as_bool()
-            {
-                ram.accesses.remove(0);
+        for ram in &self.rams {
+            if let Sort::Field(f) = &ram.idx_sort {
+                assert_eq!(
+                    f, &self.field,
+                    "RAM {} is indexed by a field other than the target field",
+                    ram.id
+                );
             }
         }
+        let field = self.field.clone();
+        let mut transcripts = Vec::new();
+        for ram in self.rams.iter() {
+            let time_width = bits(2 * ram.writes() + 1);
+            let entries = Encoder::transcript(ram, computation, time_width);
+            let sorted_entries = Encoder::sorted(ram, &entries, computation, time_width);
+            transcripts.push((ram, entries, sorted_entries));
+        }
 
-        // remove rams that are empty
-        // TODO: maybe throw a warning here?
-        self.rams = self
-            .rams
-            .drain(0..)
-            .filter(|ram| !ram.accesses.is_empty())
-            .collect();
-
-        // TODO: actually check that the sorts for ram1 and ram2 are equal...
+        // The challenges are drawn once all transcripts (and their sorts) are known.
+        let epoch = transcripts
+            .iter()
+            .flat_map(|(_, entries, sorted)| entries.iter().chain(sorted))
+            .flat_map(|e| vec![&e.time, &e.idx, &e.val, &e.is_write])
+            .map(|t| computation.known_epoch(t))
+            .max()
+            .unwrap_or(0);
         // TODO: should this actually be verifier_id?
-        // TODO: assumes that idx_sort is a field
         let alpha = computation.new_var(
-            &format!("__alpha"),
-            self.rams[0].idx_sort.clone(),
+            "__alpha",
+            Sort::Field(field.clone()),
             None,
-            0, // TODO
-            true,
-            None,
-        );
-        let beta = computation.new_var(
-            &format!("__beta"),
-            self.rams[0].idx_sort.clone(),
-            None,
-            0, // TODO
+            epoch,
             true,
             None,
         );
+        let beta = computation.new_var("__beta", Sort::Field(field), None, epoch, true, None);
 
         let mut checks = vec![];
         checks.push(computation.outputs()[0].clone());
-        for ram in self.rams.iter() {
-            let sorted_ram = ram.sorted_by_index(computation);
-            // TODO: better error handling
-            // TODO: is there a cleaner way to get the field type?
-            let sorted_check = Encoder::construct_sorted_check(&sorted_ram);
-
+        for (ram, entries, sorted_entries) in transcripts {
+            let sorted_check = Encoder::construct_sorted_check(&sorted_entries, &ram.idx_sort);
             let permutation_check =
-                Encoder::construct_permutation_check(ram, &sorted_ram, alpha.clone(), beta.clone());
+                Encoder::construct_permutation_check(&entries, &sorted_entries, &alpha, &beta);
 
             checks.push(sorted_check);
             checks.push(permutation_check);
@@ -637,7 +747,10 @@ impl Encoder {
 ///   1. Replaces reads from these RAMs with new variables.
 ///   2. Builds a transcript for each RAM.
 ///
-/// A conditional store must have form (ite C (store A I V) I) to be detected.
+/// A RAM starts from any array: a constant, an input, or an array that is not itself a RAM. Its
+/// values may be tuples or arrays. Stores and conditional stores to a RAM are writes; so are the
+/// stores in the branches of an ITE over the same RAM (see [ArrayGraph]). An array that is only
+/// read is a RAM if it is read at some non-constant index.
 ///
 /// Limitations:
 /// * This pass doesn't handle shared stuff very well. If there are two
@@ -647,16 +760,9 @@ pub fn extract(c: &mut Computation) -> Vec<Ram> {
     let mut extractor = Extactor::new(c);
     extractor.traverse(c);
     extractor.rewrite_indices();
-    println!("found {:?} rams", extractor.rams.len());
+    debug!("found {} rams", extractor.rams.len());
     for ram in &extractor.rams {
-        println!("------------");
-        println!("ram id: {:?}, len: {:?}", ram.id, ram.accesses.len());
-        //for access in ram.accesses.iter() {
-        //    println!("{:?}", access);
-        //}
-        //println!("{:?}", ram.accesses[4]);
-        //println!("{:?}", ram.accesses[5]);
-        //println!("{:?}", ram.accesses[6]);
+        debug!("ram id: {}, len: {}", ram.id, ram.accesses.len());
     }
     extractor.rams
 }
@@ -664,16 +770,15 @@ pub fn extract(c: &mut Computation) -> Vec<Ram> {
 /// Encodes the given RAMs into the computation, by ensuring there exist
 /// a valid ordering of the RAM accesses
 ///
-/// NOTE: This is currently broken for any ram that:
-///       1. doesn't do a single write, then only reads
-///       2. doesn't access every single element in the ram
-pub fn encode(c: &mut Computation, rams: Vec<Ram>) {
-    //println!("Pre-opt terms: {}", c.outputs[0].get().count_terms());
-    //println!("got {}", c.outputs[0].get());
-    let mut encoder = Encoder::new(rams);
+/// The transcript of each RAM (its initialization, then its accesses) is permuted into a sorted
+/// transcript: ordered by index, and then by time. The permutation is checked with a multiset
+/// hash at random points, and the sorted transcript is checked to be consistent: each cell is
+/// initialized first, and each read agrees with the access before it.
+///
+/// The hashes are over `field`, which should be the field of the target proof system.
+pub fn encode(c: &mut Computation, rams: Vec<Ram>, field: &FieldT) {
+    let mut encoder = Encoder::new(rams, field.clone());
     encoder.encode(c);
-    //println!("got {}", c.outputs[0].get());
-    //println!("Opt-done terms: {}", c.outputs[0].get().count_terms());
 }
 
 #[cfg(test)]
@@ -692,7 +797,7 @@ mod test {
                         (store_1 (store c_array #x0 #x1))
                         (store_2 (store c_array #x0 #x2))
                     )
-                    (select (ite true store_1 store_2) #x0)
+                    (bvand (select store_1 #x1) (select (ite true store_1 store_2) #x0))
                 )
             )
         ",
@@ -882,7 +987,175 @@ mod test {
         cs.outputs.push(select);
         let mut cs2 = cs.clone();
         let rams = extract(&mut cs2);
-        assert_eq!(0, rams.len());
-        assert_eq!(cs, cs2);
+        assert_eq!(1, rams.len());
+        assert!(matches!(rams[0].val_sort, Sort::Array(..)));
+        assert_eq!(2, rams[0].accesses.len());
+        assert_eq!(2, rams[0].accesses[0].time);
+        assert_eq!(3, rams[0].accesses[1].time);
+        // the inner select is of the value read
+        assert!(cs2.outputs[0].cs[0].is_var());
+    }
+
+    #[test]
+    fn ite_of_stores() {
+        let a = leaf_term(Op::Var("a".to_string(), Sort::Bool));
+        let cs = text::parse_computation(
+            b"
+            (computation
+                (metadata () ((a bool)) ())
+                (let
+                    (
+                        (c_array (#a (bv 4) #b000 4 ()))
+                        (store_1 (store c_array #x0 #b001))
+                        (store_2 (store c_array #x1 #b010))
+                    )
+                    (select (ite a store_1 store_2) #x0)
+                )
+            )
+        ",
+        );
+        let mut cs2 = cs.clone();
+        let rams = extract(&mut cs2);
+        extras::assert_all_vars_declared(&cs2);
+        assert_eq!(1, rams.len());
+        assert_eq!(3, rams[0].accesses.len());
+        assert_eq!(a, rams[0].accesses[0].is_write);
+        assert_eq!(term![NOT; a], rams[0].accesses[1].is_write);
+        assert_eq!(bool_lit(false), rams[0].accesses[2].is_write);
+        assert_eq!(bv_lit(0, 4), rams[0].accesses[0].idx);
+        assert_eq!(bv_lit(1, 4), rams[0].accesses[1].idx);
+        assert_eq!(
+            vec![2, 4, 5],
+            rams[0].accesses.iter().map(|a| a.time).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn input_and_partial_const() {
+        let cs = text::parse_computation(
+            b"
+            (computation
+                (metadata () ((A (array (bv 4) (bv 3) 4)) (i (bv 4))) ())
+                (let
+                    (
+                        (c_array (#a (bv 4) #b000 4 ((#x1 #b011))))
+                        (store_1 (store c_array i #b001))
+                    )
+                    (= (select A i) (select store_1 #x1))
+                )
+            )
+        ",
+        );
+        let mut cs2 = cs.clone();
+        let rams = extract(&mut cs2);
+        extras::assert_all_vars_declared(&cs2);
+        assert_eq!(2, rams.len());
+        let input_ram = rams.iter().find(|r| r.init.is_var()).unwrap();
+        assert_eq!(1, input_ram.accesses.len());
+        assert_eq!(1, input_ram.accesses[0].time);
+        let const_ram = rams.iter().find(|r| r.init.is_const()).unwrap();
+        assert_eq!(2, const_ram.accesses.len());
+        assert_eq!(
+            vec![2, 3],
+            const_ram
+                .accesses
+                .iter()
+                .map(|a| a.time)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn tuple_values() {
+        let cs = text::parse_computation(
+            b"
+            (computation
+                (metadata () ((i (bv 4)) (a bool)) ())
+                (let
+                    (
+                        (c_array (#a (bv 4) (#t #b000 false) 4 ()))
+                        (store_1 (ite a (store c_array #x0 (tuple #b001 true)) c_array))
+                    )
+                    ((field 1) (select store_1 i))
+                )
+            )
+        ",
+        );
+        let mut cs2 = cs.clone();
+        let rams = extract(&mut cs2);
+        extras::assert_all_vars_declared(&cs2);
+        assert_eq!(1, rams.len());
+        assert_eq!(
+            Sort::Tuple(vec![Sort::BitVector(3), Sort::Bool].into_boxed_slice()),
+            rams[0].val_sort
+        );
+        assert_eq!(2, rams[0].accesses.len());
+    }
+
+    fn bv_val(u: usize, w: usize) -> Value {
+        Value::BitVector(BitVector::new(rug::Integer::from(u), w))
+    }
+
+    #[test]
+    fn encode_checks_accesses() {
+        let cs = text::parse_computation(
+            b"
+            (computation
+                (metadata () ((a bool) (i (bv 2)) (j (bv 2)) (v (bv 4)) (x (bv 4)) (y (bv 4))) ())
+                (let
+                    (
+                        (c_array (#a (bv 2) #x0 4 ((#b01 #x3))))
+                        (store_1 (ite a (store c_array i v) c_array))
+                    )
+                    (and (= (select store_1 j) x) (= (select c_array i) y))
+                )
+            )
+        ",
+        );
+        let mut cs2 = cs.clone();
+        let rams = extract(&mut cs2);
+        assert_eq!(2, rams.len());
+        let reads: Vec<String> = rams
+            .iter()
+            .flat_map(|r| &r.accesses)
+            .filter(|a| a.is_write == bool_lit(false))
+            .map(|a| match &a.val.op {
+                Op::Var(name, _) => name.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        let field = FieldT::from(rug::Integer::from(1_000_000_007));
+        encode(&mut cs2, rams, &field);
+        extras::assert_all_vars_declared(&cs2);
+        for (a, i, j, v, x, y) in &[
+            (true, 1, 1, 5, 5, 3),
+            (false, 1, 1, 5, 3, 3),
+            (true, 2, 1, 5, 3, 0),
+            (true, 0, 0, 9, 9, 0),
+        ] {
+            let inputs: fxhash::FxHashMap<String, Value> = vec![
+                ("a".to_owned(), Value::Bool(*a)),
+                ("i".to_owned(), bv_val(*i, 2)),
+                ("j".to_owned(), bv_val(*j, 2)),
+                ("v".to_owned(), bv_val(*v, 4)),
+                ("x".to_owned(), bv_val(*x, 4)),
+                ("y".to_owned(), bv_val(*y, 4)),
+                ("__alpha".to_owned(), Value::Field(field.new_v(1234567))),
+                ("__beta".to_owned(), Value::Field(field.new_v(7654321))),
+            ]
+            .into_iter()
+            .collect();
+            assert_eq!(eval(&cs.outputs[0], &inputs), Value::Bool(true));
+            let mut values = cs2.precomputes.eval(&inputs);
+            assert_eq!(eval(&cs2.outputs[0], &values), Value::Bool(true));
+            // A read of the wrong value is caught by the RAM checks.
+            let ram_checks = term(AND, cs2.outputs[0].cs[1..].to_vec());
+            for read in &reads {
+                let right = values.get(read).unwrap().clone();
+                values.insert(read.clone(), bv_val(15, 4));
+                assert_eq!(eval(&ram_checks, &values), Value::Bool(false));
+                values.insert(read.clone(), right);
+            }
+        }
     }
 }
@@ -1,19 +1,31 @@
 //! Optimizations
 pub mod binarize;
 pub mod cfold;
+pub mod cse;
 pub mod flat;
+pub mod fp;
 pub mod inline;
 pub mod mem;
 pub mod scalarize_vars;
 pub mod sha;
+pub mod shrink_bv;
+pub mod stats;
 pub mod tuple;
+pub mod validate;
 mod visit;
 
 use super::term::*;
+use crate::util::field::DFL_T;
+use circ_fields::FieldT;
 
 use log::debug;
+use stats::{CsStats, OptReport};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::time::Instant;
+use thiserror::Error;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 /// An optimization pass
 pub enum Opt {
     /// Convert non-scalar (tuple, array) inputs to scalar ones
@@ -25,8 +37,12 @@ pub enum Opt {
     Flatten,
     /// Binarize n-ary operators
     Binarize,
+    /// Canonicalize commutative, associative n-ary operators, and group their shared arguments
+    Cse,
     /// SHA-2 peephole optimizations
     Sha,
+    /// Narrow bit-vector comparisons and arithmetic, using range and known-bits analysis
+    ShrinkBv,
     /// Replace oblivious arrays with tuples
     Obliv,
     /// Replace arrays with linear scans
@@ -37,14 +53,206 @@ pub enum Opt {
     Inline,
     /// Eliminate tuples
     Tuple,
-    /// Ram extraction
-    RamExt,
+    /// Ram extraction; the memory checks hash over the given (target) field
+    RamExt(FieldT),
+    /// Replace floating-point terms with soft-float circuits over their bits
+    Fp,
+}
+
+/// Operators that may appear in the ignore-list of a constant-folding pass spec.
+fn foldable_ops() -> Vec<Op> {
+    vec![
+        BV_ADD, BV_MUL, BV_AND, BV_OR, BV_XOR, BV_SUB, BV_UDIV, BV_UREM, BV_SHL, BV_LSHR, BV_ASHR,
+        BV_NEG, BV_NOT, BV_ULT, BV_UGT, BV_ULE, BV_UGE, BV_SLT, BV_SGT, BV_SLE, BV_SGE, AND, OR,
+        XOR, NOT, IMPLIES, EQ, ITE, PF_ADD, PF_MUL, PF_NEG, PF_RECIP,
+    ]
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+/// An error in a textual optimization pass spec
+pub enum OptParseError {
+    #[error("Unknown optimization '{0}'")]
+    /// No pass has this name
+    UnknownOpt(String),
+    #[error("Unknown operator '{0}' in the ignore list of '{1}'")]
+    /// An operator in a constant-folding ignore list was not recognized
+    UnknownOp(String, String),
+    #[error("Optimization '{0}' does not take arguments")]
+    /// Arguments were given to a pass that takes none
+    UnexpectedArgs(String),
+    #[error("Bad field modulus '{0}' in '{1}'")]
+    /// The argument of a RAM extraction pass is not a modulus
+    BadModulus(String, String),
+    #[error("Malformed optimization '{0}'")]
+    /// Bad parenthesization
+    Malformed(String),
+}
+
+impl FromStr for Opt {
+    type Err = OptParseError;
+    /// Parse a single pass. See [parse_opts] for the syntax.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, args) = match s.find('(') {
+            Some(i) => {
+                let args = s[i + 1..]
+                    .strip_suffix(')')
+                    .ok_or_else(|| OptParseError::Malformed(s.to_owned()))?;
+                (s[..i].trim(), Some(args))
+            }
+            None => (s, None),
+        };
+        if let Some(args) = args {
+            return if name == "cfold" {
+                let ops = foldable_ops();
+                args.split_whitespace()
+                    .map(|a| {
+                        ops.iter()
+                            .find(|o| format!("{}", o) == a)
+                            .cloned()
+                            .ok_or_else(|| OptParseError::UnknownOp(a.to_owned(), s.to_owned()))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|ops| Opt::ConstantFold(ops.into_boxed_slice()))
+            } else if name == "ramext" {
+                args.trim()
+                    .parse::<rug::Integer>()
+                    .map(|m| Opt::RamExt(FieldT::from(m)))
+                    .map_err(|_| OptParseError::BadModulus(args.to_owned(), s.to_owned()))
+            } else {
+                Err(OptParseError::UnexpectedArgs(s.to_owned()))
+            };
+        }
+        Ok(match name {
+            "scalarize" => Opt::ScalarizeVars,
+            "cfold" => Opt::ConstantFold(Box::new([])),
+            "flatten" => Opt::Flatten,
+            "binarize" => Opt::Binarize,
+            "cse" => Opt::Cse,
+            "sha" => Opt::Sha,
+            "shrinkbv" => Opt::ShrinkBv,
+            "obliv" => Opt::Obliv,
+            "linscan" => Opt::LinearScan,
+            "flattenassertions" => Opt::FlattenAssertions,
+            "inline" => Opt::Inline,
+            "tuple" => Opt::Tuple,
+            "ramext" => Opt::RamExt(DFL_T.clone()),
+            "fp" => Opt::Fp,
+            _ => return Err(OptParseError::UnknownOpt(s.to_owned())),
+        })
+    }
+}
+
+impl Display for Opt {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Opt::ScalarizeVars => write!(f, "scalarize"),
+            Opt::ConstantFold(ignore) => {
+                write!(f, "cfold")?;
+                if !ignore.is_empty() {
+                    write!(f, "(")?;
+                    for (i, o) in ignore.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, " ")?;
+                        }
+                        write!(f, "{}", o)?;
+                    }
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+            Opt::Flatten => write!(f, "flatten"),
+            Opt::Binarize => write!(f, "binarize"),
+            Opt::Cse => write!(f, "cse"),
+            Opt::Sha => write!(f, "sha"),
+            Opt::ShrinkBv => write!(f, "shrinkbv"),
+            Opt::Obliv => write!(f, "obliv"),
+            Opt::LinearScan => write!(f, "linscan"),
+            Opt::FlattenAssertions => write!(f, "flattenassertions"),
+            Opt::Inline => write!(f, "inline"),
+            Opt::Tuple => write!(f, "tuple"),
+            Opt::RamExt(field) if field.modulus() == DFL_T.modulus() => write!(f, "ramext"),
+            Opt::RamExt(field) => write!(f, "ramext({})", field.modulus()),
+            Opt::Fp => write!(f, "fp"),
+        }
+    }
+}
+
+/// Parse a pass spec: a list of passes, separated by commas or newlines.
+///
+/// Each pass is named by its [Display] form (e.g., `ramext`, `scalarize`, `linscan`). A
+/// constant-folding pass may list operators to leave unfolded: `cfold(bvshl bvlshr)`. A RAM
+/// extraction pass may give the modulus of its field, `ramext(<modulus>)`; the default field is
+/// used otherwise.
+///
+/// Everything after a `#` on a line is a comment, so a spec may be kept in a file.
+pub fn parse_opts(spec: &str) -> Result<Vec<Opt>, OptParseError> {
+    spec.lines()
+        .map(|l| l.split('#').next().unwrap())
+        .flat_map(|l| l.split(','))
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(Opt::from_str)
+        .collect()
+}
+
+/// Render a list of passes as a spec that [parse_opts] accepts.
+pub fn opts_to_spec(opts: &[Opt]) -> String {
+    opts.iter()
+        .map(|o| format!("{}", o))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Run the optimizations described by the pass spec `spec` (see [parse_opts]) on `cs`.
+pub fn opt_from_spec(cs: Computation, spec: &str) -> Result<Computation, OptParseError> {
+    Ok(opt(cs, parse_opts(spec)?))
 }
 
 /// Run optimizations on `cs`, in this order, returning the new constraint system.
-pub fn opt<I: IntoIterator<Item = Opt>>(mut cs: Computation, optimizations: I) -> Computation {
-    for i in optimizations {
+pub fn opt<I: IntoIterator<Item = Opt>>(cs: Computation, optimizations: I) -> Computation {
+    opt_inner(cs, optimizations, None, None).unwrap()
+}
+
+/// Run optimizations on `cs`, in this order, returning the new constraint system and statistics
+/// for each pass.
+///
+/// Measuring the computation between passes costs a traversal per pass.
+pub fn opt_with_report<I: IntoIterator<Item = Opt>>(
+    cs: Computation,
+    optimizations: I,
+) -> (Computation, OptReport) {
+    let mut report = OptReport::default();
+    let cs = opt_inner(cs, optimizations, Some(&mut report), None).unwrap();
+    (cs, report)
+}
+
+/// Run optimizations on `cs`, in this order, checking after each one that the computation's
+/// meaning is unchanged (see [validate]).
+///
+/// Returns the first pass that changes semantics, if any. If `report` is given, statistics for
+/// each pass are recorded in it.
+pub fn opt_validated<I: IntoIterator<Item = Opt>>(
+    cs: Computation,
+    optimizations: I,
+    method: validate::Method,
+    report: Option<&mut OptReport>,
+) -> Result<Computation, validate::Miscompile> {
+    opt_inner(cs, optimizations, report, Some(method))
+}
+
+fn opt_inner<I: IntoIterator<Item = Opt>>(
+    mut cs: Computation,
+    optimizations: I,
+    mut report: Option<&mut OptReport>,
+    validation: Option<validate::Method>,
+) -> Result<Computation, validate::Miscompile> {
+    for (idx, i) in optimizations.into_iter().enumerate() {
         debug!("Applying: {:?}", i);
+        let before = report.as_ref().map(|_| CsStats::of(&cs));
+        // only the outputs are needed to validate, and they are cheap to clone
+        let original = validation.map(|_| cs.outputs.clone());
+        let start = Instant::now();
         match i.clone() {
             Opt::ScalarizeVars => {
                 scalarize_vars::scalarize_inputs(&mut cs);
@@ -66,6 +274,9 @@ pub fn opt<I: IntoIterator<Item = Opt>>(mut cs: Computation, optimizations: I) -
                     *a = sha::sha_rewrites(a);
                 }
             }
+            Opt::ShrinkBv => {
+                shrink_bv::shrink_bv(&mut cs);
+            }
             Opt::Obliv => {
                 mem::obliv::elim_obliv(&mut cs);
             }
@@ -96,6 +307,9 @@ pub fn opt<I: IntoIterator<Item = Opt>>(mut cs: Computation, optimizations: I) -
                     *a = binarize::binarize_nary_ops_cached(a.clone(), &mut cache);
                 }
             }
+            Opt::Cse => {
+                cse::group_common_subexprs(&mut cs);
+            }
             Opt::Inline => {
                 let public_inputs = cs
                     .metadata
@@ -107,15 +321,114 @@ pub fn opt<I: IntoIterator<Item = Opt>>(mut cs: Computation, optimizations: I) -
             Opt::Tuple => {
                 tuple::eliminate_tuples(&mut cs);
             }
-            Opt::RamExt => {
+            Opt::RamExt(field) => {
                 let rams = mem::ram::extract(&mut cs);
-                mem::ram::encode(&mut cs, rams);
+                mem::ram::encode(&mut cs, rams, &field);
+            }
+            Opt::Fp => {
+                fp::lower_fp(&mut cs);
             }
         }
+        // front-ends that don't record locations don't pay for re-attributing them
+        if !cs.metadata.src_locs.is_empty() {
+            cs.metadata.src_locs.update(&cs.outputs);
+        }
+        if let Some(r) = report.as_mut() {
+            let time = start.elapsed();
+            r.push(&i, time, before.unwrap(), CsStats::of(&cs));
+        }
+        if let (Some(method), Some(original)) = (validation, original) {
+            validate::validate(idx, &i, &original, &cs, method)?;
+        }
         debug!("After {:?}: {} outputs", i, cs.outputs.len());
         //debug!("After {:?}: {}", i, Letified(cs.outputs[0].clone()));
         debug!("After {:?}: {} terms", i, cs.terms());
     }
     garbage_collect();
-    cs
+    Ok(cs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_spec() {
+        let opts =
+            parse_opts("ramext,scalarize,flatten,sha,cfold,inline,tuple,obliv,linscan").unwrap();
+        assert_eq!(
+            opts,
+            vec![
+                Opt::RamExt(DFL_T.clone()),
+                Opt::ScalarizeVars,
+                Opt::Flatten,
+                Opt::Sha,
+                Opt::ConstantFold(Box::new([])),
+                Opt::Inline,
+                Opt::Tuple,
+                Opt::Obliv,
+                Opt::LinearScan,
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_spec_file() {
+        let opts = parse_opts(
+            "
+            # mpc pipeline
+            scalarize, flatten
+            cfold(bvlshr bvshl) # keep shifts
+            binarize
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            opts,
+            vec![
+                Opt::ScalarizeVars,
+                Opt::Flatten,
+                Opt::ConstantFold(Box::new([BV_LSHR, BV_SHL])),
+                Opt::Binarize,
+            ]
+        );
+    }
+
+    #[test]
+    fn spec_roundtrip() {
+        let opts = vec![
+            Opt::ConstantFold(Box::new([BV_LSHR, BV_SHL])),
+            Opt::FlattenAssertions,
+            Opt::RamExt(FieldT::from(rug::Integer::from(101))),
+            Opt::LinearScan,
+            Opt::RamExt(DFL_T.clone()),
+        ];
+        assert_eq!(parse_opts(&opts_to_spec(&opts)).unwrap(), opts);
+    }
+
+    #[test]
+    fn bad_spec() {
+        assert_eq!(
+            parse_opts("flatten,fold"),
+            Err(OptParseError::UnknownOpt("fold".into()))
+        );
+        assert_eq!(
+            parse_opts("cfold(bvfoo)"),
+            Err(OptParseError::UnknownOp(
+                "bvfoo".into(),
+                "cfold(bvfoo)".into()
+            ))
+        );
+        assert_eq!(
+            parse_opts("tuple(bvshl)"),
+            Err(OptParseError::UnexpectedArgs("tuple(bvshl)".into()))
+        );
+        assert_eq!(
+            parse_opts("ramext(bn254)"),
+            Err(OptParseError::BadModulus(
+                "bn254".into(),
+                "ramext(bn254)".into()
+            ))
+        );
+    }
 }
@@ -0,0 +1,203 @@
+//! Shrinking bit-vector operations using range and known-bits analysis
+//!
+//! If [range analysis](crate::ir::analysis::range) or [known-bits
+//! analysis](crate::ir::analysis::bits) shows that the operands of a comparison, or the result of
+//! some arithmetic, fit in `m` bits, then we compute it at width `m`, extracting the low bits of
+//! the operands (and zero-extending the result, for arithmetic).
+//!
+//! This is sound for `+`, `*`, `-`, and bitwise operators because the low `m` bits of their
+//! result depend only on the low `m` bits of their operands. For `/` and `%`, we also require
+//! the operands to fit.
+
+use super::visit::RewritePass;
+use crate::ir::analysis::bits::{known_bits, KnownBits};
+use crate::ir::analysis::range::{bv_ranges, BvRange};
+use crate::ir::term::*;
+
+struct Pass {
+    ranges: TermMap<Option<BvRange>>,
+    known: TermMap<Option<KnownBits>>,
+}
+
+impl Pass {
+    fn range(&self, t: &Term) -> &BvRange {
+        self.ranges.get(t).unwrap().as_ref().unwrap()
+    }
+    /// The number of low bits of `t` that may be set, according to either analysis.
+    fn bits(&self, t: &Term) -> usize {
+        let known = self.known.get(t).unwrap().as_ref().unwrap();
+        std::cmp::min(self.range(t).bits(), known.bits())
+    }
+}
+
+/// The low `m` bits of `t`.
+fn narrow(t: Term, m: usize) -> Term {
+    let w = check(&t).as_bv();
+    if w == m {
+        return t;
+    }
+    if let Some(bv) = t.as_bv_opt() {
+        return leaf_term(Op::Const(Value::BitVector(bv.clone().extract(m - 1, 0))));
+    }
+    if let Op::BvUext(_) = &t.op {
+        let inner = t.cs[0].clone();
+        let inner_w = check(&inner).as_bv();
+        return if inner_w <= m {
+            if inner_w == m {
+                inner
+            } else {
+                term![Op::BvUext(m - inner_w); inner]
+            }
+        } else {
+            term![Op::BvExtract(m - 1, 0); inner]
+        };
+    }
+    term![Op::BvExtract(m - 1, 0); t]
+}
+
+impl RewritePass for Pass {
+    fn visit<F: Fn() -> Vec<Term>>(
+        &mut self,
+        _computation: &mut Computation,
+        orig: &Term,
+        rewritten_children: F,
+    ) -> Option<Term> {
+        match &orig.op {
+            Op::BvBinPred(p) => {
+                let w = self.range(&orig.cs[0]).width;
+                let m = std::cmp::max(self.bits(&orig.cs[0]), self.bits(&orig.cs[1]));
+                let unsigned = match p {
+                    BvBinPred::Ult | BvBinPred::Ugt | BvBinPred::Ule | BvBinPred::Uge => p.clone(),
+                    // both operands are non-negative: signed is unsigned.
+                    BvBinPred::Slt if m < w => BvBinPred::Ult,
+                    BvBinPred::Sgt if m < w => BvBinPred::Ugt,
+                    BvBinPred::Sle if m < w => BvBinPred::Ule,
+                    BvBinPred::Sge if m < w => BvBinPred::Uge,
+                    _ => return None,
+                };
+                if m < w {
+                    let cs = rewritten_children();
+                    Some(term(
+                        Op::BvBinPred(unsigned),
+                        cs.into_iter().map(|c| narrow(c, m)).collect(),
+                    ))
+                } else {
+                    None
+                }
+            }
+            Op::Eq => match check(&orig.cs[0]) {
+                Sort::BitVector(w) => {
+                    let m = std::cmp::max(self.bits(&orig.cs[0]), self.bits(&orig.cs[1]));
+                    if m < w {
+                        let cs = rewritten_children();
+                        Some(term(Op::Eq, cs.into_iter().map(|c| narrow(c, m)).collect()))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            },
+            Op::BvNaryOp(_) | Op::BvBinOp(BvBinOp::Sub) => {
+                let (w, m) = (self.range(orig).width, self.bits(orig));
+                if m < w {
+                    let cs = rewritten_children();
+                    let narrow_op = term(
+                        orig.op.clone(),
+                        cs.into_iter().map(|c| narrow(c, m)).collect(),
+                    );
+                    Some(term![Op::BvUext(w - m); narrow_op])
+                } else {
+                    None
+                }
+            }
+            Op::BvBinOp(BvBinOp::Udiv) | Op::BvBinOp(BvBinOp::Urem) => {
+                let w = self.range(orig).width;
+                let m = orig
+                    .cs
+                    .iter()
+                    .chain(std::iter::once(orig))
+                    .map(|c| self.bits(c))
+                    .max()
+                    .unwrap();
+                if m < w {
+                    let cs = rewritten_children();
+                    let narrow_op = term(
+                        orig.op.clone(),
+                        cs.into_iter().map(|c| narrow(c, m)).collect(),
+                    );
+                    Some(term![Op::BvUext(w - m); narrow_op])
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Narrow bit-vector comparisons and arithmetic in `cs` to the widths that their ranges and
+/// known bits require.
+pub fn shrink_bv(cs: &mut Computation) {
+    let ranges = bv_ranges(cs);
+    let known = known_bits(cs);
+    let mut pass = Pass { ranges, known };
+    pass.traverse(cs);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::distributions::Distribution;
+    use rand::SeedableRng;
+
+    use crate::ir::term::dist::UniformValue;
+    use fxhash::FxHashMap;
+
+    fn check_same(orig: Term) -> Term {
+        let mut cs = Computation::new();
+        cs.outputs.push(orig.clone());
+        shrink_bv(&mut cs);
+        let new = cs.outputs[0].clone();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let vars = extras::free_variables_with_sorts(orig.clone());
+        for _ in 0..100 {
+            let env: FxHashMap<String, Value> = vars
+                .iter()
+                .map(|(n, s)| (n.clone(), UniformValue(s).sample(&mut rng)))
+                .collect();
+            assert_eq!(eval(&orig, &env), eval(&new, &env), "{} vs {}", orig, new);
+        }
+        new
+    }
+
+    #[test]
+    fn loop_counter() {
+        let i = leaf_term(Op::Var("i".into(), Sort::BitVector(32)));
+        let j = leaf_term(Op::Var("j".into(), Sort::BitVector(32)));
+        let i = term![BV_UREM; i, bv_lit(16, 32)];
+        let j = term![BV_AND; j, bv_lit(7, 32)];
+        let cmp = check_same(term![BV_ULT; term![BV_ADD; i.clone(), j.clone()], bv_lit(10, 32)]);
+        assert_eq!(check(&cmp.cs[0]), Sort::BitVector(5));
+        let cmp = check_same(term![BV_SLE; i.clone(), j.clone()]);
+        assert_eq!(cmp.op, BV_ULE);
+        check_same(term![EQ; term![BV_MUL; i.clone(), j.clone()], bv_lit(12, 32)]);
+        check_same(term![BV_UDIV; i.clone(), term![BV_ADD; j.clone(), bv_lit(1, 32)]]);
+        check_same(term![BV_SUB; term![BV_ADD; i.clone(), bv_lit(7, 32)], j]);
+    }
+
+    #[test]
+    fn known_top_bit() {
+        let x = leaf_term(Op::Var("x".into(), Sort::BitVector(8)));
+        let low = term![BV_XOR; term![BV_OR; x, bv_lit(0x80, 8)], bv_lit(0x80, 8)];
+        let cmp = check_same(term![BV_ULT; low, bv_lit(0x10, 8)]);
+        assert_eq!(check(&cmp.cs[0]), Sort::BitVector(7));
+    }
+
+    #[test]
+    fn wide_unchanged() {
+        let x = leaf_term(Op::Var("x".into(), Sort::BitVector(8)));
+        let y = leaf_term(Op::Var("y".into(), Sort::BitVector(8)));
+        let t = term![BV_ULT; term![BV_ADD; x, y], bv_lit(3, 8)];
+        assert_eq!(check_same(t.clone()), t);
+    }
+}
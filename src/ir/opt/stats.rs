@@ -0,0 +1,112 @@
+//! Statistics about optimization passes
+//!
+//! Used to find which pass in a pipeline is slow, or blows up the term graph.
+
+use crate::ir::term::*;
+
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+/// Size statistics for a computation
+pub struct CsStats {
+    /// Number of distinct terms reachable from the outputs
+    pub terms: usize,
+    /// Number of distinct variables reachable from the outputs
+    pub vars: usize,
+    /// Number of distinct array-sorted terms
+    pub arrays: usize,
+    /// Number of distinct tuple-sorted terms
+    pub tuples: usize,
+    /// Number of outputs
+    pub outputs: usize,
+}
+
+impl CsStats {
+    /// Measure `cs`.
+    pub fn of(cs: &Computation) -> Self {
+        let mut stats = CsStats {
+            outputs: cs.outputs.len(),
+            ..Default::default()
+        };
+        for t in cs.terms_postorder() {
+            stats.terms += 1;
+            match check(&t) {
+                Sort::Array(..) => stats.arrays += 1,
+                Sort::Tuple(..) => stats.tuples += 1,
+                _ => {}
+            }
+            if let Op::Var(..) = &t.op {
+                stats.vars += 1;
+            }
+        }
+        stats
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+/// Statistics for one run of one pass
+pub struct PassStats {
+    /// The pass, as written in a pass spec
+    pub pass: String,
+    /// Wall-clock time, in seconds
+    pub seconds: f64,
+    /// The computation before the pass
+    pub before: CsStats,
+    /// The computation after the pass
+    pub after: CsStats,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+/// Statistics for a whole optimization pipeline, in pass order
+pub struct OptReport {
+    /// One entry per pass
+    pub passes: Vec<PassStats>,
+}
+
+impl OptReport {
+    /// Record a pass that ran for `time`.
+    pub fn push(&mut self, pass: &super::Opt, time: Duration, before: CsStats, after: CsStats) {
+        self.passes.push(PassStats {
+            pass: format!("{}", pass),
+            seconds: time.as_secs_f64(),
+            before,
+            after,
+        });
+    }
+
+    /// Total wall-clock time of all passes, in seconds.
+    pub fn total_seconds(&self) -> f64 {
+        self.passes.iter().map(|p| p.seconds).sum()
+    }
+
+    /// Serialize as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn count() {
+        let mut cs = Computation::new();
+        let a = leaf_term(Op::Var("a".into(), Sort::BitVector(4)));
+        let b = leaf_term(Op::Var("b".into(), Sort::BitVector(4)));
+        let t = term![Op::Tuple; a.clone(), term![BV_ADD; a.clone(), b.clone()]];
+        cs.outputs.push(term![EQ; term![Op::Field(1); t], b]);
+        let stats = CsStats::of(&cs);
+        assert_eq!(
+            stats,
+            CsStats {
+                terms: 6,
+                vars: 2,
+                arrays: 0,
+                tuples: 1,
+                outputs: 1,
+            }
+        );
+    }
+}
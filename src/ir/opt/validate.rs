@@ -0,0 +1,267 @@
+//! Translation validation for optimization passes
+//!
+//! After a pass runs, we check that the rewritten computation means the same thing as the
+//! original. Variables introduced by a pass (e.g., the scalars created by
+//! [super::Opt::ScalarizeVars]) are expressed in terms of the old ones using the precomputation
+//! that the pass registered for them.
+//!
+//! We then build a "difference" term that is satisfiable iff the pass changed semantics:
+//!
+//! * if the pass preserves the number and sorts of the outputs, some output must differ;
+//! * otherwise, if all outputs are assertions, their conjunctions must differ. If the pass
+//!   eliminated variables (e.g., [super::Opt::Inline]), the check is one-sided: we only require
+//!   that the original assertions imply the new ones. The converse would need the eliminated
+//!   variables to be existentially quantified, so a pass that *weakens* the assertions while
+//!   eliminating variables is not caught.
+//!
+//! The difference term is given to an SMT solver, or evaluated on random inputs.
+
+use super::Opt;
+use crate::ir::term::dist::UniformValue;
+use crate::ir::term::extras::{free_variables, free_variables_with_sorts, substitute_cache};
+use crate::ir::term::text::serialize_value_map;
+use crate::ir::term::*;
+
+use fxhash::{FxHashMap as HashMap, FxHashSet};
+use log::{debug, warn};
+use rand::distributions::Distribution;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fmt::{self, Display, Formatter};
+
+/// How many random environments to try when no solver is available.
+pub const DEFAULT_SAMPLES: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// How to check that a pass preserved semantics
+pub enum Method {
+    /// Prove equivalence with an SMT solver. Falls back to sampling if CirC was built without
+    /// the `smt` feature.
+    Smt,
+    /// Evaluate both computations on this many random inputs.
+    Sample(usize),
+}
+
+#[derive(Clone, Debug)]
+/// A pass that changed the meaning of a computation
+pub struct Miscompile {
+    /// The position of the pass in the pipeline
+    pub index: usize,
+    /// The pass
+    pub pass: Opt,
+    /// An input on which the computations before and after the pass differ
+    pub counterexample: HashMap<String, Value>,
+}
+
+impl Display for Miscompile {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Pass {} ({}) changed semantics. Counterexample:\n{}",
+            self.index,
+            self.pass,
+            serialize_value_map(&self.counterexample)
+        )
+    }
+}
+
+impl std::error::Error for Miscompile {}
+
+/// Rewrite the outputs of `after`, replacing variables that do not appear in `before` with their
+/// definitions in the precomputation of `after`.
+fn express_new_vars(before: &FxHashSet<String>, after: &Computation) -> Vec<Term> {
+    let precomp = &after.precomputes;
+    let mut subs = TermMap::new();
+    for name in &precomp.sequence {
+        if before.contains(name) {
+            continue;
+        }
+        let def = substitute_cache(precomp.outputs().get(name).unwrap(), &mut subs);
+        let var = leaf_term(Op::Var(name.clone(), check(&def)));
+        subs.insert(var, def);
+    }
+    after
+        .outputs
+        .iter()
+        .map(|o| substitute_cache(o, &mut subs))
+        .collect()
+}
+
+fn vars(outputs: &[Term]) -> FxHashSet<String> {
+    outputs
+        .iter()
+        .flat_map(|o| free_variables(o.clone()))
+        .collect()
+}
+
+fn conj(outputs: &[Term]) -> Term {
+    match outputs.len() {
+        1 => outputs[0].clone(),
+        _ => term(AND, outputs.to_vec()),
+    }
+}
+
+/// Build a term that is satisfiable iff `after` does not have the meaning of `before` (the
+/// outputs of the original computation). When `after` eliminates variables, it is only satisfiable
+/// if `before` does not imply `after` (see the module documentation).
+///
+/// Returns an error describing why if we do not know how to compare the two.
+fn difference(before: &[Term], after: &Computation) -> Result<Term, String> {
+    let b = before;
+    let b_vars = vars(b);
+    let a = express_new_vars(&b_vars, after);
+    let a_vars = vars(&a);
+    if let Some(v) = a_vars.difference(&b_vars).next() {
+        return Err(format!("variable '{}' is new, and not precomputed", v));
+    }
+    if b.len() == a.len() && b.iter().zip(&a).all(|(b, a)| check(b) == check(a)) {
+        if b.is_empty() {
+            return Ok(bool_lit(false));
+        }
+        let diffs: Vec<Term> = b
+            .iter()
+            .zip(&a)
+            .map(|(b, a)| term![NOT; term![EQ; b.clone(), a.clone()]])
+            .collect();
+        Ok(term(OR, diffs))
+    } else if b.iter().chain(&a).all(|o| check(o) == Sort::Bool) {
+        if a_vars.len() == b_vars.len() {
+            Ok(term![XOR; conj(b), conj(&a)])
+        } else {
+            // one-sided: variables were eliminated
+            Ok(term![AND; conj(b), term![NOT; conj(&a)]])
+        }
+    } else {
+        Err(format!(
+            "outputs changed from {} to {}, and are not all assertions",
+            b.len(),
+            a.len()
+        ))
+    }
+}
+
+#[cfg(feature = "smt")]
+fn find_difference_smt(diff: &Term) -> Option<HashMap<String, Value>> {
+    crate::target::smt::find_model(diff).map(|m| m.into_iter().collect())
+}
+
+#[cfg(not(feature = "smt"))]
+fn find_difference_smt(diff: &Term) -> Option<HashMap<String, Value>> {
+    warn!("Missing feature: smt. Validating by sampling instead.");
+    find_difference_sample(diff, DEFAULT_SAMPLES)
+}
+
+fn find_difference_sample(diff: &Term, samples: usize) -> Option<HashMap<String, Value>> {
+    let vars = free_variables_with_sorts(diff.clone());
+    let mut rng = StdRng::seed_from_u64(0);
+    (0..samples)
+        .map(|_| {
+            vars.iter()
+                .map(|(n, s)| (n.clone(), UniformValue(s).sample(&mut rng)))
+                .collect::<HashMap<String, Value>>()
+        })
+        .find(|env| eval(diff, env).as_bool())
+}
+
+/// Check that `after` (the result of running `pass` on a computation with outputs `before`) has
+/// the same meaning as the original. Passes that we cannot check are skipped, with a warning.
+pub fn validate(
+    index: usize,
+    pass: &Opt,
+    before: &[Term],
+    after: &Computation,
+    method: Method,
+) -> Result<(), Miscompile> {
+    let diff = match difference(before, after) {
+        Ok(diff) => diff,
+        Err(reason) => {
+            warn!("Cannot validate pass {} ({}): {}", index, pass, reason);
+            return Ok(());
+        }
+    };
+    let counterexample = match method {
+        Method::Smt => find_difference_smt(&diff),
+        Method::Sample(n) => find_difference_sample(&diff, n),
+    };
+    match counterexample {
+        Some(counterexample) => Err(Miscompile {
+            index,
+            pass: pass.clone(),
+            counterexample,
+        }),
+        None => {
+            debug!("Validated pass {} ({})", index, pass);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bv_var(n: &str) -> Term {
+        leaf_term(Op::Var(n.into(), Sort::BitVector(4)))
+    }
+
+    fn cs(outputs: Vec<Term>) -> Computation {
+        let mut cs = Computation::new();
+        cs.outputs = outputs;
+        cs
+    }
+
+    #[test]
+    fn same_outputs() {
+        let x = bv_var("x");
+        let before = cs(vec![term![BV_ADD; x.clone(), bv_lit(0, 4)]]);
+        let after = cs(vec![x]);
+        assert!(validate(
+            0,
+            &Opt::Flatten,
+            &before.outputs,
+            &after,
+            Method::Sample(16)
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn changed_output() {
+        let x = bv_var("x");
+        let before = cs(vec![term![BV_ADD; x.clone(), bv_lit(1, 4)]]);
+        let after = cs(vec![x]);
+        let err = validate(
+            3,
+            &Opt::Flatten,
+            &before.outputs,
+            &after,
+            Method::Sample(16),
+        )
+        .unwrap_err();
+        assert_eq!(err.index, 3);
+        assert!(err.counterexample.contains_key("x"));
+    }
+
+    #[test]
+    fn eliminated_var() {
+        let x = bv_var("x");
+        let y = bv_var("y");
+        let before = cs(vec![
+            term![EQ; y.clone(), term![BV_ADD; x.clone(), bv_lit(1, 4)]],
+            term![BV_ULT; y, bv_lit(8, 4)],
+        ]);
+        let after = cs(vec![
+            term![BV_ULT; term![BV_ADD; x.clone(), bv_lit(1, 4)], bv_lit(8, 4)],
+        ]);
+        assert!(validate(0, &Opt::Inline, &before.outputs, &after, Method::Sample(64)).is_ok());
+        let wrong = cs(vec![term![BV_ULT; x, bv_lit(2, 4)]]);
+        assert!(validate(
+            0,
+            &Opt::Inline,
+            &before.outputs,
+            &wrong,
+            Method::Sample(2048)
+        )
+        .is_err());
+    }
+}
@@ -285,6 +285,16 @@ impl<'a> rand::distributions::Distribution<Value> for UniformValue<'a> {
             Sort::Tuple(sorts) => {
                 Value::Tuple(sorts.iter().map(|s| UniformValue(s).sample(rng)).collect())
             }
+            Sort::Array(key_sort, val_sort, size) => Value::Array(Array::new(
+                (**key_sort).clone(),
+                Box::new(val_sort.default_value()),
+                key_sort
+                    .elems_iter_values()
+                    .take(*size)
+                    .map(|k| (k, UniformValue(val_sort).sample(rng)))
+                    .collect(),
+                *size,
+            )),
             s => unimplemented!("Cannot sample value of sort {}", s),
         }
     }
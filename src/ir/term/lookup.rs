@@ -0,0 +1,187 @@
+//! Lookup tables
+//!
+//! A lookup table is a finite function from keys to values. [Op::Lookup] applies a table to a key
+//! (which must be in the table). Backends can check many lookups into one table much more cheaply
+//! than they can check the computation that the table replaces.
+//!
+//! Like terms, tables are stored in a process-wide registry, and are referred to by an id: a hash
+//! of their contents. Registering the same table twice gives the same id, and so does registering
+//! it in another process. Structures that hold table ids serialize the tables themselves (see
+//! [serde_tables]), and register them when deserialized.
+
+use super::*;
+use fxhash::FxHasher;
+use std::hash::{Hash, Hasher};
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+/// A finite function from keys to values.
+pub struct Table {
+    /// The sort of keys
+    pub key_sort: Sort,
+    /// The sort of values
+    pub val_sort: Sort,
+    /// The entries
+    pub entries: BTreeMap<Value, Value>,
+}
+
+impl Table {
+    /// Create a new table from `entries`.
+    ///
+    /// Keys and values must be booleans, bit-vectors, or field elements.
+    pub fn new(
+        key_sort: Sort,
+        val_sort: Sort,
+        entries: impl IntoIterator<Item = (Value, Value)>,
+    ) -> Self {
+        for s in &[&key_sort, &val_sort] {
+            assert!(
+                matches!(s, Sort::Bool | Sort::BitVector(_) | Sort::Field(_)),
+                "Lookup tables cannot hold {} (Bool, BitVector, or Field only)",
+                s
+            );
+        }
+        let entries: BTreeMap<Value, Value> = entries.into_iter().collect();
+        for (k, v) in &entries {
+            assert_eq!(k.sort(), key_sort, "Bad key {} in lookup table", k);
+            assert_eq!(v.sort(), val_sort, "Bad value {} in lookup table", v);
+        }
+        Self {
+            key_sort,
+            val_sort,
+            entries,
+        }
+    }
+
+    /// Create a table from the values of `f` on each element of `key_sort`.
+    ///
+    /// E.g., the byte-wise XOR table, keyed by the concatenation of the arguments.
+    pub fn from_fn(key_sort: Sort, val_sort: Sort, f: impl Fn(&Value) -> Value) -> Self {
+        let entries: Vec<(Value, Value)> = key_sort
+            .elems_iter_values()
+            .map(|k| {
+                let v = f(&k);
+                (k, v)
+            })
+            .collect();
+        Self::new(key_sort, val_sort, entries)
+    }
+
+    /// The value at `key`, if `key` is in the table.
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.entries.get(key)
+    }
+
+    /// The number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether there are no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+lazy_static! {
+    static ref TABLES: RwLock<FxHashMap<usize, Arc<Table>>> = RwLock::new(FxHashMap::default());
+}
+
+/// Register `table`, returning its id.
+pub fn register(table: Table) -> usize {
+    let mut hasher = FxHasher::default();
+    table.hash(&mut hasher);
+    let id = hasher.finish() as usize;
+    let mut tables = TABLES.write().unwrap();
+    match tables.get(&id) {
+        Some(t) => assert!(**t == table, "Lookup tables collide at id {}", id),
+        None => {
+            tables.insert(id, Arc::new(table));
+        }
+    }
+    id
+}
+
+/// Get the table with id `id`.
+pub fn table(id: usize) -> Arc<Table> {
+    TABLES
+        .read()
+        .unwrap()
+        .get(&id)
+        .unwrap_or_else(|| panic!("No lookup table {}", id))
+        .clone()
+}
+
+/// (De)serialize a list of table ids as the tables that they refer to.
+///
+/// For use with `#[serde(with = "...")]`. Deserializing registers the tables.
+pub mod serde_tables {
+    use super::*;
+
+    /// Serialize the tables with ids `ids`.
+    pub fn serialize<S: Serializer>(ids: &[usize], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(ids.iter().map(|id| table(*id)))
+    }
+
+    /// Deserialize and register a list of tables, returning their ids.
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<usize>, D::Error> {
+        let tables = Vec::<Table>::deserialize(d)?;
+        Ok(tables.into_iter().map(register).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bv(u: usize, w: usize) -> Value {
+        Value::BitVector(BitVector::new(Integer::from(u), w))
+    }
+
+    fn squares() -> Table {
+        Table::from_fn(Sort::BitVector(3), Sort::BitVector(6), |k| {
+            let k = k.as_bv().uint().to_usize().unwrap();
+            bv(k * k, 6)
+        })
+    }
+
+    #[test]
+    fn register_dedups() {
+        let a = register(squares());
+        let b = register(squares());
+        assert_eq!(a, b);
+        assert_eq!(table(a).len(), 8);
+    }
+
+    #[test]
+    fn serde_roundtrip() {
+        #[derive(Serialize, Deserialize)]
+        struct Tables(#[serde(with = "serde_tables")] Vec<usize>);
+        let id = register(squares());
+        let bytes = bincode::serialize(&Tables(vec![id])).unwrap();
+        let Tables(ids) = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(ids, vec![id]);
+    }
+
+    #[test]
+    fn eval_lookup() {
+        let id = register(squares());
+        let x = leaf_term(Op::Var("x".into(), Sort::BitVector(3)));
+        let t = term![Op::Lookup(id); x];
+        assert_eq!(check(&t), Sort::BitVector(6));
+        let env = vec![("x".to_owned(), bv(5, 3))].into_iter().collect();
+        assert_eq!(eval(&t, &env), bv(25, 6));
+    }
+
+    #[test]
+    #[should_panic]
+    fn eval_missing_key() {
+        let table = Table::new(
+            Sort::BitVector(2),
+            Sort::Bool,
+            vec![(bv(1, 2), Value::Bool(true))],
+        );
+        let id = register(table);
+        let t = term![Op::Lookup(id); bv_lit(2, 2)];
+        eval(&t, &FxHashMap::default());
+    }
+}
@@ -37,14 +37,16 @@ use std::sync::{Arc, RwLock};
 pub mod bv;
 pub mod dist;
 pub mod extras;
+pub mod lookup;
 pub mod precomp;
+pub mod src_loc;
 pub mod text;
 pub mod ty;
 
 pub use bv::BitVector;
 pub use ty::{check, check_rec, TypeError, TypeErrorReason};
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// An operator
 pub enum Op {
     /// a variable
@@ -106,6 +108,8 @@ pub enum Op {
     //FpFma,
     /// cast bit-vector to floating-point, as bits
     BvToFp,
+    /// cast floating-point to bit-vector, as bits
+    FpToBv,
     /// translate the (unsigned) bit-vector number represented by the argument to a floating-point
     /// value of this width.
     UbvToFp(usize),
@@ -150,6 +154,11 @@ pub enum Op {
     /// Returns the nth smallest of its arguments
     /// May only be used in precomputes
     NthSmallest(usize),
+
+    /// Apply a lookup table (id) to a key, which must be in the table.
+    ///
+    /// See [lookup].
+    Lookup(usize),
 }
 
 /// Boolean AND
@@ -250,6 +259,7 @@ impl Op {
             Op::FpUnPred(_) => Some(1),
             Op::FpUnOp(_) => Some(1),
             Op::BvToFp => Some(1),
+            Op::FpToBv => Some(1),
             Op::UbvToFp(_) => Some(1),
             Op::SbvToFp(_) => Some(1),
             Op::FpToFp(_) => Some(1),
@@ -264,6 +274,7 @@ impl Op {
             Op::Map(op) => op.arity(),
             Op::Call(_, args, _) => Some(args.len()),
             Op::NthSmallest(_) => None,
+            Op::Lookup(_) => Some(1),
         }
     }
 }
@@ -296,6 +307,7 @@ impl Display for Op {
             Op::FpUnPred(a) => write!(f, "{}", a),
             Op::FpUnOp(a) => write!(f, "{}", a),
             Op::BvToFp => write!(f, "bv2fp"),
+            Op::FpToBv => write!(f, "fp2bv"),
             Op::UbvToFp(a) => write!(f, "(ubv2fp {})", a),
             Op::SbvToFp(a) => write!(f, "(sbv2fp {})", a),
             Op::FpToFp(a) => write!(f, "(fp2fp {})", a),
@@ -310,11 +322,12 @@ impl Display for Op {
             Op::Map(op) => write!(f, "(map({}))", op),
             Op::Call(name, _, _) => write!(f, "fn:{}", name),
             Op::NthSmallest(i) => write!(f, "(nthsmallest {})", i),
+            Op::Lookup(i) => write!(f, "(lookup {})", i),
         }
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// Boolean n-ary operator
 pub enum BoolNaryOp {
     /// Boolean AND
@@ -335,7 +348,7 @@ impl Display for BoolNaryOp {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// Bit-vector binary operator
 pub enum BvBinOp {
     /// Bit-vector (-)
@@ -365,7 +378,7 @@ impl Display for BvBinOp {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// Bit-vector binary predicate
 pub enum BvBinPred {
     // TODO: add overflow predicates.
@@ -402,7 +415,7 @@ impl Display for BvBinPred {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// Bit-vector n-ary operator
 pub enum BvNaryOp {
     /// Bit-vector (+)
@@ -429,7 +442,7 @@ impl Display for BvNaryOp {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// Bit-vector unary operator
 pub enum BvUnOp {
     /// Bit-vector bitwise not
@@ -447,7 +460,7 @@ impl Display for BvUnOp {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// Floating-point binary operator
 pub enum FpBinOp {
     /// Floating-point (+)
@@ -480,7 +493,7 @@ impl Display for FpBinOp {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// Floating-point unary operator
 pub enum FpUnOp {
     /// Floating-point unary negation
@@ -504,7 +517,7 @@ impl Display for FpUnOp {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// Floating-point binary predicate
 pub enum FpBinPred {
     /// Floating-point (<=)
@@ -531,7 +544,7 @@ impl Display for FpBinPred {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// Floating-point unary predicate
 pub enum FpUnPred {
     /// Is this normal?
@@ -564,7 +577,7 @@ impl Display for FpUnPred {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// Finite field n-ary operator
 pub enum PfNaryOp {
     /// Finite field (+)
@@ -582,7 +595,7 @@ impl Display for PfNaryOp {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 /// Finite field n-ary operator
 pub enum PfUnOp {
     /// Finite field negation
@@ -1383,48 +1396,154 @@ pub fn eval(t: &Term, h: &FxHashMap<String, Value>) -> Value {
     eval_cached(t, h, &mut vs).clone()
 }
 
+/// Apply a floating-point unary operator to a float of either width.
+macro_rules! fp_un_op {
+    ($o:expr, $a:expr) => {
+        match $o {
+            FpUnOp::Neg => -$a,
+            FpUnOp::Abs => $a.abs(),
+            FpUnOp::Sqrt => $a.sqrt(),
+            // half-way cases round away from zero
+            FpUnOp::Round => $a.round(),
+        }
+    };
+}
+/// Apply a floating-point binary predicate to floats of either width.
+macro_rules! fp_bin_pred {
+    ($o:expr, $a:expr, $b:expr) => {
+        match $o {
+            FpBinPred::Le => $a <= $b,
+            FpBinPred::Lt => $a < $b,
+            FpBinPred::Eq => $a == $b,
+            FpBinPred::Ge => $a >= $b,
+            FpBinPred::Gt => $a > $b,
+        }
+    };
+}
+/// Apply a floating-point unary predicate to a float of either width.
+macro_rules! fp_un_pred {
+    ($o:expr, $a:expr) => {
+        match $o {
+            FpUnPred::Normal => $a.is_normal(),
+            FpUnPred::Subnormal => $a.classify() == std::num::FpCategory::Subnormal,
+            FpUnPred::Zero => $a == 0.0,
+            FpUnPred::Infinite => $a.is_infinite(),
+            FpUnPred::Nan => $a.is_nan(),
+            FpUnPred::Negative => !$a.is_nan() && $a.is_sign_negative(),
+            FpUnPred::Positive => !$a.is_nan() && $a.is_sign_positive(),
+        }
+    };
+}
+fn eval_fp_bin_op(o: &FpBinOp, a: &Value, b: &Value) -> Value {
+    macro_rules! apply {
+        ($a:expr, $b:expr, $nan:expr) => {
+            match o {
+                FpBinOp::Add => $a + $b,
+                FpBinOp::Mul => $a * $b,
+                FpBinOp::Sub => $a - $b,
+                FpBinOp::Div => $a / $b,
+                // The IEEE-754 remainder: `a - n * b`, where `n` is the integer nearest to `a / b`
+                // (ties to even). This differs from `%`, which truncates `a / b`.
+                FpBinOp::Rem => {
+                    if $a.is_nan() || $b.is_nan() || $a.is_infinite() || $b == 0.0 {
+                        $nan
+                    } else if $b.is_infinite() {
+                        $a
+                    } else {
+                        let p = $b.abs();
+                        // exact, and in [0, 2p)
+                        let mut r = $a.abs() % (p + p);
+                        // doubling can only overflow when the comparison holds anyway
+                        if r + r > p {
+                            r -= p;
+                            if r + r >= p {
+                                r -= p;
+                            }
+                        }
+                        if $a.is_sign_negative() {
+                            -r
+                        } else {
+                            r
+                        }
+                    }
+                }
+                FpBinOp::Max => $a.max($b),
+                FpBinOp::Min => $a.min($b),
+            }
+        };
+    }
+    match (a, b) {
+        (Value::F32(a), Value::F32(b)) => Value::F32(apply!(*a, *b, f32::NAN)),
+        (Value::F64(a), Value::F64(b)) => Value::F64(apply!(*a, *b, f64::NAN)),
+        (a, b) => panic!("{} applied to {} and {}", o, a, b),
+    }
+}
+
+/// The `w`-bit float nearest to `i` (ties to even).
+fn int_to_fp(i: &Integer, w: usize) -> Value {
+    let mag = Integer::from(i.abs_ref());
+    // Keep the top 64 bits, and OR the rest into the lowest one. The conversion of those 64 bits
+    // then rounds as the conversion of `mag` would, and the scaling is exact (or overflows).
+    let shift = mag.significant_bits().saturating_sub(64);
+    let sticky = !mag.is_divisible_2pow(shift);
+    let top = Integer::from(&mag >> shift).to_u64().unwrap() | sticky as u64;
+    let neg = i.cmp0() == std::cmp::Ordering::Less;
+    match w {
+        32 => {
+            let f = top as f32 * 2f32.powi(shift as i32);
+            Value::F32(if neg { -f } else { f })
+        }
+        64 => {
+            let f = top as f64 * 2f64.powi(shift as i32);
+            Value::F64(if neg { -f } else { f })
+        }
+        _ => panic!("No {}-bit floating-point type", w),
+    }
+}
+
 /// Helper function for eval function. Handles a single term
 fn eval_value(vs: &mut TermMap<Value>, h: &FxHashMap<String, Value>, c: Term) -> Value {
-    let v = match &c.op {
+    let args: Vec<&Value> = c.cs.iter().map(|c| vs.get(c).unwrap()).collect();
+    let v = eval_op(&c.op, &args, h);
+    vs.insert(c.clone(), v.clone());
+    debug!("Eval {}\nAs   {}", c, v);
+    v
+}
+
+/// Apply `op` to the values `args`, using variable values in `h`.
+pub fn eval_op(op: &Op, args: &[&Value], h: &FxHashMap<String, Value>) -> Value {
+    match op {
         Op::Var(n, _) => h
             .get(n)
             .unwrap_or_else(|| panic!("Missing var: {} in {:?}", n, h))
             .clone(),
-        Op::Eq => Value::Bool(vs.get(&c.cs[0]).unwrap() == vs.get(&c.cs[1]).unwrap()),
-        Op::Not => Value::Bool(!vs.get(&c.cs[0]).unwrap().as_bool()),
-        Op::Implies => {
-            Value::Bool(!vs.get(&c.cs[0]).unwrap().as_bool() || vs.get(&c.cs[1]).unwrap().as_bool())
-        }
-        Op::BoolNaryOp(BoolNaryOp::Or) => {
-            Value::Bool(c.cs.iter().any(|c| vs.get(c).unwrap().as_bool()))
-        }
-        Op::BoolNaryOp(BoolNaryOp::And) => {
-            Value::Bool(c.cs.iter().all(|c| vs.get(c).unwrap().as_bool()))
-        }
+        Op::Eq => Value::Bool(args[0] == args[1]),
+        Op::Not => Value::Bool(!args[0].as_bool()),
+        Op::Implies => Value::Bool(!args[0].as_bool() || args[1].as_bool()),
+        Op::BoolNaryOp(BoolNaryOp::Or) => Value::Bool(args.iter().any(|a| a.as_bool())),
+        Op::BoolNaryOp(BoolNaryOp::And) => Value::Bool(args.iter().all(|a| a.as_bool())),
         Op::BoolNaryOp(BoolNaryOp::Xor) => Value::Bool(
-            c.cs.iter()
-                .map(|c| vs.get(c).unwrap().as_bool())
+            args.iter()
+                .map(|a| a.as_bool())
                 .fold(false, std::ops::BitXor::bitxor),
         ),
-        Op::BvBit(i) => Value::Bool(vs.get(&c.cs[0]).unwrap().as_bv().uint().get_bit(*i as u32)),
+        Op::BvBit(i) => Value::Bool(args[0].as_bv().uint().get_bit(*i as u32)),
         Op::BoolMaj => {
-            let c0 = vs.get(&c.cs[0]).unwrap().as_bool() as u8;
-            let c1 = vs.get(&c.cs[1]).unwrap().as_bool() as u8;
-            let c2 = vs.get(&c.cs[2]).unwrap().as_bool() as u8;
+            let c0 = args[0].as_bool() as u8;
+            let c1 = args[1].as_bool() as u8;
+            let c2 = args[2].as_bool() as u8;
             Value::Bool(c0 + c1 + c2 > 1)
         }
         Op::BvConcat => Value::BitVector({
-            let mut it = c.cs.iter().map(|c| vs.get(c).unwrap().as_bv().clone());
+            let mut it = args.iter().map(|a| a.as_bv().clone());
             let f = it.next().unwrap();
             it.fold(f, BitVector::concat)
         }),
-        Op::BvExtract(h, l) => {
-            Value::BitVector(vs.get(&c.cs[0]).unwrap().as_bv().clone().extract(*h, *l))
-        }
+        Op::BvExtract(h, l) => Value::BitVector(args[0].as_bv().clone().extract(*h, *l)),
         Op::Const(v) => v.clone(),
         Op::BvBinOp(o) => Value::BitVector({
-            let a = vs.get(&c.cs[0]).unwrap().as_bv().clone();
-            let b = vs.get(&c.cs[1]).unwrap().as_bv().clone();
+            let a = args[0].as_bv().clone();
+            let b = args[1].as_bv().clone();
             match o {
                 BvBinOp::Udiv => a / &b,
                 BvBinOp::Urem => a % &b,
@@ -1435,14 +1554,14 @@ fn eval_value(vs: &mut TermMap<Value>, h: &FxHashMap<String, Value>, c: Term) ->
             }
         }),
         Op::BvUnOp(o) => Value::BitVector({
-            let a = vs.get(&c.cs[0]).unwrap().as_bv().clone();
+            let a = args[0].as_bv().clone();
             match o {
                 BvUnOp::Not => !a,
                 BvUnOp::Neg => -a,
             }
         }),
         Op::BvNaryOp(o) => Value::BitVector({
-            let mut xs = c.cs.iter().map(|c| vs.get(c).unwrap().as_bv().clone());
+            let mut xs = args.iter().map(|a| a.as_bv().clone());
             let f = xs.next().unwrap();
             xs.fold(
                 f,
@@ -1456,32 +1575,26 @@ fn eval_value(vs: &mut TermMap<Value>, h: &FxHashMap<String, Value>, c: Term) ->
             )
         }),
         Op::BvSext(w) => Value::BitVector({
-            let a = vs.get(&c.cs[0]).unwrap().as_bv().clone();
+            let a = args[0].as_bv().clone();
             let mask = ((Integer::from(1) << *w as u32) - 1)
                 * Integer::from(a.uint().get_bit(a.width() as u32 - 1));
             BitVector::new(a.uint() | (mask << a.width() as u32), a.width() + w)
         }),
         Op::PfToBv(w) => Value::BitVector({
-            let i = vs.get(&c.cs[0]).unwrap().as_pf().i();
+            let i = args[0].as_pf().i();
             let m = Integer::from(1) << *w as u32;
             let i = i.div_rem_floor(m.clone()).1;
             assert!(i < m);
             BitVector::new(i, *w)
         }),
         Op::BvUext(w) => Value::BitVector({
-            let a = vs.get(&c.cs[0]).unwrap().as_bv().clone();
+            let a = args[0].as_bv().clone();
             BitVector::new(a.uint().clone(), a.width() + w)
         }),
-        Op::Ite => if vs.get(&c.cs[0]).unwrap().as_bool() {
-            vs.get(&c.cs[1])
-        } else {
-            vs.get(&c.cs[2])
-        }
-        .unwrap()
-        .clone(),
+        Op::Ite => if args[0].as_bool() { args[1] } else { args[2] }.clone(),
         Op::BvBinPred(o) => Value::Bool({
-            let a = vs.get(&c.cs[0]).unwrap().as_bv();
-            let b = vs.get(&c.cs[1]).unwrap().as_bv();
+            let a = args[0].as_bv();
+            let b = args[1].as_bv();
             match o {
                 BvBinPred::Sge => a.as_sint() >= b.as_sint(),
                 BvBinPred::Sgt => a.as_sint() > b.as_sint(),
@@ -1493,12 +1606,9 @@ fn eval_value(vs: &mut TermMap<Value>, h: &FxHashMap<String, Value>, c: Term) ->
                 BvBinPred::Ult => a.uint() < b.uint(),
             }
         }),
-        Op::BoolToBv => Value::BitVector(BitVector::new(
-            Integer::from(vs.get(&c.cs[0]).unwrap().as_bool()),
-            1,
-        )),
+        Op::BoolToBv => Value::BitVector(BitVector::new(Integer::from(args[0].as_bool()), 1)),
         Op::PfUnOp(o) => Value::Field({
-            let a = vs.get(&c.cs[0]).unwrap().as_pf().clone();
+            let a = args[0].as_pf().clone();
             match o {
                 PfUnOp::Recip => {
                     if a.is_zero() {
@@ -1511,7 +1621,7 @@ fn eval_value(vs: &mut TermMap<Value>, h: &FxHashMap<String, Value>, c: Term) ->
             }
         }),
         Op::PfNaryOp(o) => Value::Field({
-            let mut xs = c.cs.iter().map(|c| vs.get(c).unwrap().as_pf().clone());
+            let mut xs = args.iter().map(|a| a.as_pf().clone());
             let f = xs.next().unwrap();
             xs.fold(
                 f,
@@ -1521,79 +1631,101 @@ fn eval_value(vs: &mut TermMap<Value>, h: &FxHashMap<String, Value>, c: Term) ->
                 },
             )
         }),
-        Op::UbvToPf(fty) => Value::Field(fty.new_v(vs.get(&c.cs[0]).unwrap().as_bv().uint())),
+        Op::UbvToPf(fty) => Value::Field(fty.new_v(args[0].as_bv().uint())),
+        // floating-point
+        Op::FpBinOp(o) => eval_fp_bin_op(o, args[0], args[1]),
+        Op::FpUnOp(o) => match args[0] {
+            Value::F32(a) => Value::F32(fp_un_op!(o, *a)),
+            Value::F64(a) => Value::F64(fp_un_op!(o, *a)),
+            v => panic!("{} applied to {}", o, v),
+        },
+        Op::FpBinPred(o) => Value::Bool(match (args[0], args[1]) {
+            (Value::F32(a), Value::F32(b)) => fp_bin_pred!(o, a, b),
+            (Value::F64(a), Value::F64(b)) => fp_bin_pred!(o, a, b),
+            (a, b) => panic!("{} applied to {} and {}", o, a, b),
+        }),
+        Op::FpUnPred(o) => Value::Bool(match args[0] {
+            Value::F32(a) => fp_un_pred!(o, *a),
+            Value::F64(a) => fp_un_pred!(o, *a),
+            v => panic!("{} applied to {}", o, v),
+        }),
+        Op::BvToFp => {
+            let a = args[0].as_bv();
+            match a.width() {
+                32 => Value::F32(f32::from_bits(a.uint().to_u32().unwrap())),
+                64 => Value::F64(f64::from_bits(a.uint().to_u64().unwrap())),
+                w => panic!("bv2fp of a {}-bit bit-vector", w),
+            }
+        }
+        Op::FpToBv => Value::BitVector(match args[0] {
+            Value::F32(a) => BitVector::new(Integer::from(a.to_bits()), 32),
+            Value::F64(a) => BitVector::new(Integer::from(a.to_bits()), 64),
+            v => panic!("fp2bv of {}", v),
+        }),
+        Op::UbvToFp(w) => int_to_fp(args[0].as_bv().uint(), *w),
+        Op::SbvToFp(w) => int_to_fp(&args[0].as_bv().as_sint(), *w),
+        Op::FpToFp(w) => match (args[0], *w) {
+            (Value::F32(a), 32) => Value::F32(*a),
+            (Value::F32(a), 64) => Value::F64(*a as f64),
+            (Value::F64(a), 32) => Value::F32(*a as f32),
+            (Value::F64(a), 64) => Value::F64(*a),
+            (v, w) => panic!("fp2fp {} of {}", w, v),
+        },
         // tuple
-        Op::Tuple => Value::Tuple(c.cs.iter().map(|c| vs.get(c).unwrap().clone()).collect()),
+        Op::Tuple => Value::Tuple(args.iter().map(|a| (*a).clone()).collect()),
         Op::Field(i) => {
-            let t = vs.get(&c.cs[0]).unwrap().as_tuple();
-            assert!(i < &t.len(), "{} out of bounds for {}", i, c.cs[0]);
+            let t = args[0].as_tuple();
+            assert!(i < &t.len(), "{} out of bounds for {}", i, args[0]);
             t[*i].clone()
         }
         Op::Update(i) => {
-            let mut t = Vec::from(vs.get(&c.cs[0]).unwrap().as_tuple()).into_boxed_slice();
-            assert!(i < &t.len(), "{} out of bounds for {}", i, c.cs[0]);
-            let e = vs.get(&c.cs[1]).unwrap().clone();
+            let mut t = Vec::from(args[0].as_tuple()).into_boxed_slice();
+            assert!(i < &t.len(), "{} out of bounds for {}", i, args[0]);
+            let e = args[1].clone();
             assert_eq!(t[*i].sort(), e.sort());
             t[*i] = e;
             Value::Tuple(t)
         }
         // array
         Op::Store => {
-            let a = vs.get(&c.cs[0]).unwrap().as_array().clone();
-            let i = vs.get(&c.cs[1]).unwrap().clone();
-            let v = vs.get(&c.cs[2]).unwrap().clone();
+            let a = args[0].as_array().clone();
+            let i = args[1].clone();
+            let v = args[2].clone();
             Value::Array(a.store(i, v))
         }
         Op::Select => {
-            let a = vs.get(&c.cs[0]).unwrap().as_array().clone();
-            let i = vs.get(&c.cs[1]).unwrap();
+            let a = args[0].as_array().clone();
+            let i = args[1];
             a.select(i)
         }
         Op::Map(op) => {
-            let arg_cnt = c.cs.len();
-
-            //  term_vecs[i] will store a vector of all the i-th index entries of the array arguments
-            let mut term_vecs = vec![Vec::new(); vs.get(&c.cs[0]).unwrap().as_array().size];
-
-            for i in 0..arg_cnt {
-                let arr = vs.get(&c.cs[i]).unwrap().as_array().clone();
-                let iter = match check(&c.cs[i]) {
-                    Sort::Array(k, _, s) => (*k).clone().elems_iter_values().take(s).enumerate(),
-                    _ => panic!("Input type should be Array"),
-                };
-                for (j, jval) in iter {
-                    term_vecs[j].push(leaf_term(Op::Const(arr.clone().select(&jval))))
-                }
-            }
-
-            let mut res = match check(&c) {
-                Sort::Array(k, v, n) => Array::default((*k).clone(), &v, n),
-                _ => panic!("Output type of map should be array"),
-            };
-
-            let iter = match check(&c) {
-                Sort::Array(k, _, s) => (*k).clone().elems_iter_values().take(s).enumerate(),
-                _ => panic!("Input type should be Array"),
-            };
-            for (i, idxval) in iter {
-                let t = term((**op).clone(), term_vecs[i].clone());
-                let val = eval_value(vs, h, t);
-                res.map.insert(idxval, val);
+            let arrays: Vec<&Array> = args.iter().map(|a| a.as_array()).collect();
+            let val_sorts: Vec<Sort> = arrays.iter().map(|a| a.default.sort()).collect();
+            let val_sort = ty::rec_check_raw_helper(op, &val_sorts.iter().collect::<Vec<_>>())
+                .unwrap_or_else(|e| panic!("Bad map of {}: {:?}", op, e));
+            let (key_sort, size) = (&arrays[0].key_sort, arrays[0].size);
+            let mut res = Array::default(key_sort.clone(), &val_sort, size);
+            for k in key_sort.elems_iter_values().take(size) {
+                let elems: Vec<Value> = arrays.iter().map(|a| a.select(&k)).collect();
+                let val = eval_op(op, &elems.iter().collect::<Vec<_>>(), h);
+                res.map.insert(k, val);
             }
             Value::Array(res)
         }
         Op::NthSmallest(i) => {
-            let mut xs: Vec<Value> = c.cs.iter().map(|c| vs.get(c).unwrap().clone()).collect();
+            let mut xs: Vec<Value> = args.iter().map(|a| (*a).clone()).collect();
             xs.sort();
-            let res = xs[*i].clone();
-            println!("got: {:?}", res);
-            res
+            xs.swap_remove(*i)
+        }
+        Op::Lookup(id) => {
+            let k = args[0];
+            lookup::table(*id)
+                .get(k)
+                .unwrap_or_else(|| panic!("{} is not in lookup table {}", k, id))
+                .clone()
         }
         o => unimplemented!("eval: {:?}", o),
-    };
-    vs.insert(c.clone(), v.clone());
-    debug!("Eval {}\nAs   {}", c, v);
-    v
+    }
 }
 
 /// Make an array from a sequence of terms.
@@ -1718,7 +1850,7 @@ pub type PartyId = u8;
 /// Epoch number for a particular input
 pub type Epoch = u8;
 
-#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 /// An IR constraint system.
 pub struct ComputationMetadata {
     /// A map from party names to numbers assigned to them.
@@ -1729,6 +1861,23 @@ pub struct ComputationMetadata {
     pub input_vis: FxHashMap<String, InputMetadata>,
     /// The inputs for the computation itself (not the precomputation).
     pub computation_inputs: FxHashSet<String>,
+    /// The source locations of terms, if the front-end recorded them.
+    #[serde(skip)]
+    pub src_locs: src_loc::SrcLocs,
+    /// The lookup tables used by the computation, as ids in the [lookup] registry.
+    #[serde(with = "lookup::serde_tables")]
+    pub tables: Vec<usize>,
+}
+
+/// Source locations are not compared.
+impl PartialEq for ComputationMetadata {
+    fn eq(&self, other: &Self) -> bool {
+        self.party_ids == other.party_ids
+            && self.next_party_id == other.next_party_id
+            && self.input_vis == other.input_vis
+            && self.computation_inputs == other.computation_inputs
+            && self.tables == other.tables
+    }
 }
 
 /// An input to the computation
@@ -1806,6 +1955,17 @@ impl ComputationMetadata {
             })
             .epoch
     }
+    /// Returns the first epoch in which the input's value is known.
+    ///
+    /// A random input of epoch `e` is a challenge drawn at the end of epoch `e`, so it is known
+    /// from epoch `e + 1`. Other inputs are known in their own epoch, and unregistered variables
+    /// in epoch 0.
+    pub fn get_known_epoch(&self, input_name: &str) -> Epoch {
+        self.input_vis
+            .get(input_name)
+            .map(|input| input.epoch + input.random as Epoch)
+            .unwrap_or(0)
+    }
     /// Is this input public?
     pub fn is_input(&self, input_name: &str) -> bool {
         self.input_vis.contains_key(input_name)
@@ -1917,6 +2077,8 @@ impl ComputationMetadata {
             next_party_id,
             input_vis,
             computation_inputs,
+            src_locs: Default::default(),
+            tables: Vec::new(),
         }
     }
 
@@ -2010,6 +2172,11 @@ impl Computation {
             "Var: {} : {} (visibility: {:?}, epoch: {:?})",
             name, s, epoch, party
         );
+        // a precomputed input cannot be chosen before the inputs to its precomputation are known
+        let epoch = match &precompute {
+            Some(p) => std::cmp::max(epoch, self.known_epoch(p)),
+            None => epoch,
+        };
         self.metadata
             .new_input(name.to_owned(), party, epoch, random, s.clone());
         if let Some(p) = precompute {
@@ -2042,17 +2209,18 @@ impl Computation {
                 _ => panic!("Precomputation for new var {} with term\n\t{}\ninvolves multiple input non-public visibilities:\n\t{:?}", new_input_var, precomp, input_visiblities),
             }
         };
-        // TODO: Does this make sense?
-        //       Can you ever have a precomputed value with an epoch that isn't 0?
-        let epoch = {
-            let input_epochs: FxHashSet<Epoch> = extras::free_variables(precomp.clone())
-                .into_iter()
-                .map(|v| self.metadata.get_epoch(&v))
-                .collect();
-            *input_epochs.iter().max().unwrap_or(&0)
-        };
         let sort = check(&precomp);
-        self.new_var(&new_input_var, sort, vis, epoch, false, Some(precomp));
+        self.new_var(&new_input_var, sort, vis, 0, false, Some(precomp));
+    }
+
+    /// The first epoch in which the value of `t` is known: the latest epoch in which one of its
+    /// inputs is known. See [ComputationMetadata::get_known_epoch].
+    pub fn known_epoch(&self, t: &Term) -> Epoch {
+        extras::free_variables(t.clone())
+            .into_iter()
+            .map(|v| self.metadata.get_known_epoch(&v))
+            .max()
+            .unwrap_or(0)
     }
 
     /// Change the sort of a variables
@@ -2060,6 +2228,15 @@ impl Computation {
         self.metadata.remove_var(var);
     }
 
+    /// Register a lookup table for use by this computation, getting its id.
+    pub fn add_table(&mut self, table: lookup::Table) -> usize {
+        let id = lookup::register(table);
+        if !self.metadata.tables.contains(&id) {
+            self.metadata.tables.push(id);
+        }
+        id
+    }
+
     /// Assert `s` in the system.
     pub fn assert(&mut self, s: Term) {
         assert!(check(&s) == Sort::Bool);
@@ -18,6 +18,9 @@ pub struct PreComp {
     outputs: FxHashMap<String, Term>,
     /// The order that precomputes must be resolved in.
     pub sequence: Vec<String>,
+    /// The lookup tables that the outputs use, as ids in the [lookup] registry.
+    #[serde(with = "lookup::serde_tables")]
+    pub tables: Vec<usize>,
 }
 
 impl PreComp {
@@ -38,29 +41,36 @@ impl PreComp {
 
     /// Retain only the parts of this precomputation that can be evaluated from
     /// the `known` inputs.
+    ///
+    /// Outputs are resolved in sequence, so an output may use an earlier output that is retained.
     pub fn restrict_to_inputs(&mut self, mut known: FxHashSet<String>) {
-        // TODO: test this
         let os = &mut self.outputs;
-        let seq = &mut self.sequence;
-        let o_tuple = term(Op::Tuple, os.values().cloned().collect());
-        let cache = &mut TermSet::new();
-        for t in PostOrderIter::new(o_tuple) {
-            if let Op::Var(ref name, _) = &t.op {
-                if !known.contains(name) {
-                    cache.insert(t);
+        // whether each term depends on an unknown variable
+        let mut unknown: TermMap<bool> = TermMap::new();
+        self.sequence.retain(|s| {
+            let o = os.get(s).unwrap().clone();
+            let mut stack = vec![o.clone()];
+            while let Some(t) = stack.pop() {
+                if unknown.contains_key(&t) {
+                    continue;
+                }
+                let pending: Vec<Term> =
+                    t.cs.iter()
+                        .filter(|c| !unknown.contains_key(c))
+                        .cloned()
+                        .collect();
+                if pending.is_empty() {
+                    let u = match &t.op {
+                        Op::Var(name, _) => !known.contains(name),
+                        _ => t.cs.iter().any(|c| *unknown.get(c).unwrap()),
+                    };
+                    unknown.insert(t, u);
+                } else {
+                    stack.push(t);
+                    stack.extend(pending);
                 }
-                continue;
-            }
-
-            if t.cs.iter().any(|c| cache.contains(c)) {
-                cache.insert(t);
             }
-            continue;
-        }
-
-        seq.retain(|s| {
-            let o = os.get(s).unwrap();
-            let drop = cache.contains(o);
+            let drop = *unknown.get(&o).unwrap();
             if drop {
                 os.remove(s);
             } else {
@@ -106,6 +116,32 @@ impl PreComp {
             self.outputs.insert(o_name.clone(), o);
             self.sequence.push(o_name.clone());
         }
+        for id in &other.tables {
+            if !self.tables.contains(id) {
+                self.tables.push(*id);
+            }
+        }
         self
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn var(name: &str) -> Term {
+        leaf_term(Op::Var(name.to_owned(), Sort::Bool))
+    }
+
+    #[test]
+    fn restrict_chain() {
+        let mut p = PreComp::new();
+        p.add_output("b".into(), term![NOT; var("a")]);
+        p.add_output("c".into(), term![AND; var("b"), var("a")]);
+        p.add_output("d".into(), term![OR; var("c"), var("r")]);
+        p.add_output("e".into(), term![NOT; var("d")]);
+        p.restrict_to_inputs(vec!["a".to_owned()].into_iter().collect());
+        assert_eq!(p.sequence, vec!["b".to_owned(), "c".to_owned()]);
+        assert_eq!(p.outputs().len(), 2);
+    }
+}
@@ -0,0 +1,143 @@
+//! Source locations of terms
+//!
+//! Front-ends attribute each term to the source location (file, function, and line) that first
+//! produced it. Optimizations that rewrite terms should call [SrcLocs::update], which attributes
+//! each new term to the most recently recorded location among its children, and forgets terms
+//! that are no longer in the computation.
+//!
+//! Locations are debugging information only: they are not serialized and don't affect the equality
+//! of computations.
+
+use super::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+/// A source location
+pub struct SrcLoc {
+    /// The source file
+    pub file: String,
+    /// The enclosing function
+    pub function: String,
+    /// The line (starting at 1)
+    pub line: usize,
+}
+
+impl Display for SrcLoc {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{} ({})", self.file, self.line, self.function)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// A map from terms to source locations.
+///
+/// Locations are numbered in the order that they are first recorded.
+pub struct SrcLocs {
+    locs: Vec<SrcLoc>,
+    ids: FxHashMap<SrcLoc, usize>,
+    terms: TermMap<usize>,
+}
+
+impl SrcLocs {
+    /// Get the number for `loc`, numbering it if needed.
+    pub fn id(&mut self, loc: &SrcLoc) -> usize {
+        if let Some(id) = self.ids.get(loc) {
+            return *id;
+        }
+        let id = self.locs.len();
+        self.locs.push(loc.clone());
+        self.ids.insert(loc.clone(), id);
+        id
+    }
+
+    /// Attribute `t`, and all of its descendents that have no location, to `loc`.
+    pub fn record(&mut self, t: &Term, loc: &SrcLoc) {
+        if self.terms.contains_key(t) {
+            return;
+        }
+        let id = self.id(loc);
+        let mut stack = vec![t.clone()];
+        while let Some(t) = stack.pop() {
+            if !self.terms.contains_key(&t) {
+                stack.extend(t.cs.iter().cloned());
+                self.terms.insert(t, id);
+            }
+        }
+    }
+
+    /// The number of the location of `t`.
+    pub fn get_id(&self, t: &Term) -> Option<usize> {
+        self.terms.get(t).cloned()
+    }
+
+    /// The location of `t`.
+    pub fn get(&self, t: &Term) -> Option<&SrcLoc> {
+        self.get_id(t).map(|id| &self.locs[id])
+    }
+
+    /// All locations, by number.
+    pub fn locs(&self) -> &[SrcLoc] {
+        &self.locs
+    }
+
+    /// Are there no locations?
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Re-attribute the terms of a computation with `outputs`.
+    ///
+    /// Each term without a location gets the most recent location of its children. Terms that
+    /// aren't in the computation are forgotten.
+    pub fn update(&mut self, outputs: &[Term]) {
+        if self.is_empty() {
+            return;
+        }
+        let mut terms = TermMap::new();
+        let root = term(Op::Tuple, outputs.to_vec());
+        for t in PostOrderIter::new(root.clone()) {
+            let id = self
+                .terms
+                .get(&t)
+                .cloned()
+                .or_else(|| t.cs.iter().filter_map(|c| terms.get(c).cloned()).max());
+            if let Some(id) = id {
+                terms.insert(t, id);
+            }
+        }
+        terms.remove(&root);
+        self.terms = terms;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loc(line: usize) -> SrcLoc {
+        SrcLoc {
+            file: "a.zok".into(),
+            function: "main".into(),
+            line,
+        }
+    }
+
+    #[test]
+    fn record_and_update() {
+        let x = leaf_term(Op::Var("x".into(), Sort::Bool));
+        let y = leaf_term(Op::Var("y".into(), Sort::Bool));
+        let x_and_y = term![AND; x.clone(), y.clone()];
+        let mut locs = SrcLocs::default();
+        locs.record(&x, &loc(1));
+        locs.record(&x_and_y, &loc(2));
+        assert_eq!(locs.get(&x), Some(&loc(1)));
+        assert_eq!(locs.get(&y), Some(&loc(2)));
+        assert_eq!(locs.get(&x_and_y), Some(&loc(2)));
+
+        // a rewrite: (not x) or y
+        let new = term![OR; term![NOT; x.clone()], y.clone()];
+        locs.update(&[new.clone()]);
+        assert_eq!(locs.get(&new), Some(&loc(2)));
+        assert_eq!(locs.get(&term![NOT; x.clone()]), Some(&loc(1)));
+        assert_eq!(locs.get(&x_and_y), None);
+    }
+}
@@ -255,6 +255,7 @@ impl<'src> IrInterp<'src> {
             Leaf(Ident, b"fpnegative") => Ok(Op::FpUnPred(FpUnPred::Negative)),
             Leaf(Ident, b"fppositive") => Ok(Op::FpUnPred(FpUnPred::Positive)),
             Leaf(Ident, b"bv2fp") => Ok(Op::BvToFp),
+            Leaf(Ident, b"fp2bv") => Ok(Op::FpToBv),
             Leaf(Ident, b"+") => Ok(Op::PfNaryOp(PfNaryOp::Add)),
             Leaf(Ident, b"*") => Ok(Op::PfNaryOp(PfNaryOp::Mul)),
             Leaf(Ident, b"pfrecip") => Ok(Op::PfUnOp(PfUnOp::Recip)),
@@ -275,6 +276,7 @@ impl<'src> IrInterp<'src> {
                 [Leaf(Ident, b"field"), a] => Ok(Op::Field(self.usize(a))),
                 [Leaf(Ident, b"update"), a] => Ok(Op::Update(self.usize(a))),
                 [Leaf(Ident, b"nthsmallest"), a] => Ok(Op::NthSmallest(self.usize(a))),
+                [Leaf(Ident, b"lookup"), a] => Ok(Op::Lookup(self.usize(a))),
                 _ => todo!("Unparsed op: {}", tt),
             },
             _ => todo!("Unparsed op: {}", tt),
@@ -51,6 +51,7 @@ fn check_dependencies(t: &Term) -> Vec<Term> {
         Op::FpUnPred(_) => Vec::new(),
         Op::FpUnOp(_) => vec![t.cs[0].clone()],
         Op::BvToFp => vec![t.cs[0].clone()],
+        Op::FpToBv => vec![t.cs[0].clone()],
         Op::UbvToFp(_) => Vec::new(),
         Op::SbvToFp(_) => Vec::new(),
         Op::FpToFp(_) => Vec::new(),
@@ -67,6 +68,7 @@ fn check_dependencies(t: &Term) -> Vec<Term> {
         // TODO: for now, I'm assuming the type of all args will be the same
         //       though I don't think anything enforces this...
         Op::NthSmallest(_) => vec![t.cs[0].clone()],
+        Op::Lookup(_) => Vec::new(),
     }
 }
 
@@ -118,6 +120,11 @@ fn check_raw_step(t: &Term, tys: &TypeTable) -> Result<Sort, TypeErrorReason> {
             ))),
             Err(e) => Err(e),
         },
+        Op::FpToBv => match fp_or(get_ty(&t.cs[0]), "fp-to-bv") {
+            Ok(Sort::F32) => Ok(Sort::BitVector(32)),
+            Ok(_) => Ok(Sort::BitVector(64)),
+            Err(e) => Err(e),
+        },
         Op::UbvToFp(64) => Ok(Sort::F64),
         Op::UbvToFp(32) => Ok(Sort::F32),
         Op::SbvToFp(64) => Ok(Sort::F64),
@@ -181,6 +188,7 @@ fn check_raw_step(t: &Term, tys: &TypeTable) -> Result<Sort, TypeErrorReason> {
         }
         Op::Call(_, _, ret) => Ok(ret.clone()),
         Op::NthSmallest(_) => Ok(get_ty(&t.cs[0]).clone()),
+        Op::Lookup(id) => Ok(super::lookup::table(*id).val_sort.clone()),
         o => Err(TypeErrorReason::Custom(format!("other operator: {}", o))),
     }
 }
@@ -319,7 +327,9 @@ pub fn rec_check_raw_helper(oper: &Op, a: &[&Sort]) -> Result<Sort, TypeErrorRea
         (Op::FpUnOp(_), &[a]) => fp_or(a, "fp unary op").map(|a| a.clone()),
         (Op::FpUnPred(_), &[a]) => fp_or(a, "fp unary predicate").map(|_| Sort::Bool),
         (Op::BvToFp, &[Sort::BitVector(64)]) => Ok(Sort::F64),
-        (Op::BvToFp, &[Sort::BitVector(32)]) => Ok(Sort::F64),
+        (Op::BvToFp, &[Sort::BitVector(32)]) => Ok(Sort::F32),
+        (Op::FpToBv, &[Sort::F64]) => Ok(Sort::BitVector(64)),
+        (Op::FpToBv, &[Sort::F32]) => Ok(Sort::BitVector(32)),
         (Op::UbvToFp(64), &[a]) => bv_or(a, "ubv-to-fp").map(|_| Sort::F64),
         (Op::UbvToFp(32), &[a]) => bv_or(a, "ubv-to-fp").map(|_| Sort::F32),
         (Op::SbvToFp(64), &[a]) => bv_or(a, "sbv-to-fp").map(|_| Sort::F64),
@@ -431,6 +441,11 @@ pub fn rec_check_raw_helper(oper: &Op, a: &[&Sort]) -> Result<Sort, TypeErrorRea
                 )))
             }
         }
+        (Op::Lookup(id), &[a]) => {
+            let table = super::lookup::table(*id);
+            eq_or(&table.key_sort, a, "lookup")?;
+            Ok(table.val_sort.clone())
+        }
         (_, _) => Err(TypeErrorReason::Custom("other".to_string())),
     }
 }
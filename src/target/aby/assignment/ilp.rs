@@ -0,0 +1,546 @@
+//! ILP-based sharing assignment
+//!
+//! Loosely based on ["Efficient MPC via Program Analysis: A Framework for Efficient Optimal
+//! Mixing"](https://dl.acm.org/doi/pdf/10.1145/3319535.3339818) by Ishaq, Muhammad and Milanova,
+//! Ana L. and Zikas, Vassilis.
+//!
+//! Our actual ILP is as follows:
+//!
+//! Let `s`, `t` denote terms, and `a`, `b` denote protocols.
+//!
+//! Let `T[t, a]` be a binary variable indicating whether term `t` is evaluated using protocol `a`.
+//! Let `C[t, a, b]` be a binary variable indicating whether term `t` needs to be converted from
+//! `a` to `b`.
+//!
+//! Since each term is evaluated using one protocol,
+//!
+//! `forall t. 1 = \sum_a T[t, a]             (1)`
+//!
+//! Sometimes conversions are needed
+//!
+//! `forall t a b. forall s in Uses(t). C[t, a, b] >= T[t, a] + T[s, b] - 1     (2)`
+//!
+//! The constraint (2) is intendend to encode
+//!
+//! `forall t a b. C[t, a, b] = OR_(s in Uses(t)) T[t, a] AND T[s, b]`
+//!
+//! It does this well because (a) the system is SAT and (b) our objective is a linear combination
+//! of all variables (term and conversion) scaled by their cost. In trying to minimize that, `C`
+//! will be set to the smallest value possible (0) if either of the variables on the right of (2)
+//! are 0.  If they are both 1 (for ANY `s`), then it must be 1.
+//!
+//! ## Partitioning
+//!
+//! The ILP for a large computation is too large to solve, so [assign_partitioned] partitions the
+//! terms into regions, and solves one ILP per region, in order.
+//!
+//! Regions are runs of the terms in post-order, so each region's terms only use terms from it
+//! or from earlier regions. Each region is cut where the fewest terms are used on both sides of
+//! the cut (among the last half of the positions that keep it within the size limit), so that
+//! chains of terms tend to stay in one region.
+//!
+//! In the ILP for a region, each term `t` from an earlier region already has a protocol `a`, so
+//! its conversions are
+//!
+//! `forall b. forall s in Uses(t). C[t, a, b] >= T[s, b]     (3)`
+//!
+//! unless an earlier region already converts `t` to `b`. The ILP also includes the later uses of
+//! the region's terms (but not their other arguments), so that a region's choices account for
+//! the conversions and costs of its uses in the next regions. Only the region's own terms are
+//! assigned by its ILP. Since a region still cannot see beyond those uses, the result may cost more
+//! than that of the whole ILP.
+
+use fxhash::{FxHashMap, FxHashSet};
+use std::cmp::Reverse;
+
+use super::{ShareType, SharingMap, SHARE_TYPES};
+use crate::ir::term::*;
+use crate::target::aby::assignment::{get_cost_model, CostModel};
+
+use crate::target::ilp::{variable, Expression, Ilp, Variable};
+
+/// Uses an ILP to assign...
+pub fn assign(c: &Computation, cm: &str) -> SharingMap {
+    let costs = get_cost_model(cm);
+    build_ilp(c, &costs)
+}
+
+/// The default number of terms in each region for [assign_partitioned]
+pub const DEFAULT_REGION_SIZE: usize = 1000;
+
+/// Uses an ILP for each region of (at most) `region_size` terms to assign sharings.
+///
+/// See the module documentation.
+pub fn assign_partitioned(c: &Computation, cm: &str, region_size: usize) -> SharingMap {
+    let costs = get_cost_model(cm);
+    build_partitioned_ilp(c, &costs, region_size)
+}
+
+/// The terms of `c`, each after its children
+fn terms_in_order(c: &Computation) -> Vec<Term> {
+    let mut seen = TermSet::new();
+    let mut terms = Vec::new();
+    for o in &c.outputs {
+        for t in PostOrderIter::new(o.clone()) {
+            if seen.insert(t.clone()) {
+                terms.push(t);
+            }
+        }
+    }
+    terms
+}
+
+fn build_ilp(c: &Computation, costs: &CostModel) -> SharingMap {
+    build_region_ilp(
+        &terms_in_order(c),
+        &[],
+        &TermMap::new(),
+        &FxHashSet::default(),
+        costs,
+    )
+}
+
+/// Split `terms` (each after its children) into regions of at most `size` consecutive terms.
+///
+/// Each region is at least half full (except the last), and is cut where the fewest terms are
+/// used on both sides of the cut.
+fn regions(terms: &[Term], size: usize) -> Vec<&[Term]> {
+    let positions: FxHashMap<Term, usize> = terms
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.clone(), i))
+        .collect();
+    let mut last_use: Vec<usize> = (0..terms.len()).collect();
+    for (i, t) in terms.iter().enumerate() {
+        for c in &t.cs {
+            let j = positions[c];
+            last_use[j] = last_use[j].max(i);
+        }
+    }
+    // live[k]: the number of terms before position k that are used at or after it
+    let mut delta = vec![0i64; terms.len() + 2];
+    for (i, l) in last_use.into_iter().enumerate() {
+        if l > i {
+            delta[i + 1] += 1;
+            delta[l + 1] -= 1;
+        }
+    }
+    let live: Vec<i64> = delta
+        .iter()
+        .scan(0, |acc, d| {
+            *acc += d;
+            Some(*acc)
+        })
+        .collect();
+    let mut regions = Vec::new();
+    let mut start = 0;
+    while terms.len() - start > size {
+        // the latest of the best cuts
+        let end = (start + (size + 1) / 2..=start + size)
+            .min_by_key(|k| (live[*k], Reverse(*k)))
+            .unwrap();
+        regions.push(&terms[start..end]);
+        start = end;
+    }
+    if start < terms.len() {
+        regions.push(&terms[start..]);
+    }
+    regions
+}
+
+fn build_partitioned_ilp(c: &Computation, costs: &CostModel, region_size: usize) -> SharingMap {
+    assert!(region_size > 0, "Regions must have at least one term");
+    let terms = terms_in_order(c);
+    let mut uses: FxHashMap<Term, Vec<Term>> = FxHashMap::default();
+    for t in &terms {
+        for child in &t.cs {
+            uses.entry(child.clone()).or_default().push(t.clone());
+        }
+    }
+    let mut assignment = TermMap::new();
+    let mut converted = FxHashSet::default();
+    for region in regions(&terms, region_size) {
+        let in_region: TermSet = region.iter().cloned().collect();
+        let mut later_uses = Vec::new();
+        let mut seen = TermSet::new();
+        for t in region {
+            for u in uses.get(t).into_iter().flatten() {
+                if !in_region.contains(u) && seen.insert(u.clone()) {
+                    later_uses.push(u.clone());
+                }
+            }
+        }
+        let region_assignment =
+            build_region_ilp(region, &later_uses, &assignment, &converted, costs);
+        for t in region {
+            let ty = *region_assignment.get(t).unwrap();
+            for child in &t.cs {
+                let child_ty = region_assignment
+                    .get(child)
+                    .or_else(|| assignment.get(child));
+                if child_ty != Some(&ty) {
+                    converted.insert((child.clone(), ty));
+                }
+            }
+        }
+        for (t, ty) in region_assignment {
+            assignment.insert(t, ty);
+        }
+    }
+    assignment
+}
+
+/// Build and solve the ILP for the terms of `region` (each after its children), returning their
+/// assignment. Their other children come from earlier regions, and have sharings in `fixed`.
+/// Converting one of those to some sharing is free if an earlier region already does (i.e., it is
+/// in `converted`).
+///
+/// The ILP also includes `later_uses`: terms from later regions that use the region's terms.
+/// Their children from neither this region nor an earlier one are ignored.
+fn build_region_ilp(
+    region: &[Term],
+    later_uses: &[Term],
+    fixed: &SharingMap,
+    converted: &FxHashSet<(Term, ShareType)>,
+    costs: &CostModel,
+) -> SharingMap {
+    let terms: FxHashMap<Term, usize> = region
+        .iter()
+        .chain(later_uses)
+        .enumerate()
+        .map(|(i, t)| (t.clone(), i))
+        .collect();
+    let mut def_uses: FxHashSet<(Term, Term)> = FxHashSet::default();
+    // uses of terms from earlier regions
+    let mut fixed_uses: FxHashSet<(Term, Term)> = FxHashSet::default();
+    for (i, t) in region.iter().chain(later_uses).enumerate() {
+        for c in &t.cs {
+            if terms.contains_key(c) {
+                def_uses.insert((c.clone(), t.clone()));
+            } else if fixed.contains_key(c) {
+                fixed_uses.insert((c.clone(), t.clone()));
+            } else {
+                assert!(
+                    i >= region.len(),
+                    "Term {} is not in this region or an earlier one",
+                    c
+                );
+            }
+        }
+    }
+    let mut term_vars: FxHashMap<(Term, ShareType), (Variable, f64, String)> = FxHashMap::default();
+    let mut conv_vars: FxHashMap<(Term, ShareType, ShareType), (Variable, f64)> =
+        FxHashMap::default();
+    let mut ilp = Ilp::new();
+
+    // build variables for all term assignments
+    for (t, i) in terms.iter() {
+        let mut vars = vec![];
+        match &t.op {
+            Op::Var(..) | Op::Const(_) => {
+                for ty in &SHARE_TYPES {
+                    let name = format!("t_{}_{}", i, ty.char());
+                    let v = ilp.new_variable(variable().binary(), name.clone());
+                    term_vars.insert((t.clone(), *ty), (v, 0.0, name));
+                    vars.push(v);
+                }
+            }
+            _ => {
+                if let Some(costs) = costs.op_costs(t) {
+                    for (ty, cost) in costs {
+                        let name = format!("t_{}_{}", i, ty.char());
+                        let v = ilp.new_variable(variable().binary(), name.clone());
+                        term_vars.insert((t.clone(), ty), (v, cost, name));
+                        vars.push(v);
+                    }
+                } else {
+                    panic!("No cost for op {}", &t.op)
+                }
+            }
+        }
+        // Sum of assignments is at least 1.
+        ilp.new_constraint(
+            vars.into_iter()
+                .fold((0.0).into(), |acc: Expression, v| acc + v)
+                >> 1.0,
+        );
+    }
+
+    // build variables for all conversions assignments
+    for (def, use_) in &def_uses {
+        let def_i = terms.get(def).unwrap();
+        for from_ty in &SHARE_TYPES {
+            for to_ty in &SHARE_TYPES {
+                // if def can be from_ty, and use can be to_ty
+                if term_vars.contains_key(&(def.clone(), *from_ty))
+                    && term_vars.contains_key(&(use_.clone(), *to_ty))
+                    && from_ty != to_ty
+                {
+                    let v = ilp.new_variable(
+                        variable().binary(),
+                        format!("c_{}_{}2{}", def_i, from_ty.char(), to_ty.char()),
+                    );
+                    conv_vars.insert(
+                        (def.clone(), *from_ty, *to_ty),
+                        (v, costs.conversion_cost(def, *from_ty, *to_ty)),
+                    );
+                }
+            }
+        }
+    }
+
+    let def_uses: FxHashMap<Term, Vec<Term>> = {
+        let mut t = FxHashMap::default();
+        for (d, u) in def_uses {
+            t.entry(d).or_insert_with(Vec::new).push(u);
+        }
+        t
+    };
+
+    for (def, uses) in def_uses {
+        for use_ in uses {
+            for from_ty in &SHARE_TYPES {
+                for to_ty in &SHARE_TYPES {
+                    conv_vars.get(&(def.clone(), *from_ty, *to_ty)).map(|c| {
+                        term_vars.get(&(def.clone(), *from_ty)).map(|t_from| {
+                            // c[term i from pi to pi'] >= t[term j with pi'] + t[term i with pi] - 1
+                            term_vars
+                                .get(&(use_.clone(), *to_ty))
+                                .map(|t_to| ilp.new_constraint(c.0 >> (t_from.0 + t_to.0 - 1.0)))
+                        })
+                    });
+                }
+            }
+        }
+    }
+
+    // conversions of terms from earlier regions
+    let mut fixed_conv_vars: FxHashMap<(Term, ShareType), (Variable, f64)> = FxHashMap::default();
+    for (def, use_) in &fixed_uses {
+        let from_ty = *fixed.get(def).unwrap();
+        for to_ty in &SHARE_TYPES {
+            if *to_ty == from_ty || converted.contains(&(def.clone(), *to_ty)) {
+                continue;
+            }
+            if let Some(t_to) = term_vars.get(&(use_.clone(), *to_ty)) {
+                let key = (def.clone(), *to_ty);
+                if !fixed_conv_vars.contains_key(&key) {
+                    let v = ilp.new_variable(
+                        variable().binary(),
+                        format!("f_{}_{}", fixed_conv_vars.len(), to_ty.char()),
+                    );
+                    let cost = costs.conversion_cost(def, from_ty, *to_ty);
+                    fixed_conv_vars.insert(key.clone(), (v, cost));
+                }
+                // c[term i to pi'] >= t[term j with pi']
+                ilp.new_constraint(fixed_conv_vars[&key].0 >> t_to.0);
+            }
+        }
+    }
+
+    ilp.maximize(
+        -conv_vars
+            .values()
+            .chain(fixed_conv_vars.values())
+            .map(|(a, b)| (a, b))
+            .chain(term_vars.values().map(|(a, b, _)| (a, b)))
+            .fold(0.0.into(), |acc: Expression, (v, cost)| acc + *v * *cost),
+    );
+
+    let (_opt, solution) = ilp.default_solve().unwrap();
+
+    let mut assignment = TermMap::new();
+    for ((term, ty), (_, _, var_name)) in &term_vars {
+        if terms[term] < region.len() && solution.get(var_name).unwrap() == &1.0 {
+            assignment.insert(term.clone(), *ty);
+        }
+    }
+    assignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::var;
+
+    #[test]
+    fn parse_cost_model() {
+        let p = format!(
+            "{}/third_party/opa/adapted_costs.json",
+            var("CARGO_MANIFEST_DIR").expect("Could not find env var CARGO_MANIFEST_DIR")
+        );
+        let c = CostModel::from_opa_cost_file(&p);
+        // random checks from the file...
+        let mul = c.op_costs_at(&BV_MUL, 32).unwrap();
+        assert_eq!(&1127.0, mul.get(&ShareType::Yao).unwrap());
+        assert_eq!(&1731.0, mul.get(&ShareType::Boolean).unwrap());
+        assert_eq!(
+            &7.0,
+            c.op_costs_at(&BV_XOR, 32)
+                .unwrap()
+                .get(&ShareType::Boolean)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn mul1_bv_opt() {
+        let p = format!(
+            "{}/third_party/opa/adapted_costs.json",
+            var("CARGO_MANIFEST_DIR").expect("Could not find env var CARGO_MANIFEST_DIR")
+        );
+        let costs = CostModel::from_opa_cost_file(&p);
+        let cs = Computation {
+            precomputes: Default::default(),
+            outputs: vec![term![BV_MUL;
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+                leaf_term(Op::Var("b".to_owned(), Sort::BitVector(32)))
+            ]],
+            metadata: ComputationMetadata::default(),
+        };
+        let _assignment = build_ilp(&cs, &costs);
+    }
+
+    #[test]
+    fn huge_mul_then_eq() {
+        let p = format!(
+            "{}/third_party/opa/adapted_costs.json",
+            var("CARGO_MANIFEST_DIR").expect("Could not find env var CARGO_MANIFEST_DIR")
+        );
+        let costs = CostModel::from_opa_cost_file(&p);
+        let cs = Computation {
+            precomputes: Default::default(),
+            outputs: vec![term![Op::Eq;
+                term![BV_MUL;
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+                term![BV_MUL;
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+                term![BV_MUL;
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+                term![BV_MUL;
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+                term![BV_MUL;
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+                term![BV_MUL;
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+                term![BV_MUL;
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32)))
+            ]
+            ]
+            ]
+            ]
+            ]
+            ]
+            ],
+            leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32)))
+            ]],
+            metadata: ComputationMetadata::default(),
+        };
+        let assignment = build_ilp(&cs, &costs);
+        // Big enough to do the math with arith
+        assert_eq!(
+            &ShareType::Arithmetic,
+            assignment.get(&cs.outputs[0].cs[0]).unwrap()
+        );
+        // Then convert to boolean
+        assert_eq!(&ShareType::Boolean, assignment.get(&cs.outputs[0]).unwrap());
+    }
+
+    #[test]
+    fn big_mul_then_eq() {
+        let p = format!(
+            "{}/third_party/opa/adapted_costs.json",
+            var("CARGO_MANIFEST_DIR").expect("Could not find env var CARGO_MANIFEST_DIR")
+        );
+        let costs = CostModel::from_opa_cost_file(&p);
+        let cs = Computation {
+            precomputes: Default::default(),
+            outputs: vec![term![Op::Eq;
+                term![BV_MUL;
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+                term![BV_MUL;
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+                term![BV_MUL;
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+                term![BV_MUL;
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+                leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32)))
+            ]
+            ]
+            ]
+            ],
+            leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32)))
+            ]],
+            metadata: ComputationMetadata::default(),
+        };
+        let assignment = build_ilp(&cs, &costs);
+        // All yao
+        assert_eq!(
+            &ShareType::Yao,
+            assignment.get(&cs.outputs[0].cs[0]).unwrap()
+        );
+        assert_eq!(&ShareType::Yao, assignment.get(&cs.outputs[0]).unwrap());
+    }
+
+    #[test]
+    fn partitioned_one_region() {
+        let costs = get_cost_model("opa");
+        let a = leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32)));
+        let cs = Computation {
+            precomputes: Default::default(),
+            outputs: vec![term![Op::Eq;
+                term![BV_MUL; a.clone(), term![BV_MUL; a.clone(), a.clone()]],
+                a
+            ]],
+            metadata: ComputationMetadata::default(),
+        };
+        let assignment = build_partitioned_ilp(&cs, &costs, DEFAULT_REGION_SIZE);
+        let whole = build_ilp(&cs, &costs);
+        for t in terms_in_order(&cs) {
+            assert_eq!(whole.get(&t), assignment.get(&t));
+        }
+    }
+
+    #[test]
+    fn regions_cut_between_chains() {
+        let v = |n: &str| leaf_term(Op::Var(n.to_owned(), Sort::BitVector(32)));
+        let cs = Computation {
+            precomputes: Default::default(),
+            outputs: vec![term![BV_XOR; v("a"), v("b")], term![BV_XOR; v("c"), v("d")]],
+            metadata: ComputationMetadata::default(),
+        };
+        let terms = terms_in_order(&cs);
+        // not [a b (xor a b) c] [d (xor c d)]
+        let lens: Vec<usize> = regions(&terms, 4).iter().map(|r| r.len()).collect();
+        assert_eq!(lens, vec![3, 3]);
+        assert_eq!(regions(&terms, 6).len(), 1);
+        assert_eq!(regions(&terms, 1).len(), 6);
+    }
+
+    #[test]
+    fn partitioned_boundary_conversion() {
+        let costs = get_cost_model("opa");
+        let x = term![BV_XOR;
+            leaf_term(Op::Var("a".to_owned(), Sort::BitVector(32))),
+            leaf_term(Op::Var("b".to_owned(), Sort::BitVector(32)))
+        ];
+        let cs = Computation {
+            precomputes: Default::default(),
+            outputs: vec![term![Op::Eq; x.clone(), x.clone()]],
+            metadata: ComputationMetadata::default(),
+        };
+        // All yao
+        let whole = build_ilp(&cs, &costs);
+        assert_eq!(&ShareType::Yao, whole.get(&x).unwrap());
+        assert_eq!(&ShareType::Yao, whole.get(&cs.outputs[0]).unwrap());
+        // Alone, the first region would pick boolean XOR, which is cheaper than yao XOR. But it
+        // sees that converting XOR to yao for EQ costs more than boolean EQ saves.
+        let partitioned = build_partitioned_ilp(&cs, &costs, 3);
+        assert!(terms_in_order(&cs)
+            .iter()
+            .all(|t| partitioned.contains_key(t)));
+        assert_eq!(&ShareType::Yao, partitioned.get(&x).unwrap());
+        assert_eq!(&ShareType::Yao, partitioned.get(&cs.outputs[0]).unwrap());
+    }
+}
@@ -0,0 +1,428 @@
+//! Machinery for assigning operations to sharing schemes
+use crate::ir::term::*;
+use fxhash::FxHashMap;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::{fs::File, path::Path};
+
+#[cfg(feature = "lp")]
+pub mod ilp;
+
+/// The sharing scheme used for an operation
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum ShareType {
+    /// Arithmetic sharing (additive mod `Z_(2^l)`)
+    Arithmetic,
+    /// Boolean sharing (additive mod `Z_2`)
+    Boolean,
+    /// Yao sharing (one party holds `k_a`, `k_b`, other knows the `{k_a, k_b} <-> {0, 1}` mapping)
+    Yao,
+}
+
+/// List of share types.
+pub const SHARE_TYPES: [ShareType; 3] = [ShareType::Arithmetic, ShareType::Boolean, ShareType::Yao];
+
+impl ShareType {
+    /// Output associated char for each ShareType
+    pub fn char(&self) -> char {
+        match self {
+            ShareType::Arithmetic => 'a',
+            ShareType::Boolean => 'b',
+            ShareType::Yao => 'y',
+        }
+    }
+}
+
+/// A map from terms (operations or inputs) to sharing schemes they use
+pub type SharingMap = TermMap<ShareType>;
+
+/// The network between the parties, which determines the cost of communication
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Network {
+    /// The time taken by one round of communication
+    pub latency: f64,
+    /// The number of bytes sent per unit of time
+    pub bandwidth: f64,
+}
+
+impl Default for Network {
+    /// A network on which communication is free
+    fn default() -> Self {
+        Self {
+            latency: 0.0,
+            bandwidth: f64::INFINITY,
+        }
+    }
+}
+
+/// The cost of an operation (or conversion) at each of several bit-widths.
+///
+/// Between two of those widths, the cost is interpolated linearly. Below the smallest width, the
+/// cost is that of the smallest; above the largest, it grows in proportion to the width.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WidthCosts(BTreeMap<usize, f64>);
+
+impl WidthCosts {
+    /// The cost at `width`
+    pub fn at(&self, width: usize) -> f64 {
+        let below = self.0.range(..=width).next_back();
+        let above = self.0.range(width..).next();
+        match (below, above) {
+            (Some((w0, c0)), Some((w1, c1))) if w0 < w1 => {
+                c0 + (c1 - c0) * (width - w0) as f64 / (w1 - w0) as f64
+            }
+            (_, Some((_, c))) => *c,
+            (Some((w, c)), None) => c * width as f64 / *w as f64,
+            (None, None) => panic!("No costs"),
+        }
+    }
+}
+
+/// A cost model for ABY operations and share conversions
+#[derive(Debug)]
+pub struct CostModel {
+    /// Conversion costs: maps (from, to) pairs to cost
+    conversions: FxHashMap<(ShareType, ShareType), WidthCosts>,
+
+    /// Operator costs: maps (op, type) to cost
+    ops: FxHashMap<Op, FxHashMap<ShareType, WidthCosts>>,
+}
+
+/// The bit-width at which `t` is priced: that of its arguments for predicates, and its own for
+/// everything else.
+fn term_width(t: &Term) -> usize {
+    match check(t) {
+        Sort::Bool if !t.cs.is_empty() => t.cs.iter().map(value_width).max().unwrap(),
+        _ => value_width(t),
+    }
+}
+
+/// The bit-width of the value of `t`.
+fn value_width(t: &Term) -> usize {
+    match check(t) {
+        Sort::Bool => 1,
+        Sort::BitVector(w) => w,
+        s => panic!("ABY cannot compute on {}", s),
+    }
+}
+
+impl CostModel {
+    /// Create a cost model from an OPA json file, like [this](https://github.com/ishaq/OPA/blob/d613c15ff715fa62c03e37b673548f94c16bfe0d/solver/sample-costs.json)
+    ///
+    /// See [CostModel::from_json] for the format.
+    pub fn from_opa_cost_file(p: &impl AsRef<Path>) -> CostModel {
+        let f = File::open(p).unwrap_or_else(|e| {
+            panic!("Could not open cost model {}: {}", p.as_ref().display(), e)
+        });
+        let json: Value = serde_json::from_reader(f).expect("Bad JSON");
+        Self::from_json(&json)
+    }
+
+    /// Create a cost model from OPA-style JSON.
+    ///
+    /// The JSON maps each operator name (e.g., `"add"`) to a map from sharings (`"a"`, `"b"`, or
+    /// `"y"`) to costs, and each conversion name (e.g., `"a2b"`) to costs. Costs map bit-widths
+    /// (e.g., `"32"`) to either a number, or an object `{"time": t, "rounds": r, "bytes": n}`,
+    /// which costs `t + r * latency + n / bandwidth`. The optional `"network"` entry, `{"latency":
+    /// l, "bandwidth": b}`, gives the latency and bandwidth (see [Network]).
+    pub fn from_json(json: &Value) -> CostModel {
+        use ShareType::*;
+        let costs = json.as_object().expect("Cost model is not an object");
+        let network = match costs.get("network") {
+            Some(n) => {
+                let get = |k: &str| {
+                    n.get(k)
+                        .and_then(Value::as_f64)
+                        .unwrap_or_else(|| panic!("Missing '{}' number in network {}", k, n))
+                };
+                Network {
+                    latency: get("latency"),
+                    bandwidth: get("bandwidth"),
+                }
+            }
+            None => Network::default(),
+        };
+        let parse_entry = |e: &Value| -> f64 {
+            match e {
+                Value::Object(o) => {
+                    let get = |k: &str| -> f64 {
+                        o.get(k).map_or(0.0, |v| {
+                            v.as_f64()
+                                .unwrap_or_else(|| panic!("'{}' is not a number in {}", k, e))
+                        })
+                    };
+                    get("time") + get("rounds") * network.latency + get("bytes") / network.bandwidth
+                }
+                _ => e.as_f64().expect("not a number"),
+            }
+        };
+        let parse_costs = |o: &Value| -> WidthCosts {
+            let o = o
+                .as_object()
+                .unwrap_or_else(|| panic!("Bad costs {:#?}", o));
+            let costs: BTreeMap<usize, f64> = o
+                .iter()
+                .map(|(w, e)| {
+                    let w = w
+                        .parse()
+                        .unwrap_or_else(|_| panic!("Bad bit-width '{}' in {:#?}", w, o));
+                    (w, parse_entry(e))
+                })
+                .collect();
+            if costs.is_empty() {
+                panic!("No bit-widths in {:#?}", o);
+            }
+            WidthCosts(costs)
+        };
+        let get_cost = |op_name: &str| -> WidthCosts {
+            parse_costs(
+                costs
+                    .get(op_name)
+                    .unwrap_or_else(|| panic!("Missing op {} in {:#?}", op_name, costs)),
+            )
+        };
+        let mut conversions = FxHashMap::default();
+        let mut ops = FxHashMap::default();
+        // conversions
+        conversions.insert((Arithmetic, Boolean), get_cost("a2b"));
+        conversions.insert((Boolean, Arithmetic), get_cost("b2a"));
+        conversions.insert((Yao, Boolean), get_cost("y2b"));
+        conversions.insert((Boolean, Yao), get_cost("b2y"));
+        conversions.insert((Yao, Arithmetic), get_cost("y2a"));
+        conversions.insert((Arithmetic, Yao), get_cost("a2y"));
+
+        let ops_from_name = |name: &str| {
+            match name {
+                // assume comparisions are unsigned
+                "ge" => vec![BV_UGE],
+                "le" => vec![BV_ULE],
+                "gt" => vec![BV_UGT],
+                "lt" => vec![BV_ULT],
+                // assume n-ary ops apply to BVs
+                "add" => vec![BV_ADD],
+                "mul" => vec![BV_MUL],
+                "and" => vec![BV_AND],
+                "or" => vec![BV_OR],
+                "xor" => vec![BV_XOR],
+                // assume eq applies to BVs
+                "eq" => vec![Op::Eq],
+                "shl" => vec![BV_SHL],
+                // assume shr is logical, not arithmetic
+                "shr" => vec![BV_LSHR],
+                "sub" => vec![BV_SUB],
+                "mux" => vec![ITE],
+                "ne" => vec![Op::Not, Op::Eq],
+                "div" => vec![BV_UDIV],
+                "rem" => vec![BV_UREM],
+                // added to pass test case
+                "&&" => vec![AND],
+                "||" => vec![OR],
+                _ => panic!("Unknown operator name: {}", name),
+            }
+        };
+        for (op_name, cost) in costs {
+            // HACK: assumes the presence of 2 partitions names into conversion and otherwise.
+            if !op_name.contains('2') && op_name != "network" {
+                for op in ops_from_name(op_name) {
+                    for (share_type, share_name) in &[(Arithmetic, "a"), (Boolean, "b"), (Yao, "y")]
+                    {
+                        if let Some(c) = cost.get(share_name) {
+                            ops.entry(op.clone())
+                                .or_insert_with(FxHashMap::default)
+                                .insert(*share_type, parse_costs(c));
+                        }
+                    }
+                }
+            }
+        }
+        CostModel { conversions, ops }
+    }
+
+    /// The cost of `op` at `width` bits in each sharing that supports it, if any does.
+    pub fn op_costs_at(&self, op: &Op, width: usize) -> Option<FxHashMap<ShareType, f64>> {
+        self.ops
+            .get(op)
+            .map(|costs| costs.iter().map(|(ty, c)| (*ty, c.at(width))).collect())
+    }
+
+    /// The cost of computing `t` in each sharing that supports it, if any does.
+    pub fn op_costs(&self, t: &Term) -> Option<FxHashMap<ShareType, f64>> {
+        self.op_costs_at(&t.op, term_width(t))
+    }
+
+    /// The cost of converting the value of `t` from sharing `from` to `to`.
+    pub fn conversion_cost(&self, t: &Term, from: ShareType, to: ShareType) -> f64 {
+        self.conversions
+            .get(&(from, to))
+            .unwrap_or_else(|| panic!("No cost for converting {:?} to {:?}", from, to))
+            .at(value_width(t))
+    }
+}
+
+/// Get a cost model: `"opa"` or `"hycc"` (the models bundled with CirC), or the path of a cost
+/// model file (see [CostModel::from_json]).
+pub fn get_cost_model(cm: &str) -> CostModel {
+    let json = match cm {
+        "opa" => include_str!("../../../../third_party/opa/adapted_costs.json"),
+        "hycc" => include_str!("../../../../third_party/hycc/adapted_costs.json"),
+        path => return CostModel::from_opa_cost_file(&path),
+    };
+    CostModel::from_json(&serde_json::from_str(json).expect("Bad JSON"))
+}
+
+/// The cheapest sharing for `t` among `tys` (the earliest, on ties), skipping those that the cost
+/// model has no cost for. If it has none of them, `default`.
+fn cheapest(cost_model: &CostModel, t: &Term, tys: &[ShareType], default: ShareType) -> ShareType {
+    let costs = cost_model.op_costs(t).unwrap_or_default();
+    let mut best: Option<(ShareType, f64)> = None;
+    for ty in tys {
+        if let Some(c) = costs.get(ty) {
+            if best.map_or(true, |(_, min)| *c < min) {
+                best = Some((*ty, *c));
+            }
+        }
+    }
+    best.map_or(default, |(ty, _)| ty)
+}
+
+/// Assigns boolean sharing to all terms
+pub fn assign_all_boolean(c: &Computation, _cm: &str) -> SharingMap {
+    c.outputs
+        .iter()
+        .flat_map(|output| {
+            PostOrderIter::new(output.clone()).map(|term| (term, ShareType::Boolean))
+        })
+        .collect()
+}
+
+/// Assigns Yao sharing to all terms
+pub fn assign_all_yao(c: &Computation, _cm: &str) -> SharingMap {
+    c.outputs
+        .iter()
+        .flat_map(|output| PostOrderIter::new(output.clone()).map(|term| (term, ShareType::Yao)))
+        .collect()
+}
+
+/// Assign greedy Arithmetic and Boolean sharings based on cost model
+pub fn assign_arithmetic_and_boolean(c: &Computation, cm: &str) -> SharingMap {
+    use ShareType::*;
+    let cost_model = get_cost_model(cm);
+    c.outputs
+        .iter()
+        .flat_map(|output| {
+            PostOrderIter::new(output.clone()).map(|term| {
+                let ty = cheapest(&cost_model, &term, &[Boolean, Arithmetic], Boolean);
+                (term, ty)
+            })
+        })
+        .collect()
+}
+
+/// Assign greedy Arithmetic and yao sharings based on cost model
+pub fn assign_arithmetic_and_yao(c: &Computation, cm: &str) -> SharingMap {
+    use ShareType::*;
+    let cost_model = get_cost_model(cm);
+    c.outputs
+        .iter()
+        .flat_map(|output| {
+            PostOrderIter::new(output.clone()).map(|term| {
+                let ty = cheapest(&cost_model, &term, &[Yao, Arithmetic], Yao);
+                (term, ty)
+            })
+        })
+        .collect()
+}
+
+/// Assign all greedy sharings based on cost model
+pub fn assign_greedy(c: &Computation, cm: &str) -> SharingMap {
+    use ShareType::*;
+    let cost_model = get_cost_model(cm);
+    c.outputs
+        .iter()
+        .flat_map(|output| {
+            PostOrderIter::new(output.clone()).map(|term| {
+                let ty = cheapest(&cost_model, &term, &[Yao, Arithmetic, Boolean], Boolean);
+                (term, ty)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn conversions(cost: f64) -> Value {
+        json!({ "32": cost })
+    }
+
+    fn model(extra: Value) -> CostModel {
+        let mut json = json!({
+            "a2b": conversions(1.0),
+            "b2a": conversions(1.0),
+            "a2y": conversions(1.0),
+            "y2a": conversions(1.0),
+            "b2y": conversions(1.0),
+            "y2b": conversions(2.0),
+            "mul": {
+                "a": { "8": 10.0, "16": 20.0, "32": 60.0 },
+                "y": { "32": { "time": 5.0, "rounds": 2, "bytes": 1000 } }
+            },
+        });
+        for (k, v) in extra.as_object().unwrap() {
+            json[k] = v.clone();
+        }
+        CostModel::from_json(&json)
+    }
+
+    #[test]
+    fn width_interpolation() {
+        let m = model(json!({}));
+        let cost = |w| m.op_costs_at(&BV_MUL, w).unwrap()[&ShareType::Arithmetic];
+        assert_eq!(cost(1), 10.0);
+        assert_eq!(cost(8), 10.0);
+        assert_eq!(cost(12), 15.0);
+        assert_eq!(cost(24), 40.0);
+        assert_eq!(cost(64), 120.0);
+        let a = leaf_term(Op::Var("a".into(), Sort::BitVector(12)));
+        let t = term![BV_MUL; a.clone(), a.clone()];
+        assert_eq!(m.op_costs(&t).unwrap()[&ShareType::Arithmetic], 15.0);
+        let cmp = term![BV_ULT; a.clone(), a];
+        assert!(m.op_costs(&cmp).is_none());
+        assert_eq!(
+            m.conversion_cost(&cmp, ShareType::Yao, ShareType::Boolean),
+            2.0
+        );
+    }
+
+    #[test]
+    fn network() {
+        let yao = |m: &CostModel| m.op_costs_at(&BV_MUL, 32).unwrap()[&ShareType::Yao];
+        assert_eq!(yao(&model(json!({}))), 5.0);
+        let m = model(json!({ "network": { "latency": 100.0, "bandwidth": 10.0 } }));
+        assert_eq!(yao(&m), 5.0 + 200.0 + 100.0);
+    }
+
+    #[test]
+    fn greedy_skips_missing_sharings() {
+        use ShareType::*;
+        let m = model(json!({}));
+        let a = leaf_term(Op::Var("a".into(), Sort::BitVector(32)));
+        let t = term![BV_MUL; a.clone(), a];
+        // no boolean cost for mul
+        assert_eq!(
+            cheapest(&m, &t, &[Boolean, Arithmetic], Boolean),
+            Arithmetic
+        );
+        assert_eq!(cheapest(&m, &t, &[Yao, Arithmetic, Boolean], Boolean), Yao);
+        assert_eq!(cheapest(&m, &t, &[Boolean], Yao), Yao);
+    }
+
+    #[test]
+    fn bundled_models() {
+        for cm in &["opa", "hycc"] {
+            assert!(get_cost_model(cm).op_costs_at(&BV_MUL, 32).is_some());
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! A plaintext interpreter for ABY bytecode
+//!
+//! [super::trans] lowers a computation to a bytecode, which the ABY framework runs as an MPC. This
+//! module runs it in the clear, so that the lowering can be tested without ABY.
+//!
+//! The bytecode has one gate per line: `<#inputs> <#outputs> <inputs> <outputs> <op>`. Inputs and
+//! outputs are share numbers, except:
+//! * `IN` gates have inputs `<name> <party> <share>` (or `<name> 2 <bitlen> <share>` for public
+//!   inputs),
+//! * `CONS_bool` and `CONS_bv` gates have a constant as their input,
+//! * `SHL` and `LSHR` gates have a constant shift amount as their second input, and
+//! * `OUT` gates have one input, and no outputs.
+//!
+//! The share map has one line per share: `<share> <a|b|y>`.
+//!
+//! All values are ABY's `BITLEN`-bit unsigned integers; booleans are 0 or 1. ABY computes the same
+//! values under every sharing, so the interpreter only checks that each share has one.
+
+use super::assignment::ShareType;
+use super::trans::{aby_var_name, to_aby_bytecode};
+use crate::ir::term::*;
+
+use fxhash::FxHashMap as HashMap;
+
+/// The width of ABY's values
+const BITLEN: usize = 32;
+const MASK: u64 = (1 << BITLEN) - 1;
+
+#[derive(Debug, Clone)]
+enum Gate {
+    /// An input, and its share
+    In(String, usize),
+    /// A constant, and its share
+    Const(u64, usize),
+    /// A shift, its argument share, its amount, and its share
+    Shift(String, usize, u64, usize),
+    /// Any other operator, its argument shares, and its share
+    Op(String, Vec<usize>, usize),
+    /// An output
+    Out(usize),
+}
+
+/// An ABY program: bytecode, and a share map
+#[derive(Debug, Clone)]
+pub struct Program {
+    gates: Vec<Gate>,
+    share_types: HashMap<usize, ShareType>,
+}
+
+fn parse_share(s: &str) -> usize {
+    s.parse()
+        .unwrap_or_else(|_| panic!("Bad share number: {}", s))
+}
+
+fn parse_gate(line: &str) -> Gate {
+    let toks: Vec<&str> = line.split_whitespace().collect();
+    if toks.len() < 3 {
+        bad_gate(line)
+    }
+    let n_in: usize = toks[0].parse().unwrap_or_else(|_| bad_gate(line));
+    let n_out: usize = toks[1].parse().unwrap_or_else(|_| bad_gate(line));
+    if toks.len() != n_in + n_out + 3 {
+        bad_gate(line)
+    }
+    let ins = &toks[2..2 + n_in];
+    let outs = &toks[2 + n_in..2 + n_in + n_out];
+    let op = toks[toks.len() - 1];
+    match (op, n_in, n_out) {
+        ("IN", 2, 1) | ("IN", 3, 1) => Gate::In(ins[0].to_owned(), parse_share(outs[0])),
+        ("CONS_bool", 1, 1) | ("CONS_bv", 1, 1) => {
+            let c: i64 = ins[0].parse().unwrap_or_else(|_| bad_gate(line));
+            Gate::Const(c as u64 & MASK, parse_share(outs[0]))
+        }
+        ("SHL", 2, 1) | ("LSHR", 2, 1) => Gate::Shift(
+            op.to_owned(),
+            parse_share(ins[0]),
+            ins[1].parse().unwrap_or_else(|_| bad_gate(line)),
+            parse_share(outs[0]),
+        ),
+        ("OUT", 1, 0) => Gate::Out(parse_share(ins[0])),
+        ("NOT", 1, 1) | ("MUX", 3, 1) => Gate::Op(
+            op.to_owned(),
+            ins.iter().map(|s| parse_share(s)).collect(),
+            parse_share(outs[0]),
+        ),
+        (
+            "EQ" | "AND" | "OR" | "XOR" | "GT" | "LT" | "GE" | "LE" | "ADD" | "SUB" | "MUL" | "DIV"
+            | "REM",
+            2,
+            1,
+        ) => Gate::Op(
+            op.to_owned(),
+            ins.iter().map(|s| parse_share(s)).collect(),
+            parse_share(outs[0]),
+        ),
+        _ => bad_gate(line),
+    }
+}
+
+fn bad_gate(line: &str) -> ! {
+    panic!("Bad gate: {}", line)
+}
+
+fn apply(op: &str, args: &[u64]) -> u64 {
+    match op {
+        "NOT" => args[0] ^ 1,
+        "MUX" => {
+            if args[0] != 0 {
+                args[1]
+            } else {
+                args[2]
+            }
+        }
+        "EQ" => (args[0] == args[1]) as u64,
+        "GT" => (args[0] > args[1]) as u64,
+        "LT" => (args[0] < args[1]) as u64,
+        "GE" => (args[0] >= args[1]) as u64,
+        "LE" => (args[0] <= args[1]) as u64,
+        "AND" => args[0] & args[1],
+        "OR" => args[0] | args[1],
+        "XOR" => args[0] ^ args[1],
+        "ADD" => args[0].wrapping_add(args[1]) & MASK,
+        "SUB" => args[0].wrapping_sub(args[1]) & MASK,
+        "MUL" => args[0].wrapping_mul(args[1]) & MASK,
+        // division by zero is as in the IR
+        "DIV" => args[0].checked_div(args[1]).unwrap_or(MASK),
+        "REM" => args[0].checked_rem(args[1]).unwrap_or(args[0]),
+        _ => unreachable!("Unknown operator {}", op),
+    }
+}
+
+impl Program {
+    /// Parse a program from the text of its bytecode and share map.
+    pub fn parse(bytecode: &str, share_map: &str) -> Self {
+        let mut share_types = HashMap::default();
+        for line in share_map.lines().filter(|l| !l.trim().is_empty()) {
+            let toks: Vec<&str> = line.split_whitespace().collect();
+            assert_eq!(toks.len(), 2, "Bad share map line: {}", line);
+            let share_type = match toks[1] {
+                "a" => ShareType::Arithmetic,
+                "b" => ShareType::Boolean,
+                "y" => ShareType::Yao,
+                t => panic!("Unknown sharing {} in share map line: {}", t, line),
+            };
+            let share = parse_share(toks[0]);
+            if share_types.insert(share, share_type).is_some() {
+                panic!("Share {} appears twice in the share map", share);
+            }
+        }
+        let gates = bytecode
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(parse_gate)
+            .collect();
+        Self { gates, share_types }
+    }
+
+    /// Run the program, given a value for each input (by bytecode name), returning its outputs.
+    pub fn eval(&self, inputs: &HashMap<String, u64>) -> Vec<u64> {
+        let mut shares: HashMap<usize, u64> = HashMap::default();
+        let mut outputs = Vec::new();
+        let get = |shares: &HashMap<usize, u64>, s: &usize| -> u64 {
+            *shares
+                .get(s)
+                .unwrap_or_else(|| panic!("Share {} is used before it is set", s))
+        };
+        for gate in &self.gates {
+            let (share, value) = match gate {
+                Gate::In(name, share) => {
+                    let value = *inputs
+                        .get(name)
+                        .unwrap_or_else(|| panic!("No value for input {}", name));
+                    assert!(
+                        value <= MASK,
+                        "Input {} is wider than {} bits",
+                        name,
+                        BITLEN
+                    );
+                    (*share, value)
+                }
+                Gate::Const(c, share) => (*share, *c),
+                Gate::Shift(op, a, amt, share) => {
+                    let a = get(&shares, a);
+                    let value = match op.as_str() {
+                        "SHL" => a.checked_shl(*amt as u32).unwrap_or(0) & MASK,
+                        _ => a.checked_shr(*amt as u32).unwrap_or(0),
+                    };
+                    (*share, value)
+                }
+                Gate::Op(op, args, share) => {
+                    let args: Vec<u64> = args.iter().map(|a| get(&shares, a)).collect();
+                    (*share, apply(op, &args))
+                }
+                Gate::Out(share) => {
+                    outputs.push(get(&shares, share));
+                    continue;
+                }
+            };
+            assert!(
+                self.share_types.contains_key(&share),
+                "Share {} has no sharing",
+                share
+            );
+            if shares.insert(share, value).is_some() {
+                panic!("Share {} is set twice", share);
+            }
+        }
+        outputs
+    }
+}
+
+/// The ABY representation of `v`.
+fn to_aby_value(v: &Value) -> u64 {
+    match v {
+        Value::Bool(b) => *b as u64,
+        Value::BitVector(bv) if bv.width() <= BITLEN => bv.uint().to_u64().unwrap(),
+        _ => panic!("ABY cannot represent {}", v),
+    }
+}
+
+/// Lower `ir` to ABY (with cost model `cm` and selection scheme `ss`), run the bytecode on
+/// `values`, and check that its outputs are those of `ir`.
+pub fn check_lowering(ir: &Computation, cm: &str, ss: &str, values: &HashMap<String, Value>) {
+    let expected: Vec<u64> = ir
+        .outputs
+        .iter()
+        .map(|o| to_aby_value(&eval(o, values)))
+        .collect();
+    let (bytecode, share_map) = to_aby_bytecode(ir.clone(), cm, ss);
+    let program = Program::parse(&bytecode.concat(), &share_map.concat());
+    let inputs: HashMap<String, u64> = values
+        .iter()
+        .map(|(name, v)| (aby_var_name(name), to_aby_value(v)))
+        .collect();
+    assert_eq!(
+        expected,
+        program.eval(&inputs),
+        "ABY outputs differ from the IR's on {:?}",
+        values
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rug::Integer;
+
+    fn bv(u: u64) -> Value {
+        Value::BitVector(BitVector::new(Integer::from(u), BITLEN))
+    }
+
+    #[test]
+    fn bv_ops() {
+        let mut cs = Computation::new();
+        let a = cs.new_var("a", Sort::BitVector(BITLEN), Some(0), 0, false, None);
+        let b = cs.new_var("b", Sort::BitVector(BITLEN), Some(1), 0, false, None);
+        cs.outputs = vec![
+            term![BV_ADD; a.clone(), b.clone(), a.clone()],
+            term![BV_SUB; a.clone(), b.clone()],
+            term![BV_MUL; a.clone(), b.clone()],
+            term![BV_UDIV; a.clone(), b.clone()],
+            term![BV_UREM; a.clone(), b.clone()],
+            term![BV_XOR; term![BV_SHL; a.clone(), bv_lit(3, BITLEN)], b.clone()],
+            term![ITE; term![BV_ULT; a.clone(), b.clone()], a.clone(), bv_lit(7, BITLEN)],
+        ];
+        for (x, y) in &[(5, 9), (9, 5), (0xffff_ffff, 2), (17, 0)] {
+            let values = vec![("a".to_owned(), bv(*x)), ("b".to_owned(), bv(*y))]
+                .into_iter()
+                .collect();
+            for ss in &["b", "y"] {
+                check_lowering(&cs, "hycc", ss, &values);
+            }
+        }
+    }
+
+    #[test]
+    fn bool_ops() {
+        let mut cs = Computation::new();
+        let a = cs.new_var("a", Sort::Bool, Some(0), 0, false, None);
+        let b = cs.new_var("b", Sort::Bool, Some(1), 0, false, None);
+        let c = cs.new_var("c", Sort::Bool, None, 0, false, None);
+        cs.outputs = vec![
+            term![AND; a.clone(), b.clone(), c.clone()],
+            term![OR; term![NOT; a.clone()], b.clone()],
+            term![EQ; term![XOR; a.clone(), c.clone()], b.clone()],
+        ];
+        for bits in 0..8 {
+            let values = vec![
+                ("a".to_owned(), Value::Bool(bits & 1 != 0)),
+                ("b".to_owned(), Value::Bool(bits & 2 != 0)),
+                ("c".to_owned(), Value::Bool(bits & 4 != 0)),
+            ]
+            .into_iter()
+            .collect();
+            check_lowering(&cs, "hycc", "b", &values);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn use_before_set() {
+        Program::parse("2 1 0 1 2 ADD\n1 0 2 OUT\n", "0 b\n1 b\n2 b\n").eval(&HashMap::default());
+    }
+}
@@ -0,0 +1,5 @@
+//! ABY
+pub mod assignment;
+pub mod interp;
+pub mod trans;
+pub mod utils;
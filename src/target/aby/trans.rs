@@ -0,0 +1,531 @@
+//! Lowering IR to ABY DSL
+//! [EzPC Compiler](https://github.com/mpc-msri/EzPC/blob/da94a982709123c8186d27c9c93e27f243d85f0e/EzPC/EzPC/ABY_example/common/ezpc.h)
+
+//! Inv gates need to typecast circuit object to boolean circuit
+//! [Link to comment in EzPC Compiler](https://github.com/mpc-msri/EzPC/blob/da94a982709123c8186d27c9c93e27f243d85f0e/EzPC/EzPC/codegen.ml)
+
+use crate::ir::opt::cfold::fold;
+use crate::ir::term::*;
+#[cfg(feature = "lp")]
+use crate::target::aby::assignment::ilp::{assign, assign_partitioned, DEFAULT_REGION_SIZE};
+use crate::target::aby::assignment::SharingMap;
+use crate::target::aby::utils::*;
+use crate::target::compound::lower_compound_terms;
+use std::fmt;
+use std::path::Path;
+
+use super::assignment::assign_all_boolean;
+use super::assignment::assign_all_yao;
+use super::assignment::assign_arithmetic_and_boolean;
+use super::assignment::assign_arithmetic_and_yao;
+use super::assignment::assign_greedy;
+
+const PUBLIC: u8 = 2;
+
+#[derive(Clone)]
+enum EmbeddedTerm {
+    Bool(String),
+    Bv(String),
+}
+
+impl fmt::Display for EmbeddedTerm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmbeddedTerm::Bool(s) => {
+                write!(f, "bool({})", s)
+            }
+            EmbeddedTerm::Bv(s) => {
+                write!(f, "bv({})", s)
+            }
+        }
+    }
+}
+
+struct ToABY {
+    md: ComputationMetadata,
+    inputs: TermSet,
+    cache: TermMap<EmbeddedTerm>,
+    term_to_share_cnt: TermMap<i32>,
+    s_map: SharingMap,
+    share_cnt: i32,
+    bytecode_output: Vec<String>,
+    share_map_output: Vec<String>,
+}
+
+impl Drop for ToABY {
+    fn drop(&mut self) {
+        use std::mem::take;
+        // drop everything that uses a Term
+        drop(take(&mut self.md));
+        self.inputs.clear();
+        self.cache.clear();
+        self.term_to_share_cnt.clear();
+        self.s_map.clear();
+        // clean up
+        garbage_collect();
+    }
+}
+
+impl ToABY {
+    fn new(s_map: SharingMap, md: ComputationMetadata) -> Self {
+        Self {
+            md,
+            inputs: TermSet::new(),
+            cache: TermMap::new(),
+            term_to_share_cnt: TermMap::new(),
+            s_map,
+            share_cnt: 0,
+            bytecode_output: Vec::new(),
+            share_map_output: Vec::new(),
+        }
+    }
+
+    /// Give each new term in `term_` a share, and record its sharing in the share map.
+    fn map_terms_to_shares(&mut self, term_: Term) {
+        for t in PostOrderIter::new(term_) {
+            if !self.term_to_share_cnt.contains_key(&t) {
+                let share_type = self.s_map.get(&t).unwrap();
+                let line = format!("{} {}\n", self.share_cnt, share_type.char());
+                self.share_map_output.push(line);
+                self.term_to_share_cnt.insert(t, self.share_cnt);
+                self.share_cnt += 1;
+            }
+        }
+    }
+
+    fn get_var_name(t: &Term) -> String {
+        match &t.op {
+            Op::Var(name, _) => aby_var_name(name),
+            _ => panic!("Term {} is not of type Var", t),
+        }
+    }
+
+    fn get_share_name(&mut self, t: &Term) -> String {
+        let share_cnt = self.term_to_share_cnt.get(t).unwrap();
+        format!("s_{}", share_cnt)
+    }
+
+    fn unwrap_vis(&self, name: &str) -> u8 {
+        match self.md.get_input_visibility(name) {
+            Some(role) => role,
+            None => PUBLIC,
+        }
+    }
+
+    /// Embed the n-ary operator `t` as a chain of binary `op` gates, where all but the last gate
+    /// write fresh shares (with the same sharing as `t`).
+    fn embed_nary(&mut self, t: &Term, op: &str) {
+        let s = *self.term_to_share_cnt.get(t).unwrap();
+        let mut acc = *self.term_to_share_cnt.get(&t.cs[0]).unwrap();
+        for (i, c) in t.cs.iter().enumerate().skip(1) {
+            let out = if i + 1 == t.cs.len() {
+                s
+            } else {
+                let out = self.share_cnt;
+                self.share_cnt += 1;
+                let share_type = self.s_map.get(t).unwrap();
+                let line = format!("{} {}\n", out, share_type.char());
+                self.share_map_output.push(line);
+                out
+            };
+            let b = self.term_to_share_cnt.get(c).unwrap();
+            let line = format!("2 1 {} {} {} {}\n", acc, b, out, op);
+            self.bytecode_output.push(line);
+            acc = out;
+        }
+    }
+
+    fn embed_eq(&mut self, t: Term, a_term: Term, b_term: Term) {
+        let share = self.get_share_name(&t);
+        let s = self.term_to_share_cnt.get(&t).unwrap();
+        let a = self.term_to_share_cnt.get(&t.cs[0]).unwrap();
+        let b = self.term_to_share_cnt.get(&t.cs[1]).unwrap();
+        let op = "EQ";
+        let line = format!("2 1 {} {} {} {}\n", a, b, s, op);
+        self.bytecode_output.push(line);
+        match check(&a_term) {
+            Sort::Bool => {
+                self.check_bool(&a_term);
+                self.check_bool(&b_term);
+                self.cache.insert(t, EmbeddedTerm::Bool(share));
+            }
+            Sort::BitVector(_) => {
+                self.check_bv(&a_term);
+                self.check_bv(&b_term);
+                self.cache.insert(t, EmbeddedTerm::Bool(share));
+            }
+            e => panic!("Unimplemented sort for Eq: {:?}", e),
+        }
+    }
+
+    /// Given term `t`, type-check `t` is of type Bool
+    fn check_bool(&self, t: &Term) {
+        match self
+            .cache
+            .get(t)
+            .unwrap_or_else(|| panic!("Missing wire for {:?}", t))
+        {
+            EmbeddedTerm::Bool(_) => (),
+            _ => panic!("Non-bool for {:?}", t),
+        }
+    }
+
+    fn embed_bool(&mut self, t: Term) {
+        let share = self.get_share_name(&t);
+        let s = self.term_to_share_cnt.get(&t).unwrap();
+        match &t.op {
+            Op::Var(name, Sort::Bool) => {
+                if !self.inputs.contains(&t) && self.md.input_vis.contains_key(name) {
+                    let term_name = ToABY::get_var_name(&t);
+                    let vis = self.unwrap_vis(name);
+                    let share_cnt = self.term_to_share_cnt.get(&t).unwrap();
+                    let op = "IN";
+
+                    if vis == PUBLIC {
+                        let bitlen = 1;
+                        let line = format!(
+                            "3 1 {} {} {} {} {}\n",
+                            term_name, vis, bitlen, share_cnt, op
+                        );
+                        self.bytecode_output.insert(0, line);
+                    } else {
+                        let line = format!("2 1 {} {} {} {}\n", term_name, vis, share_cnt, op);
+                        self.bytecode_output.insert(0, line);
+                    }
+                    self.inputs.insert(t.clone());
+                }
+
+                if !self.cache.contains_key(&t) {
+                    self.cache.insert(
+                        t.clone(),
+                        EmbeddedTerm::Bool(format!("s_{}", ToABY::get_var_name(&t))),
+                    );
+                }
+            }
+            Op::Const(Value::Bool(b)) => {
+                let op = "CONS_bool";
+                let line = format!("1 1 {} {} {}\n", *b as i32, s, op);
+                self.bytecode_output.push(line);
+                self.cache.insert(t.clone(), EmbeddedTerm::Bool(share));
+            }
+            Op::Eq => {
+                self.embed_eq(t.clone(), t.cs[0].clone(), t.cs[1].clone());
+            }
+            Op::Ite => {
+                let op = "MUX";
+
+                self.check_bool(&t.cs[0]);
+                self.check_bool(&t.cs[1]);
+                self.check_bool(&t.cs[2]);
+
+                let sel = self.term_to_share_cnt.get(&t.cs[0]).unwrap();
+                let a = self.term_to_share_cnt.get(&t.cs[1]).unwrap();
+                let b = self.term_to_share_cnt.get(&t.cs[2]).unwrap();
+
+                let line = format!("3 1 {} {} {} {} {}\n", sel, a, b, s, op);
+                self.bytecode_output.push(line);
+
+                self.cache.insert(t.clone(), EmbeddedTerm::Bool(share));
+            }
+            Op::Not => {
+                let op = "NOT";
+
+                self.check_bool(&t.cs[0]);
+
+                let a = self.term_to_share_cnt.get(&t.cs[0]).unwrap();
+                let line = format!("1 1 {} {} {}\n", a, s, op);
+                self.bytecode_output.push(line);
+
+                self.cache.insert(t.clone(), EmbeddedTerm::Bool(share));
+            }
+            Op::BoolNaryOp(o) => {
+                if t.cs.len() == 1 {
+                    // HACK: Conditionals might not contain two variables
+                    // If t.cs len is 1, just output that term
+                    // This is to bypass adding an AND gate with a single conditional term
+                    // Refer to pub fn condition() in src/circify/mod.rs
+                    self.check_bool(&t.cs[0]);
+                    let a = *self.term_to_share_cnt.get(&t.cs[0]).unwrap();
+                    match o {
+                        BoolNaryOp::And => self.term_to_share_cnt.insert(t.clone(), a),
+                        _ => {
+                            unimplemented!("Single operand boolean operation");
+                        }
+                    };
+                    self.cache.insert(t.clone(), EmbeddedTerm::Bool(share));
+                } else {
+                    for c in &t.cs {
+                        self.check_bool(c);
+                    }
+
+                    let op = match o {
+                        BoolNaryOp::Or => "OR",
+                        BoolNaryOp::And => "AND",
+                        BoolNaryOp::Xor => "XOR",
+                    };
+                    self.embed_nary(&t, op);
+
+                    self.cache.insert(t.clone(), EmbeddedTerm::Bool(share));
+                }
+            }
+            Op::BvBinPred(o) => {
+                let op = match o {
+                    BvBinPred::Ugt => "GT",
+                    BvBinPred::Ult => "LT",
+                    BvBinPred::Uge => "GE",
+                    BvBinPred::Ule => "LE",
+                    _ => panic!("Non-field in bool BvBinPred: {}", o),
+                };
+
+                self.check_bv(&t.cs[0]);
+                self.check_bv(&t.cs[1]);
+
+                let a = self.term_to_share_cnt.get(&t.cs[0]).unwrap();
+                let b = self.term_to_share_cnt.get(&t.cs[1]).unwrap();
+                let line = format!("2 1 {} {} {} {}\n", a, b, s, op);
+                self.bytecode_output.push(line);
+
+                self.cache.insert(t.clone(), EmbeddedTerm::Bool(share));
+            }
+            _ => panic!("Non-field in embed_bool: {}", t),
+        }
+    }
+
+    /// Given term `t`, type-check `t` is of type Bv
+    fn check_bv(&self, t: &Term) {
+        match self
+            .cache
+            .get(t)
+            .unwrap_or_else(|| panic!("Missing wire for {:?}", t))
+        {
+            EmbeddedTerm::Bv(_) => (),
+            _ => panic!("Non-bv for {:?}", t),
+        }
+    }
+
+    fn embed_bv(&mut self, t: Term) {
+        let share = self.get_share_name(&t);
+        let s = self.term_to_share_cnt.get(&t).unwrap();
+        match &t.op {
+            Op::Var(name, Sort::BitVector(_)) => {
+                if !self.inputs.contains(&t) && self.md.input_vis.contains_key(name) {
+                    let term_name = ToABY::get_var_name(&t);
+                    let vis = self.unwrap_vis(name);
+                    let share_cnt = self.term_to_share_cnt.get(&t).unwrap();
+                    let op = "IN";
+
+                    if vis == PUBLIC {
+                        let bitlen = 32;
+                        let line = format!(
+                            "3 1 {} {} {} {} {}\n",
+                            term_name, vis, bitlen, share_cnt, op
+                        );
+                        self.bytecode_output.insert(0, line);
+                    } else {
+                        let line = format!("2 1 {} {} {} {}\n", term_name, vis, share_cnt, op);
+                        self.bytecode_output.insert(0, line);
+                    }
+                    self.inputs.insert(t.clone());
+                }
+
+                if !self.cache.contains_key(&t) {
+                    self.cache.insert(
+                        t.clone(),
+                        EmbeddedTerm::Bv(format!("s_{}", ToABY::get_var_name(&t))),
+                    );
+                }
+            }
+            Op::Const(Value::BitVector(b)) => {
+                let op = "CONS_bv";
+                let line = format!("1 1 {} {} {}\n", b.as_sint(), s, op);
+                self.bytecode_output.push(line);
+                self.cache.insert(t.clone(), EmbeddedTerm::Bv(share));
+            }
+            Op::Ite => {
+                let op = "MUX";
+
+                self.check_bool(&t.cs[0]);
+                self.check_bv(&t.cs[1]);
+                self.check_bv(&t.cs[2]);
+
+                let sel = self.term_to_share_cnt.get(&t.cs[0]).unwrap();
+                let a = self.term_to_share_cnt.get(&t.cs[1]).unwrap();
+                let b = self.term_to_share_cnt.get(&t.cs[2]).unwrap();
+
+                let line = format!("3 1 {} {} {} {} {}\n", sel, a, b, s, op);
+                self.bytecode_output.push(line);
+
+                self.cache.insert(t.clone(), EmbeddedTerm::Bv(share));
+            }
+            Op::BvNaryOp(o) => {
+                let op = match o {
+                    BvNaryOp::Xor => "XOR",
+                    BvNaryOp::Or => "OR",
+                    BvNaryOp::And => "AND",
+                    BvNaryOp::Add => "ADD",
+                    BvNaryOp::Mul => "MUL",
+                };
+
+                for c in &t.cs {
+                    self.check_bv(c);
+                }
+                self.embed_nary(&t, op);
+
+                self.cache.insert(t.clone(), EmbeddedTerm::Bv(share));
+            }
+            Op::BvBinOp(o) => {
+                let op = match o {
+                    BvBinOp::Sub => "SUB",
+                    BvBinOp::Udiv => "DIV",
+                    BvBinOp::Urem => "REM",
+                    BvBinOp::Shl => "SHL",
+                    BvBinOp::Lshr => "LSHR",
+                    _ => panic!("Binop not supported: {}", o),
+                };
+
+                match o {
+                    BvBinOp::Sub | BvBinOp::Udiv | BvBinOp::Urem => {
+                        self.check_bv(&t.cs[0]);
+                        self.check_bv(&t.cs[1]);
+
+                        let a = self.term_to_share_cnt.get(&t.cs[0]).unwrap();
+                        let b = self.term_to_share_cnt.get(&t.cs[1]).unwrap();
+
+                        let line = format!("2 1 {} {} {} {}\n", a, b, s, op);
+                        self.bytecode_output.push(line);
+
+                        self.cache.insert(t.clone(), EmbeddedTerm::Bv(share));
+                    }
+                    BvBinOp::Shl | BvBinOp::Lshr => {
+                        self.check_bv(&t.cs[0]);
+                        self.check_bv(&t.cs[1]);
+
+                        let a = self.term_to_share_cnt.get(&t.cs[0]).unwrap();
+                        let const_shift_amount_term = fold(&t.cs[1], &[]);
+                        let const_shift_amount =
+                            const_shift_amount_term.as_bv_opt().unwrap().uint();
+
+                        let line = format!("2 1 {} {} {} {}\n", a, const_shift_amount, s, op);
+                        self.bytecode_output.push(line);
+
+                        self.cache.insert(t.clone(), EmbeddedTerm::Bv(share));
+                    }
+                    _ => panic!("Binop not supported: {}", o),
+                };
+            }
+            _ => panic!("Non-field in embed_bv: {:?}", t),
+        }
+    }
+
+    fn embed(&mut self, t: Term) {
+        for c in PostOrderIter::new(t) {
+            // terms shared with an earlier output are already embedded
+            if self.cache.contains_key(&c) {
+                continue;
+            }
+            match check(&c) {
+                Sort::Bool => {
+                    self.embed_bool(c);
+                }
+                Sort::BitVector(_) => {
+                    self.embed_bv(c);
+                }
+                e => panic!("Unsupported sort in embed: {:?}", e),
+            }
+        }
+    }
+
+    /// Given a term `t`, lower `t` to ABY Circuits
+    fn lower(&mut self, t: Term) {
+        self.embed(t.clone());
+
+        let op = "OUT";
+        let s = self.term_to_share_cnt.get(&t).unwrap();
+        let line = format!("1 0 {} {}\n", s, op);
+        self.bytecode_output.push(line);
+    }
+}
+
+/// The name of the ABY input for the IR variable `name`.
+pub fn aby_var_name(name: &str) -> String {
+    let new_name = name.replace('.', "_");
+    let n = new_name.split('_').collect::<Vec<&str>>();
+
+    match n.len() {
+        1 => n[0].to_string(),
+        2 => {
+            format!("{}_{}", n[0], n[1])
+        }
+        5 => n[3].to_string(),
+        6.. => {
+            let l = n.len() - 1;
+            format!("{}_{}", n[l - 2], n[l])
+        }
+        _ => {
+            panic!("Invalid variable name: {}", name);
+        }
+    }
+}
+
+/// Convert this (IR) `ir` to ABY, writing the bytecode and share map files.
+pub fn to_aby(ir: Computation, path: &Path, lang: &str, cm: &str, ss: &str) {
+    let (bytecode, share_map) = to_aby_bytecode(ir, cm, ss);
+    write_lines_to_file(&get_path(path, lang, "bytecode"), &bytecode);
+    write_lines_to_file(&get_path(path, lang, "share_map"), &share_map);
+}
+
+/// Convert this (IR) `ir` to ABY, returning the lines of the bytecode and of the share map.
+///
+/// See [super::interp] for the format.
+pub fn to_aby_bytecode(mut ir: Computation, cm: &str, ss: &str) -> (Vec<String>, Vec<String>) {
+    lower_compound_terms(&mut ir);
+    let s_map: SharingMap = match ss {
+        "b" => assign_all_boolean(&ir, cm),
+        "y" => assign_all_yao(&ir, cm),
+        "a+b" => assign_arithmetic_and_boolean(&ir, cm),
+        "a+y" => assign_arithmetic_and_yao(&ir, cm),
+        "greedy" => assign_greedy(&ir, cm),
+        #[cfg(feature = "lp")]
+        "lp" => assign(&ir, cm),
+        #[cfg(feature = "lp")]
+        "glp" => assign(&ir, cm),
+        #[cfg(feature = "lp")]
+        "plp" => assign_partitioned(&ir, cm, DEFAULT_REGION_SIZE),
+        #[cfg(feature = "lp")]
+        _ if ss.starts_with("plp:") => {
+            let region_size = ss["plp:".len()..]
+                .parse()
+                .unwrap_or_else(|_| panic!("Bad region size in sharing scheme: {}", ss));
+            assign_partitioned(&ir, cm, region_size)
+        }
+        _ => {
+            panic!("Unsupported sharing scheme: {}", ss);
+        }
+    };
+
+    to_aby_bytecode_with_sharing(ir, s_map)
+}
+
+/// Convert this (IR) `ir`, which has no compound terms, to ABY using the sharings in `s_map`,
+/// returning the lines of the bytecode and of the share map.
+pub fn to_aby_bytecode_with_sharing(
+    ir: Computation,
+    s_map: SharingMap,
+) -> (Vec<String>, Vec<String>) {
+    let Computation {
+        outputs: terms,
+        metadata: md,
+        ..
+    } = ir;
+    let mut converter = ToABY::new(s_map, md);
+
+    for t in terms {
+        // println!("terms: {}", t);
+        converter.map_terms_to_shares(t.clone());
+        converter.lower(t.clone());
+    }
+    (
+        std::mem::take(&mut converter.bytecode_output),
+        std::mem::take(&mut converter.share_map_output),
+    )
+}
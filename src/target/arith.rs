@@ -0,0 +1,714 @@
+//! Lowering IR to arithmetic circuits for N-party MPC
+//!
+//! Secret-sharing MPC protocols over a prime field (SPDZ and its relatives, e.g., in MP-SPDZ)
+//! evaluate arithmetic circuits, for any number of parties: additions (and multiplications by
+//! constants) are local, and multiplications cost communication. This module lowers a computation
+//! over booleans, bit-vectors, and field elements to such a circuit.
+//!
+//! A field element is one wire. A boolean is a wire that holds 0 or 1, and a bit-vector is a
+//! vector of such wires, least-significant first. Boolean and bit-vector operators are lowered
+//! bit-wise (see [super::bitblast]), with `a AND b = ab` and `a XOR b = a + b - 2ab`. Field
+//! equality uses an inverse hint: for `d = a - b`, an `inv` gate gives `i = 1/d` (or 0, if `d` is
+//! 0), so `(a = b) = z = 1 - di`, and the circuit asserts `dz = 0`. Field inverses and
+//! conversions from fields to bit-vectors are not supported.
+//!
+//! The circuit also asserts that each boolean or bit-vector input wire `x` holds 0 or 1, as
+//! `x(x - 1) = 0`.
+//!
+//! [ArithCircuit::write_json] writes the circuit as a JSON netlist:
+//!
+//! ```json
+//! {
+//!   "modulus": "101",
+//!   "parties": 3,
+//!   "wires": 9,
+//!   "inputs": [
+//!     { "name": "a", "party": 0, "sort": "(mod 101)", "wires": [0] },
+//!     { "name": "c", "party": null, "sort": "bool", "wires": [1] }
+//!   ],
+//!   "gates": [
+//!     { "op": "mul", "in": [1, 1], "out": 2 },
+//!     { "op": "cmul", "value": "100", "in": [1], "out": 3 },
+//!     { "op": "add", "in": [2, 3], "out": 4 },
+//!     { "op": "assert_zero", "in": [4] },
+//!     { "op": "const", "value": "5", "out": 5 },
+//!     { "op": "add", "in": [0, 5], "out": 6 },
+//!     { "op": "mul", "in": [6, 1], "out": 7 },
+//!     { "op": "cmul", "value": "100", "in": [7], "out": 8 }
+//!   ],
+//!   "outputs": [ { "sort": "(mod 101)", "wires": [8] } ]
+//! }
+//! ```
+//!
+//! Each input is supplied by its party (or, if the party is `null`, is public), one field element
+//! per wire: the bits of a bit-vector are supplied separately. Gates are in topological order.
+//! An `inv` gate outputs the inverse of its input (or 0), and an `assert_zero` gate, which has no
+//! output, fails the computation unless its input is 0. Outputs are revealed to all parties.
+//! Constants are decimal.
+
+use super::bitblast::BitGates;
+use crate::ir::term::*;
+use crate::target::compound::lower_compound_terms;
+
+use circ_fields::FieldT;
+use fxhash::FxHashMap as HashMap;
+use rug::Integer;
+use serde_json::json;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Bit {
+    Const(bool),
+    Wire(usize),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Elem {
+    Const(Integer),
+    Wire(usize),
+}
+
+impl From<Bit> for Elem {
+    fn from(b: Bit) -> Self {
+        match b {
+            Bit::Const(b) => Elem::Const(Integer::from(b as u8)),
+            Bit::Wire(w) => Elem::Wire(w),
+        }
+    }
+}
+
+/// The wires of a term
+#[derive(Clone, Debug)]
+enum Lowered {
+    /// A boolean, or a bit-vector
+    Bits(Vec<Bit>),
+    /// A field element
+    Field(Elem),
+}
+
+impl Lowered {
+    fn bits(&self) -> &[Bit] {
+        match self {
+            Lowered::Bits(b) => b,
+            Lowered::Field(_) => panic!("Expected a boolean or bit-vector"),
+        }
+    }
+
+    fn elem(&self) -> &Elem {
+        match self {
+            Lowered::Field(e) => e,
+            Lowered::Bits(_) => panic!("Expected a field element"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Gate {
+    Const(Integer, usize),
+    Add(usize, usize, usize),
+    Mul(usize, usize, usize),
+    CMul(Integer, usize, usize),
+    /// The inverse of a wire, or 0
+    Inv(usize, usize),
+    AssertZero(usize),
+}
+
+/// An input value of an [ArithCircuit]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Input {
+    /// The input variable
+    pub name: String,
+    /// The party that supplies it, or [None] if it is public
+    pub party: Option<PartyId>,
+    /// Its sort (a boolean, bit-vector, or field element)
+    pub sort: Sort,
+    /// Its wires
+    pub wires: Vec<usize>,
+}
+
+/// An arithmetic circuit over a prime field
+#[derive(Clone, Debug)]
+pub struct ArithCircuit {
+    field: FieldT,
+    n_parties: usize,
+    n_wires: usize,
+    inputs: Vec<Input>,
+    gates: Vec<Gate>,
+    outputs: Vec<(Sort, Vec<usize>)>,
+}
+
+struct Builder {
+    field: FieldT,
+    gates: Vec<Gate>,
+    next_wire: usize,
+    /// Wires holding constants
+    consts: HashMap<Integer, usize>,
+    vars: HashMap<String, Lowered>,
+    lowered: TermMap<Lowered>,
+}
+
+impl Builder {
+    fn new(field: FieldT) -> Self {
+        Self {
+            field,
+            gates: Vec::new(),
+            next_wire: 0,
+            consts: HashMap::default(),
+            vars: HashMap::default(),
+            lowered: TermMap::new(),
+        }
+    }
+
+    fn fresh(&mut self) -> usize {
+        self.next_wire += 1;
+        self.next_wire - 1
+    }
+
+    fn modulus(&self) -> &Integer {
+        self.field.modulus()
+    }
+
+    fn check_field(&self, f: &FieldT) {
+        assert_eq!(
+            f, &self.field,
+            "Field {} does not match the circuit field {}",
+            f, self.field
+        );
+    }
+
+    /// A wire holding `e`
+    fn wire(&mut self, e: &Elem) -> usize {
+        match e {
+            Elem::Wire(w) => *w,
+            Elem::Const(c) => {
+                if let Some(w) = self.consts.get(c) {
+                    return *w;
+                }
+                let w = self.fresh();
+                self.gates.push(Gate::Const(c.clone(), w));
+                self.consts.insert(c.clone(), w);
+                w
+            }
+        }
+    }
+
+    fn add_f(&mut self, a: &Elem, b: &Elem) -> Elem {
+        match (a, b) {
+            (Elem::Const(x), Elem::Const(y)) => Elem::Const(Integer::from(x + y) % self.modulus()),
+            (Elem::Const(z), e) | (e, Elem::Const(z)) if *z == 0 => e.clone(),
+            _ => {
+                let (x, y) = (self.wire(a), self.wire(b));
+                let z = self.fresh();
+                self.gates.push(Gate::Add(x, y, z));
+                Elem::Wire(z)
+            }
+        }
+    }
+
+    fn mul_f(&mut self, a: &Elem, b: &Elem) -> Elem {
+        match (a, b) {
+            (Elem::Const(x), Elem::Const(y)) => Elem::Const(Integer::from(x * y) % self.modulus()),
+            (Elem::Const(c), Elem::Wire(w)) | (Elem::Wire(w), Elem::Const(c)) => {
+                if *c == 0 {
+                    Elem::Const(Integer::new())
+                } else if *c == 1 {
+                    Elem::Wire(*w)
+                } else {
+                    let z = self.fresh();
+                    self.gates.push(Gate::CMul(c.clone(), *w, z));
+                    Elem::Wire(z)
+                }
+            }
+            (Elem::Wire(x), Elem::Wire(y)) => {
+                let z = self.fresh();
+                self.gates.push(Gate::Mul(*x, *y, z));
+                Elem::Wire(z)
+            }
+        }
+    }
+
+    /// `c * a`, for a (possibly negative) integer `c`
+    fn scale_f(&mut self, c: i64, a: &Elem) -> Elem {
+        let c = Integer::from(c).rem_euc(self.modulus());
+        self.mul_f(&Elem::Const(c), a)
+    }
+
+    fn sub_f(&mut self, a: &Elem, b: &Elem) -> Elem {
+        let neg_b = self.scale_f(-1, b);
+        self.add_f(a, &neg_b)
+    }
+
+    fn assert_zero(&mut self, e: &Elem) {
+        match e {
+            Elem::Const(c) => assert!(*c == 0, "Arithmetic circuit asserts {} = 0", c),
+            Elem::Wire(w) => self.gates.push(Gate::AssertZero(*w)),
+        }
+    }
+
+    /// `a = b`, as `z = 1 - d inv(d)` for `d = a - b`, with `dz = 0`
+    fn eq_f(&mut self, a: &Elem, b: &Elem) -> Bit {
+        let d = match self.sub_f(a, b) {
+            Elem::Const(c) => return Bit::Const(c == 0),
+            Elem::Wire(d) => d,
+        };
+        let inv = self.fresh();
+        self.gates.push(Gate::Inv(d, inv));
+        let one = Elem::Const(Integer::from(1));
+        let prod = self.mul_f(&Elem::Wire(d), &Elem::Wire(inv));
+        let z = self.sub_f(&one, &prod);
+        let check = self.mul_f(&Elem::Wire(d), &z);
+        self.assert_zero(&check);
+        Self::to_bit(z)
+    }
+
+    /// Assert that `x` is 0 or 1, as `x(x - 1) = 0`.
+    fn assert_bit(&mut self, x: usize) {
+        let x = Elem::Wire(x);
+        let sq = self.mul_f(&x, &x);
+        let d = self.sub_f(&sq, &x);
+        self.assert_zero(&d);
+    }
+
+    /// `c ? t : f`, as `f + c(t - f)`
+    fn ite_f(&mut self, c: Bit, t: &Elem, f: &Elem) -> Elem {
+        let d = self.sub_f(t, f);
+        let d = self.mul_f(&c.into(), &d);
+        self.add_f(f, &d)
+    }
+
+    fn to_bit(e: Elem) -> Bit {
+        match e {
+            Elem::Const(c) => Bit::Const(c == 1),
+            Elem::Wire(w) => Bit::Wire(w),
+        }
+    }
+
+    fn lower(&mut self, t: &Term) -> Lowered {
+        for c in PostOrderIter::new(t.clone()) {
+            if !self.lowered.contains_key(&c) {
+                let l = self.lower_op(&c);
+                self.lowered.insert(c, l);
+            }
+        }
+        self.lowered.get(t).unwrap().clone()
+    }
+
+    fn lower_op(&mut self, t: &Term) -> Lowered {
+        let args: Vec<Lowered> =
+            t.cs.iter()
+                .map(|c| self.lowered.get(c).unwrap().clone())
+                .collect();
+        match &t.op {
+            Op::Var(name, _) => self
+                .vars
+                .get(name)
+                .unwrap_or_else(|| panic!("Unknown input {}", name))
+                .clone(),
+            Op::Const(Value::Field(v)) => {
+                self.check_field(&v.ty());
+                Lowered::Field(Elem::Const(v.i()))
+            }
+            Op::PfNaryOp(o) => {
+                let mut acc = args[0].elem().clone();
+                for a in &args[1..] {
+                    acc = match o {
+                        PfNaryOp::Add => self.add_f(&acc, a.elem()),
+                        PfNaryOp::Mul => self.mul_f(&acc, a.elem()),
+                    };
+                }
+                Lowered::Field(acc)
+            }
+            Op::PfUnOp(PfUnOp::Neg) => Lowered::Field(self.scale_f(-1, args[0].elem())),
+            Op::PfUnOp(PfUnOp::Recip) => {
+                panic!("Field inverses are not supported in arithmetic circuits")
+            }
+            Op::UbvToPf(f) => {
+                self.check_field(f);
+                let mut acc = Elem::Const(Integer::new());
+                let mut pow = Integer::from(1);
+                for b in args[0].bits() {
+                    let pow_mod = Integer::from(&pow % self.modulus());
+                    let term = self.mul_f(&Elem::Const(pow_mod), &(*b).into());
+                    acc = self.add_f(&acc, &term);
+                    pow <<= 1;
+                }
+                Lowered::Field(acc)
+            }
+            Op::Eq if matches!(args[0], Lowered::Field(_)) => {
+                Lowered::Bits(vec![self.eq_f(args[0].elem(), args[1].elem())])
+            }
+            Op::Ite if matches!(args[1], Lowered::Field(_)) => {
+                let c = args[0].bits()[0];
+                Lowered::Field(self.ite_f(c, args[1].elem(), args[2].elem()))
+            }
+            op => {
+                let bits: Vec<Vec<Bit>> = args.iter().map(|a| a.bits().to_vec()).collect();
+                Lowered::Bits(self.bit_op(op, &bits).unwrap_or_else(|| {
+                    panic!(
+                        "Unsupported operator in arithmetic circuit lowering: {}",
+                        op
+                    )
+                }))
+            }
+        }
+    }
+}
+
+impl BitGates for Builder {
+    type Bit = Bit;
+
+    fn constant(&mut self, b: bool) -> Bit {
+        Bit::Const(b)
+    }
+
+    fn xor(&mut self, a: Bit, b: Bit) -> Bit {
+        match (a, b) {
+            (Bit::Const(x), Bit::Const(y)) => Bit::Const(x ^ y),
+            (Bit::Const(false), w) | (w, Bit::Const(false)) => w,
+            (Bit::Const(true), w) | (w, Bit::Const(true)) => self.inv(w),
+            (Bit::Wire(x), Bit::Wire(y)) if x == y => Bit::Const(false),
+            (Bit::Wire(_), Bit::Wire(_)) => {
+                // a + b - 2ab
+                let (a, b): (Elem, Elem) = (a.into(), b.into());
+                let sum = self.add_f(&a, &b);
+                let prod = self.mul_f(&a, &b);
+                let prod2 = self.scale_f(-2, &prod);
+                Self::to_bit(self.add_f(&sum, &prod2))
+            }
+        }
+    }
+
+    fn and(&mut self, a: Bit, b: Bit) -> Bit {
+        match (a, b) {
+            (Bit::Const(false), _) | (_, Bit::Const(false)) => Bit::Const(false),
+            (Bit::Const(true), w) | (w, Bit::Const(true)) => w,
+            (Bit::Wire(x), Bit::Wire(y)) if x == y => a,
+            _ => Self::to_bit(self.mul_f(&a.into(), &b.into())),
+        }
+    }
+
+    fn inv(&mut self, a: Bit) -> Bit {
+        match a {
+            Bit::Const(b) => Bit::Const(!b),
+            // 1 - a
+            Bit::Wire(_) => Self::to_bit(self.sub_f(&Elem::Const(Integer::from(1)), &a.into())),
+        }
+    }
+}
+
+fn wires_for(s: &Sort) -> usize {
+    match s {
+        Sort::Bool | Sort::Field(_) => 1,
+        Sort::BitVector(w) => *w,
+        _ => panic!("Arithmetic circuits cannot hold {}", s),
+    }
+}
+
+/// Lower `cs`, a computation over booleans, bit-vectors, and elements of `field`, to an
+/// arithmetic circuit over `field`.
+pub fn to_arith(mut cs: Computation, field: FieldT) -> ArithCircuit {
+    lower_compound_terms(&mut cs);
+    let mut names: Vec<&String> = cs.metadata.input_vis.keys().collect();
+    names.sort_by_key(|n| {
+        let party = cs.metadata.get_input_visibility(n);
+        (party.is_none(), party, n.as_str())
+    });
+    let n_parties = names
+        .iter()
+        .filter_map(|n| cs.metadata.get_input_visibility(n))
+        .map(|p| p as usize + 1)
+        .chain(std::iter::once(cs.metadata.next_party_id as usize))
+        .max()
+        .unwrap();
+
+    let mut builder = Builder::new(field.clone());
+    let mut inputs = Vec::new();
+    for name in names {
+        let sort = cs.metadata.input_sort(name);
+        let wires: Vec<usize> = (0..wires_for(&sort)).map(|_| builder.fresh()).collect();
+        let lowered = match &sort {
+            Sort::Field(f) => {
+                builder.check_field(f);
+                Lowered::Field(Elem::Wire(wires[0]))
+            }
+            _ => {
+                for w in &wires {
+                    builder.assert_bit(*w);
+                }
+                Lowered::Bits(wires.iter().map(|w| Bit::Wire(*w)).collect())
+            }
+        };
+        builder.vars.insert(name.clone(), lowered);
+        inputs.push(Input {
+            name: name.clone(),
+            party: cs.metadata.get_input_visibility(name),
+            sort,
+            wires,
+        });
+    }
+
+    let mut outputs = Vec::new();
+    for o in &cs.outputs {
+        let sort = check(o);
+        let wires = match builder.lower(o) {
+            Lowered::Bits(bits) => bits.into_iter().map(|b| builder.wire(&b.into())).collect(),
+            Lowered::Field(e) => vec![builder.wire(&e)],
+        };
+        outputs.push((sort, wires));
+    }
+    ArithCircuit {
+        field,
+        n_parties,
+        n_wires: builder.next_wire,
+        inputs,
+        gates: builder.gates,
+        outputs,
+    }
+}
+
+impl ArithCircuit {
+    /// The input values
+    pub fn inputs(&self) -> &[Input] {
+        &self.inputs
+    }
+
+    /// The number of multiplication gates (the usual cost measure)
+    pub fn mul_count(&self) -> usize {
+        self.gates
+            .iter()
+            .filter(|g| matches!(g, Gate::Mul(..)))
+            .count()
+    }
+
+    /// Write the circuit as a JSON netlist (see the module documentation).
+    pub fn write_json<W: Write>(&self, w: W) -> io::Result<()> {
+        let inputs: Vec<_> = self
+            .inputs
+            .iter()
+            .map(|i| {
+                json!({
+                    "name": i.name,
+                    "party": i.party,
+                    "sort": format!("{}", i.sort),
+                    "wires": i.wires,
+                })
+            })
+            .collect();
+        let gates: Vec<_> = self
+            .gates
+            .iter()
+            .map(|g| match g {
+                Gate::Const(c, o) => json!({"op": "const", "value": c.to_string(), "out": o}),
+                Gate::Add(a, b, o) => json!({"op": "add", "in": [a, b], "out": o}),
+                Gate::Mul(a, b, o) => json!({"op": "mul", "in": [a, b], "out": o}),
+                Gate::CMul(c, a, o) => {
+                    json!({"op": "cmul", "value": c.to_string(), "in": [a], "out": o})
+                }
+                Gate::Inv(a, o) => json!({"op": "inv", "in": [a], "out": o}),
+                Gate::AssertZero(a) => json!({"op": "assert_zero", "in": [a]}),
+            })
+            .collect();
+        let outputs: Vec<_> = self
+            .outputs
+            .iter()
+            .map(|(s, wires)| json!({"sort": format!("{}", s), "wires": wires}))
+            .collect();
+        let netlist = json!({
+            "modulus": self.field.modulus().to_string(),
+            "parties": self.n_parties,
+            "wires": self.n_wires,
+            "inputs": inputs,
+            "gates": gates,
+            "outputs": outputs,
+        });
+        serde_json::to_writer_pretty(w, &netlist).map_err(io::Error::from)
+    }
+
+    /// Run the circuit in the clear.
+    pub fn eval(&self, values: &HashMap<String, Value>) -> Vec<Value> {
+        let p = self.field.modulus();
+        let mut wires = vec![Integer::new(); self.n_wires];
+        for i in &self.inputs {
+            let value = values
+                .get(&i.name)
+                .unwrap_or_else(|| panic!("No value for input {}", i.name));
+            assert_eq!(&value.sort(), &i.sort, "Bad value for input {}", i.name);
+            for (j, w) in i.wires.iter().enumerate() {
+                wires[*w] = match value {
+                    Value::Bool(b) => Integer::from(*b as u8),
+                    Value::BitVector(bv) => Integer::from(bv.uint().get_bit(j as u32) as u8),
+                    Value::Field(f) => f.i(),
+                    _ => unreachable!(),
+                };
+            }
+        }
+        for g in &self.gates {
+            match g {
+                Gate::Const(c, o) => wires[*o] = c.clone(),
+                Gate::Add(a, b, o) => wires[*o] = Integer::from(&wires[*a] + &wires[*b]) % p,
+                Gate::Mul(a, b, o) => wires[*o] = Integer::from(&wires[*a] * &wires[*b]) % p,
+                Gate::CMul(c, a, o) => wires[*o] = Integer::from(c * &wires[*a]) % p,
+                Gate::Inv(a, o) => {
+                    wires[*o] = wires[*a]
+                        .clone()
+                        .invert(p)
+                        .unwrap_or_else(|_| Integer::new())
+                }
+                Gate::AssertZero(a) => assert!(wires[*a] == 0, "Wire {} is not zero", a),
+            }
+        }
+        self.outputs
+            .iter()
+            .map(|(s, ws)| match s {
+                Sort::Bool => Value::Bool(wires[ws[0]] == 1),
+                Sort::BitVector(w) => {
+                    let mut i = Integer::new();
+                    for (j, wire) in ws.iter().enumerate() {
+                        i.set_bit(j as u32, wires[*wire] == 1);
+                    }
+                    Value::BitVector(BitVector::new(i, *w))
+                }
+                _ => Value::Field(self.field.new_v(wires[ws[0]].clone())),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn field() -> FieldT {
+        FieldT::from(&Integer::from(101))
+    }
+
+    fn check_agrees(cs: &Computation, values: &[Vec<(&str, Value)>]) {
+        let circuit = to_arith(cs.clone(), field());
+        for values in values {
+            let values: HashMap<String, Value> = values
+                .iter()
+                .map(|(n, v)| (n.to_string(), v.clone()))
+                .collect();
+            let expected: Vec<Value> = cs.outputs.iter().map(|o| eval(o, &values)).collect();
+            assert_eq!(expected, circuit.eval(&values), "on {:?}", values);
+        }
+    }
+
+    fn three_parties() -> Computation {
+        let mut cs = Computation::new();
+        for p in &["a", "b", "c"] {
+            cs.metadata.add_party(p.to_string());
+        }
+        cs
+    }
+
+    #[test]
+    fn field_ops() {
+        let mut cs = three_parties();
+        let f = Sort::Field(field());
+        let x = cs.new_var("x", f.clone(), Some(0), 0, false, None);
+        let y = cs.new_var("y", f.clone(), Some(1), 0, false, None);
+        let z = cs.new_var("z", Sort::BitVector(4), Some(2), 0, false, None);
+        let c = cs.new_var("c", Sort::Bool, None, 0, false, None);
+        cs.outputs = vec![
+            term![PF_ADD; x.clone(), y.clone(), pf_lit(field().new_v(7))],
+            term![PF_MUL; x.clone(), y.clone(), x.clone()],
+            term![PF_NEG; y.clone()],
+            term![EQ; x.clone(), y.clone()],
+            term![ITE; c.clone(), x.clone(), y.clone()],
+            term![PF_ADD; x.clone(), term![Op::UbvToPf(field()); z.clone()]],
+            term![ITE; term![EQ; x, y], z.clone(), term![BV_ADD; z, bv_lit(3, 4)]],
+            c,
+        ];
+        let pf = |i: u64| Value::Field(field().new_v(i));
+        let bv = |i: usize| Value::BitVector(BitVector::new(Integer::from(i), 4));
+        let mut values = Vec::new();
+        for (x, y, z) in &[(3, 5, 9), (4, 4, 15), (0, 100, 0)] {
+            for c in &[false, true] {
+                values.push(vec![
+                    ("x", pf(*x)),
+                    ("y", pf(*y)),
+                    ("z", bv(*z)),
+                    ("c", Value::Bool(*c)),
+                ]);
+            }
+        }
+        check_agrees(&cs, &values);
+    }
+
+    #[test]
+    fn bv_ops() {
+        let mut cs = three_parties();
+        let a = cs.new_var("a", Sort::BitVector(4), Some(0), 0, false, None);
+        let b = cs.new_var("b", Sort::BitVector(4), Some(2), 0, false, None);
+        cs.outputs = vec![
+            term![BV_MUL; a.clone(), b.clone()],
+            term![BV_UREM; a.clone(), b.clone()],
+            term![BV_XOR; a.clone(), b.clone()],
+            term![BV_SLT; a.clone(), b.clone()],
+            term![BV_LSHR; a, b],
+        ];
+        let bv = |i: usize| Value::BitVector(BitVector::new(Integer::from(i), 4));
+        let values: Vec<_> = (0..16)
+            .flat_map(|x| (0..16).map(move |y| vec![("a", bv(x)), ("b", bv(y))]))
+            .collect();
+        check_agrees(&cs, &values);
+    }
+
+    #[test]
+    fn asserts() {
+        let mut cs = three_parties();
+        let f = Sort::Field(field());
+        let x = cs.new_var("x", f.clone(), Some(0), 0, false, None);
+        let y = cs.new_var("y", f, Some(1), 0, false, None);
+        let z = cs.new_var("z", Sort::BitVector(3), Some(2), 0, false, None);
+        let c = cs.new_var("c", Sort::Bool, None, 0, false, None);
+        cs.outputs = vec![term![AND; term![EQ; x, y], c], z];
+        let circuit = to_arith(cs, field());
+        let asserts = |circuit: &ArithCircuit| {
+            circuit
+                .gates
+                .iter()
+                .filter(|g| matches!(g, Gate::AssertZero(_)))
+                .count()
+        };
+        // one per input bit, and one for the equality
+        assert_eq!(asserts(&circuit), 5);
+        // x(x - 1) for 4 input bits; d inv(d), dz, and the AND for the equality
+        assert_eq!(circuit.mul_count(), 7);
+        let mut values: HashMap<String, Value> = vec![
+            ("x", Value::Field(field().new_v(3))),
+            ("y", Value::Field(field().new_v(3))),
+            ("z", Value::BitVector(BitVector::new(Integer::from(5), 3))),
+            ("c", Value::Bool(true)),
+        ]
+        .into_iter()
+        .map(|(n, v)| (n.to_owned(), v))
+        .collect();
+        assert_eq!(circuit.eval(&values)[0], Value::Bool(true));
+        values.insert("y".to_owned(), Value::Field(field().new_v(4)));
+        assert_eq!(circuit.eval(&values)[0], Value::Bool(false));
+    }
+
+    #[test]
+    fn netlist() {
+        let mut cs = three_parties();
+        let f = Sort::Field(field());
+        let x = cs.new_var("x", f.clone(), Some(2), 0, false, None);
+        let y = cs.new_var("y", f, None, 0, false, None);
+        cs.outputs = vec![term![PF_MUL; x, y]];
+        let mut out = Vec::new();
+        to_arith(cs, field()).write_json(&mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["modulus"], "101");
+        assert_eq!(json["parties"], 3);
+        assert_eq!(json["inputs"][0]["name"], "x");
+        assert_eq!(json["inputs"][0]["party"], 2);
+        assert_eq!(json["inputs"][1]["party"], serde_json::Value::Null);
+        assert_eq!(
+            json["gates"][0],
+            json!({"op": "mul", "in": [0, 1], "out": 2})
+        );
+        assert_eq!(json["outputs"][0]["wires"], json!([2]));
+    }
+}
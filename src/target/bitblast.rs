@@ -0,0 +1,274 @@
+//! Bit-blasting
+//!
+//! Circuits for boolean and bit-vector operators, built from bit-level gates. A target supplies
+//! the gates (constants, XOR, AND, and NOT) by implementing [BitGates], and gets the rest. A
+//! bit-vector is a vector of bits, least-significant first.
+
+use crate::ir::term::*;
+
+/// Bit-level gates, and circuits built from them
+pub(super) trait BitGates: Sized {
+    /// A bit of the circuit
+    type Bit: Copy;
+
+    /// The constant `b`
+    fn constant(&mut self, b: bool) -> Self::Bit;
+    /// `a XOR b`
+    fn xor(&mut self, a: Self::Bit, b: Self::Bit) -> Self::Bit;
+    /// `a AND b`
+    fn and(&mut self, a: Self::Bit, b: Self::Bit) -> Self::Bit;
+    /// `NOT a`
+    fn inv(&mut self, a: Self::Bit) -> Self::Bit;
+
+    /// `a OR b`
+    fn or(&mut self, a: Self::Bit, b: Self::Bit) -> Self::Bit {
+        let x = self.xor(a, b);
+        let y = self.and(a, b);
+        self.xor(x, y)
+    }
+
+    /// `s ? t : f`
+    fn mux(&mut self, s: Self::Bit, t: Self::Bit, f: Self::Bit) -> Self::Bit {
+        let d = self.xor(t, f);
+        let d = self.and(s, d);
+        self.xor(f, d)
+    }
+
+    /// `s ? t : f`, bit-wise
+    fn mux_all(&mut self, s: Self::Bit, t: &[Self::Bit], f: &[Self::Bit]) -> Vec<Self::Bit> {
+        t.iter().zip(f).map(|(t, f)| self.mux(s, *t, *f)).collect()
+    }
+
+    /// `NOT a`, bit-wise
+    fn inv_all(&mut self, a: &[Self::Bit]) -> Vec<Self::Bit> {
+        a.iter().map(|b| self.inv(*b)).collect()
+    }
+
+    /// Fold `f` over `args`, bit-wise
+    fn bitwise(
+        &mut self,
+        args: &[Vec<Self::Bit>],
+        f: fn(&mut Self, Self::Bit, Self::Bit) -> Self::Bit,
+    ) -> Vec<Self::Bit> {
+        let mut acc = args[0].clone();
+        for arg in &args[1..] {
+            acc = acc.iter().zip(arg).map(|(a, b)| f(self, *a, *b)).collect();
+        }
+        acc
+    }
+
+    /// The disjunction of `a`
+    fn or_all(&mut self, a: &[Self::Bit]) -> Self::Bit {
+        let zero = self.constant(false);
+        a.iter().fold(zero, |acc, b| self.or(acc, *b))
+    }
+
+    /// `a = b`
+    fn eq(&mut self, a: &[Self::Bit], b: &[Self::Bit]) -> Self::Bit {
+        let diffs: Vec<Self::Bit> = a.iter().zip(b).map(|(a, b)| self.xor(*a, *b)).collect();
+        let any_diff = self.or_all(&diffs);
+        self.inv(any_diff)
+    }
+
+    /// Ripple-carry addition; returns the sum and the carry out.
+    fn add(
+        &mut self,
+        a: &[Self::Bit],
+        b: &[Self::Bit],
+        mut carry: Self::Bit,
+    ) -> (Vec<Self::Bit>, Self::Bit) {
+        let mut sum = Vec::with_capacity(a.len());
+        for (a, b) in a.iter().zip(b) {
+            let a_c = self.xor(*a, carry);
+            let b_c = self.xor(*b, carry);
+            sum.push(self.xor(a_c, *b));
+            let c = self.and(a_c, b_c);
+            carry = self.xor(carry, c);
+        }
+        (sum, carry)
+    }
+
+    /// `a - b`, and whether `a >= b` (unsigned)
+    fn sub(&mut self, a: &[Self::Bit], b: &[Self::Bit]) -> (Vec<Self::Bit>, Self::Bit) {
+        let not_b = self.inv_all(b);
+        let one = self.constant(true);
+        self.add(a, &not_b, one)
+    }
+
+    /// `a < b` (unsigned)
+    fn ult(&mut self, a: &[Self::Bit], b: &[Self::Bit]) -> Self::Bit {
+        let (_, ge) = self.sub(a, b);
+        self.inv(ge)
+    }
+
+    /// `a < b` (signed)
+    fn slt(&mut self, a: &[Self::Bit], b: &[Self::Bit]) -> Self::Bit {
+        let flip_sign = |s: &mut Self, x: &[Self::Bit]| {
+            let mut x = x.to_vec();
+            let last = x.len() - 1;
+            x[last] = s.inv(x[last]);
+            x
+        };
+        let a = flip_sign(self, a);
+        let b = flip_sign(self, b);
+        self.ult(&a, &b)
+    }
+
+    /// Shift-and-add multiplication
+    fn mul(&mut self, a: &[Self::Bit], b: &[Self::Bit]) -> Vec<Self::Bit> {
+        let w = a.len();
+        let zero = self.constant(false);
+        let mut acc = vec![zero; w];
+        for (i, b_i) in b.iter().enumerate() {
+            let partial: Vec<Self::Bit> = a[..w - i].iter().map(|a| self.and(*a, *b_i)).collect();
+            let (sum, _) = self.add(&acc[i..], &partial, zero);
+            acc[i..].copy_from_slice(&sum);
+        }
+        acc
+    }
+
+    /// Restoring division; returns the quotient and remainder. Division by zero is as in the IR.
+    fn div_rem(&mut self, a: &[Self::Bit], b: &[Self::Bit]) -> (Vec<Self::Bit>, Vec<Self::Bit>) {
+        let w = a.len();
+        let zero = self.constant(false);
+        let mut b_ext = b.to_vec();
+        b_ext.push(zero);
+        let mut q = vec![zero; w];
+        let mut r = vec![zero; w];
+        for i in (0..w).rev() {
+            let mut shifted = vec![a[i]];
+            shifted.extend(r.iter().cloned());
+            let (diff, ge) = self.sub(&shifted, &b_ext);
+            q[i] = ge;
+            // either way, the remainder is less than b, so it fits in w bits
+            r = self.mux_all(ge, &diff[..w], &shifted[..w]);
+        }
+        (q, r)
+    }
+
+    /// A barrel shifter
+    fn shift(&mut self, a: &[Self::Bit], amt: &[Self::Bit], op: &BvBinOp) -> Vec<Self::Bit> {
+        let w = a.len();
+        let zero = self.constant(false);
+        let fill = match op {
+            BvBinOp::Ashr => a[w - 1],
+            _ => zero,
+        };
+        let shift_by = |x: &[Self::Bit], n: usize| -> Vec<Self::Bit> {
+            (0..w)
+                .map(|i| match op {
+                    BvBinOp::Shl if i >= n => x[i - n],
+                    BvBinOp::Shl => zero,
+                    _ if i + n < w => x[i + n],
+                    _ => fill,
+                })
+                .collect()
+        };
+        let mut r = a.to_vec();
+        let mut k = 0;
+        while k < w && (1 << k) < w {
+            let shifted = shift_by(&r, 1 << k);
+            r = self.mux_all(amt[k], &shifted, &r);
+            k += 1;
+        }
+        // shifting by at least w leaves only the fill
+        let too_far = self.or_all(&amt[k..]);
+        let filled = vec![fill; w];
+        self.mux_all(too_far, &filled, &r)
+    }
+
+    /// The bits of `op`, applied to arguments with bits `args`.
+    ///
+    /// Returns [None] if `op` is not a boolean or bit-vector operator. Callers must handle `eq`
+    /// and `ite` over other sorts themselves.
+    fn bit_op(&mut self, op: &Op, args: &[Vec<Self::Bit>]) -> Option<Vec<Self::Bit>> {
+        Some(match op {
+            Op::Const(Value::Bool(b)) => vec![self.constant(*b)],
+            Op::Const(Value::BitVector(bv)) => (0..bv.width())
+                .map(|i| self.constant(bv.uint().get_bit(i as u32)))
+                .collect(),
+            Op::Not | Op::BvUnOp(BvUnOp::Not) => self.inv_all(&args[0]),
+            Op::Implies => {
+                let not_a = self.inv(args[0][0]);
+                vec![self.or(not_a, args[1][0])]
+            }
+            Op::BoolNaryOp(BoolNaryOp::And) | Op::BvNaryOp(BvNaryOp::And) => {
+                self.bitwise(args, Self::and)
+            }
+            Op::BoolNaryOp(BoolNaryOp::Or) | Op::BvNaryOp(BvNaryOp::Or) => {
+                self.bitwise(args, Self::or)
+            }
+            Op::BoolNaryOp(BoolNaryOp::Xor) | Op::BvNaryOp(BvNaryOp::Xor) => {
+                self.bitwise(args, Self::xor)
+            }
+            Op::BvNaryOp(BvNaryOp::Add) => {
+                let zero = self.constant(false);
+                let mut acc = args[0].clone();
+                for arg in &args[1..] {
+                    acc = self.add(&acc, arg, zero).0;
+                }
+                acc
+            }
+            Op::BvNaryOp(BvNaryOp::Mul) => {
+                let mut acc = args[0].clone();
+                for arg in &args[1..] {
+                    acc = self.mul(&acc, arg);
+                }
+                acc
+            }
+            Op::BvUnOp(BvUnOp::Neg) => {
+                let zero = vec![self.constant(false); args[0].len()];
+                self.sub(&zero, &args[0]).0
+            }
+            Op::BvBinOp(BvBinOp::Sub) => self.sub(&args[0], &args[1]).0,
+            Op::BvBinOp(BvBinOp::Udiv) => self.div_rem(&args[0], &args[1]).0,
+            Op::BvBinOp(BvBinOp::Urem) => self.div_rem(&args[0], &args[1]).1,
+            Op::BvBinOp(o) => self.shift(&args[0], &args[1], o),
+            Op::BvBinPred(o) => {
+                let (a, b) = (&args[0], &args[1]);
+                vec![match o {
+                    BvBinPred::Ult => self.ult(a, b),
+                    BvBinPred::Ugt => self.ult(b, a),
+                    BvBinPred::Ule => {
+                        let gt = self.ult(b, a);
+                        self.inv(gt)
+                    }
+                    BvBinPred::Uge => {
+                        let lt = self.ult(a, b);
+                        self.inv(lt)
+                    }
+                    BvBinPred::Slt => self.slt(a, b),
+                    BvBinPred::Sgt => self.slt(b, a),
+                    BvBinPred::Sle => {
+                        let gt = self.slt(b, a);
+                        self.inv(gt)
+                    }
+                    BvBinPred::Sge => {
+                        let lt = self.slt(a, b);
+                        self.inv(lt)
+                    }
+                }]
+            }
+            Op::Eq => vec![self.eq(&args[0], &args[1])],
+            Op::Ite => self.mux_all(args[0][0], &args[1], &args[2]),
+            Op::BoolToBv => args[0].clone(),
+            Op::BvBit(i) => vec![args[0][*i]],
+            Op::BvExtract(high, low) => args[0][*low..=*high].to_vec(),
+            // the first argument is the most significant
+            Op::BvConcat => args.iter().rev().flatten().cloned().collect(),
+            Op::BvUext(n) => {
+                let mut bits = args[0].clone();
+                let zero = self.constant(false);
+                bits.extend(std::iter::repeat(zero).take(*n));
+                bits
+            }
+            Op::BvSext(n) => {
+                let mut bits = args[0].clone();
+                let sign = *bits.last().unwrap();
+                bits.extend(std::iter::repeat(sign).take(*n));
+                bits
+            }
+            _ => return None,
+        })
+    }
+}
@@ -0,0 +1,460 @@
+//! Lowering IR to Bristol Fashion boolean circuits
+//!
+//! Many MPC engines (e.g., MP-SPDZ, EMP, SCALE-MAMBA, and garbling libraries) run boolean circuits
+//! in the [Bristol Fashion](https://nigelsmart.github.io/MPC-Circuits/) netlist format. This module
+//! lowers a computation over booleans and bit-vectors to a circuit of AND, XOR, and INV gates.
+//!
+//! Each input variable is one input value of the circuit, and each output term is one output
+//! value. The bits of a value are least-significant first. Input values are grouped by party
+//! (party 0's, then party 1's, ..., then the public inputs), and sorted by name within a party;
+//! [BristolCircuit::write_io] describes them.
+//!
+//! Constants are folded as the circuit is built. Bristol Fashion has no constant wires, so any
+//! constant output bits are derived from an input wire (as `x XOR x`).
+
+use super::bitblast::BitGates;
+use crate::ir::term::*;
+use crate::target::compound::lower_compound_terms;
+
+use fxhash::FxHashMap as HashMap;
+use rug::Integer;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Bit {
+    Const(bool),
+    Wire(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Gate {
+    And(usize, usize, usize),
+    Xor(usize, usize, usize),
+    Inv(usize, usize),
+}
+
+/// An input value of a [BristolCircuit]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Input {
+    /// The input variable
+    pub name: String,
+    /// The party that supplies it, or [None] if it is public
+    pub party: Option<PartyId>,
+    /// Its sort (a boolean or a bit-vector)
+    pub sort: Sort,
+}
+
+/// A boolean circuit of AND, XOR, and INV gates
+#[derive(Clone, Debug)]
+pub struct BristolCircuit {
+    inputs: Vec<Input>,
+    outputs: Vec<Sort>,
+    gates: Vec<Gate>,
+    n_wires: usize,
+}
+
+fn width(s: &Sort) -> usize {
+    match s {
+        Sort::Bool => 1,
+        Sort::BitVector(w) => *w,
+        _ => panic!("Bristol circuits cannot hold {}", s),
+    }
+}
+
+struct Builder {
+    gates: Vec<Gate>,
+    next_wire: usize,
+    /// The bits of each input variable
+    vars: HashMap<String, Vec<Bit>>,
+    /// The bits of each lowered term
+    bits: TermMap<Vec<Bit>>,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Self {
+            gates: Vec::new(),
+            next_wire: 0,
+            vars: HashMap::default(),
+            bits: TermMap::new(),
+        }
+    }
+
+    fn fresh(&mut self) -> usize {
+        self.next_wire += 1;
+        self.next_wire - 1
+    }
+
+    fn lower(&mut self, t: &Term) -> Vec<Bit> {
+        for c in PostOrderIter::new(t.clone()) {
+            if !self.bits.contains_key(&c) {
+                let bits = self.lower_op(&c);
+                self.bits.insert(c, bits);
+            }
+        }
+        self.bits.get(t).unwrap().clone()
+    }
+
+    fn lower_op(&mut self, t: &Term) -> Vec<Bit> {
+        let args: Vec<Vec<Bit>> =
+            t.cs.iter()
+                .map(|c| self.bits.get(c).unwrap().clone())
+                .collect();
+        match &t.op {
+            Op::Var(name, _) => self
+                .vars
+                .get(name)
+                .unwrap_or_else(|| panic!("Unknown input {}", name))
+                .clone(),
+            op => self
+                .bit_op(op, &args)
+                .unwrap_or_else(|| panic!("Unsupported operator in Bristol lowering: {}", op)),
+        }
+    }
+
+    /// Give the outputs wires of their own, and number them last (as Bristol Fashion requires).
+    fn finish(
+        mut self,
+        inputs: Vec<Input>,
+        outputs: Vec<Sort>,
+        output_bits: Vec<Bit>,
+    ) -> BristolCircuit {
+        let n_input_wires = inputs.iter().map(|i| width(&i.sort)).sum();
+        let mut zero = None;
+        let mut out_wires = Vec::new();
+        let mut is_output = vec![false; self.next_wire];
+        for bit in output_bits {
+            // an output wire must be a gate output, and may not be another output
+            let wire = match bit {
+                Bit::Wire(w) if w >= n_input_wires && !is_output[w] => w,
+                _ => {
+                    let z = *zero.get_or_insert_with(|| {
+                        assert!(
+                            n_input_wires > 0,
+                            "Bristol circuits with constant outputs need an input"
+                        );
+                        let z = self.fresh();
+                        self.gates.push(Gate::Xor(0, 0, z));
+                        z
+                    });
+                    let w = self.fresh();
+                    match bit {
+                        Bit::Const(false) => self.gates.push(Gate::Xor(z, z, w)),
+                        Bit::Const(true) => self.gates.push(Gate::Inv(z, w)),
+                        Bit::Wire(x) => self.gates.push(Gate::Xor(x, z, w)),
+                    }
+                    w
+                }
+            };
+            is_output.resize(self.next_wire, false);
+            is_output[wire] = true;
+            out_wires.push(wire);
+        }
+        is_output.resize(self.next_wire, false);
+
+        let n_wires = self.next_wire;
+        let mut renumber: Vec<usize> = (0..n_wires).collect();
+        let mut next = n_input_wires;
+        for w in n_input_wires..n_wires {
+            if !is_output[w] {
+                renumber[w] = next;
+                next += 1;
+            }
+        }
+        for w in out_wires {
+            renumber[w] = next;
+            next += 1;
+        }
+        let gates = self
+            .gates
+            .iter()
+            .map(|g| match *g {
+                Gate::And(a, b, c) => Gate::And(renumber[a], renumber[b], renumber[c]),
+                Gate::Xor(a, b, c) => Gate::Xor(renumber[a], renumber[b], renumber[c]),
+                Gate::Inv(a, b) => Gate::Inv(renumber[a], renumber[b]),
+            })
+            .collect();
+        BristolCircuit {
+            inputs,
+            outputs,
+            gates,
+            n_wires,
+        }
+    }
+}
+
+impl BitGates for Builder {
+    type Bit = Bit;
+
+    fn constant(&mut self, b: bool) -> Bit {
+        Bit::Const(b)
+    }
+
+    fn xor(&mut self, a: Bit, b: Bit) -> Bit {
+        match (a, b) {
+            (Bit::Const(x), Bit::Const(y)) => Bit::Const(x ^ y),
+            (Bit::Const(false), w) | (w, Bit::Const(false)) => w,
+            (Bit::Const(true), w) | (w, Bit::Const(true)) => self.inv(w),
+            (Bit::Wire(x), Bit::Wire(y)) if x == y => Bit::Const(false),
+            (Bit::Wire(x), Bit::Wire(y)) => {
+                let z = self.fresh();
+                self.gates.push(Gate::Xor(x, y, z));
+                Bit::Wire(z)
+            }
+        }
+    }
+
+    fn and(&mut self, a: Bit, b: Bit) -> Bit {
+        match (a, b) {
+            (Bit::Const(false), _) | (_, Bit::Const(false)) => Bit::Const(false),
+            (Bit::Const(true), w) | (w, Bit::Const(true)) => w,
+            (Bit::Wire(x), Bit::Wire(y)) if x == y => a,
+            (Bit::Wire(x), Bit::Wire(y)) => {
+                let z = self.fresh();
+                self.gates.push(Gate::And(x, y, z));
+                Bit::Wire(z)
+            }
+        }
+    }
+
+    fn inv(&mut self, a: Bit) -> Bit {
+        match a {
+            Bit::Const(b) => Bit::Const(!b),
+            Bit::Wire(x) => {
+                let z = self.fresh();
+                self.gates.push(Gate::Inv(x, z));
+                Bit::Wire(z)
+            }
+        }
+    }
+}
+
+/// Lower `cs`, a computation over booleans and bit-vectors, to a Bristol Fashion circuit.
+pub fn to_bristol(mut cs: Computation) -> BristolCircuit {
+    lower_compound_terms(&mut cs);
+    let mut inputs: Vec<Input> = cs
+        .metadata
+        .input_vis
+        .keys()
+        .map(|name| Input {
+            name: name.clone(),
+            party: cs.metadata.get_input_visibility(name),
+            sort: cs.metadata.input_sort(name),
+        })
+        .collect();
+    inputs.sort_by(|a, b| {
+        (a.party.is_none(), a.party, &a.name).cmp(&(b.party.is_none(), b.party, &b.name))
+    });
+
+    let mut builder = Builder::new();
+    for input in &inputs {
+        let bits = (0..width(&input.sort))
+            .map(|_| Bit::Wire(builder.fresh()))
+            .collect();
+        builder.vars.insert(input.name.clone(), bits);
+    }
+    let mut outputs = Vec::new();
+    let mut output_bits = Vec::new();
+    for o in &cs.outputs {
+        let sort = check(o);
+        output_bits.extend(builder.lower(o));
+        outputs.push(sort);
+    }
+    builder.finish(inputs, outputs, output_bits)
+}
+
+impl BristolCircuit {
+    /// The input values, in order
+    pub fn inputs(&self) -> &[Input] {
+        &self.inputs
+    }
+
+    /// The number of AND gates (the usual cost measure)
+    pub fn and_count(&self) -> usize {
+        self.gates
+            .iter()
+            .filter(|g| matches!(g, Gate::And(..)))
+            .count()
+    }
+
+    /// Write the circuit, in Bristol Fashion.
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "{} {}", self.gates.len(), self.n_wires)?;
+        write!(w, "{}", self.inputs.len())?;
+        for i in &self.inputs {
+            write!(w, " {}", width(&i.sort))?;
+        }
+        writeln!(w)?;
+        write!(w, "{}", self.outputs.len())?;
+        for o in &self.outputs {
+            write!(w, " {}", width(o))?;
+        }
+        writeln!(w)?;
+        writeln!(w)?;
+        for g in &self.gates {
+            match g {
+                Gate::And(a, b, c) => writeln!(w, "2 1 {} {} {} AND", a, b, c)?,
+                Gate::Xor(a, b, c) => writeln!(w, "2 1 {} {} {} XOR", a, b, c)?,
+                Gate::Inv(a, b) => writeln!(w, "1 1 {} {} INV", a, b)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Describe the input and output values: one line per value, of the form
+    /// `input <name> <party | public> <sort>`, or `output <index> <sort>`.
+    pub fn write_io<W: Write>(&self, mut w: W) -> io::Result<()> {
+        for i in &self.inputs {
+            match i.party {
+                Some(p) => writeln!(w, "input {} {} {}", i.name, p, i.sort)?,
+                None => writeln!(w, "input {} public {}", i.name, i.sort)?,
+            }
+        }
+        for (i, o) in self.outputs.iter().enumerate() {
+            writeln!(w, "output {} {}", i, o)?;
+        }
+        Ok(())
+    }
+
+    /// Run the circuit in the clear.
+    pub fn eval(&self, values: &HashMap<String, Value>) -> Vec<Value> {
+        let mut wires = vec![false; self.n_wires];
+        let mut next = 0;
+        for i in &self.inputs {
+            let value = values
+                .get(&i.name)
+                .unwrap_or_else(|| panic!("No value for input {}", i.name));
+            assert_eq!(&value.sort(), &i.sort, "Bad value for input {}", i.name);
+            for b in 0..width(&i.sort) {
+                wires[next] = match value {
+                    Value::Bool(v) => *v,
+                    Value::BitVector(bv) => bv.uint().get_bit(b as u32),
+                    _ => unreachable!(),
+                };
+                next += 1;
+            }
+        }
+        for g in &self.gates {
+            match *g {
+                Gate::And(a, b, c) => wires[c] = wires[a] & wires[b],
+                Gate::Xor(a, b, c) => wires[c] = wires[a] ^ wires[b],
+                Gate::Inv(a, b) => wires[b] = !wires[a],
+            }
+        }
+        let n_output_wires: usize = self.outputs.iter().map(width).sum();
+        let mut next = self.n_wires - n_output_wires;
+        self.outputs
+            .iter()
+            .map(|s| {
+                let bits = &wires[next..next + width(s)];
+                next += width(s);
+                match s {
+                    Sort::Bool => Value::Bool(bits[0]),
+                    _ => {
+                        let mut i = Integer::new();
+                        for (j, b) in bits.iter().enumerate() {
+                            i.set_bit(j as u32, *b);
+                        }
+                        Value::BitVector(BitVector::new(i, bits.len()))
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bv(u: usize) -> Value {
+        Value::BitVector(BitVector::new(Integer::from(u), 8))
+    }
+
+    fn check_agrees(cs: &Computation, values: &[Vec<(&str, Value)>]) {
+        let circuit = to_bristol(cs.clone());
+        for values in values {
+            let values: HashMap<String, Value> = values
+                .iter()
+                .map(|(n, v)| (n.to_string(), v.clone()))
+                .collect();
+            let expected: Vec<Value> = cs.outputs.iter().map(|o| eval(o, &values)).collect();
+            assert_eq!(expected, circuit.eval(&values), "on {:?}", values);
+        }
+    }
+
+    #[test]
+    fn bv_ops() {
+        let mut cs = Computation::new();
+        let a = cs.new_var("a", Sort::BitVector(8), Some(0), 0, false, None);
+        let b = cs.new_var("b", Sort::BitVector(8), Some(1), 0, false, None);
+        let c = cs.new_var("c", Sort::Bool, None, 0, false, None);
+        let small_b = term![BV_AND; b.clone(), bv_lit(7, 8)];
+        cs.outputs = vec![
+            term![BV_ADD; a.clone(), b.clone(), a.clone()],
+            term![BV_SUB; a.clone(), b.clone()],
+            term![BV_MUL; a.clone(), b.clone()],
+            term![BV_UDIV; a.clone(), b.clone()],
+            term![BV_UREM; a.clone(), b.clone()],
+            term![BV_SHL; a.clone(), b.clone()],
+            term![BV_LSHR; a.clone(), b.clone()],
+            term![BV_ASHR; a.clone(), small_b],
+            term![BV_SHL; a.clone(), bv_lit(3, 8)],
+            term![BV_NEG; a.clone()],
+            term![BV_ULE; a.clone(), b.clone()],
+            term![BV_SLT; a.clone(), b.clone()],
+            term![EQ; a.clone(), b.clone()],
+            term![ITE; c.clone(), a.clone(), b.clone()],
+            term![BV_CONCAT; term![Op::BvExtract(5, 2); a.clone()], b.clone()],
+            term![Op::BvSext(3); a.clone()],
+            term![Op::BvUext(3); a.clone()],
+            term![Op::BvBit(7); b.clone()],
+            term![IMPLIES; c, term![BV_UGT; a, b]],
+        ];
+        let mut values = Vec::new();
+        for (x, y) in &[(5, 9), (200, 3), (255, 255), (17, 0), (128, 2), (3, 8)] {
+            for z in &[false, true] {
+                values.push(vec![("a", bv(*x)), ("b", bv(*y)), ("c", Value::Bool(*z))]);
+            }
+        }
+        check_agrees(&cs, &values);
+    }
+
+    #[test]
+    fn constant_and_repeated_outputs() {
+        let mut cs = Computation::new();
+        let a = cs.new_var("a", Sort::Bool, Some(0), 0, false, None);
+        cs.outputs = vec![
+            a.clone(),
+            term![AND; a.clone(), term![NOT; a.clone()]],
+            term![OR; a.clone(), term![NOT; a.clone()]],
+            a,
+        ];
+        let values: Vec<_> = vec![false, true]
+            .into_iter()
+            .map(|v| vec![("a", Value::Bool(v))])
+            .collect();
+        check_agrees(&cs, &values);
+    }
+
+    #[test]
+    fn format() {
+        let mut cs = Computation::new();
+        let a = cs.new_var("a", Sort::Bool, Some(1), 0, false, None);
+        let b = cs.new_var("b", Sort::Bool, Some(0), 0, false, None);
+        cs.outputs = vec![term![AND; a, b]];
+        let circuit = to_bristol(cs);
+        let mut out = Vec::new();
+        circuit.write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1 3\n2 1 1\n1 1\n\n2 1 1 0 2 AND\n"
+        );
+        let mut io = Vec::new();
+        circuit.write_io(&mut io).unwrap();
+        assert_eq!(
+            String::from_utf8(io).unwrap(),
+            "input b 0 bool\ninput a 1 bool\noutput 0 bool\n"
+        );
+    }
+}
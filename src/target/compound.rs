@@ -0,0 +1,323 @@
+//! Structural lowering of tuple and array terms
+//!
+//! The backends only embed scalar terms. This rewrite removes the tuple- and array-sorted terms
+//! that remain in a computation, by viewing each such term as a tree of scalar elements:
+//!
+//! * `(eq a b)` becomes the conjunction of the element-wise equalities of `a` and `b`,
+//! * `(ite c t f)` becomes the element-wise `ite` of `t` and `f`,
+//! * `field`, `update`, `select` and `store` pick or replace elements. Array accesses at
+//!   constant indices are resolved directly; others become linear scans.
+//!
+//! Tuple and array variables are not scalarized; their elements are left as `field` and `select`
+//! terms. See [crate::ir::opt::scalarize_vars].
+
+use crate::ir::term::*;
+
+use log::debug;
+
+#[derive(Clone)]
+enum Shape {
+    Scalar(Term),
+    /// The fields of a tuple, or the elements of an array (in key order)
+    Compound(Vec<Shape>),
+}
+
+impl Shape {
+    fn scalar(&self) -> &Term {
+        match self {
+            Shape::Scalar(t) => t,
+            Shape::Compound(_) => panic!("Expected a scalar"),
+        }
+    }
+
+    fn elems(&self) -> &[Shape] {
+        match self {
+            Shape::Compound(es) => es,
+            Shape::Scalar(t) => panic!("Expected a tuple or array, got {}", t),
+        }
+    }
+
+    fn leaves(&self, out: &mut Vec<Term>) {
+        match self {
+            Shape::Scalar(t) => out.push(t.clone()),
+            Shape::Compound(es) => es.iter().for_each(|e| e.leaves(out)),
+        }
+    }
+
+    fn ite(c: &Term, t: &Shape, f: &Shape) -> Shape {
+        match (t, f) {
+            (Shape::Scalar(t), Shape::Scalar(f)) => {
+                Shape::Scalar(term![Op::Ite; c.clone(), t.clone(), f.clone()])
+            }
+            (Shape::Compound(ts), Shape::Compound(fs)) => {
+                assert_eq!(ts.len(), fs.len());
+                Shape::Compound(
+                    ts.iter()
+                        .zip(fs)
+                        .map(|(t, f)| Shape::ite(c, t, f))
+                        .collect(),
+                )
+            }
+            _ => panic!("Ite branches have different shapes"),
+        }
+    }
+
+    fn eq(a: &Shape, b: &Shape) -> Term {
+        let (mut a_leaves, mut b_leaves) = (Vec::new(), Vec::new());
+        a.leaves(&mut a_leaves);
+        b.leaves(&mut b_leaves);
+        assert_eq!(a_leaves.len(), b_leaves.len());
+        let eqs: Vec<Term> = a_leaves
+            .into_iter()
+            .zip(b_leaves)
+            .map(|(a, b)| term![Op::Eq; a, b])
+            .collect();
+        match eqs.len() {
+            0 => bool_lit(true),
+            1 => eqs.into_iter().next().unwrap(),
+            _ => term(AND, eqs),
+        }
+    }
+
+    /// The shape of a constant.
+    fn of_value(v: &Value) -> Shape {
+        match v {
+            Value::Tuple(vs) => Shape::Compound(vs.iter().map(Shape::of_value).collect()),
+            Value::Array(a) => Shape::Compound(
+                a.key_sort
+                    .elems_iter_values()
+                    .take(a.size)
+                    .map(|k| Shape::of_value(&a.select(&k)))
+                    .collect(),
+            ),
+            _ => Shape::Scalar(leaf_term(Op::Const(v.clone()))),
+        }
+    }
+
+    /// The shape of a term that we cannot look into (e.g., a variable): its elements are
+    /// projections.
+    fn of_opaque(t: Term, sort: &Sort) -> Shape {
+        match sort {
+            Sort::Tuple(sorts) => Shape::Compound(
+                sorts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, s)| Shape::of_opaque(term![Op::Field(i); t.clone()], s))
+                    .collect(),
+            ),
+            Sort::Array(_, val_sort, _) => Shape::Compound(
+                extras::array_elements(&t)
+                    .into_iter()
+                    .map(|e| Shape::of_opaque(e, val_sort))
+                    .collect(),
+            ),
+            _ => Shape::Scalar(t),
+        }
+    }
+}
+
+/// The position of the constant `idx` among the first `size` keys of `key_sort`.
+fn key_position(key_sort: &Sort, size: usize, idx: &Term) -> Option<usize> {
+    match &idx.op {
+        Op::Const(v) => Some(
+            key_sort
+                .elems_iter_values()
+                .take(size)
+                .position(|k| &k == v)
+                .unwrap_or_else(|| panic!("Index {} out of bounds", v)),
+        ),
+        _ => None,
+    }
+}
+
+struct Lowerer {
+    cache: TermMap<Shape>,
+}
+
+impl Lowerer {
+    fn lower(&mut self, t: &Term) -> Shape {
+        for c in PostOrderIter::new(t.clone()) {
+            if self.cache.contains_key(&c) {
+                continue;
+            }
+            let shape = self.lower_step(&c);
+            self.cache.insert(c, shape);
+        }
+        self.cache.get(t).unwrap().clone()
+    }
+
+    fn lower_step(&mut self, t: &Term) -> Shape {
+        let sort = check(t);
+        let cs: Vec<Shape> =
+            t.cs.iter()
+                .map(|c| self.cache.get(c).unwrap().clone())
+                .collect();
+        match &t.op {
+            Op::Tuple => Shape::Compound(cs),
+            Op::Field(i) => cs[0].elems()[*i].clone(),
+            Op::Update(i) => {
+                let mut es = cs[0].elems().to_vec();
+                es[*i] = cs[1].clone();
+                Shape::Compound(es)
+            }
+            Op::Ite if !sort.is_scalar() => Shape::ite(cs[0].scalar(), &cs[1], &cs[2]),
+            Op::Eq if !check(&t.cs[0]).is_scalar() => Shape::Scalar(Shape::eq(&cs[0], &cs[1])),
+            Op::Const(v) => Shape::of_value(v),
+            Op::Select => {
+                let (key_sort, size) = array_keys(&check(&t.cs[0]));
+                let es = cs[0].elems();
+                let idx = cs[1].scalar();
+                match key_position(&key_sort, size, idx) {
+                    Some(i) => es[i].clone(),
+                    None => {
+                        debug!("Linear scan for {}", t.op);
+                        key_sort
+                            .elems_iter()
+                            .zip(es)
+                            .skip(1)
+                            .fold(es[0].clone(), |acc, (k, e)| {
+                                Shape::ite(&term![Op::Eq; idx.clone(), k], e, &acc)
+                            })
+                    }
+                }
+            }
+            Op::Store => {
+                let (key_sort, size) = array_keys(&sort);
+                let mut es = cs[0].elems().to_vec();
+                let idx = cs[1].scalar();
+                match key_position(&key_sort, size, idx) {
+                    Some(i) => es[i] = cs[2].clone(),
+                    None => {
+                        debug!("Linear scan for {}", t.op);
+                        for (k, e) in key_sort.elems_iter().zip(&mut es) {
+                            *e = Shape::ite(&term![Op::Eq; idx.clone(), k], &cs[2], e);
+                        }
+                    }
+                }
+                Shape::Compound(es)
+            }
+            Op::Map(op) => {
+                let size = cs[0].elems().len();
+                Shape::Compound(
+                    (0..size)
+                        .map(|i| {
+                            let args = cs.iter().map(|c| c.elems()[i].scalar().clone()).collect();
+                            self.lower(&term((**op).clone(), args))
+                        })
+                        .collect(),
+                )
+            }
+            _ => {
+                let new = term(
+                    t.op.clone(),
+                    cs.iter().map(|c| c.scalar().clone()).collect(),
+                );
+                if sort.is_scalar() {
+                    Shape::Scalar(new)
+                } else {
+                    Shape::of_opaque(new, &sort)
+                }
+            }
+        }
+    }
+}
+
+fn array_keys(s: &Sort) -> (Sort, usize) {
+    match s {
+        Sort::Array(k, _, size) => ((**k).clone(), *size),
+        _ => panic!("Expected an array sort, got {}", s),
+    }
+}
+
+/// Remove the tuple- and array-sorted terms from `cs`.
+///
+/// Each output of compound sort is replaced by the sequence of its scalar elements.
+pub fn lower_compound_terms(cs: &mut Computation) {
+    let mut lowerer = Lowerer {
+        cache: TermMap::new(),
+    };
+    let mut outputs = Vec::new();
+    for o in &cs.outputs {
+        lowerer.lower(o).leaves(&mut outputs);
+    }
+    cs.outputs = outputs;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fxhash::FxHashMap;
+    use rug::Integer;
+
+    fn lower(t: &Term) -> Term {
+        let mut cs = Computation::from_constraint_system_parts(vec![t.clone()], vec![]);
+        lower_compound_terms(&mut cs);
+        assert_eq!(cs.outputs.len(), 1);
+        cs.outputs.pop().unwrap()
+    }
+
+    fn is_scalar(t: &Term) -> bool {
+        PostOrderIter::new(t.clone()).all(|c| check(&c).is_scalar())
+    }
+
+    fn bv_var(name: &str) -> Term {
+        leaf_term(Op::Var(name.into(), Sort::BitVector(4)))
+    }
+
+    fn bv_val(u: usize, w: usize) -> Value {
+        Value::BitVector(BitVector::new(Integer::from(u), w))
+    }
+
+    fn array(es: Vec<Term>) -> Term {
+        make_array(Sort::BitVector(2), Sort::BitVector(4), es)
+    }
+
+    #[test]
+    fn tuple_eq_ite() {
+        let c = leaf_term(Op::Var("c".into(), Sort::Bool));
+        let t = term![Op::Tuple; bv_var("a"), bv_var("b")];
+        let f = term![Op::Tuple; bv_var("b"), bv_lit(3, 4)];
+        let eq = term![Op::Eq; term![Op::Ite; c, t.clone(), f], t];
+        let l = lower(&eq);
+        assert!(is_scalar(&l));
+        for (c, a, b, expected) in &[
+            (true, 1, 2, true),
+            (false, 1, 1, false),
+            (false, 3, 3, true),
+        ] {
+            let vs: FxHashMap<String, Value> = vec![
+                ("c".to_owned(), Value::Bool(*c)),
+                ("a".to_owned(), bv_val(*a, 4)),
+                ("b".to_owned(), bv_val(*b, 4)),
+            ]
+            .into_iter()
+            .collect();
+            assert_eq!(eval(&eq, &vs), Value::Bool(*expected));
+            assert_eq!(eval(&l, &vs), Value::Bool(*expected));
+        }
+    }
+
+    #[test]
+    fn array_eq_store_select() {
+        let i = leaf_term(Op::Var("i".into(), Sort::BitVector(2)));
+        let a = array(vec![bv_var("x"), bv_var("y"), bv_lit(0, 4), bv_lit(0, 4)]);
+        let stored = term![Op::Store; a.clone(), i.clone(), bv_lit(5, 4)];
+        let b = array(vec![bv_var("x"), bv_lit(5, 4), bv_lit(0, 4), bv_lit(0, 4)]);
+        let eq = term![AND;
+            term![Op::Eq; stored.clone(), b],
+            term![Op::Eq; term![Op::Select; stored, i], bv_lit(5, 4)]
+        ];
+        let l = lower(&eq);
+        assert!(is_scalar(&l));
+        for (i, x, y) in &[(1, 2, 7), (0, 5, 5), (2, 3, 5), (1, 0, 0)] {
+            let vs: FxHashMap<String, Value> = vec![
+                ("i".to_owned(), bv_val(*i, 2)),
+                ("x".to_owned(), bv_val(*x, 4)),
+                ("y".to_owned(), bv_val(*y, 4)),
+            ]
+            .into_iter()
+            .collect();
+            assert_eq!(eval(&eq, &vs), eval(&l, &vs));
+        }
+    }
+}
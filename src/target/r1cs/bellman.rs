@@ -0,0 +1,509 @@
+//! Exporting our R1CS to bellman
+use bellman_proof::{
+    groth16::{
+        create_random_proof, generate_random_parameters, prepare_verifying_key, verify_proof,
+        Parameters, Proof, VerifyingKey,
+    },
+    Circuit, ConstraintSystem, LinearCombination, SynthesisError, Variable, VerificationError,
+};
+use bincode::{deserialize_from, serialize_into};
+use ff::{Field, PrimeField, PrimeFieldBits};
+use fxhash::FxHashMap;
+use group::{Curve, Group, WnafGroup};
+use log::debug;
+use pairing::{Engine, MillerLoopResult, MultiMillerLoop};
+use rand::RngCore;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Cursor};
+use std::path::Path;
+use std::str::FromStr;
+
+use rug::integer::{IsPrime, Order};
+use rug::Integer;
+
+use super::*;
+
+/// Convert one our our linear combinations to a bellman linear combination.
+/// Takes a zero linear combination. We could build it locally, but bellman provides one, so...
+fn lc_to_bellman<F: PrimeField, CS: ConstraintSystem<F>>(
+    vars: &HashMap<usize, Variable>,
+    lc: &Lc,
+    zero_lc: LinearCombination<F>,
+) -> LinearCombination<F> {
+    let mut lc_bellman = zero_lc;
+    // This zero test is needed until https://github.com/zkcrypto/bellman/pull/78 is resolved
+    if !lc.constant.is_zero() {
+        lc_bellman = lc_bellman + (int_to_ff((&lc.constant).into()), CS::one());
+    }
+    for (v, c) in &lc.monomials {
+        // ditto
+        if !c.is_zero() {
+            lc_bellman = lc_bellman + (int_to_ff(c.into()), *vars.get(v).unwrap());
+        }
+    }
+    lc_bellman
+}
+
+// hmmm... this should work essentially all the time, I think
+fn get_modulus<F: Field + PrimeField>() -> Integer {
+    let neg_1_f = -F::one();
+    let p_lsf: Integer = Integer::from_digits(neg_1_f.to_repr().as_ref(), Order::Lsf) + 1;
+    let p_msf: Integer = Integer::from_digits(neg_1_f.to_repr().as_ref(), Order::Msf) + 1;
+    if p_lsf.is_probably_prime(30) != IsPrime::No {
+        p_lsf
+    } else if p_msf.is_probably_prime(30) != IsPrime::No {
+        p_msf
+    } else {
+        panic!("could not determine ff::Field byte order")
+    }
+}
+
+/// A synthesizable bellman circuit.
+///
+/// Optionally contains a variable value map. This must be populated to use the
+/// bellman prover.
+pub struct SynthInput<'a>(&'a R1cs<String>, &'a Option<FxHashMap<String, Value>>);
+
+impl<'a, F: PrimeField> Circuit<F> for SynthInput<'a> {
+    #[track_caller]
+    fn synthesize<CS>(self, cs: &mut CS) -> std::result::Result<(), SynthesisError>
+    where
+        CS: ConstraintSystem<F>,
+    {
+        let f_mod = get_modulus::<F>();
+        assert_eq!(
+            self.0.modulus.modulus(),
+            &f_mod,
+            "\nR1CS has modulus \n{},\n but Bellman CS expects \n{}",
+            self.0.modulus,
+            f_mod
+        );
+        let mut uses = HashMap::with_capacity(self.0.next_idx);
+        for (a, b, c) in self.0.constraints.iter() {
+            [a, b, c].iter().for_each(|y| {
+                y.monomials.keys().for_each(|k| {
+                    uses.get_mut(k)
+                        .map(|i| {
+                            *i += 1;
+                        })
+                        .or_else(|| {
+                            uses.insert(*k, 1);
+                            None
+                        });
+                })
+            });
+        }
+        let mut vars = HashMap::with_capacity(self.0.next_idx);
+        for i in 0..self.0.next_idx {
+            if let Some(s) = self.0.idxs_signals.get(&i) {
+                //for (_i, s) in self.0.idxs_signals.get() {
+                if uses.get(&i).is_some() {
+                    let name_f = || s.to_string();
+                    let val_f = || {
+                        Ok({
+                            let i_val = self.1.as_ref().expect("missing values").get(s).unwrap();
+                            let ff_val = int_to_ff(i_val.as_pf().into());
+                            debug!("value : {} -> {:?} ({})", s, ff_val, i_val);
+                            ff_val
+                        })
+                    };
+                    let public = self.0.public_idxs.contains(&i);
+                    debug!("var: {}, public: {}", s, public);
+                    let v = if public {
+                        cs.alloc_input(name_f, val_f)?
+                    } else {
+                        cs.alloc(name_f, val_f)?
+                    };
+                    vars.insert(i, v);
+                } else {
+                    debug!("drop dead var: {}", s);
+                }
+            }
+        }
+        for (i, (a, b, c)) in self.0.constraints.iter().enumerate() {
+            cs.enforce(
+                || format!("con{}", i),
+                |z| lc_to_bellman::<F, CS>(&vars, a, z),
+                |z| lc_to_bellman::<F, CS>(&vars, b, z),
+                |z| lc_to_bellman::<F, CS>(&vars, c, z),
+            );
+        }
+        debug!(
+            "done with synth: {} vars {} cs",
+            vars.len(),
+            self.0.constraints.len()
+        );
+        Ok(())
+    }
+}
+
+/// Convert a (rug) integer to a prime field element.
+pub fn parse_instance<P: AsRef<Path>, F: PrimeField>(path: P) -> Vec<F> {
+    let f = BufReader::new(File::open(path).unwrap());
+    f.lines()
+        .map(|line| {
+            let s = line.unwrap();
+            let i = Integer::from_str(s.trim()).unwrap();
+            int_to_ff(i)
+        })
+        .collect()
+}
+
+/// Given
+/// * a proving-key path,
+/// * a verifying-key path,
+/// * prover data, and
+/// * verifier data
+/// generate parameters and write them and the data to files at those paths.
+pub fn gen_params<E: Engine, P1: AsRef<Path>, P2: AsRef<Path>>(
+    pk_path: P1,
+    vk_path: P2,
+    p_data: &ProverData,
+    v_data: &VerifierData,
+) -> io::Result<()>
+where
+    E::G1: WnafGroup,
+    E::G2: WnafGroup,
+{
+    let rng = &mut rand::thread_rng();
+    let p = generate_random_parameters::<E, _, _>(SynthInput(&p_data.r1cs, &None), rng).unwrap();
+    write_prover_key_and_data(pk_path, &p, p_data)?;
+    write_verifier_key_and_data(vk_path, &p.vk, v_data)?;
+    Ok(())
+}
+
+fn write_prover_key_and_data<P: AsRef<Path>, E: Engine>(
+    path: P,
+    params: &Parameters<E>,
+    data: &ProverData,
+) -> io::Result<()> {
+    let mut pk: Vec<u8> = Vec::new();
+    params.write(&mut pk)?;
+    let mut file = File::create(path)?;
+    serialize_into(&mut file, &(&pk, &data)).unwrap();
+    Ok(())
+}
+
+fn read_prover_key_and_data<P: AsRef<Path>, E: Engine>(
+    path: P,
+) -> io::Result<(Parameters<E>, ProverData)> {
+    let mut file = File::open(path)?;
+    let (pk_bytes, data): (Vec<u8>, ProverData) = deserialize_from(&mut file).unwrap();
+    let pk: Parameters<E> = Parameters::read(pk_bytes.as_slice(), false)?;
+    Ok((pk, data))
+}
+
+fn write_verifier_key_and_data<P: AsRef<Path>, E: Engine>(
+    path: P,
+    key: &VerifyingKey<E>,
+    data: &VerifierData,
+) -> io::Result<()> {
+    let mut vk: Vec<u8> = Vec::new();
+    key.write(&mut vk)?;
+    let mut file = File::create(path)?;
+    serialize_into(&mut file, &(&vk, &data)).unwrap();
+    Ok(())
+}
+
+fn read_verifier_key_and_data<P: AsRef<Path>, E: Engine>(
+    path: P,
+) -> io::Result<(VerifyingKey<E>, VerifierData)> {
+    let mut file = File::open(path)?;
+    let (vk_bytes, data): (Vec<u8>, VerifierData) = deserialize_from(&mut file).unwrap();
+    let vk: VerifyingKey<E> = VerifyingKey::read(vk_bytes.as_slice())?;
+    Ok((vk, data))
+}
+
+/// Given
+/// * a proving-key path,
+/// * a proof path, and
+/// * a prover input map
+/// generate a random proof and writes it to the path
+pub fn prove<E: Engine, P1: AsRef<Path>, P2: AsRef<Path>>(
+    pk_path: P1,
+    pf_path: P2,
+    inputs_map: &FxHashMap<String, Value>,
+) -> io::Result<()>
+where
+    E::Fr: PrimeFieldBits,
+{
+    let (pk, prover_data) = read_prover_key_and_data::<_, E>(pk_path)?;
+    let rng = &mut rand::thread_rng();
+
+    // what we will do is compute in rounds 2 rounds
+    // each precompute input will have its epoch...
+    //
+    for (input, (sort, _epoch)) in &prover_data.precompute_inputs {
+        let value = inputs_map
+            .get(input)
+            .unwrap_or_else(|| panic!("No input for {}", input));
+        let sort2 = value.sort();
+        assert_eq!(
+            sort, &sort2,
+            "Sort mismatch for {}. Expected\n\t{} but got\n\t{}",
+            input, sort, sort2
+        );
+    }
+    let new_map = prover_data.precompute.eval(inputs_map);
+    prover_data.r1cs.check_all(&new_map);
+    let pf = create_random_proof(SynthInput(&prover_data.r1cs, &Some(new_map)), &pk, rng).unwrap();
+    let mut pf_file = File::create(pf_path)?;
+    pf.write(&mut pf_file)?;
+    Ok(())
+}
+
+/// Given
+/// * a verifying-key path,
+/// * a proof path,
+/// * and a verifier input map
+/// checks the proof at that path
+pub fn verify<E: MultiMillerLoop, P1: AsRef<Path>, P2: AsRef<Path>>(
+    vk_path: P1,
+    pf_path: P2,
+    inputs_map: &FxHashMap<String, Value>,
+) -> io::Result<()> {
+    let (vk, verifier_data) = read_verifier_key_and_data::<_, E>(vk_path)?;
+    let pvk = prepare_verifying_key(&vk);
+    let inputs = verifier_data.eval(inputs_map);
+    let inputs_as_ff: Vec<E::Fr> = inputs.into_iter().map(int_to_ff).collect();
+    let mut pf_file = File::open(pf_path).unwrap();
+    let pf = Proof::read(&mut pf_file).unwrap();
+    verify_proof(&pvk, &pf, &inputs_as_ff).unwrap();
+    Ok(())
+}
+
+/// A proof in a batch: the verifier's input map, and the proof bytes.
+type BatchEntry = (FxHashMap<String, Value>, Vec<u8>);
+
+/// Given
+/// * a batch path,
+/// * a proof path, and
+/// * a verifier input map
+/// appends the proof and the input map to the batch at that path (creating it if needed).
+///
+/// A batch file is a sequence of bincode-encoded (input map, proof) entries.
+pub fn add_to_batch<P1: AsRef<Path>, P2: AsRef<Path>>(
+    batch_path: P1,
+    pf_path: P2,
+    inputs_map: &FxHashMap<String, Value>,
+) -> io::Result<()> {
+    let pf = std::fs::read(pf_path)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(batch_path)?;
+    serialize_into(&mut file, &(inputs_map, &pf))
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+fn read_batch<P: AsRef<Path>>(path: P) -> io::Result<Vec<BatchEntry>> {
+    let bytes = std::fs::read(path)?;
+    let len = bytes.len() as u64;
+    let mut cursor = Cursor::new(bytes);
+    let mut entries = Vec::new();
+    while cursor.position() < len {
+        let entry = deserialize_from(&mut cursor)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Check many proofs, each with its (verifier) inputs, against one verifying key.
+///
+/// Each Groth16 check is `e(A, B) = e(alpha, beta) e(IC(x), gamma) e(C, delta)`. We check a
+/// random linear combination of these equations instead, so the `gamma` and `delta` pairings,
+/// the final exponentiation, and the input multi-exponentiation are shared by the whole batch.
+/// Each proof costs one Miller loop, and some scalar multiplications.
+///
+/// If the batch fails, some proof in it is invalid (and if it passes, then all proofs are valid,
+/// except with negligible probability).
+pub fn batch_verify_proofs<E: MultiMillerLoop, R: RngCore>(
+    vk: &VerifyingKey<E>,
+    batch: &[(Proof<E>, Vec<E::Fr>)],
+    rng: &mut R,
+) -> Result<(), VerificationError> {
+    // coefficients of the IC points, summed over the batch
+    let mut ic_coeffs = vec![E::Fr::zero(); vk.ic.len()];
+    let mut acc_c = E::G1::identity();
+    let mut a_terms = Vec::with_capacity(batch.len());
+    for (pf, inputs) in batch {
+        if inputs.len() + 1 != vk.ic.len() {
+            return Err(VerificationError::InvalidVerifyingKey);
+        }
+        let r = E::Fr::random(&mut *rng);
+        ic_coeffs[0] += r;
+        for (coeff, input) in ic_coeffs[1..].iter_mut().zip(inputs) {
+            *coeff += r * input;
+        }
+        acc_c += pf.c * r;
+        a_terms.push(((pf.a * r).to_affine(), E::G2Prepared::from(pf.b)));
+    }
+    let r_sum = ic_coeffs[0];
+    let mut acc_ic = E::G1::identity();
+    for (coeff, ic) in ic_coeffs.into_iter().zip(&vk.ic) {
+        acc_ic += *ic * coeff;
+    }
+    let neg_acc_ic = (-acc_ic).to_affine();
+    let neg_acc_c = (-acc_c).to_affine();
+    let gamma = E::G2Prepared::from(vk.gamma_g2);
+    let delta = E::G2Prepared::from(vk.delta_g2);
+    let mut terms: Vec<(&E::G1Affine, &E::G2Prepared)> =
+        a_terms.iter().map(|(a, b)| (a, b)).collect();
+    terms.push((&neg_acc_ic, &gamma));
+    terms.push((&neg_acc_c, &delta));
+    let lhs = E::multi_miller_loop(&terms).final_exponentiation();
+    let rhs = E::pairing(&vk.alpha_g1, &vk.beta_g2) * r_sum;
+    if lhs == rhs {
+        Ok(())
+    } else {
+        Err(VerificationError::InvalidProof)
+    }
+}
+
+/// Given
+/// * a verifying-key path, and
+/// * a batch path (see [add_to_batch])
+/// checks all proofs in the batch at that path.
+///
+/// If the batch does not verify, the error names the first proof that does not.
+pub fn batch_verify<E: MultiMillerLoop, P1: AsRef<Path>, P2: AsRef<Path>>(
+    vk_path: P1,
+    batch_path: P2,
+) -> io::Result<()> {
+    let (vk, verifier_data) = read_verifier_key_and_data::<_, E>(vk_path)?;
+    let mut batch = Vec::new();
+    for (inputs_map, pf_bytes) in read_batch(batch_path)? {
+        let inputs = verifier_data.eval(&inputs_map);
+        let inputs_as_ff: Vec<E::Fr> = inputs.into_iter().map(int_to_ff).collect();
+        let pf = Proof::read(pf_bytes.as_slice())?;
+        batch.push((pf, inputs_as_ff));
+    }
+    debug!("batch verifying {} proofs", batch.len());
+    if batch_verify_proofs(&vk, &batch, &mut rand::thread_rng()).is_err() {
+        // find a culprit
+        let pvk = prepare_verifying_key(&vk);
+        for (i, (pf, inputs)) in batch.iter().enumerate() {
+            if let Err(e) = verify_proof(&pvk, pf, inputs) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Proof {} in the batch does not verify: {:?}", i, e),
+                ));
+            }
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The batch does not verify, but each proof in it does",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::proof::PROVER_ID;
+    use crate::target::r1cs::trans::to_r1cs;
+    use crate::util::field::DFL_T;
+    use bls12_381::{Bls12, Scalar};
+    use quickcheck::{Arbitrary, Gen};
+    use quickcheck_macros::quickcheck;
+    use std::io::Write;
+
+    #[derive(Clone, Debug)]
+    struct BlsScalar(Integer);
+
+    impl Arbitrary for BlsScalar {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let mut rug_rng = rug::rand::RandState::new_mersenne_twister();
+            rug_rng.seed(&Integer::from(u32::arbitrary(g)));
+            let modulus = Integer::from(
+                Integer::parse_radix(
+                    "73eda753299d7d483339d80809a1d80553bda402fffe5bfeffffffff00000001",
+                    16,
+                )
+                .unwrap(),
+            );
+            let i = Integer::from(modulus.random_below_ref(&mut rug_rng));
+            BlsScalar(i)
+        }
+    }
+
+    #[quickcheck]
+    fn int_to_ff_random(BlsScalar(i): BlsScalar) -> bool {
+        let by_fn = int_to_ff::<Scalar>(i.clone());
+        let by_str = Scalar::from_str_vartime(&format!("{}", i)).unwrap();
+        by_fn == by_str
+    }
+
+    fn convert(i: Integer) {
+        let by_fn = int_to_ff::<Scalar>(i.clone());
+        let by_str = Scalar::from_str_vartime(&format!("{}", i)).unwrap();
+        assert_eq!(by_fn, by_str);
+    }
+
+    #[test]
+    fn neg_one() {
+        let modulus = Integer::from(
+            Integer::parse_radix(
+                "73eda753299d7d483339d80809a1d80553bda402fffe5bfeffffffff00000001",
+                16,
+            )
+            .unwrap(),
+        );
+        convert(modulus - 1);
+    }
+
+    #[test]
+    fn zero() {
+        convert(Integer::from(0));
+    }
+
+    #[test]
+    fn one() {
+        convert(Integer::from(1));
+    }
+
+    #[test]
+    fn parse() {
+        let path = format!("{}/instance", std::env::temp_dir().to_str().unwrap());
+        {
+            let mut f = File::create(&path).unwrap();
+            write!(f, "5\n6").unwrap();
+        }
+        let i = parse_instance::<_, Scalar>(&path);
+        assert_eq!(i[0], Scalar::from(5));
+        assert_eq!(i[1], Scalar::from(6));
+    }
+
+    #[test]
+    fn batch() {
+        let mut cs = Computation::new();
+        let f = Sort::Field(DFL_T.clone());
+        let x = cs.new_var("x", f.clone(), None, 0, false, None);
+        let w = cs.new_var("w", f, Some(PROVER_ID), 0, false, None);
+        cs.assert(term![EQ; x, term![PF_MUL; w.clone(), w]]);
+        let (_, pd, vd) = to_r1cs(cs, DFL_T.clone());
+        let rng = &mut rand::thread_rng();
+        let params =
+            generate_random_parameters::<Bls12, _, _>(SynthInput(&pd.r1cs, &None), rng).unwrap();
+        let field = |i: u64| Value::Field(DFL_T.new_v(i));
+        let mut batch: Vec<(Proof<Bls12>, Vec<Scalar>)> = (1..4)
+            .map(|i| {
+                let public: FxHashMap<String, Value> =
+                    vec![("x".to_owned(), field(i * i))].into_iter().collect();
+                let mut values = public.clone();
+                values.insert("w".to_owned(), field(i));
+                let values = pd.precompute.eval(&values);
+                let pf =
+                    create_random_proof(SynthInput(&pd.r1cs, &Some(values)), &params, rng).unwrap();
+                let inputs = vd.eval(&public).into_iter().map(int_to_ff).collect();
+                (pf, inputs)
+            })
+            .collect();
+        assert!(batch_verify_proofs(&params.vk, &batch, rng).is_ok());
+        batch[0].1 = batch[1].1.clone();
+        assert!(batch_verify_proofs(&params.vk, &batch, rng).is_err());
+    }
+}
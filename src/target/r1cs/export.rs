@@ -0,0 +1,287 @@
+//! Exporting R1CS instances to other tools
+//!
+//! We support:
+//!
+//! * the [circom](https://github.com/iden3/r1csfile/blob/master/doc/r1cs_bin_format.md) binary
+//!   `.r1cs` format (read by snarkjs, and many other provers),
+//! * the circom binary `.wtns` witness format, and
+//! * a human-readable JSON dump, which keeps signal names.
+//!
+//! In the binary formats, wire 0 is the constant one, and the public signals come next (as public
+//! inputs, in index order), followed by all other signals (in index order). Random signals (verifier
+//! challenges) have no circom counterpart; they are exported as ordinary witness wires.
+
+use super::*;
+
+use rug::integer::Order;
+use serde_json::json;
+use std::io::{self, Write};
+
+/// The circom signal order: the original signal indices, in exported wire order (wire 0, the
+/// constant one, is not included).
+pub(super) fn wire_order(r1cs: &R1cs<String>) -> Vec<usize> {
+    let public = (0..r1cs.next_idx)
+        .filter(|i| r1cs.public_idxs.contains(i) && !r1cs.random_idxs.contains(i));
+    let private = (0..r1cs.next_idx)
+        .filter(|i| !r1cs.public_idxs.contains(i) || r1cs.random_idxs.contains(i));
+    public.chain(private).collect()
+}
+
+fn n_public(r1cs: &R1cs<String>) -> usize {
+    r1cs.public_idxs.difference(&r1cs.random_idxs).count()
+}
+
+/// The number of bytes used for each field element: the modulus size, rounded up to 8 bytes.
+pub(super) fn field_bytes(modulus: &Integer) -> usize {
+    (modulus.significant_bits() as usize + 63) / 64 * 8
+}
+
+fn write_u32<W: Write>(w: &mut W, x: usize) -> io::Result<()> {
+    w.write_all(&(x as u32).to_le_bytes())
+}
+
+fn write_u64<W: Write>(w: &mut W, x: usize) -> io::Result<()> {
+    w.write_all(&(x as u64).to_le_bytes())
+}
+
+fn write_int<W: Write>(w: &mut W, i: &Integer, n8: usize) -> io::Result<()> {
+    let mut bytes = i.to_digits::<u8>(Order::Lsf);
+    assert!(bytes.len() <= n8);
+    bytes.resize(n8, 0);
+    w.write_all(&bytes)
+}
+
+fn write_section<W: Write>(w: &mut W, ty: usize, contents: &[u8]) -> io::Result<()> {
+    write_u32(w, ty)?;
+    write_u64(w, contents.len())?;
+    w.write_all(contents)
+}
+
+/// Write `r1cs` in the circom binary `.r1cs` format.
+pub fn write_circom_r1cs<W: Write>(r1cs: &R1cs<String>, mut w: W) -> io::Result<()> {
+    let modulus = r1cs.modulus();
+    let n8 = field_bytes(modulus);
+    let order = wire_order(r1cs);
+    let wire_of: HashMap<usize, usize> = order
+        .iter()
+        .enumerate()
+        .map(|(wire, idx)| (*idx, wire + 1))
+        .collect();
+    let n_wires = order.len() + 1;
+
+    let mut header = Vec::new();
+    write_u32(&mut header, n8)?;
+    write_int(&mut header, modulus, n8)?;
+    write_u32(&mut header, n_wires)?;
+    // public outputs
+    write_u32(&mut header, 0)?;
+    // public inputs
+    write_u32(&mut header, n_public(r1cs))?;
+    // private inputs
+    write_u32(&mut header, 0)?;
+    // labels
+    write_u64(&mut header, n_wires)?;
+    write_u32(&mut header, r1cs.constraints.len())?;
+
+    let mut constraints = Vec::new();
+    for (a, b, c) in &r1cs.constraints {
+        for lc in [a, b, c] {
+            let mut terms: Vec<(usize, Integer)> = lc
+                .monomials
+                .iter()
+                .map(|(idx, coeff)| (*wire_of.get(idx).unwrap(), coeff.i()))
+                .collect();
+            if !lc.constant.is_zero() {
+                terms.push((0, lc.constant.i()));
+            }
+            terms.sort();
+            write_u32(&mut constraints, terms.len())?;
+            for (wire, coeff) in terms {
+                write_u32(&mut constraints, wire)?;
+                write_int(&mut constraints, &coeff, n8)?;
+            }
+        }
+    }
+
+    let mut labels = Vec::new();
+    for wire in 0..n_wires {
+        write_u64(&mut labels, wire)?;
+    }
+
+    w.write_all(b"r1cs")?;
+    write_u32(&mut w, 1)?;
+    write_u32(&mut w, 3)?;
+    write_section(&mut w, 1, &header)?;
+    write_section(&mut w, 2, &constraints)?;
+    write_section(&mut w, 3, &labels)?;
+    Ok(())
+}
+
+/// Write a witness for `r1cs` in the circom binary `.wtns` format.
+///
+/// `values` must bind every signal (e.g., as computed by the [ProverData] precomputation).
+pub fn write_circom_wtns<W: Write>(
+    r1cs: &R1cs<String>,
+    values: &HashMap<String, Value>,
+    w: W,
+) -> io::Result<()> {
+    let witness: Vec<Integer> = wire_order(r1cs)
+        .into_iter()
+        .map(|idx| {
+            let name = r1cs.idxs_signals.get(&idx).unwrap();
+            values
+                .get(name)
+                .unwrap_or_else(|| panic!("Missing value for signal {}", name))
+                .as_pf()
+                .i()
+        })
+        .collect();
+    write_wtns(r1cs.modulus(), &witness, w)
+}
+
+/// Write the circom binary `.wtns` format, given the values of the wires after wire 0 (in wire
+/// order).
+pub(super) fn write_wtns<W: Write>(
+    modulus: &Integer,
+    witness: &[Integer],
+    mut w: W,
+) -> io::Result<()> {
+    let n8 = field_bytes(modulus);
+
+    let mut header = Vec::new();
+    write_u32(&mut header, n8)?;
+    write_int(&mut header, modulus, n8)?;
+    write_u32(&mut header, witness.len() + 1)?;
+
+    let mut wires = Vec::new();
+    write_int(&mut wires, &Integer::from(1), n8)?;
+    for value in witness {
+        write_int(&mut wires, value, n8)?;
+    }
+
+    w.write_all(b"wtns")?;
+    write_u32(&mut w, 2)?;
+    write_u32(&mut w, 2)?;
+    write_section(&mut w, 1, &header)?;
+    write_section(&mut w, 2, &wires)?;
+    Ok(())
+}
+
+fn lc_json(r1cs: &R1cs<String>, lc: &Lc) -> serde_json::Value {
+    let mut monomials: Vec<(&usize, &FieldV)> = lc.monomials.iter().collect();
+    monomials.sort_by_key(|(idx, _)| **idx);
+    json!({
+        "constant": lc.constant.i().to_string(),
+        "terms": monomials
+            .into_iter()
+            .map(|(idx, coeff)| json!([r1cs.idxs_signals.get(idx).unwrap(), coeff.i().to_string()]))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Write `r1cs` as (pretty-printed) JSON, with signal names.
+///
+/// The output has the modulus, a list of signals (with their visibility and epoch), and a list of
+/// constraints `{a, b, c}`, meaning `a * b = c`. Each linear combination has a constant and a list
+/// of `[signal, coefficient]` terms. Field elements are decimal strings.
+pub fn write_json<W: Write>(r1cs: &R1cs<String>, w: W) -> io::Result<()> {
+    let signals: Vec<serde_json::Value> = (0..r1cs.next_idx)
+        .map(|i| {
+            let name = r1cs.idxs_signals.get(&i).unwrap();
+            json!({
+                "name": name,
+                "public": r1cs.public_idxs.contains(&i),
+                "random": r1cs.random_idxs.contains(&i),
+                "epoch": r1cs.signal_epochs.get(name).unwrap(),
+            })
+        })
+        .collect();
+    let constraints: Vec<serde_json::Value> = r1cs
+        .constraints
+        .iter()
+        .map(|(a, b, c)| {
+            json!({
+                "a": lc_json(r1cs, a),
+                "b": lc_json(r1cs, b),
+                "c": lc_json(r1cs, c),
+            })
+        })
+        .collect();
+    let doc = json!({
+        "modulus": r1cs.modulus().to_string(),
+        "signals": signals,
+        "constraints": constraints,
+    });
+    serde_json::to_writer_pretty(w, &doc).map_err(io::Error::from)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn xy_r1cs() -> R1cs<String> {
+        let field = FieldT::from(Integer::from(101));
+        let mut r1cs = R1cs::new(field.clone());
+        let x = leaf_term(Op::Var("x".into(), Sort::Field(field.clone())));
+        let y = leaf_term(Op::Var("y".into(), Sort::Field(field.clone())));
+        r1cs.add_signal("x".into(), x, 0);
+        r1cs.add_signal("y".into(), y, 0);
+        r1cs.publicize(&"y".to_owned());
+        // x * x = y + 3
+        let x_lc = r1cs.signal_lc(&"x".to_owned());
+        let y_lc = r1cs.signal_lc(&"y".to_owned()) + &field.new_v(3);
+        r1cs.constraint(x_lc.clone(), x_lc, y_lc);
+        r1cs
+    }
+
+    #[test]
+    fn r1cs_header() {
+        let r1cs = xy_r1cs();
+        let mut bytes = Vec::new();
+        write_circom_r1cs(&r1cs, &mut bytes).unwrap();
+        assert_eq!(&bytes[0..4], b"r1cs");
+        // version, sections, header type, header size
+        assert_eq!(&bytes[4..8], &1u32.to_le_bytes());
+        assert_eq!(&bytes[8..12], &3u32.to_le_bytes());
+        assert_eq!(&bytes[12..16], &1u32.to_le_bytes());
+        assert_eq!(&bytes[16..24], &(4 + 8 + 4 * 4 + 8 + 4u64).to_le_bytes());
+        // field size and modulus
+        assert_eq!(&bytes[24..28], &8u32.to_le_bytes());
+        assert_eq!(&bytes[28..36], &101u64.to_le_bytes());
+        // wires: one, y (public), x
+        assert_eq!(&bytes[36..40], &3u32.to_le_bytes());
+        assert_eq!(wire_order(&r1cs), vec![1, 0]);
+    }
+
+    #[test]
+    fn wtns() {
+        let r1cs = xy_r1cs();
+        let field = FieldT::from(Integer::from(101));
+        let values: HashMap<String, Value> = vec![
+            ("x".to_owned(), Value::Field(field.new_v(5))),
+            ("y".to_owned(), Value::Field(field.new_v(22))),
+        ]
+        .into_iter()
+        .collect();
+        r1cs.check_all(&values);
+        let mut bytes = Vec::new();
+        write_circom_wtns(&r1cs, &values, &mut bytes).unwrap();
+        assert_eq!(&bytes[0..4], b"wtns");
+        let witness = &bytes[bytes.len() - 24..];
+        assert_eq!(&witness[0..8], &1u64.to_le_bytes());
+        assert_eq!(&witness[8..16], &22u64.to_le_bytes());
+        assert_eq!(&witness[16..24], &5u64.to_le_bytes());
+    }
+
+    #[test]
+    fn json_names() {
+        let r1cs = xy_r1cs();
+        let mut bytes = Vec::new();
+        write_json(&r1cs, &mut bytes).unwrap();
+        let doc: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(doc["signals"][1]["name"], "y");
+        assert_eq!(doc["signals"][1]["public"], true);
+        assert_eq!(doc["constraints"][0]["c"]["constant"], "3");
+        assert_eq!(doc["constraints"][0]["a"]["terms"][0][0], "x");
+    }
+}
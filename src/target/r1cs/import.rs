@@ -0,0 +1,238 @@
+//! Importing R1CS instances from other tools
+//!
+//! We read the [circom](https://github.com/iden3/r1csfile/blob/master/doc/r1cs_bin_format.md)
+//! binary `.r1cs` format, optionally naming signals with a circom `.sym` file. Wire 0 (the
+//! constant one) becomes the constant term of each combination. The public outputs and inputs
+//! become public signals.
+//!
+//! An imported system can be lifted to an IR [Computation] (see [R1cs::lift]), and so compiled
+//! like any other.
+
+use super::export::field_bytes;
+use super::*;
+
+use crate::ir::proof::Constraints;
+
+use rug::integer::Order;
+use std::io::{self, Read};
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// A little-endian cursor over a byte buffer.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(invalid(format!(
+                "Unexpected end of data (wanted {} bytes)",
+                n
+            )));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+    fn u32(&mut self) -> io::Result<usize> {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b) as usize)
+    }
+    fn u64(&mut self) -> io::Result<usize> {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b) as usize)
+    }
+    fn int(&mut self, n8: usize) -> io::Result<Integer> {
+        Ok(Integer::from_digits(self.take(n8)?, Order::Lsf))
+    }
+}
+
+/// Parse a circom `.sym` file: lines of `label,wire,component,name`. Returns a map from wires to
+/// names. Labels without a wire (eliminated by circom) are skipped; if a wire has several
+/// labels, the first is used.
+pub fn parse_circom_sym(sym: &str) -> io::Result<HashMap<usize, String>> {
+    let mut names = HashMap::default();
+    for line in sym.lines().filter(|l| !l.trim().is_empty()) {
+        let fields: Vec<&str> = line.splitn(4, ',').collect();
+        if fields.len() != 4 {
+            return Err(invalid(format!("Bad .sym line: {}", line)));
+        }
+        let wire: i64 = fields[1]
+            .trim()
+            .parse()
+            .map_err(|_| invalid(format!("Bad wire in .sym line: {}", line)))?;
+        if wire > 0 {
+            names
+                .entry(wire as usize)
+                .or_insert_with(|| fields[3].trim().to_owned());
+        }
+    }
+    Ok(names)
+}
+
+/// Read a circom binary `.r1cs` file, naming wire `i` `w{i}`.
+pub fn read_circom_r1cs<R: Read>(r: R) -> io::Result<R1cs<String>> {
+    read_circom_r1cs_with_names(r, &HashMap::default())
+}
+
+/// Read a circom binary `.r1cs` file, naming wires according to `names` (see
+/// [parse_circom_sym]). Unnamed wire `i` is named `w{i}`.
+pub fn read_circom_r1cs_with_names<R: Read>(
+    mut r: R,
+    names: &HashMap<usize, String>,
+) -> io::Result<R1cs<String>> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    let mut bytes = Bytes(&data);
+    if bytes.take(4)? != b"r1cs" {
+        return Err(invalid("Not an .r1cs file".into()));
+    }
+    let version = bytes.u32()?;
+    if version != 1 {
+        return Err(invalid(format!("Unsupported .r1cs version {}", version)));
+    }
+    let n_sections = bytes.u32()?;
+    let mut header = None;
+    let mut constraints = None;
+    for _ in 0..n_sections {
+        let ty = bytes.u32()?;
+        let size = bytes.u64()?;
+        let contents = bytes.take(size)?;
+        match ty {
+            1 => header = Some(contents),
+            2 => constraints = Some(contents),
+            // wire-to-label map, custom gates, ...
+            _ => {}
+        }
+    }
+    let mut header = Bytes(header.ok_or_else(|| invalid("Missing header section".into()))?);
+    let n8 = header.u32()?;
+    let prime = header.int(n8)?;
+    let n_wires = header.u32()?;
+    let n_pub_out = header.u32()?;
+    let n_pub_in = header.u32()?;
+    let _n_prv_in = header.u32()?;
+    let _n_labels = header.u64()?;
+    let n_constraints = header.u32()?;
+    if n8 != field_bytes(&prime) {
+        return Err(invalid(format!(
+            "Bad field size {} for prime {}",
+            n8, prime
+        )));
+    }
+
+    let field = FieldT::from(prime);
+    let mut r1cs = R1cs::new(field.clone());
+    let name = |wire: usize| {
+        names
+            .get(&wire)
+            .cloned()
+            .unwrap_or_else(|| format!("w{}", wire))
+    };
+    for wire in 1..n_wires {
+        let n = name(wire);
+        let var = leaf_term(Op::Var(n.clone(), Sort::Field(field.clone())));
+        r1cs.add_signal(n.clone(), var, 0);
+        if wire <= n_pub_out + n_pub_in {
+            r1cs.publicize(&n);
+        }
+    }
+
+    let constraints = constraints.ok_or_else(|| invalid("Missing constraints section".into()))?;
+    let mut cs = Bytes(constraints);
+    for _ in 0..n_constraints {
+        let mut lcs = Vec::new();
+        for _ in 0..3 {
+            let mut lc = r1cs.zero();
+            for _ in 0..cs.u32()? {
+                let wire = cs.u32()?;
+                let coeff = field.new_v(cs.int(n8)?);
+                if wire == 0 {
+                    lc += &coeff;
+                } else if wire < n_wires {
+                    lc += &(r1cs.signal_lc(&name(wire)) * &coeff);
+                } else {
+                    return Err(invalid(format!("Wire {} out of range", wire)));
+                }
+            }
+            lcs.push(lc);
+        }
+        let c = lcs.pop().unwrap();
+        let b = lcs.pop().unwrap();
+        let a = lcs.pop().unwrap();
+        r1cs.constraint(a, b, c);
+    }
+    Ok(r1cs)
+}
+
+impl R1cs<String> {
+    /// Lift this system to an IR computation that asserts its constraints (see
+    /// [R1cs::ir_term]). Each signal becomes an input: public signals are public; the rest are
+    /// known to the prover.
+    pub fn lift(&self) -> Computation {
+        let public_inputs = (0..self.next_idx)
+            .filter(|i| self.public_idxs.contains(i))
+            .map(|i| {
+                let name = self.idxs_signals.get(&i).unwrap().clone();
+                leaf_term(Op::Var(name, Sort::Field(self.modulus.clone())))
+            })
+            .collect();
+        Computation::from_constraint_system_parts(vec![self.ir_term()], public_inputs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::export::write_circom_r1cs;
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let field = FieldT::from(Integer::from(101));
+        let mut r1cs = R1cs::new(field.clone());
+        for n in &["x", "y", "z"] {
+            let v = leaf_term(Op::Var(n.to_string(), Sort::Field(field.clone())));
+            r1cs.add_signal(n.to_string(), v, 0);
+        }
+        r1cs.publicize(&"z".to_owned());
+        // x * (y + 2) = z
+        let x = r1cs.signal_lc(&"x".to_owned());
+        let y = r1cs.signal_lc(&"y".to_owned()) + &field.new_v(2);
+        let z = r1cs.signal_lc(&"z".to_owned());
+        r1cs.constraint(x, y, z);
+        let mut bytes = Vec::new();
+        write_circom_r1cs(&r1cs, &mut bytes).unwrap();
+
+        // wires: 1 -> z, 2 -> x, 3 -> y
+        let names =
+            parse_circom_sym("1,1,0,main.z\n2,2,0,main.x\n3,3,0,main.y\n4,-1,0,main.t\n").unwrap();
+        let imported = read_circom_r1cs_with_names(&bytes[..], &names).unwrap();
+        assert_eq!(imported.constraints().len(), 1);
+        assert_eq!(imported.public_idxs.len(), 1);
+        let values = |x: u32, y: u32, z: u32| -> HashMap<String, Value> {
+            vec![("main.x", x), ("main.y", y), ("main.z", z)]
+                .into_iter()
+                .map(|(n, v)| (n.to_owned(), Value::Field(field.new_v(v))))
+                .collect()
+        };
+        imported.check_all(&values(3, 4, 18));
+
+        let cs = imported.lift();
+        assert!(cs.metadata.is_input_public("main.z"));
+        assert!(!cs.metadata.is_input_public("main.x"));
+        assert_eq!(eval(&cs.outputs[0], &values(3, 4, 18)), Value::Bool(true));
+        assert_eq!(eval(&cs.outputs[0], &values(3, 4, 19)), Value::Bool(false));
+
+        // without names
+        let unnamed = read_circom_r1cs(&bytes[..]).unwrap();
+        assert_eq!(unnamed.idxs_signals.get(&0).unwrap(), "w1");
+    }
+
+    #[test]
+    fn not_r1cs() {
+        assert!(read_circom_r1cs(&b"wtns\x02\x00\x00\x00"[..]).is_err());
+    }
+}
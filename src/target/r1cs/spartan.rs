@@ -0,0 +1,1417 @@
+//! A transparent proof system for our R1CS, in the style of Spartan
+//!
+//! This is the Spartan NIZK of [Setty](https://eprint.iacr.org/2019/550), with Hyrax-style
+//! commitments. It needs no trusted setup: the Pedersen generators are derived by hashing a fixed
+//! label, so the "keys" are just the instance itself. It works over any prime-order group whose
+//! scalar field is the R1CS field (e.g., the BLS12-381 G1 group, for our default field).
+//!
+//! Writing the relation as `(A z) * (B z) = C z` for `z = (w, 1, x)`, the prover:
+//!
+//! 1. commits to the witness `w` (as a matrix of Pedersen row commitments), epoch by epoch,
+//! 2. runs a sumcheck showing that `sum_x eq(tau, x) (Az(x) Bz(x) - Cz(x)) = 0` for random `tau`,
+//! 3. runs a second sumcheck reducing the evaluations of `Az`, `Bz`, and `Cz` at the resulting
+//!    point to a single evaluation of `z`, and
+//! 4. opens the witness commitment at that point.
+//!
+//! All sumcheck messages and evaluations are sent as commitments, with sigma-protocol proofs of
+//! the relations between them, so the proof is zero-knowledge. The verifier evaluates the
+//! constraint matrices itself, so verification takes time linear in the size of the instance.
+//! Proofs are `O(sqrt(|w|) + log(|constraints|))` group elements.
+//!
+//! Computations with verifier randomness (i.e., random signals) are proven in epochs: the prover
+//! commits to each epoch's rows of the witness matrix, and then draws that epoch's challenges from
+//! a [Sha256Transcript] of the commitments so far. The verifier re-derives them from the proof.
+
+use bincode::{deserialize_from, serialize_into};
+use ff::{Field, PrimeField};
+use fxhash::FxHashMap;
+use group::{Group, GroupEncoding};
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use thiserror::Error;
+
+use rug::Integer;
+
+use super::transcript::{Sha256Transcript, Transcript};
+use super::*;
+
+/// The label from which the Pedersen generators are derived.
+const GENS_LABEL: &[u8] = b"circ spartan generators";
+
+#[derive(Debug, Error)]
+/// An error in proving or verifying
+pub enum SpartanError {
+    #[error("IO error: {0}")]
+    /// Reading or writing a key or proof failed
+    Io(#[from] io::Error),
+    #[error("Malformed proof: {0}")]
+    /// The proof could not be decoded, or has the wrong shape
+    Malformed(&'static str),
+    #[error("Unsupported R1CS instance: {0}")]
+    /// The R1CS (e.g., in a verifying key) is malformed, or is over the wrong field
+    Instance(&'static str),
+    #[error("Proof rejected: {0} check failed")]
+    /// The proof is well-formed, but invalid
+    Rejected(&'static str),
+}
+
+type Result<T> = std::result::Result<T, SpartanError>;
+
+fn bincode_err(e: bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn dot<F: Field>(a: &[F], b: &[F]) -> F {
+    a.iter().zip(b).fold(F::zero(), |acc, (a, b)| acc + *a * b)
+}
+
+fn log2(n: usize) -> usize {
+    debug_assert!(n.is_power_of_two());
+    n.trailing_zeros() as usize
+}
+
+/// The table of `eq(r, x)` for all `x` in the boolean hypercube. `r[0]` is the most significant
+/// bit of the table index.
+fn eq_table<F: Field>(r: &[F]) -> Vec<F> {
+    let mut table = vec![F::one()];
+    for r_i in r {
+        let mut next = Vec::with_capacity(table.len() * 2);
+        for e in table {
+            let hi = e * r_i;
+            next.push(e - hi);
+            next.push(hi);
+        }
+        table = next;
+    }
+    table
+}
+
+/// `eq(a, b)`: one if the (boolean) points are equal, and zero otherwise.
+fn eq<F: Field>(a: &[F], b: &[F]) -> F {
+    a.iter().zip(b).fold(F::one(), |acc, (a, b)| {
+        acc * (*a * b + (F::one() - a) * (F::one() - b))
+    })
+}
+
+/// Fix the most significant variable of the multilinear extension of `table` to `r`.
+fn bind<F: Field>(table: &mut Vec<F>, r: F) {
+    let half = table.len() / 2;
+    for i in 0..half {
+        table[i] = table[i] + r * (table[i + half] - table[i]);
+    }
+    table.truncate(half);
+}
+
+/// The weights `l` such that `p(r) = sum_j l[j] p(j)`, for `p` of degree at most `degree`.
+fn lagrange<F: PrimeField>(degree: usize, r: F) -> Vec<F> {
+    (0..=degree)
+        .map(|j| {
+            let (mut num, mut den) = (F::one(), F::one());
+            for k in (0..=degree).filter(|k| *k != j) {
+                num *= r - F::from(k as u64);
+                den *= F::from(j as u64) - F::from(k as u64);
+            }
+            num * den.invert().unwrap()
+        })
+        .collect()
+}
+
+/// Pedersen generators: `g` for scalars, `gs` for vectors, and `h` for blinding.
+struct Gens<G> {
+    g: G,
+    h: G,
+    gs: Vec<G>,
+}
+
+impl<G: Group> Gens<G> {
+    /// Derive generators (for vectors of length up to `n`) from [GENS_LABEL].
+    ///
+    /// Nobody knows their discrete logarithms, *assuming* that `G::random` samples a point
+    /// directly (as `bls12_381` does, by trying random coordinates until one is on the curve). If
+    /// it instead multiplied a fixed generator by a random scalar, anyone could recompute the
+    /// scalars from the public seed, and commitments would not be binding.
+    fn new(n: usize) -> Self {
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(&Sha256::digest(GENS_LABEL));
+        let mut rng = ChaCha20Rng::from_seed(seed);
+        let g = G::random(&mut rng);
+        let h = G::random(&mut rng);
+        let gs = (0..n).map(|_| G::random(&mut rng)).collect();
+        Gens { g, h, gs }
+    }
+    fn commit(&self, v: G::Scalar, blind: G::Scalar) -> G {
+        self.g * v + self.h * blind
+    }
+    fn commit_vec(&self, vs: &[G::Scalar], blind: G::Scalar) -> G {
+        assert!(vs.len() <= self.gs.len());
+        vs.iter()
+            .zip(&self.gs)
+            .fold(self.h * blind, |acc, (v, g)| acc + *g * v)
+    }
+}
+
+/// A proof that `c_x` commits to a vector `x` and `c_y` commits to `<a, x>`, for public `a`.
+struct DotProductProof<G: Group> {
+    delta: G,
+    beta: G,
+    z: Vec<G::Scalar>,
+    z_delta: G::Scalar,
+    z_beta: G::Scalar,
+}
+
+impl<G: Group + GroupEncoding> DotProductProof<G> {
+    /// `x` and `<a, x>` are committed with blinds `r_x` and `r_y`.
+    #[allow(clippy::too_many_arguments)]
+    fn prove<R: RngCore>(
+        gens: &Gens<G>,
+        t: &mut Sha256Transcript,
+        rng: &mut R,
+        a: &[G::Scalar],
+        x: &[G::Scalar],
+        r_x: G::Scalar,
+        r_y: G::Scalar,
+    ) -> Self {
+        let d: Vec<G::Scalar> = x.iter().map(|_| G::Scalar::random(&mut *rng)).collect();
+        let r_delta = G::Scalar::random(&mut *rng);
+        let r_beta = G::Scalar::random(&mut *rng);
+        let delta = gens.commit_vec(&d, r_delta);
+        let beta = gens.commit(dot(a, &d), r_beta);
+        t.append_point(b"delta", &delta);
+        t.append_point(b"beta", &beta);
+        let c: G::Scalar = t.challenge_scalar(b"c");
+        DotProductProof {
+            delta,
+            beta,
+            z: x.iter().zip(&d).map(|(x, d)| c * x + d).collect(),
+            z_delta: c * r_x + r_delta,
+            z_beta: c * r_y + r_beta,
+        }
+    }
+    fn verify(
+        &self,
+        gens: &Gens<G>,
+        t: &mut Sha256Transcript,
+        a: &[G::Scalar],
+        c_x: G,
+        c_y: G,
+    ) -> Result<()> {
+        if self.z.len() != a.len() || a.len() > gens.gs.len() {
+            return Err(SpartanError::Malformed("dot product proof length"));
+        }
+        t.append_point(b"delta", &self.delta);
+        t.append_point(b"beta", &self.beta);
+        let c: G::Scalar = t.challenge_scalar(b"c");
+        if c_x * c + self.delta != gens.commit_vec(&self.z, self.z_delta)
+            || c_y * c + self.beta != gens.commit(dot(a, &self.z), self.z_beta)
+        {
+            return Err(SpartanError::Rejected("dot product"));
+        }
+        Ok(())
+    }
+}
+
+/// A proof that `c_x`, `c_y`, and `c_z` commit to `x`, `y`, and `x * y`.
+struct ProductProof<G: Group> {
+    alpha: G,
+    beta: G,
+    delta: G,
+    z: [G::Scalar; 5],
+}
+
+impl<G: Group + GroupEncoding> ProductProof<G> {
+    /// `x`, `y`, and `x * y` are committed with blinds `r_x`, `r_y`, and `r_z`.
+    #[allow(clippy::too_many_arguments)]
+    fn prove<R: RngCore>(
+        gens: &Gens<G>,
+        t: &mut Sha256Transcript,
+        rng: &mut R,
+        c_x: G,
+        (x, r_x): (G::Scalar, G::Scalar),
+        (y, r_y): (G::Scalar, G::Scalar),
+        r_z: G::Scalar,
+    ) -> Self {
+        let b: Vec<G::Scalar> = (0..5).map(|_| G::Scalar::random(&mut *rng)).collect();
+        let alpha = gens.commit(b[0], b[1]);
+        let beta = gens.commit(b[2], b[3]);
+        let delta = c_x * b[2] + gens.h * b[4];
+        t.append_point(b"alpha", &alpha);
+        t.append_point(b"beta", &beta);
+        t.append_point(b"delta", &delta);
+        let c: G::Scalar = t.challenge_scalar(b"c");
+        ProductProof {
+            alpha,
+            beta,
+            delta,
+            z: [
+                b[0] + c * x,
+                b[1] + c * r_x,
+                b[2] + c * y,
+                b[3] + c * r_y,
+                b[4] + c * (r_z - r_x * y),
+            ],
+        }
+    }
+    fn verify(
+        &self,
+        gens: &Gens<G>,
+        t: &mut Sha256Transcript,
+        c_x: G,
+        c_y: G,
+        c_z: G,
+    ) -> Result<()> {
+        t.append_point(b"alpha", &self.alpha);
+        t.append_point(b"beta", &self.beta);
+        t.append_point(b"delta", &self.delta);
+        let c: G::Scalar = t.challenge_scalar(b"c");
+        let z = &self.z;
+        if self.alpha + c_x * c != gens.commit(z[0], z[1])
+            || self.beta + c_y * c != gens.commit(z[2], z[3])
+            || self.delta + c_z * c != c_x * z[2] + gens.h * z[4]
+        {
+            return Err(SpartanError::Rejected("product"));
+        }
+        Ok(())
+    }
+}
+
+/// A proof that two commitments (with blinds `r_1` and `r_2`) are to the same value.
+struct EqualityProof<G: Group> {
+    alpha: G,
+    z: G::Scalar,
+}
+
+impl<G: Group + GroupEncoding> EqualityProof<G> {
+    fn prove<R: RngCore>(
+        gens: &Gens<G>,
+        t: &mut Sha256Transcript,
+        rng: &mut R,
+        r_1: G::Scalar,
+        r_2: G::Scalar,
+    ) -> Self {
+        let k = G::Scalar::random(&mut *rng);
+        let alpha = gens.h * k;
+        t.append_point(b"alpha", &alpha);
+        let c: G::Scalar = t.challenge_scalar(b"c");
+        EqualityProof {
+            alpha,
+            z: k + c * (r_1 - r_2),
+        }
+    }
+    fn verify(&self, gens: &Gens<G>, t: &mut Sha256Transcript, c_1: G, c_2: G) -> Result<()> {
+        t.append_point(b"alpha", &self.alpha);
+        let c: G::Scalar = t.challenge_scalar(b"c");
+        if gens.h * self.z != self.alpha + (c_1 - c_2) * c {
+            return Err(SpartanError::Rejected("equality"));
+        }
+        Ok(())
+    }
+}
+
+/// One round of a zero-knowledge sumcheck.
+///
+/// The prover commits to the evaluations of the round polynomial `p` at `0..=degree`, and (after
+/// the challenge `r`) to `p(r)`. It then shows that `p(0) + p(1)` is the previous claim and that
+/// `p(r)` is the new claim, by proving a random linear combination of the two with a
+/// [DotProductProof].
+struct Round<G: Group> {
+    c_poly: G,
+    c_next: G,
+    proof: DotProductProof<G>,
+}
+
+/// The public vector `w_0 (1, 1, 0, ...) + w_1 lagrange(r)`.
+fn round_check_vector<F: PrimeField>(w_0: F, w_1: F, weights: &[F]) -> Vec<F> {
+    weights
+        .iter()
+        .enumerate()
+        .map(|(j, l)| if j < 2 { w_0 + w_1 * l } else { w_1 * l })
+        .collect()
+}
+
+/// Evaluations at `0..=degree` of the next sumcheck round polynomial for `sum f(tables)`.
+fn round_evals<F: PrimeField>(tables: &[Vec<F>], degree: usize, f: &impl Fn(&[F]) -> F) -> Vec<F> {
+    let half = tables[0].len() / 2;
+    let mut evals = vec![F::zero(); degree + 1];
+    let mut point = vec![F::zero(); tables.len()];
+    for i in 0..half {
+        for (t, e) in evals.iter_mut().enumerate() {
+            let t = F::from(t as u64);
+            for (p, table) in point.iter_mut().zip(tables) {
+                *p = table[i] + t * (table[i + half] - table[i]);
+            }
+            *e += f(&point);
+        }
+    }
+    evals
+}
+
+/// Prove that the sum of `f` (applied pointwise to `tables`) over the boolean hypercube is the
+/// claim committed with blind `blind`.
+///
+/// Binds the tables to the challenges. Returns the rounds, the challenges, and the blind of the
+/// final claim (which is `f` of the bound tables).
+#[allow(clippy::too_many_arguments)]
+fn prove_sumcheck<G: Group + GroupEncoding, R: RngCore>(
+    gens: &Gens<G>,
+    t: &mut Sha256Transcript,
+    rng: &mut R,
+    tables: &mut [Vec<G::Scalar>],
+    degree: usize,
+    f: impl Fn(&[G::Scalar]) -> G::Scalar,
+    mut blind: G::Scalar,
+) -> (Vec<Round<G>>, Vec<G::Scalar>, G::Scalar) {
+    let n_vars = log2(tables[0].len());
+    let mut rounds = Vec::with_capacity(n_vars);
+    let mut rs = Vec::with_capacity(n_vars);
+    for _ in 0..n_vars {
+        let evals = round_evals(tables, degree, &f);
+        let r_poly = G::Scalar::random(&mut *rng);
+        let c_poly = gens.commit_vec(&evals, r_poly);
+        t.append_point(b"poly", &c_poly);
+        let r: G::Scalar = t.challenge_scalar(b"r");
+        let weights = lagrange(degree, r);
+        let next = dot(&weights, &evals);
+        let r_next = G::Scalar::random(&mut *rng);
+        let c_next = gens.commit(next, r_next);
+        t.append_point(b"claim", &c_next);
+        let w_0: G::Scalar = t.challenge_scalar(b"w_0");
+        let w_1: G::Scalar = t.challenge_scalar(b"w_1");
+        let a = round_check_vector(w_0, w_1, &weights);
+        let proof =
+            DotProductProof::prove(gens, t, rng, &a, &evals, r_poly, w_0 * blind + w_1 * r_next);
+        rounds.push(Round {
+            c_poly,
+            c_next,
+            proof,
+        });
+        for table in tables.iter_mut() {
+            bind(table, r);
+        }
+        rs.push(r);
+        blind = r_next;
+    }
+    (rounds, rs, blind)
+}
+
+/// Check a sumcheck of `n_vars` variables, for the claim committed in `c_claim`. Returns the
+/// challenges and the commitment to the final claim.
+fn verify_sumcheck<G: Group + GroupEncoding>(
+    gens: &Gens<G>,
+    t: &mut Sha256Transcript,
+    rounds: &[Round<G>],
+    n_vars: usize,
+    degree: usize,
+    mut c_claim: G,
+) -> Result<(Vec<G::Scalar>, G)> {
+    if rounds.len() != n_vars {
+        return Err(SpartanError::Malformed("number of sumcheck rounds"));
+    }
+    let mut rs = Vec::with_capacity(n_vars);
+    for round in rounds {
+        t.append_point(b"poly", &round.c_poly);
+        let r: G::Scalar = t.challenge_scalar(b"r");
+        t.append_point(b"claim", &round.c_next);
+        let w_0: G::Scalar = t.challenge_scalar(b"w_0");
+        let w_1: G::Scalar = t.challenge_scalar(b"w_1");
+        let a = round_check_vector(w_0, w_1, &lagrange(degree, r));
+        round.proof.verify(
+            gens,
+            t,
+            &a,
+            round.c_poly,
+            c_claim * w_0 + round.c_next * w_1,
+        )?;
+        rs.push(r);
+        c_claim = round.c_next;
+    }
+    Ok((rs, c_claim))
+}
+
+/// A Spartan proof.
+struct Proof<G: Group> {
+    /// Commitments to the rows of the witness matrix
+    c_w: Vec<G>,
+    /// The first sumcheck
+    sc_1: Vec<Round<G>>,
+    /// Commitments to `Az(rx)`, `Bz(rx)`, `Cz(rx)`, and `Az(rx) * Bz(rx)`
+    c_abc: [G; 3],
+    c_prod: G,
+    prod: ProductProof<G>,
+    /// The final claim of the first sumcheck is `eq(tau, rx) (Az(rx) Bz(rx) - Cz(rx))`
+    eq_1: EqualityProof<G>,
+    /// The second sumcheck
+    sc_2: Vec<Round<G>>,
+    /// A commitment to `w(ry)`, and its opening
+    c_w_eval: G,
+    w_eval: DotProductProof<G>,
+    /// The final claim of the second sumcheck is `M(rx, ry) z(ry)`
+    eq_2: EqualityProof<G>,
+}
+
+/// An R1CS instance in the form that Spartan wants: sparse matrices over `z = (w, 1, x)`, where
+/// the witness `w`, and the constant one followed by the public inputs `x`, each fill (and are
+/// zero-padded to) one half of `z`. The number of constraints is padded to a power of two.
+///
+/// In `x`, the inputs that the verifier knows are followed by the challenges, epoch by epoch. The
+/// witness is also laid out by epoch, with each epoch starting a new row of the witness matrix, so
+/// that the prover can commit to an epoch's rows before the challenges that end it are drawn.
+struct Instance<F> {
+    /// `log2` of the (padded) number of constraints
+    m: usize,
+    /// `log2` of the length of `z`
+    s: usize,
+    /// `(row, column, value)` entries of `A`, `B`, and `C`
+    matrices: [Vec<(usize, usize, F)>; 3],
+    /// The signals in `w`, by epoch
+    witness: Vec<Vec<usize>>,
+    /// The signals in `x` that the verifier knows
+    inputs: Vec<usize>,
+    /// The challenges drawn at the end of each epoch: their signals (in `x`), and names
+    coins: Vec<Vec<(usize, String)>>,
+    /// The first row of the witness matrix in each epoch, and then the number of rows
+    epoch_rows: Vec<usize>,
+}
+
+/// Split the variables of a witness polynomial into those that select a row of the witness
+/// matrix, and those that select a column.
+fn split_vars(l: usize) -> (usize, usize) {
+    (l / 2, l - l / 2)
+}
+
+impl<F: PrimeField> Instance<F> {
+    /// The instance for `r1cs`, if it is well-formed, and its field is the scalar field `F`.
+    fn new(r1cs: &R1cs<String>) -> Result<Self> {
+        if int_to_ff::<F>(r1cs.modulus().clone()) != F::zero()
+            || int_to_ff::<F>(Integer::from(r1cs.modulus() - 1)) == F::zero()
+        {
+            return Err(SpartanError::Instance(
+                "the modulus is not the order of the group",
+            ));
+        }
+        let num_epochs = r1cs
+            .signal_epochs
+            .values()
+            .max()
+            .map_or(1, |e| *e as usize + 1);
+        let mut witness = vec![Vec::new(); num_epochs];
+        let mut coins = vec![Vec::new(); num_epochs];
+        let mut inputs = Vec::new();
+        for i in 0..r1cs.next_idx {
+            let name = r1cs
+                .idxs_signals
+                .get(&i)
+                .ok_or(SpartanError::Instance("a signal has no name"))?;
+            let epoch = *r1cs
+                .signal_epochs
+                .get(name)
+                .ok_or(SpartanError::Instance("a signal has no epoch"))?
+                as usize;
+            if r1cs.random_idxs.contains(&i) {
+                coins[epoch].push((i, name.clone()));
+            } else if r1cs.public_idxs.contains(&i) {
+                if epoch != 0 {
+                    return Err(SpartanError::Instance(
+                        "a public input is not known in epoch 0",
+                    ));
+                }
+                inputs.push(i);
+            } else {
+                witness[epoch].push(i);
+            }
+        }
+        let num_x = inputs.len() + coins.iter().map(Vec::len).sum::<usize>();
+        let num_w = witness.iter().map(Vec::len).sum::<usize>();
+        let mut half = std::cmp::max(num_w, num_x + 1).next_power_of_two();
+        // starting each epoch on a new row may not fit
+        let mut epoch_rows = loop {
+            let row_len = 1 << split_vars(log2(half)).1;
+            let mut rows = vec![0];
+            for w in &witness {
+                rows.push(rows.last().unwrap() + (w.len() + row_len - 1) / row_len);
+            }
+            if rows.last().unwrap() * row_len <= half {
+                break rows;
+            }
+            half *= 2;
+        };
+        // the last epoch commits to the padding rows
+        *epoch_rows.last_mut().unwrap() = 1 << split_vars(log2(half)).0;
+        let row_len = 1 << split_vars(log2(half)).1;
+        let mut columns: FxHashMap<usize, usize> = FxHashMap::default();
+        for (w, first_row) in witness.iter().zip(&epoch_rows) {
+            columns.extend(
+                w.iter()
+                    .enumerate()
+                    .map(|(k, i)| (*i, first_row * row_len + k)),
+            );
+        }
+        let x_signals = inputs.iter().chain(coins.iter().flatten().map(|(i, _)| i));
+        columns.extend(x_signals.enumerate().map(|(k, i)| (*i, half + 1 + k)));
+        let mut matrices = [Vec::new(), Vec::new(), Vec::new()];
+        for (row, (a, b, c)) in r1cs.constraints.iter().enumerate() {
+            for (matrix, lc) in matrices.iter_mut().zip([a, b, c].iter()) {
+                if !lc.constant.is_zero() {
+                    matrix.push((row, half, int_to_ff(lc.constant.i())));
+                }
+                for (idx, coeff) in &lc.monomials {
+                    if !coeff.is_zero() {
+                        let col = *columns
+                            .get(idx)
+                            .ok_or(SpartanError::Instance("a constraint has an unknown signal"))?;
+                        matrix.push((row, col, int_to_ff(coeff.i())));
+                    }
+                }
+            }
+        }
+        Ok(Instance {
+            m: log2(std::cmp::max(r1cs.constraints.len(), 1).next_power_of_two()),
+            s: log2(2 * half),
+            matrices,
+            witness,
+            inputs,
+            coins,
+            epoch_rows,
+        })
+    }
+
+    /// The number of variables of the witness polynomial, split into those that select a row of
+    /// the witness matrix, and those that select a column.
+    fn witness_vars(&self) -> (usize, usize) {
+        split_vars(self.s - 1)
+    }
+
+    fn gens<G: Group<Scalar = F>>(&self) -> Gens<G> {
+        // sumcheck rounds commit to at most four evaluations
+        Gens::new(std::cmp::max(1 << self.witness_vars().1, 4))
+    }
+
+    /// A transcript that starts with the instance.
+    fn transcript(&self) -> Sha256Transcript {
+        let mut t = Sha256Transcript::new("circ spartan");
+        t.append_u64(b"m", self.m as u64);
+        t.append_u64(b"s", self.s as u64);
+        for matrix in &self.matrices {
+            t.append_u64(b"entries", matrix.len() as u64);
+            for (row, col, v) in matrix {
+                t.append_u64(b"row", *row as u64);
+                t.append_u64(b"col", *col as u64);
+                t.append_scalar(b"value", v);
+            }
+        }
+        t
+    }
+
+    /// Absorb the row commitments of epoch `e`, after (in epoch 0) the public inputs `x`.
+    fn absorb_epoch<G: GroupEncoding>(
+        &self,
+        t: &mut Sha256Transcript,
+        e: usize,
+        x: &[F],
+        c_w: &[G],
+    ) {
+        if e == 0 {
+            for x_i in x {
+                t.append_scalar(b"input", x_i);
+            }
+        }
+        for c in &c_w[self.epoch_rows[e]..self.epoch_rows[e + 1]] {
+            t.append_point(b"witness", c);
+        }
+    }
+
+    /// Draw the challenges of epoch `e`, as [ProverData::solve] does.
+    fn draw_coins(&self, t: &mut Sha256Transcript, e: usize) -> Vec<F> {
+        self.coins[e]
+            .iter()
+            .map(|(_, name)| t.challenge_scalar(name.as_bytes()))
+            .collect()
+    }
+
+    /// The values of `signals`.
+    fn values<'b>(
+        &self,
+        r1cs: &R1cs<String>,
+        values: &FxHashMap<String, Value>,
+        signals: impl Iterator<Item = &'b usize>,
+    ) -> Vec<F> {
+        signals
+            .map(|i| {
+                let name = r1cs.idxs_signals.get(i).unwrap();
+                let v = values
+                    .get(name)
+                    .unwrap_or_else(|| panic!("Missing value for signal {}", name));
+                int_to_ff(v.as_pf().i())
+            })
+            .collect()
+    }
+
+    /// The rows of the witness matrix in epoch `e`, from signal values.
+    fn epoch_witness(
+        &self,
+        r1cs: &R1cs<String>,
+        values: &FxHashMap<String, Value>,
+        e: usize,
+    ) -> Vec<F> {
+        let mut w = self.values(r1cs, values, self.witness[e].iter());
+        let rows = self.epoch_rows[e + 1] - self.epoch_rows[e];
+        w.resize(rows << self.witness_vars().1, F::zero());
+        w
+    }
+
+    /// The public inputs that the verifier knows, from signal values.
+    fn inputs(&self, r1cs: &R1cs<String>, values: &FxHashMap<String, Value>) -> Vec<F> {
+        self.values(r1cs, values, self.inputs.iter())
+    }
+
+    /// The challenges, from signal values.
+    fn coin_values(&self, r1cs: &R1cs<String>, values: &FxHashMap<String, Value>) -> Vec<F> {
+        self.values(r1cs, values, self.coins.iter().flatten().map(|(i, _)| i))
+    }
+
+    /// `z = (w, 1, x)`, padded.
+    fn z(&self, w: &[F], x: &[F]) -> Vec<F> {
+        let mut z = w.to_vec();
+        z.push(F::one());
+        z.extend_from_slice(x);
+        z.resize(1 << self.s, F::zero());
+        z
+    }
+
+    /// The products of the matrices with `z`.
+    fn mul(&self, z: &[F]) -> Vec<Vec<F>> {
+        self.matrices
+            .iter()
+            .map(|matrix| {
+                let mut out = vec![F::zero(); 1 << self.m];
+                for (row, col, v) in matrix {
+                    out[*row] += *v * z[*col];
+                }
+                out
+            })
+            .collect()
+    }
+
+    /// The table of `sum_i coeffs[i] M_i(rx, y)` over the columns `y`.
+    fn bound_rows(&self, rx: &[F], coeffs: &[F]) -> Vec<F> {
+        let eq_rx = eq_table(rx);
+        let mut out = vec![F::zero(); 1 << self.s];
+        for (matrix, coeff) in self.matrices.iter().zip(coeffs) {
+            for (row, col, v) in matrix {
+                out[*col] += *coeff * v * eq_rx[*row];
+            }
+        }
+        out
+    }
+
+    /// The multilinear extension of `(1, x)` (the second half of `z`) at `r`.
+    fn io_eval(&self, x: &[F], r: &[F]) -> F {
+        let eq_r = eq_table(r);
+        eq_r[0] + dot(&eq_r[1..], x)
+    }
+}
+
+/// The prover's commitment to the witness, as a matrix: its rows so far, their blinds, and their
+/// commitments.
+struct WitnessCommitment<G: Group> {
+    w: Vec<G::Scalar>,
+    blinds: Vec<G::Scalar>,
+    c_w: Vec<G>,
+}
+
+impl<G: Group + GroupEncoding> WitnessCommitment<G> {
+    fn new() -> Self {
+        WitnessCommitment {
+            w: Vec::new(),
+            blinds: Vec::new(),
+            c_w: Vec::new(),
+        }
+    }
+
+    /// Commit to `w_e`, the rows of epoch `e`, absorbing the commitments (and, in epoch 0, the
+    /// public inputs `x`).
+    #[allow(clippy::too_many_arguments)]
+    fn commit_epoch<R: RngCore>(
+        &mut self,
+        inst: &Instance<G::Scalar>,
+        gens: &Gens<G>,
+        t: &mut Sha256Transcript,
+        rng: &mut R,
+        e: usize,
+        x: &[G::Scalar],
+        w_e: &[G::Scalar],
+    ) {
+        for row in w_e.chunks(1 << inst.witness_vars().1) {
+            let blind = G::Scalar::random(&mut *rng);
+            self.c_w.push(gens.commit_vec(row, blind));
+            self.blinds.push(blind);
+        }
+        self.w.extend_from_slice(w_e);
+        inst.absorb_epoch(t, e, x, &self.c_w);
+    }
+}
+
+/// Prove, computing each epoch's witness with [ProverData::solve], and then committing to it
+/// before drawing the challenges that end the epoch.
+fn prove_data<G: Group + GroupEncoding, R: RngCore>(
+    prover_data: &ProverData,
+    inputs_map: &FxHashMap<String, Value>,
+    rng: &mut R,
+) -> Result<Proof<G>> {
+    let r1cs = &prover_data.r1cs;
+    let inst = Instance::new(r1cs)?;
+    let gens = inst.gens::<G>();
+    let mut t = inst.transcript();
+    let mut wc = WitnessCommitment::new();
+    let mut x = Vec::new();
+    let values = prover_data.solve(inputs_map, &mut t, |e, values, t| {
+        if e == 0 {
+            x = inst.inputs(r1cs, values);
+        }
+        let w_e = inst.epoch_witness(r1cs, values, e);
+        wc.commit_epoch(&inst, &gens, t, &mut *rng, e, &x, &w_e);
+    });
+    r1cs.check_all(&values);
+    x.extend(inst.coin_values(r1cs, &values));
+    Ok(prove_instance(&inst, &gens, t, wc, &x, rng))
+}
+
+/// Finish a proof, once the prover has committed to the witness. `x` holds the public inputs and
+/// the challenges.
+fn prove_instance<G: Group + GroupEncoding, R: RngCore>(
+    inst: &Instance<G::Scalar>,
+    gens: &Gens<G>,
+    mut t: Sha256Transcript,
+    wc: WitnessCommitment<G>,
+    x: &[G::Scalar],
+    rng: &mut R,
+) -> Proof<G> {
+    let rand = |rng: &mut R| G::Scalar::random(rng);
+    let WitnessCommitment {
+        w,
+        blinds: blinds_w,
+        c_w,
+    } = wc;
+    let (row_vars, col_vars) = inst.witness_vars();
+    let row_len = 1 << col_vars;
+    assert_eq!(c_w.len(), 1 << row_vars);
+
+    // sumcheck 1: sum_x eq(tau, x) (Az(x) Bz(x) - Cz(x)) = 0
+    let z = inst.z(&w, x);
+    let tau: Vec<G::Scalar> = t.challenge_scalars(b"tau", inst.m);
+    let mut tables = vec![eq_table(&tau)];
+    tables.extend(inst.mul(&z));
+    let (sc_1, rx, blind_x) = prove_sumcheck(
+        &gens,
+        &mut t,
+        rng,
+        &mut tables,
+        3,
+        |p| p[0] * (p[1] * p[2] - p[3]),
+        G::Scalar::zero(),
+    );
+    let eq_tau = tables[0][0];
+    let abc = [tables[1][0], tables[2][0], tables[3][0]];
+    let blinds_abc = [rand(rng), rand(rng), rand(rng)];
+    let c_abc = [
+        gens.commit(abc[0], blinds_abc[0]),
+        gens.commit(abc[1], blinds_abc[1]),
+        gens.commit(abc[2], blinds_abc[2]),
+    ];
+    let blind_prod = rand(rng);
+    let c_prod = gens.commit(abc[0] * abc[1], blind_prod);
+    for c in c_abc.iter().chain(std::iter::once(&c_prod)) {
+        t.append_point(b"evals", c);
+    }
+    let prod = ProductProof::prove(
+        &gens,
+        &mut t,
+        rng,
+        c_abc[0],
+        (abc[0], blinds_abc[0]),
+        (abc[1], blinds_abc[1]),
+        blind_prod,
+    );
+    let eq_1 = EqualityProof::prove(
+        &gens,
+        &mut t,
+        rng,
+        blind_x,
+        eq_tau * (blind_prod - blinds_abc[2]),
+    );
+
+    // sumcheck 2: sum_y M(rx, y) z(y) = coeffs . abc, where M = coeffs . (A, B, C)
+    let coeffs: Vec<G::Scalar> = t.challenge_scalars(b"coeffs", 3);
+    let blind = dot(&coeffs, &blinds_abc);
+    let mut tables = vec![inst.bound_rows(&rx, &coeffs), z];
+    let (sc_2, ry, blind_y) =
+        prove_sumcheck(&gens, &mut t, rng, &mut tables, 2, |p| p[0] * p[1], blind);
+    let m_eval = tables[0][0];
+
+    // open the witness at ry (without its first variable, which selects w from z)
+    let (eq_rows, eq_cols) = (
+        eq_table(&ry[1..1 + row_vars]),
+        eq_table(&ry[1 + row_vars..]),
+    );
+    let combined_row: Vec<G::Scalar> = (0..row_len)
+        .map(|j| {
+            (0..eq_rows.len()).fold(G::Scalar::zero(), |acc, i| {
+                acc + eq_rows[i] * w[i * row_len + j]
+            })
+        })
+        .collect();
+    let blind_row = dot(&eq_rows, &blinds_w);
+    let blind_w_eval = rand(rng);
+    let c_w_eval = gens.commit(dot(&eq_cols, &combined_row), blind_w_eval);
+    t.append_point(b"witness eval", &c_w_eval);
+    let w_eval = DotProductProof::prove(
+        &gens,
+        &mut t,
+        rng,
+        &eq_cols,
+        &combined_row,
+        blind_row,
+        blind_w_eval,
+    );
+    let eq_2 = EqualityProof::prove(
+        &gens,
+        &mut t,
+        rng,
+        blind_y,
+        m_eval * (G::Scalar::one() - ry[0]) * blind_w_eval,
+    );
+    Proof {
+        c_w,
+        sc_1,
+        c_abc,
+        c_prod,
+        prod,
+        eq_1,
+        sc_2,
+        c_w_eval,
+        w_eval,
+        eq_2,
+    }
+}
+
+/// Verify a proof, given the public inputs that the verifier knows. The challenges are re-derived
+/// from the witness commitments.
+fn verify_instance<G: Group + GroupEncoding>(
+    inst: &Instance<G::Scalar>,
+    inputs: &[G::Scalar],
+    pf: &Proof<G>,
+) -> Result<()> {
+    if inputs.len() != inst.inputs.len() {
+        return Err(SpartanError::Malformed("number of public inputs"));
+    }
+    let gens = inst.gens::<G>();
+    let mut t = inst.transcript();
+
+    let (row_vars, _) = inst.witness_vars();
+    if pf.c_w.len() != 1 << row_vars {
+        return Err(SpartanError::Malformed("number of witness commitments"));
+    }
+    let mut x = inputs.to_vec();
+    for e in 0..inst.coins.len() {
+        inst.absorb_epoch(&mut t, e, inputs, &pf.c_w);
+        x.extend(inst.draw_coins(&mut t, e));
+    }
+
+    let tau: Vec<G::Scalar> = t.challenge_scalars(b"tau", inst.m);
+    let (rx, c_x) = verify_sumcheck(&gens, &mut t, &pf.sc_1, inst.m, 3, G::identity())?;
+    for c in pf.c_abc.iter().chain(std::iter::once(&pf.c_prod)) {
+        t.append_point(b"evals", c);
+    }
+    pf.prod
+        .verify(&gens, &mut t, pf.c_abc[0], pf.c_abc[1], pf.c_prod)?;
+    let eq_tau = eq(&tau, &rx);
+    pf.eq_1
+        .verify(&gens, &mut t, c_x, (pf.c_prod - pf.c_abc[2]) * eq_tau)?;
+
+    let coeffs: Vec<G::Scalar> = t.challenge_scalars(b"coeffs", 3);
+    let c_claim = pf
+        .c_abc
+        .iter()
+        .zip(&coeffs)
+        .fold(G::identity(), |acc, (c, coeff)| acc + *c * coeff);
+    let (ry, c_y) = verify_sumcheck(&gens, &mut t, &pf.sc_2, inst.s, 2, c_claim)?;
+
+    let (eq_rows, eq_cols) = (
+        eq_table(&ry[1..1 + row_vars]),
+        eq_table(&ry[1 + row_vars..]),
+    );
+    let c_row = pf
+        .c_w
+        .iter()
+        .zip(&eq_rows)
+        .fold(G::identity(), |acc, (c, e)| acc + *c * e);
+    t.append_point(b"witness eval", &pf.c_w_eval);
+    pf.w_eval
+        .verify(&gens, &mut t, &eq_cols, c_row, pf.c_w_eval)?;
+
+    // z(ry) = (1 - ry[0]) w(ry[1..]) + ry[0] (1, x)(ry[1..])
+    let m_eval = dot(&inst.bound_rows(&rx, &coeffs), &eq_table(&ry));
+    let io_eval = inst.io_eval(&x, &ry[1..]);
+    let c_target =
+        pf.c_w_eval * (m_eval * (G::Scalar::one() - ry[0])) + gens.g * (m_eval * ry[0] * io_eval);
+    pf.eq_2.verify(&gens, &mut t, c_y, c_target)
+}
+
+/// Proof (de)serialization.
+trait Encode: Sized {
+    fn write(&self, w: &mut Vec<u8>);
+    fn read(r: &mut &[u8]) -> Result<Self>;
+}
+
+fn take<'a>(r: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    if r.len() < n {
+        return Err(SpartanError::Malformed("unexpected end of proof"));
+    }
+    let (head, tail) = r.split_at(n);
+    *r = tail;
+    Ok(head)
+}
+
+fn write_len(w: &mut Vec<u8>, n: usize) {
+    w.extend_from_slice(&(n as u64).to_le_bytes());
+}
+
+fn read_len(r: &mut &[u8]) -> Result<usize> {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(take(r, 8)?);
+    let n = u64::from_le_bytes(b) as usize;
+    // every element takes at least one byte
+    if n > r.len() {
+        return Err(SpartanError::Malformed("bad length"));
+    }
+    Ok(n)
+}
+
+fn write_point<G: GroupEncoding>(w: &mut Vec<u8>, p: &G) {
+    w.extend_from_slice(p.to_bytes().as_ref());
+}
+
+fn read_point<G: GroupEncoding>(r: &mut &[u8]) -> Result<G> {
+    let mut repr = G::Repr::default();
+    let n = repr.as_ref().len();
+    repr.as_mut().copy_from_slice(take(r, n)?);
+    Option::from(G::from_bytes(&repr)).ok_or(SpartanError::Malformed("bad group element"))
+}
+
+fn write_scalar<F: PrimeField>(w: &mut Vec<u8>, s: &F) {
+    w.extend_from_slice(s.to_repr().as_ref());
+}
+
+fn read_scalar<F: PrimeField>(r: &mut &[u8]) -> Result<F> {
+    let mut repr = F::Repr::default();
+    let n = repr.as_ref().len();
+    repr.as_mut().copy_from_slice(take(r, n)?);
+    Option::from(F::from_repr(repr)).ok_or(SpartanError::Malformed("bad scalar"))
+}
+
+fn write_vec<T: Encode>(w: &mut Vec<u8>, ts: &[T]) {
+    write_len(w, ts.len());
+    for t in ts {
+        t.write(w);
+    }
+}
+
+fn read_vec<T: Encode>(r: &mut &[u8]) -> Result<Vec<T>> {
+    (0..read_len(r)?).map(|_| T::read(r)).collect()
+}
+
+/// A group element, for [Encode].
+struct Point<G>(G);
+
+impl<G: GroupEncoding> Encode for Point<G> {
+    fn write(&self, w: &mut Vec<u8>) {
+        write_point(w, &self.0);
+    }
+    fn read(r: &mut &[u8]) -> Result<Self> {
+        read_point(r).map(Point)
+    }
+}
+
+impl<G: Group + GroupEncoding> Encode for DotProductProof<G> {
+    fn write(&self, w: &mut Vec<u8>) {
+        write_point(w, &self.delta);
+        write_point(w, &self.beta);
+        write_len(w, self.z.len());
+        for z in &self.z {
+            write_scalar(w, z);
+        }
+        write_scalar(w, &self.z_delta);
+        write_scalar(w, &self.z_beta);
+    }
+    fn read(r: &mut &[u8]) -> Result<Self> {
+        let delta = read_point(r)?;
+        let beta = read_point(r)?;
+        let z = (0..read_len(r)?)
+            .map(|_| read_scalar(r))
+            .collect::<Result<_>>()?;
+        Ok(DotProductProof {
+            delta,
+            beta,
+            z,
+            z_delta: read_scalar(r)?,
+            z_beta: read_scalar(r)?,
+        })
+    }
+}
+
+impl<G: Group + GroupEncoding> Encode for ProductProof<G> {
+    fn write(&self, w: &mut Vec<u8>) {
+        write_point(w, &self.alpha);
+        write_point(w, &self.beta);
+        write_point(w, &self.delta);
+        for z in &self.z {
+            write_scalar(w, z);
+        }
+    }
+    fn read(r: &mut &[u8]) -> Result<Self> {
+        Ok(ProductProof {
+            alpha: read_point(r)?,
+            beta: read_point(r)?,
+            delta: read_point(r)?,
+            z: [
+                read_scalar(r)?,
+                read_scalar(r)?,
+                read_scalar(r)?,
+                read_scalar(r)?,
+                read_scalar(r)?,
+            ],
+        })
+    }
+}
+
+impl<G: Group + GroupEncoding> Encode for EqualityProof<G> {
+    fn write(&self, w: &mut Vec<u8>) {
+        write_point(w, &self.alpha);
+        write_scalar(w, &self.z);
+    }
+    fn read(r: &mut &[u8]) -> Result<Self> {
+        Ok(EqualityProof {
+            alpha: read_point(r)?,
+            z: read_scalar(r)?,
+        })
+    }
+}
+
+impl<G: Group + GroupEncoding> Encode for Round<G> {
+    fn write(&self, w: &mut Vec<u8>) {
+        write_point(w, &self.c_poly);
+        write_point(w, &self.c_next);
+        self.proof.write(w);
+    }
+    fn read(r: &mut &[u8]) -> Result<Self> {
+        Ok(Round {
+            c_poly: read_point(r)?,
+            c_next: read_point(r)?,
+            proof: DotProductProof::read(r)?,
+        })
+    }
+}
+
+impl<G: Group + GroupEncoding> Encode for Proof<G> {
+    fn write(&self, w: &mut Vec<u8>) {
+        let c_w: Vec<Point<G>> = self.c_w.iter().map(|c| Point(*c)).collect();
+        write_vec(w, &c_w);
+        write_vec(w, &self.sc_1);
+        for c in &self.c_abc {
+            write_point(w, c);
+        }
+        write_point(w, &self.c_prod);
+        self.prod.write(w);
+        self.eq_1.write(w);
+        write_vec(w, &self.sc_2);
+        write_point(w, &self.c_w_eval);
+        self.w_eval.write(w);
+        self.eq_2.write(w);
+    }
+    fn read(r: &mut &[u8]) -> Result<Self> {
+        let c_w = read_vec::<Point<G>>(r)?.into_iter().map(|p| p.0).collect();
+        Ok(Proof {
+            c_w,
+            sc_1: read_vec(r)?,
+            c_abc: [read_point(r)?, read_point(r)?, read_point(r)?],
+            c_prod: read_point(r)?,
+            prod: ProductProof::read(r)?,
+            eq_1: EqualityProof::read(r)?,
+            sc_2: read_vec(r)?,
+            c_w_eval: read_point(r)?,
+            w_eval: DotProductProof::read(r)?,
+            eq_2: EqualityProof::read(r)?,
+        })
+    }
+}
+
+/// Given
+/// * a proving-key path,
+/// * a verifying-key path,
+/// * prover data, and
+/// * verifier data
+/// write the data to files at those paths.
+///
+/// There is no setup: the keys are just the data (and, for the verifier, the R1CS).
+pub fn gen_params<G: Group, P1: AsRef<Path>, P2: AsRef<Path>>(
+    pk_path: P1,
+    vk_path: P2,
+    p_data: &ProverData,
+    v_data: &VerifierData,
+) -> io::Result<()> {
+    // check that the instance is supported
+    Instance::<G::Scalar>::new(&p_data.r1cs)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let mut file = File::create(pk_path)?;
+    serialize_into(&mut file, p_data).map_err(bincode_err)?;
+    let mut file = File::create(vk_path)?;
+    serialize_into(&mut file, &(&p_data.r1cs, v_data)).map_err(bincode_err)?;
+    Ok(())
+}
+
+/// Given
+/// * a proving-key path,
+/// * a proof path, and
+/// * a prover input map
+/// generate a random proof and writes it to the path
+pub fn prove<G: Group + GroupEncoding, P1: AsRef<Path>, P2: AsRef<Path>>(
+    pk_path: P1,
+    pf_path: P2,
+    inputs_map: &FxHashMap<String, Value>,
+) -> Result<()> {
+    let prover_data: ProverData =
+        deserialize_from(&mut File::open(pk_path)?).map_err(bincode_err)?;
+    for (input, (sort, _epoch)) in &prover_data.precompute_inputs {
+        // challenges come from the transcript
+        if prover_data.random_coins.contains(input) {
+            continue;
+        }
+        let value = inputs_map
+            .get(input)
+            .unwrap_or_else(|| panic!("No input for {}", input));
+        let sort2 = value.sort();
+        assert_eq!(
+            sort, &sort2,
+            "Sort mismatch for {}. Expected\n\t{} but got\n\t{}",
+            input, sort, sort2
+        );
+    }
+    let pf = prove_data::<G, _>(&prover_data, inputs_map, &mut rand::thread_rng())?;
+    let mut bytes = Vec::new();
+    pf.write(&mut bytes);
+    File::create(pf_path)?.write_all(&bytes)?;
+    Ok(())
+}
+
+/// Given
+/// * a verifying-key path,
+/// * a proof path,
+/// * and a verifier input map
+/// checks the proof at that path
+pub fn verify<G: Group + GroupEncoding, P1: AsRef<Path>, P2: AsRef<Path>>(
+    vk_path: P1,
+    pf_path: P2,
+    inputs_map: &FxHashMap<String, Value>,
+) -> Result<()> {
+    let (r1cs, verifier_data): (R1cs<String>, VerifierData) =
+        deserialize_from(&mut File::open(vk_path)?).map_err(bincode_err)?;
+    let inst = Instance::new(&r1cs)?;
+    let inputs: Vec<G::Scalar> = verifier_data
+        .eval(inputs_map)
+        .into_iter()
+        .map(int_to_ff)
+        .collect();
+    let mut bytes = Vec::new();
+    File::open(pf_path)?.read_to_end(&mut bytes)?;
+    let mut r = &bytes[..];
+    let pf = Proof::<G>::read(&mut r)?;
+    if !r.is_empty() {
+        return Err(SpartanError::Malformed("trailing bytes"));
+    }
+    verify_instance(&inst, &inputs, &pf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::field::DFL_T;
+    use bls12_381::{G1Projective, Scalar};
+
+    /// `x^3 + x + 5 = y`, with `y` public; and a value map for it.
+    fn cubic(x: u64) -> (R1cs<String>, FxHashMap<String, Value>) {
+        let mut r1cs = R1cs::new(DFL_T.clone());
+        let mut values = FxHashMap::default();
+        let x2 = x * x;
+        for (name, v) in &[("x", x), ("x2", x2), ("x3", x2 * x), ("y", x2 * x + x + 5)] {
+            let var = leaf_term(Op::Var(name.to_string(), Sort::Field(DFL_T.clone())));
+            r1cs.add_signal(name.to_string(), var, 0);
+            values.insert(name.to_string(), Value::Field(DFL_T.new_v(*v)));
+        }
+        r1cs.publicize(&"y".to_owned());
+        let s = |n: &str| r1cs.signal_lc(&n.to_owned());
+        let (x, x2, x3, y) = (s("x"), s("x2"), s("x3"), s("y"));
+        r1cs.constraint(x.clone(), x.clone(), x2.clone());
+        r1cs.constraint(x2, x.clone(), x3.clone());
+        let one = r1cs.constant(DFL_T.new_v(1));
+        r1cs.constraint(x3 + &x + &DFL_T.new_v(5), one, y);
+        r1cs.check_all(&values);
+        (r1cs, values)
+    }
+
+    /// Prove a single-epoch instance from signal values, whether or not they satisfy it.
+    fn prove_values(
+        r1cs: &R1cs<String>,
+        values: &FxHashMap<String, Value>,
+    ) -> (Instance<Scalar>, Vec<Scalar>, Proof<G1Projective>) {
+        let inst = Instance::new(r1cs).unwrap();
+        let gens = inst.gens();
+        let mut t = inst.transcript();
+        let mut rng = rand::thread_rng();
+        let x = inst.inputs(r1cs, values);
+        let w = inst.epoch_witness(r1cs, values, 0);
+        let mut wc = WitnessCommitment::new();
+        wc.commit_epoch(&inst, &gens, &mut t, &mut rng, 0, &x, &w);
+        let pf = prove_instance(&inst, &gens, t, wc, &x, &mut rng);
+        (inst, x, pf)
+    }
+
+    fn prove_cubic(x: u64) -> (Instance<Scalar>, Vec<Scalar>, Proof<G1Projective>) {
+        let (r1cs, values) = cubic(x);
+        prove_values(&r1cs, &values)
+    }
+
+    #[test]
+    fn eq_table_matches_eq() {
+        let r: Vec<Scalar> = vec![Scalar::from(3), Scalar::from(7)];
+        let table = eq_table(&r);
+        for (i, e) in table.iter().enumerate() {
+            let bits = vec![Scalar::from((i >> 1) as u64), Scalar::from((i & 1) as u64)];
+            assert_eq!(*e, eq(&r, &bits));
+        }
+    }
+
+    #[test]
+    fn complete() {
+        let (inst, io, pf) = prove_cubic(3);
+        assert_eq!(io, vec![Scalar::from(35)]);
+        verify_instance(&inst, &io, &pf).unwrap();
+    }
+
+    #[test]
+    fn wrong_input() {
+        let (inst, _, pf) = prove_cubic(3);
+        assert!(verify_instance(&inst, &[Scalar::from(36)], &pf).is_err());
+    }
+
+    #[test]
+    fn wrong_input_count() {
+        let (inst, io, pf) = prove_cubic(3);
+        let inputs = vec![io[0]; 2];
+        assert!(verify_instance(&inst, &inputs, &pf).is_err());
+    }
+
+    #[test]
+    fn malformed_instance() {
+        let small = R1cs::new(FieldT::from(Integer::from(101)));
+        assert!(Instance::<Scalar>::new(&small).is_err());
+        let (mut r1cs, _) = cubic(3);
+        r1cs.signal_epochs.insert("y".to_owned(), 1);
+        assert!(Instance::<Scalar>::new(&r1cs).is_err());
+        let (mut r1cs, _) = cubic(3);
+        r1cs.idxs_signals.remove(&0);
+        assert!(Instance::<Scalar>::new(&r1cs).is_err());
+    }
+
+    #[test]
+    fn bad_witness() {
+        let (r1cs, mut values) = cubic(3);
+        values.insert("x2".to_owned(), Value::Field(DFL_T.new_v(10)));
+        let (inst, io, pf) = prove_values(&r1cs, &values);
+        assert!(verify_instance(&inst, &io, &pf).is_err());
+    }
+
+    /// `y = x * a` and `z = y * b`, for challenges `a` and `b`, and a public `c = x + 1`.
+    fn challenged() -> ProverData {
+        let mut cs = Computation::new();
+        let prover = Some(crate::ir::proof::PROVER_ID);
+        let f = Sort::Field(DFL_T.clone());
+        let x = cs.new_var("x", f.clone(), prover, 0, false, None);
+        let c = cs.new_var("c", f.clone(), None, 0, false, None);
+        let a = cs.new_var("a", f.clone(), None, 0, true, None);
+        let xa = term![PF_MUL; x.clone(), a];
+        let y = cs.new_var("y", f.clone(), prover, 0, false, Some(xa.clone()));
+        let b = cs.new_var("b", f.clone(), None, 1, true, None);
+        let yb = term![PF_MUL; y.clone(), b];
+        let z = cs.new_var("z", f, prover, 0, false, Some(yb.clone()));
+        cs.assert(term![EQ; c, term![PF_ADD; x, pf_lit(DFL_T.new_v(1))]]);
+        cs.assert(term![EQ; y, xa]);
+        cs.assert(term![EQ; z, yb]);
+        let (_, pd, _) = crate::target::r1cs::trans::to_r1cs(cs, DFL_T.clone());
+        assert_eq!(pd.epochs.len(), 3);
+        pd
+    }
+
+    /// Draws fixed challenges, as a cheating prover might.
+    struct Fixed(u64);
+
+    impl Transcript for Fixed {
+        fn absorb(&mut self, _label: &str, _bytes: &[u8]) {}
+        fn challenge(&mut self, _label: &str, field: &FieldT) -> FieldV {
+            self.0 += 1;
+            field.new_v(self.0)
+        }
+    }
+
+    #[test]
+    fn multi_epoch() {
+        let pd = challenged();
+        let inputs: FxHashMap<String, Value> = vec![
+            ("x".to_owned(), Value::Field(DFL_T.new_v(5))),
+            ("c".to_owned(), Value::Field(DFL_T.new_v(6))),
+        ]
+        .into_iter()
+        .collect();
+        let inst = Instance::new(&pd.r1cs).unwrap();
+        let io = vec![Scalar::from(6)];
+        let pf = prove_data::<G1Projective, _>(&pd, &inputs, &mut rand::thread_rng()).unwrap();
+        verify_instance(&inst, &io, &pf).unwrap();
+        assert!(verify_instance(&inst, &[Scalar::from(7)], &pf).is_err());
+
+        // commit to each epoch as an honest prover would, but pick the challenges
+        let r1cs = &pd.r1cs;
+        let gens = inst.gens::<G1Projective>();
+        let mut t = inst.transcript();
+        let mut rng = rand::thread_rng();
+        let mut wc = WitnessCommitment::new();
+        let values = pd.solve(&inputs, &mut Fixed(16), |_, _, _| {});
+        r1cs.check_all(&values);
+        for e in 0..3 {
+            let w_e = inst.epoch_witness(r1cs, &values, e);
+            wc.commit_epoch(&inst, &gens, &mut t, &mut rng, e, &io, &w_e);
+            inst.draw_coins(&mut t, e);
+        }
+        let mut x = io.clone();
+        x.extend(inst.coin_values(r1cs, &values));
+        let pf = prove_instance(&inst, &gens, t, wc, &x, &mut rng);
+        assert!(verify_instance(&inst, &io, &pf).is_err());
+    }
+
+    #[test]
+    fn encoding() {
+        let (inst, io, pf) = prove_cubic(4);
+        let mut bytes = Vec::new();
+        pf.write(&mut bytes);
+        let pf2 = Proof::<G1Projective>::read(&mut &bytes[..]).unwrap();
+        verify_instance(&inst, &io, &pf2).unwrap();
+        // flip a bit in the last scalar
+        let n = bytes.len();
+        bytes[n - 2] ^= 1;
+        if let Ok(pf3) = Proof::<G1Projective>::read(&mut &bytes[..]) {
+            assert!(verify_instance(&inst, &io, &pf3).is_err());
+        }
+        assert!(Proof::<G1Projective>::read(&mut &bytes[..n - 1]).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! Fiat-Shamir transcripts
+//!
+//! A computation with random inputs is an interactive protocol: in each epoch, the prover commits
+//! to that epoch's signals, and then the verifier draws the challenges of that epoch. A
+//! [Transcript] makes the protocol non-interactive, by deriving each challenge from a hash of the
+//! statement and every commitment before it. See [super::ProverData::solve].
+//!
+//! The transcript only ever sees what the verifier sees (commitments, not signal values), so the
+//! verifier can re-derive the challenges from the proof.
+
+use circ_fields::{FieldT, FieldV};
+#[cfg(feature = "spartan")]
+pub use sha256::Sha256Transcript;
+
+/// A Fiat-Shamir transcript over prime-field elements.
+pub trait Transcript {
+    /// Absorb a (public) prover message, such as a commitment, into the transcript.
+    fn absorb(&mut self, label: &str, bytes: &[u8]);
+    /// Squeeze a challenge in `field`, which depends on everything absorbed so far.
+    fn challenge(&mut self, label: &str, field: &FieldT) -> FieldV;
+}
+
+#[cfg(feature = "spartan")]
+mod sha256 {
+    use super::*;
+    use ff::PrimeField;
+    use group::GroupEncoding;
+    use rug::integer::Order;
+    use rug::Integer;
+    use sha2::{Digest, Sha256};
+
+    /// A [Transcript] built from a SHA-256 hash chain.
+    #[derive(Clone)]
+    pub struct Sha256Transcript(Sha256);
+
+    impl Sha256Transcript {
+        /// Create a transcript for the protocol named `label`.
+        pub fn new(label: &str) -> Self {
+            let mut t = Sha256Transcript(Sha256::new());
+            t.append(b"protocol", label.as_bytes());
+            t
+        }
+        /// Absorb `bytes`.
+        pub fn append(&mut self, label: &[u8], bytes: &[u8]) {
+            for b in &[label, bytes] {
+                self.0.update(&(b.len() as u64).to_le_bytes());
+                self.0.update(b);
+            }
+        }
+        /// Absorb `n`.
+        pub fn append_u64(&mut self, label: &[u8], n: u64) {
+            self.append(label, &n.to_le_bytes());
+        }
+        /// Absorb a field element.
+        pub fn append_scalar<F: PrimeField>(&mut self, label: &[u8], s: &F) {
+            self.append(label, s.to_repr().as_ref());
+        }
+        /// Absorb a group element.
+        pub fn append_point<G: GroupEncoding>(&mut self, label: &[u8], p: &G) {
+            self.append(label, p.to_bytes().as_ref());
+        }
+        /// 512 bits of hash output, after which the state moves on.
+        fn challenge_bytes(&mut self, label: &[u8]) -> Vec<u8> {
+            self.append(label, &[]);
+            let mut bytes = Vec::new();
+            for i in 0..2u8 {
+                let mut h = self.0.clone();
+                h.update(&[i]);
+                bytes.extend_from_slice(&h.finalize());
+            }
+            let state = self.0.clone().finalize();
+            self.0.update(&state);
+            bytes
+        }
+        /// A challenge in `F`. It is reduced from 512 bits (so it is close to uniform), and is
+        /// the same as [Transcript::challenge] would draw in the field of order `F`.
+        pub fn challenge_scalar<F: PrimeField>(&mut self, label: &[u8]) -> F {
+            let base = F::from(256);
+            self.challenge_bytes(label)
+                .iter()
+                .fold(F::zero(), |acc, b| acc * base + F::from(*b as u64))
+        }
+        /// `n` challenges in `F`.
+        pub fn challenge_scalars<F: PrimeField>(&mut self, label: &[u8], n: usize) -> Vec<F> {
+            (0..n).map(|_| self.challenge_scalar(label)).collect()
+        }
+    }
+
+    impl Transcript for Sha256Transcript {
+        fn absorb(&mut self, label: &str, bytes: &[u8]) {
+            self.append(label.as_bytes(), bytes);
+        }
+
+        fn challenge(&mut self, label: &str, field: &FieldT) -> FieldV {
+            let bytes = self.challenge_bytes(label.as_bytes());
+            field.new_v(Integer::from_digits(&bytes, Order::Msf) % field.modulus())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "spartan"))]
+mod test {
+    use super::*;
+    use crate::util::field::DFL_T;
+    use bls12_381::Scalar;
+    use ff::PrimeField;
+
+    #[test]
+    fn challenges_bind_transcript() {
+        let draw = |msgs: &[&[u8]]| {
+            let mut t = Sha256Transcript::new("test");
+            for m in msgs {
+                t.absorb("commitment", m);
+            }
+            (t.challenge("a", &DFL_T), t.challenge("a", &DFL_T))
+        };
+        let (a, b) = draw(&[b"1", b"2"]);
+        assert_ne!(a, b);
+        assert_eq!(draw(&[b"1", b"2"]), (a.clone(), b));
+        assert_ne!(draw(&[b"2", b"1"]).0, a);
+        assert_ne!(draw(&[b"1", b"2", b""]).0, a);
+        assert_ne!(draw(&[b"12"]).0, a);
+    }
+
+    #[test]
+    fn scalar_challenges_agree() {
+        let mut t1 = Sha256Transcript::new("test");
+        let mut t2 = t1.clone();
+        let v = t1.challenge("c", &DFL_T);
+        let s: Scalar = t2.challenge_scalar(b"c");
+        assert_eq!(Scalar::from_str_vartime(&format!("{}", v.i())), Some(s));
+    }
+}
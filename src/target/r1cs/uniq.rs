@@ -0,0 +1,201 @@
+//! Detection of under-constrained R1CS instances
+//!
+//! An R1CS instance is under-constrained if two witnesses that agree on the public signals (and the
+//! verifier's random signals) can differ on a private signal. We search for such witness pairs
+//! with an SMT solver, by conjoining the instance with a copy of itself in which the private
+//! signals are renamed.
+
+use super::*;
+use crate::ir::term::extras::substitute;
+use crate::target::smt::find_model;
+
+use std::fmt::{self, Formatter};
+
+/// The suffix of the renamed private signals in the second copy of the instance.
+const COPY_SUFFIX: &str = "__uniq_copy";
+
+#[derive(Debug, Clone)]
+/// Two witnesses for the same public signals.
+pub struct Ambiguity {
+    /// The first witness
+    pub left: HashMap<String, Value>,
+    /// The second witness
+    pub right: HashMap<String, Value>,
+    /// The private signals on which the witnesses differ
+    pub signals: Vec<String>,
+}
+
+impl Display for Ambiguity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for s in &self.signals {
+            writeln!(f, "  {}: {} or {}", s, self.left[s], self.right[s])?;
+        }
+        Ok(())
+    }
+}
+
+/// Find the private signals of `r1cs` that are not determined by its public signals.
+///
+/// Returns witness pairs that, between them, differ on every such signal. Each pair differs on
+/// some signal that the earlier pairs agree on. If the result is empty, every private signal is
+/// uniquely determined.
+pub fn find_ambiguities(r1cs: &R1cs<String>) -> Vec<Ambiguity> {
+    let sort = Sort::Field(r1cs.modulus.clone());
+    let private: Vec<Term> = (0..r1cs.next_idx)
+        .filter(|i| !r1cs.public_idxs.contains(i) && !r1cs.random_idxs.contains(i))
+        .map(|i| {
+            let name = r1cs.idxs_signals.get(&i).unwrap().clone();
+            leaf_term(Op::Var(name, sort.clone()))
+        })
+        .collect();
+    find_term_ambiguities(&r1cs.ir_term(), &private)
+}
+
+/// Find the `private` variables of the predicate `system` that are not determined by its other
+/// variables. The result is as for [find_ambiguities].
+pub fn find_term_ambiguities(system: &Term, private: &[Term]) -> Vec<Ambiguity> {
+    let private: Vec<(String, Term, Term)> = private
+        .iter()
+        .map(|v| match &v.op {
+            Op::Var(name, sort) => {
+                let copy = leaf_term(Op::Var(format!("{}{}", name, COPY_SUFFIX), sort.clone()));
+                (name.clone(), v.clone(), copy)
+            }
+            _ => panic!("{} is not a variable", v),
+        })
+        .collect();
+    let left = system.clone();
+    let mut subs = TermMap::new();
+    for (_, v, copy) in &private {
+        subs.insert(v.clone(), copy.clone());
+    }
+    let right = substitute(&left, subs);
+
+    let mut ambiguities = Vec::new();
+    let mut undetermined = vec![false; private.len()];
+    loop {
+        let differences: Vec<Term> = private
+            .iter()
+            .zip(&undetermined)
+            .filter(|(_, u)| !**u)
+            .map(|((_, v, copy), _)| term![NOT; term![EQ; v.clone(), copy.clone()]])
+            .collect();
+        if differences.is_empty() {
+            break;
+        }
+        let query = term![AND; left.clone(), right.clone(), term(OR, differences)];
+        let model = match find_model(&query) {
+            Some(m) => m,
+            None => break,
+        };
+        let mut a = Ambiguity {
+            left: HashMap::default(),
+            right: HashMap::default(),
+            signals: Vec::new(),
+        };
+        for (name, v) in &model {
+            if !name.ends_with(COPY_SUFFIX) {
+                a.left.insert(name.clone(), v.clone());
+                a.right.insert(name.clone(), v.clone());
+            }
+        }
+        for ((name, _, _), u) in private.iter().zip(&mut undetermined) {
+            if let Some(v) = model.get(&format!("{}{}", name, COPY_SUFFIX)) {
+                a.right.insert(name.clone(), v.clone());
+            }
+            if a.left.get(name) != a.right.get(name) {
+                a.signals.push(name.clone());
+                *u = true;
+            }
+        }
+        debug!("Undetermined signals: {:?}", a.signals);
+        ambiguities.push(a);
+    }
+    ambiguities
+}
+
+/// The private signals of `r1cs` that are not determined by its public signals, in signal order.
+pub fn undetermined_signals(r1cs: &R1cs<String>) -> Vec<String> {
+    let ambiguous: HashSet<String> = find_ambiguities(r1cs)
+        .into_iter()
+        .flat_map(|a| a.signals)
+        .collect();
+    (0..r1cs.next_idx)
+        .map(|i| r1cs.idxs_signals.get(&i).unwrap())
+        .filter(|s| ambiguous.contains(*s))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn field() -> FieldT {
+        FieldT::from(Integer::from(17))
+    }
+
+    /// An instance that decomposes public `x` into private bits `b0`, `b1`.
+    ///
+    /// Without booleanity constraints, the bits are not determined by `x`.
+    fn bit_split(booleanity: bool) -> R1cs<String> {
+        let mut r1cs = R1cs::new(field());
+        let dummy = leaf_term(Op::Var("dummy".into(), Sort::Field(field())));
+        for s in &["x", "b0", "b1"] {
+            r1cs.add_signal(s.to_string(), dummy.clone(), 0);
+        }
+        r1cs.publicize(&"x".to_string());
+        let x = r1cs.signal_lc(&"x".to_string());
+        let b0 = r1cs.signal_lc(&"b0".to_string());
+        let b1 = r1cs.signal_lc(&"b1".to_string());
+        if booleanity {
+            for b in &[&b0, &b1] {
+                r1cs.constraint((*b).clone(), (*b).clone(), (*b).clone());
+            }
+        }
+        let one = r1cs.constant(field().new_v(1));
+        r1cs.constraint(b0 + &(b1 * 2), one, x);
+        r1cs
+    }
+
+    // ignored until FF support in cvc5 is upstreamed.
+    #[ignore]
+    #[test]
+    fn bits_without_booleanity() {
+        assert_eq!(
+            undetermined_signals(&bit_split(false)),
+            vec!["b0".to_string(), "b1".to_string()]
+        );
+    }
+
+    // ignored until FF support in cvc5 is upstreamed.
+    #[ignore]
+    #[test]
+    fn bits_with_booleanity() {
+        assert!(find_ambiguities(&bit_split(true)).is_empty());
+    }
+
+    fn bv(name: &str) -> Term {
+        leaf_term(Op::Var(name.into(), Sort::BitVector(4)))
+    }
+
+    #[test]
+    fn bv_doubling() {
+        // doubling loses the top bit of `y`
+        let (x, y) = (bv("x"), bv("y"));
+        let system = term![EQ; term![BV_ADD; y.clone(), y.clone()], x];
+        let ambiguities = find_term_ambiguities(&system, &[y]);
+        assert_eq!(ambiguities.len(), 1);
+        assert_eq!(ambiguities[0].signals, vec!["y".to_string()]);
+        let (l, r) = (&ambiguities[0].left["y"], &ambiguities[0].right["y"]);
+        assert_ne!(l, r);
+        assert_eq!(ambiguities[0].left["x"], ambiguities[0].right["x"]);
+    }
+
+    #[test]
+    fn bv_increment() {
+        let (x, y) = (bv("x"), bv("y"));
+        let system = term![EQ; term![BV_ADD; y.clone(), bv_lit(1, 4)], x];
+        assert!(find_term_ambiguities(&system, &[y]).is_empty());
+    }
+}
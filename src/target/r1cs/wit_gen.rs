@@ -0,0 +1,521 @@
+//! Standalone witness generators
+//!
+//! Computing a witness by evaluating [ProverData::precompute] with the IR interpreter visits (and
+//! hashes) every term, every time. A [WitnessGenerator] compiles that precomputation once, into a
+//! flat register program: each instruction applies an operator to some registers, and writes a
+//! register. Running the program involves no terms, and it can be serialized and run by a process
+//! that has never seen the computation (see `examples/wit_gen.rs`).
+//!
+//! Only the parts of the precomputation that some signal depends on are compiled, and a register
+//! is reused as soon as the value in it is dead.
+//!
+//! The inputs to a generator are the prover's inputs. A computation whose witness depends on
+//! verifier challenges (e.g., one with lookups or RAM checks) is compiled to one segment of
+//! instructions per epoch. Between segments, [WitnessGenerator::solve] commits to the signals
+//! computed so far, and draws the challenges from a [Transcript], just as [ProverData::solve] does.
+
+use super::*;
+
+use bincode::{deserialize_from, serialize_into};
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Apply `op` to the `args` registers, writing the `dst` register.
+struct Instr {
+    op: Op,
+    args: Vec<u32>,
+    dst: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A compiled witness computation for an R1CS instance.
+pub struct WitnessGenerator {
+    modulus: FieldT,
+    /// The prover's inputs, with their sorts and the registers they are loaded into
+    inputs: Vec<(String, Sort, u32)>,
+    /// The challenges drawn at the end of each epoch: their labels (signal names), and the
+    /// registers they are loaded into, if any instruction or signal uses them
+    challenges: Vec<Vec<(String, Option<u32>)>>,
+    instrs: Vec<Instr>,
+    /// The end of each epoch's segment of `instrs`
+    segments: Vec<usize>,
+    /// The lookup tables used; [Op::Lookup]s in `instrs` index into these.
+    tables: Vec<lookup::Table>,
+    /// The signals, in circom wire order, with the registers that hold them at the end, and the
+    /// epochs in which they are computed
+    signals: Vec<(String, u32, usize)>,
+    n_regs: usize,
+}
+
+struct Compiler {
+    /// The register holding each input and precomputed value
+    env: HashMap<String, u32>,
+    regs: TermMap<u32>,
+    inputs: Vec<(String, Sort, u32)>,
+    instrs: Vec<Instr>,
+    tables: Vec<lookup::Table>,
+    /// Global table ids to indices in `tables`
+    table_idxs: HashMap<usize, usize>,
+    next_reg: u32,
+}
+
+impl Compiler {
+    fn new() -> Self {
+        Self {
+            env: HashMap::default(),
+            regs: TermMap::new(),
+            inputs: Vec::new(),
+            instrs: Vec::new(),
+            tables: Vec::new(),
+            table_idxs: HashMap::default(),
+            next_reg: 0,
+        }
+    }
+
+    fn fresh_reg(&mut self) -> u32 {
+        self.next_reg += 1;
+        self.next_reg - 1
+    }
+
+    /// Compile `t`, returning the register that holds its value.
+    fn compile(&mut self, t: &Term) -> u32 {
+        // (children pushed, term)
+        let mut stack = vec![(false, t.clone())];
+        while let Some((children_pushed, node)) = stack.pop() {
+            if self.regs.contains_key(&node) {
+                continue;
+            }
+            if children_pushed {
+                let reg = self.compile_step(&node);
+                self.regs.insert(node, reg);
+            } else {
+                stack.push((true, node.clone()));
+                for c in &node.cs {
+                    if !self.regs.contains_key(c) {
+                        stack.push((false, c.clone()));
+                    }
+                }
+            }
+        }
+        *self.regs.get(t).unwrap()
+    }
+
+    fn compile_step(&mut self, t: &Term) -> u32 {
+        let op = match &t.op {
+            Op::Var(name, sort) => {
+                if let Some(reg) = self.env.get(name) {
+                    return *reg;
+                }
+                let reg = self.fresh_reg();
+                self.env.insert(name.clone(), reg);
+                self.inputs.push((name.clone(), sort.clone(), reg));
+                return reg;
+            }
+            Op::Lookup(id) => {
+                let tables = &mut self.tables;
+                let idx = *self.table_idxs.entry(*id).or_insert_with(|| {
+                    tables.push((*lookup::table(*id)).clone());
+                    tables.len() - 1
+                });
+                Op::Lookup(idx)
+            }
+            op => op.clone(),
+        };
+        let args = t.cs.iter().map(|c| *self.regs.get(c).unwrap()).collect();
+        let dst = self.fresh_reg();
+        self.instrs.push(Instr { op, args, dst });
+        dst
+    }
+
+    /// Map our (single-assignment) registers to as few registers as possible. The registers of
+    /// `signals` are never reused.
+    ///
+    /// `challenges` are the labels of each epoch's challenges, and the inputs they are loaded
+    /// into; `segments` end each epoch's instructions.
+    fn finish(
+        mut self,
+        modulus: FieldT,
+        signals: Vec<(String, u32, usize)>,
+        challenges: Vec<Vec<(String, String)>>,
+        segments: Vec<usize>,
+    ) -> WitnessGenerator {
+        let n = self.next_reg as usize;
+        let mut pinned = vec![false; n];
+        for (_, reg, _) in &signals {
+            pinned[*reg as usize] = true;
+        }
+        let mut last_use: Vec<Option<usize>> = vec![None; n];
+        for (i, instr) in self.instrs.iter().enumerate() {
+            for a in &instr.args {
+                last_use[*a as usize] = Some(i);
+            }
+        }
+        let mut map = vec![0u32; n];
+        let mut free: Vec<u32> = Vec::new();
+        let mut n_regs = 0u32;
+        let mut alloc = |free: &mut Vec<u32>| {
+            free.pop().unwrap_or_else(|| {
+                n_regs += 1;
+                n_regs - 1
+            })
+        };
+        // inputs (including challenges) get registers that no instruction writes before their
+        // last use
+        for (_, _, reg) in &mut self.inputs {
+            map[*reg as usize] = alloc(&mut free);
+            *reg = map[*reg as usize];
+        }
+        for (i, instr) in self.instrs.iter_mut().enumerate() {
+            for a in &mut instr.args {
+                let dead = last_use[*a as usize] == Some(i) && !pinned[*a as usize];
+                if dead {
+                    // free each dead register once, even if it is used twice
+                    last_use[*a as usize] = None;
+                    free.push(map[*a as usize]);
+                }
+                *a = map[*a as usize];
+            }
+            let dst = instr.dst as usize;
+            map[dst] = alloc(&mut free);
+            instr.dst = map[dst];
+            if last_use[dst].is_none() && !pinned[dst] {
+                free.push(instr.dst);
+            }
+        }
+        let signals = signals
+            .into_iter()
+            .map(|(s, reg, epoch)| (s, map[reg as usize], epoch))
+            .collect();
+        // challenges are loaded between segments, rather than given as inputs
+        let regs: HashMap<String, u32> = self
+            .inputs
+            .iter()
+            .map(|(name, _, reg)| (name.clone(), *reg))
+            .collect();
+        let coin_vars: HashSet<&String> = challenges.iter().flatten().map(|(_, v)| v).collect();
+        self.inputs.retain(|(name, _, _)| !coin_vars.contains(name));
+        let challenges = challenges
+            .iter()
+            .map(|coins| {
+                coins
+                    .iter()
+                    .map(|(label, var)| (label.clone(), regs.get(var).cloned()))
+                    .collect()
+            })
+            .collect();
+        debug!(
+            "Witness generator: {} instructions in {} epochs, {} registers",
+            self.instrs.len(),
+            segments.len(),
+            n_regs
+        );
+        WitnessGenerator {
+            modulus,
+            inputs: self.inputs,
+            challenges,
+            instrs: self.instrs,
+            segments,
+            tables: self.tables,
+            signals,
+            n_regs: n_regs as usize,
+        }
+    }
+}
+
+impl WitnessGenerator {
+    /// Compile the witness computation of `prover_data`.
+    pub fn new(prover_data: &ProverData) -> Self {
+        let r1cs = &prover_data.r1cs;
+        let precompute = &prover_data.precompute;
+        let outputs = precompute.outputs();
+        let signal_names: Vec<&String> = export::wire_order(r1cs)
+            .into_iter()
+            .map(|i| r1cs.idxs_signals.get(&i).unwrap())
+            .collect();
+
+        // the precomputed values that some signal depends on
+        let mut needed: HashSet<String> = signal_names.iter().map(|s| (*s).clone()).collect();
+        for name in precompute.sequence.iter().rev() {
+            if needed.contains(name) {
+                needed.extend(extras::free_variables(outputs.get(name).unwrap().clone()));
+            }
+        }
+
+        // the epoch in which each input, and then each precomputed value, is known
+        let num_epochs = prover_data.epochs.len();
+        let mut epochs: HashMap<String, usize> = HashMap::default();
+        for (e, known) in prover_data.epochs.iter().enumerate().rev() {
+            epochs.extend(known.iter().map(|i| (i.clone(), e)));
+        }
+        for name in &precompute.sequence {
+            if needed.contains(name) {
+                let epoch = extras::free_variables(outputs.get(name).unwrap().clone())
+                    .iter()
+                    .map(|v| epochs.get(v).cloned().unwrap_or(0))
+                    .max()
+                    .unwrap_or(0);
+                epochs.insert(name.clone(), epoch);
+            }
+        }
+
+        let mut compiler = Compiler::new();
+        let mut segments = Vec::new();
+        for e in 0..num_epochs {
+            for name in &precompute.sequence {
+                if needed.contains(name) && epochs.get(name) == Some(&e) {
+                    let reg = compiler.compile(outputs.get(name).unwrap());
+                    compiler.env.insert(name.clone(), reg);
+                }
+            }
+            segments.push(compiler.instrs.len());
+        }
+        let signals = signal_names
+            .into_iter()
+            .map(|s| {
+                let reg = *compiler
+                    .env
+                    .get(s)
+                    .unwrap_or_else(|| panic!("No precomputation for signal {}", s));
+                (s.clone(), reg, epochs.get(s).cloned().unwrap_or(0))
+            })
+            .collect();
+        // as in [ProverData::solve]
+        let mut challenges = vec![Vec::new(); num_epochs];
+        for i in 0..r1cs.next_idx {
+            let s = r1cs.idxs_signals.get(&i).unwrap();
+            if r1cs.random_idxs.contains(&i) {
+                let epoch = *r1cs.signal_epochs.get(s).unwrap() as usize;
+                let coin = r1cs.signal_to_term.get(s).unwrap();
+                challenges[epoch].push((s.clone(), coin.clone()));
+            }
+        }
+        compiler.finish(r1cs.modulus.clone(), signals, challenges, segments)
+    }
+
+    /// The names and sorts of the inputs.
+    pub fn inputs(&self) -> impl Iterator<Item = (&str, &Sort)> {
+        self.inputs.iter().map(|(n, s, _)| (n.as_str(), s))
+    }
+
+    /// Whether the witness depends on verifier challenges.
+    pub fn has_challenges(&self) -> bool {
+        self.challenges.iter().any(|coins| !coins.is_empty())
+    }
+
+    /// Run the program on `value_map`, calling `between` with the registers at the end of each
+    /// epoch's segment.
+    fn run(
+        &self,
+        value_map: &HashMap<String, Value>,
+        mut between: impl FnMut(usize, &mut Vec<Value>),
+    ) -> Vec<Value> {
+        let mut regs = vec![Value::Bool(false); self.n_regs];
+        for (name, sort, reg) in &self.inputs {
+            let value = value_map
+                .get(name)
+                .unwrap_or_else(|| panic!("No input for {}", name));
+            let sort2 = value.sort();
+            assert_eq!(
+                sort, &sort2,
+                "Sort mismatch for {}. Expected\n\t{} but got\n\t{}",
+                name, sort, sort2
+            );
+            regs[*reg as usize] = value.clone();
+        }
+        let no_vars = HashMap::default();
+        let mut start = 0;
+        for (epoch, end) in self.segments.iter().enumerate() {
+            for instr in &self.instrs[start..*end] {
+                let args: Vec<&Value> = instr.args.iter().map(|a| &regs[*a as usize]).collect();
+                let value = match &instr.op {
+                    Op::Lookup(idx) => self.tables[*idx]
+                        .get(args[0])
+                        .unwrap_or_else(|| panic!("{} is not in lookup table {}", args[0], idx))
+                        .clone(),
+                    op => eval_op(op, &args, &no_vars),
+                };
+                regs[instr.dst as usize] = value;
+            }
+            between(epoch, &mut regs);
+            start = *end;
+        }
+        regs
+    }
+
+    /// The values of the signals, in circom wire order.
+    fn signal_values(&self, regs: &[Value]) -> Vec<(&str, Value)> {
+        self.signals
+            .iter()
+            .map(|(s, reg, _)| (s.as_str(), regs[*reg as usize].clone()))
+            .collect()
+    }
+
+    /// Compute the values of the signals, in circom wire order.
+    ///
+    /// Panics if the witness depends on verifier challenges; see [WitnessGenerator::solve].
+    pub fn eval_signals(&self, value_map: &HashMap<String, Value>) -> Vec<(&str, Value)> {
+        assert!(
+            !self.has_challenges(),
+            "The witness depends on verifier challenges, which must be drawn from a transcript"
+        );
+        self.signal_values(&self.run(value_map, |_, _| {}))
+    }
+
+    /// Compute the values of the signals.
+    ///
+    /// Panics if the witness depends on verifier challenges; see [WitnessGenerator::solve].
+    pub fn eval(&self, value_map: &HashMap<String, Value>) -> HashMap<String, Value> {
+        self.eval_signals(value_map)
+            .into_iter()
+            .map(|(s, v)| (s.to_owned(), v))
+            .collect()
+    }
+
+    /// Compute the values of the signals, drawing each epoch's challenges from `transcript`.
+    ///
+    /// As in [ProverData::solve], at the end of each epoch, `commit` commits to the signals known
+    /// so far, absorbing the commitment into the transcript, and then the challenges drawn at the
+    /// end of that epoch are squeezed.
+    pub fn solve<T: Transcript>(
+        &self,
+        value_map: &HashMap<String, Value>,
+        transcript: &mut T,
+        mut commit: impl FnMut(usize, &HashMap<String, Value>, &mut T),
+    ) -> HashMap<String, Value> {
+        let regs = self.run(value_map, |epoch, regs| {
+            let known: HashMap<String, Value> = self
+                .signals
+                .iter()
+                .filter(|(_, _, e)| *e <= epoch)
+                .map(|(s, reg, _)| (s.clone(), regs[*reg as usize].clone()))
+                .collect();
+            commit(epoch, &known, transcript);
+            for (label, reg) in &self.challenges[epoch] {
+                let value = Value::Field(transcript.challenge(label, &self.modulus));
+                if let Some(reg) = reg {
+                    regs[*reg as usize] = value;
+                }
+            }
+        });
+        self.signal_values(&regs)
+            .into_iter()
+            .map(|(s, v)| (s.to_owned(), v))
+            .collect()
+    }
+
+    /// Compute a witness, and write it in the circom binary `.wtns` format.
+    pub fn write_circom_wtns<W: Write>(
+        &self,
+        value_map: &HashMap<String, Value>,
+        w: W,
+    ) -> io::Result<()> {
+        let witness: Vec<Integer> = self
+            .eval_signals(value_map)
+            .into_iter()
+            .map(|(_, v)| v.as_pf().i())
+            .collect();
+        export::write_wtns(self.modulus.modulus(), &witness, w)
+    }
+
+    /// Serialize this generator.
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        serialize_into(&mut w, self).map_err(bincode_err)
+    }
+
+    /// Deserialize a generator.
+    pub fn read<R: Read>(mut r: R) -> io::Result<Self> {
+        deserialize_from(&mut r).map_err(bincode_err)
+    }
+}
+
+fn bincode_err(e: bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::target::r1cs::opt::reduce_linearities;
+    use crate::target::r1cs::trans::to_r1cs;
+    use crate::util::field::DFL_T;
+
+    fn bv(u: usize, w: usize) -> Value {
+        Value::BitVector(BitVector::new(Integer::from(u), w))
+    }
+
+    /// Check that the generator for `cs` agrees with the precomputation on `values`, before and
+    /// after serialization.
+    fn check_agrees(cs: Computation, values: HashMap<String, Value>) {
+        let (r1cs, mut pd, _) = to_r1cs(cs, DFL_T.clone());
+        pd.r1cs = reduce_linearities(r1cs, None);
+        let gen = WitnessGenerator::new(&pd);
+        let mut bytes = Vec::new();
+        gen.write(&mut bytes).unwrap();
+        let gen2 = WitnessGenerator::read(bytes.as_slice()).unwrap();
+        let expected = pd.precompute.eval(&values);
+        for gen in &[gen, gen2] {
+            let actual = gen.eval(&values);
+            assert_eq!(actual.len(), pd.r1cs.next_idx);
+            for (s, v) in &actual {
+                assert_eq!(Some(v), expected.get(s), "signal {}", s);
+            }
+            pd.r1cs.check_all(&actual);
+        }
+    }
+
+    #[test]
+    fn bv_arith() {
+        let mut cs = Computation::new();
+        let prover = Some(crate::ir::proof::PROVER_ID);
+        let x = cs.new_var("x", Sort::BitVector(8), prover, 0, false, None);
+        let y = cs.new_var("y", Sort::BitVector(8), None, 0, false, None);
+        let sum = term![BV_ADD; x.clone(), term![BV_MUL; x.clone(), y.clone()]];
+        cs.assert(term![Op::Eq; term![BV_UREM; sum, y.clone()], term![BV_XOR; x, y]]);
+        let values = vec![("x".to_owned(), bv(6, 8)), ("y".to_owned(), bv(3, 8))]
+            .into_iter()
+            .collect();
+        check_agrees(cs, values);
+    }
+
+    #[test]
+    #[cfg(feature = "spartan")]
+    fn lookup() {
+        use crate::target::r1cs::transcript::Sha256Transcript;
+        let mut cs = Computation::new();
+        let squares = cs.add_table(lookup::Table::from_fn(
+            Sort::BitVector(4),
+            Sort::BitVector(8),
+            |k| {
+                let k = k.as_bv().uint().to_usize().unwrap();
+                bv(k * k, 8)
+            },
+        ));
+        let prover = Some(crate::ir::proof::PROVER_ID);
+        let x = cs.new_var("x", Sort::BitVector(4), prover, 0, false, None);
+        cs.assert(term![Op::Eq; term![Op::Lookup(squares); x], leaf_term(Op::Const(bv(49, 8)))]);
+        let (r1cs, mut pd, _) = to_r1cs(cs, DFL_T.clone());
+        pd.r1cs = reduce_linearities(r1cs, None);
+        // the multiplicities' fractions depend on the lookup challenges
+        let gen = WitnessGenerator::new(&pd);
+        assert!(gen.has_challenges());
+        assert!(gen.inputs().all(|(i, _)| !pd.random_coins.contains(i)));
+        let values = vec![("x".to_owned(), bv(7, 4))].into_iter().collect();
+        // commit as a prover would, to the signals known so far
+        let commit = |e: usize, known: &HashMap<String, Value>, t: &mut Sha256Transcript| {
+            let mut known: Vec<String> = known
+                .iter()
+                .filter(|(s, _)| pd.r1cs.signal_epochs.get(*s) == Some(&(e as u8)))
+                .map(|(s, v)| format!("{}={}", s, v))
+                .collect();
+            known.sort();
+            t.absorb("signals", known.join(",").as_bytes());
+        };
+        let expected = pd.solve(&values, &mut Sha256Transcript::new("test"), commit);
+        let actual = gen.solve(&values, &mut Sha256Transcript::new("test"), commit);
+        assert_eq!(actual.len(), pd.r1cs.next_idx);
+        for (s, v) in &actual {
+            assert_eq!(Some(v), expected.get(s), "signal {}", s);
+        }
+        pd.r1cs.check_all(&actual);
+    }
+}
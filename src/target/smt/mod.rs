@@ -0,0 +1,1374 @@
+//! The SMT back-end.
+//!
+//! Every IR operator has an SMT-LIB encoding. Most are printed directly: bit-vectors use the
+//! theory of bit-vectors, floating-point uses the FloatingPoint theory (with round-to-nearest-even
+//! unless the IR specifies otherwise), prime fields use cvc5's finite field theory, tuples use
+//! cvc5's tuple datatypes, and function calls are uninterpreted functions.
+//!
+//! Before printing, terms are encoded:
+//!
+//! * `Map`, `Update`, `NthSmallest`, and majority are expanded into other operators,
+//! * field-to-bit-vector conversions, field reciprocals, and table lookups become fresh variables
+//!   with side conditions, and
+//! * subterms with multiple uses are named, so that the encoding is linear in the size of the
+//!   term DAG.
+//!
+//! The SMT solver's invocation command can be configured by setting the environmental variable
+//! [rsmt2::conf::CVC4_ENV_VAR].
+
+use crate::ir::term::*;
+
+use rsmt2::errors::SmtRes;
+use rsmt2::parse::{IdentParser, ModelParser, SmtParser};
+use rsmt2::print::{Expr2Smt, Sort2Smt, Sym2Smt};
+
+use fxhash::FxHashMap;
+use rug::Integer;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::io::{BufRead, Write};
+
+use ieee754::Ieee754;
+
+struct SmtDisp<'a, T>(pub &'a T);
+
+impl<'a, T: Expr2Smt<()> + 'a> Display for SmtDisp<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut s = Vec::new();
+        <T as Expr2Smt<()>>::expr_to_smt2(self.0, &mut s, ()).unwrap();
+        write!(f, "{}", std::str::from_utf8(&s).unwrap())?;
+        Ok(())
+    }
+}
+
+struct SmtSortDisp<'a, T>(pub &'a T);
+impl<'a, T: Sort2Smt + 'a> Display for SmtSortDisp<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut s = Vec::new();
+        <T as Sort2Smt>::sort_to_smt2(self.0, &mut s).unwrap();
+        write!(f, "{}", std::str::from_utf8(&s).unwrap())?;
+        Ok(())
+    }
+}
+
+/// The exponent and significand widths of an SMT-LIB float with this total width.
+fn fp_dims(width: usize) -> (usize, usize) {
+    match width {
+        32 => (8, 24),
+        64 => (11, 53),
+        w => panic!("No SMT-LIB float of width {}", w),
+    }
+}
+
+impl Expr2Smt<()> for Value {
+    fn expr_to_smt2<W: Write>(&self, w: &mut W, (): ()) -> SmtRes<()> {
+        match self {
+            Value::Bool(b) => write!(w, "{}", b)?,
+            Value::Field(f) => write!(w, "#f{}m{}", f.i(), f.modulus())?,
+            Value::Int(i) if *i < 0 => write!(w, "(- {})", Integer::from(-i))?,
+            Value::Int(i) => write!(w, "{}", i)?,
+            Value::BitVector(b) => write!(w, "{}", b)?,
+            Value::F32(f) => {
+                let (sign, exp, mant) = f.decompose_raw();
+                write!(w, "(fp #b{} #b", sign as u8)?;
+                for i in (0..8).rev() {
+                    write!(w, "{}", (exp >> i) & 1)?;
+                }
+                write!(w, " #b")?;
+                for i in (0..23).rev() {
+                    write!(w, "{}", (mant >> i) & 1)?;
+                }
+                write!(w, ")")?;
+            }
+            Value::F64(f) => {
+                let (sign, exp, mant) = f.decompose_raw();
+                write!(w, "(fp #b{} #b", sign as u8)?;
+                for i in (0..11).rev() {
+                    write!(w, "{}", (exp >> i) & 1)?;
+                }
+                write!(w, " #b")?;
+                for i in (0..52).rev() {
+                    write!(w, "{}", (mant >> i) & 1)?;
+                }
+                write!(w, ")")?;
+            }
+            Value::Array(Array {
+                key_sort,
+                default,
+                map,
+                size,
+            }) => {
+                for _ in 0..map.len() {
+                    write!(w, "(store ")?;
+                }
+                let val_s = check(&leaf_term(Op::Const((**default).clone())));
+                let s = Sort::Array(Box::new(key_sort.clone()), Box::new(val_s), *size);
+                write!(
+                    w,
+                    "((as const {}) {})",
+                    SmtSortDisp(&s),
+                    SmtDisp(&**default)
+                )?;
+                for (k, v) in map {
+                    write!(w, " {} {})", SmtDisp(k), SmtDisp(v))?;
+                }
+            }
+            Value::Tuple(fs) => {
+                write!(w, "(mkTuple")?;
+                for t in fs.iter() {
+                    write!(w, " {}", SmtDisp(t))?;
+                }
+                write!(w, ")")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write `(head c0 c1 ...)`; with one child just the child, and with none the `unit`.
+fn nary_to_smt2<W: Write>(w: &mut W, head: &str, cs: &[Term], unit: Option<&str>) -> SmtRes<()> {
+    match cs.len() {
+        0 => write!(w, "{}", unit.expect("empty n-ary operator without a unit"))?,
+        1 => write!(w, "{}", SmtDisp(&*cs[0]))?,
+        _ => {
+            write!(w, "({}", head)?;
+            for c in cs {
+                write!(w, " {}", SmtDisp(&**c))?;
+            }
+            write!(w, ")")?;
+        }
+    }
+    Ok(())
+}
+
+impl Expr2Smt<()> for TermData {
+    fn expr_to_smt2<W: Write>(&self, w: &mut W, (): ()) -> SmtRes<()> {
+        let head: String = match &self.op {
+            Op::Var(n, _) | Op::Random(n, _) => {
+                write!(w, "{}", SmtSym(n))?;
+                return Ok(());
+            }
+            Op::Const(c) => {
+                write!(w, "{}", SmtDisp(c))?;
+                return Ok(());
+            }
+            Op::Eq => "=".into(),
+            Op::Ite => "ite".into(),
+            Op::Not => "not".into(),
+            Op::Implies => "=>".into(),
+            Op::BoolNaryOp(o) => {
+                let unit = match o {
+                    BoolNaryOp::And => "true",
+                    BoolNaryOp::Or | BoolNaryOp::Xor => "false",
+                };
+                return nary_to_smt2(w, &o.to_string(), &self.cs, Some(unit));
+            }
+            Op::BvBinPred(_) | Op::BvBinOp(_) | Op::BvUnOp(_) => self.op.to_string(),
+            Op::BvNaryOp(o) => return nary_to_smt2(w, &o.to_string(), &self.cs, None),
+            Op::BoolToBv => {
+                write!(w, "(ite {} #b1 #b0)", SmtDisp(&*self.cs[0]))?;
+                return Ok(());
+            }
+            Op::BvExtract(h, l) => format!("(_ extract {} {})", h, l),
+            Op::BvConcat => {
+                // concat is associative, so nest to the right.
+                let (last, init) = self.cs.split_last().unwrap();
+                for c in init {
+                    write!(w, "(concat {} ", SmtDisp(&**c))?;
+                }
+                write!(w, "{}", SmtDisp(&**last))?;
+                for _ in init {
+                    write!(w, ")")?;
+                }
+                return Ok(());
+            }
+            Op::BvUext(n) => format!("(_ zero_extend {})", n),
+            Op::BvSext(n) => format!("(_ sign_extend {})", n),
+            Op::BvBit(i) => {
+                write!(
+                    w,
+                    "(= ((_ extract {} {}) {}) #b1)",
+                    i,
+                    i,
+                    SmtDisp(&*self.cs[0])
+                )?;
+                return Ok(());
+            }
+            Op::FpBinOp(o) => match o {
+                FpBinOp::Add => "fp.add RNE",
+                FpBinOp::Sub => "fp.sub RNE",
+                FpBinOp::Mul => "fp.mul RNE",
+                FpBinOp::Div => "fp.div RNE",
+                FpBinOp::Rem => "fp.rem",
+                FpBinOp::Max => "fp.max",
+                FpBinOp::Min => "fp.min",
+            }
+            .into(),
+            Op::FpUnOp(o) => match o {
+                FpUnOp::Neg => "fp.neg",
+                FpUnOp::Abs => "fp.abs",
+                FpUnOp::Sqrt => "fp.sqrt RNE",
+                // rounds half-way cases away from zero, like `f64::round`
+                FpUnOp::Round => "fp.roundToIntegral RNA",
+            }
+            .into(),
+            Op::FpBinPred(o) => match o {
+                FpBinPred::Le => "fp.leq",
+                FpBinPred::Lt => "fp.lt",
+                FpBinPred::Eq => "fp.eq",
+                FpBinPred::Ge => "fp.geq",
+                FpBinPred::Gt => "fp.gt",
+            }
+            .into(),
+            Op::FpUnPred(o) => match o {
+                FpUnPred::Normal => "fp.isNormal",
+                FpUnPred::Subnormal => "fp.isSubnormal",
+                FpUnPred::Zero => "fp.isZero",
+                FpUnPred::Infinite => "fp.isInfinite",
+                FpUnPred::Nan => "fp.isNaN",
+                FpUnPred::Negative => "fp.isNegative",
+                FpUnPred::Positive => "fp.isPositive",
+            }
+            .into(),
+            Op::BvToFp => {
+                let (e, s) = fp_dims(check(&self.cs[0]).as_bv());
+                format!("(_ to_fp {} {})", e, s)
+            }
+            Op::UbvToFp(n) => {
+                let (e, s) = fp_dims(*n);
+                format!("(_ to_fp_unsigned {} {}) RNE", e, s)
+            }
+            Op::SbvToFp(n) | Op::FpToFp(n) => {
+                let (e, s) = fp_dims(*n);
+                format!("(_ to_fp {} {}) RNE", e, s)
+            }
+            Op::PfUnOp(PfUnOp::Neg) => "ffneg".into(),
+            Op::PfNaryOp(PfNaryOp::Add) => return nary_to_smt2(w, "ffadd", &self.cs, None),
+            Op::PfNaryOp(PfNaryOp::Mul) => return nary_to_smt2(w, "ffmul", &self.cs, None),
+            Op::UbvToPf(f) => {
+                // the sum of the field elements for the set bits.
+                let x = &self.cs[0];
+                let n = check(x).as_bv();
+                let zero = Value::Field(f.zero());
+                if n > 1 {
+                    write!(w, "(ffadd")?;
+                }
+                for i in 0..n {
+                    let bit = Value::Field(f.new_v(Integer::from(1) << i as u32));
+                    write!(
+                        w,
+                        " (ite (= ((_ extract {} {}) {}) #b1) {} {})",
+                        i,
+                        i,
+                        SmtDisp(&**x),
+                        SmtDisp(&bit),
+                        SmtDisp(&zero)
+                    )?;
+                }
+                if n > 1 {
+                    write!(w, ")")?;
+                }
+                return Ok(());
+            }
+            Op::Select => "select".into(),
+            Op::Store => "store".into(),
+            Op::Tuple => "mkTuple".into(),
+            Op::Field(i) => format!("(_ tupSel {})", i),
+            Op::Call(n, _, _) => {
+                if self.cs.is_empty() {
+                    write!(w, "{}", SmtSym(n))?;
+                    return Ok(());
+                }
+                SmtSym(n).to_string()
+            }
+            Op::PfToBv(_)
+            | Op::FpToBv
+            | Op::PfUnOp(PfUnOp::Recip)
+            | Op::BoolMaj
+            | Op::Update(_)
+            | Op::Map(_)
+            | Op::NthSmallest(_)
+            | Op::Lookup(_) => panic!("{} must be encoded before printing SMT-LIB", self.op),
+        };
+        write!(w, "({}", head)?;
+        for c in &self.cs {
+            write!(w, " {}", SmtDisp(&**c))?;
+        }
+        write!(w, ")")?;
+        Ok(())
+    }
+}
+
+impl Sort2Smt for Sort {
+    fn sort_to_smt2<W: Write>(&self, w: &mut W) -> SmtRes<()> {
+        match self {
+            Sort::BitVector(b) => write!(w, "(_ BitVec {})", b)?,
+            Sort::Array(k, v, _size) => {
+                write!(w, "(Array {} {})", SmtSortDisp(&**k), SmtSortDisp(&**v))?;
+            }
+            Sort::F64 => write!(w, "Float64")?,
+            Sort::F32 => write!(w, "Float32")?,
+            Sort::Bool => write!(w, "Bool")?,
+            Sort::Int => write!(w, "Int")?,
+            Sort::Tuple(fs) => {
+                write!(w, "(Tuple")?;
+                for t in fs.iter() {
+                    write!(w, " {}", SmtSortDisp(t))?;
+                }
+                write!(w, ")")?;
+            }
+            Sort::Field(f) => write!(w, "(_ FiniteField {})", f.modulus())?,
+        }
+        Ok(())
+    }
+}
+
+impl Expr2Smt<()> for BitVector {
+    fn expr_to_smt2<W: Write>(&self, w: &mut W, (): ()) -> SmtRes<()> {
+        write!(w, "#b")?;
+        for i in (0..self.width()).rev() {
+            write!(w, "{}", self.uint().get_bit(i as u32) as u8)?;
+        }
+        Ok(())
+    }
+}
+
+/// An SMT-LIB symbol, quoted if it isn't a simple symbol.
+struct SmtSym<'a>(&'a str);
+
+impl<'a> Display for SmtSym<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let simple = !self.0.is_empty()
+            && !self.0.starts_with(|c: char| c.is_ascii_digit())
+            && self
+                .0
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "~!@$%^&*_-+=<>.?/".contains(c));
+        if simple {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "|{}|", self.0)
+        }
+    }
+}
+
+struct SmtSymDisp<'a, T>(pub &'a T);
+
+impl<'a, T: Display + 'a> Sym2Smt<()> for SmtSymDisp<'a, T> {
+    fn sym_to_smt2<W: Write>(&self, w: &mut W, (): ()) -> SmtRes<()> {
+        write!(w, "{}", SmtSym(&self.0.to_string()))?;
+        Ok(())
+    }
+}
+
+/// Rewrites terms into the operators that [Expr2Smt] prints.
+#[derive(Default)]
+struct Lowerer {
+    cache: TermMap<Term>,
+    /// The bit-vector variable for each field term that has been converted to bits.
+    pf_bits: TermMap<Term>,
+    /// Side conditions on fresh variables.
+    side_conditions: Vec<Term>,
+    fresh_vars: Vec<(String, Sort)>,
+}
+
+impl Lowerer {
+    fn fresh(&mut self, kind: &str, sort: Sort) -> Term {
+        let name = format!("__smt_{}{}", kind, self.fresh_vars.len());
+        self.fresh_vars.push((name.clone(), sort.clone()));
+        leaf_term(Op::Var(name, sort))
+    }
+
+    fn lower(&mut self, t: &Term) -> Term {
+        for c in PostOrderIter::new(t.clone()) {
+            if !self.cache.contains_key(&c) {
+                let cs: Vec<Term> =
+                    c.cs.iter()
+                        .map(|c| self.cache.get(c).unwrap().clone())
+                        .collect();
+                let new = self.lower_op(&c, cs);
+                self.cache.insert(c, new);
+            }
+        }
+        self.cache.get(t).unwrap().clone()
+    }
+
+    /// Lower `t`, whose children have been lowered to `cs`.
+    fn lower_op(&mut self, t: &Term, cs: Vec<Term>) -> Term {
+        match &t.op {
+            Op::PfToBv(w) => self.pf_to_bv(&cs[0], *w),
+            // `=` is structural in SMT-LIB, but IEEE-754 in the IR.
+            Op::Eq if matches!(check(&cs[0]), Sort::F32 | Sort::F64) => {
+                term(Op::FpBinPred(FpBinPred::Eq), cs)
+            }
+            Op::FpToBv => {
+                // SMT-LIB has no conversion to bits, since NaN has many bit patterns.
+                let x = cs[0].clone();
+                let y = self.fresh("fp2bv", Sort::BitVector(check(t).as_bv()));
+                self.side_conditions
+                    .push(term![EQ; term![Op::BvToFp; y.clone()], x]);
+                y
+            }
+            Op::PfUnOp(PfUnOp::Recip) => {
+                let x = cs[0].clone();
+                let sort = check(&x);
+                let r = self.fresh("recip", sort.clone());
+                let zero = sort.default_term();
+                let one = match &sort {
+                    Sort::Field(f) => pf_lit(f.new_v(1)),
+                    _ => unreachable!(),
+                };
+                self.side_conditions.push(term![OR;
+                    term![AND; term![EQ; x.clone(), zero.clone()], term![EQ; r.clone(), zero]],
+                    term![EQ; term![PF_MUL; x, r.clone()], one]
+                ]);
+                r
+            }
+            Op::BoolMaj => term![OR;
+                term![AND; cs[0].clone(), cs[1].clone()],
+                term![AND; cs[1].clone(), cs[2].clone()],
+                term![AND; cs[0].clone(), cs[2].clone()]
+            ],
+            Op::Update(i) => {
+                let n = match check(&cs[0]) {
+                    Sort::Tuple(fs) => fs.len(),
+                    s => panic!("Update of non-tuple sort {}", s),
+                };
+                term(
+                    Op::Tuple,
+                    (0..n)
+                        .map(|j| {
+                            if j == *i {
+                                cs[1].clone()
+                            } else {
+                                term![Op::Field(j); cs[0].clone()]
+                            }
+                        })
+                        .collect(),
+                )
+            }
+            Op::Map(op) => {
+                let (key_sort, val_sort, size) = match check(t) {
+                    Sort::Array(k, v, n) => (*k, *v, n),
+                    s => panic!("Map of non-array sort {}", s),
+                };
+                let elems = key_sort
+                    .elems_iter()
+                    .take(size)
+                    .map(|idx| {
+                        let args = cs
+                            .iter()
+                            .map(|a| term![Op::Select; a.clone(), idx.clone()])
+                            .collect();
+                        self.lower(&term((**op).clone(), args))
+                    })
+                    .collect();
+                make_array(key_sort, val_sort, elems)
+            }
+            Op::NthSmallest(i) => {
+                // x_j is the answer if exactly i arguments precede it in a stable sort.
+                let n = cs.len();
+                let w = (usize::BITS - n.leading_zeros()) as usize;
+                let rank_is_i = |s: &mut Self, j: usize| -> Term {
+                    let before: Vec<Term> = (0..n)
+                        .filter(|k| *k != j)
+                        .map(|k| {
+                            let mut prec = s.lt(&cs[k], &cs[j]);
+                            if k < j {
+                                prec = term![OR; prec, term![EQ; cs[k].clone(), cs[j].clone()]];
+                            }
+                            let bit = term![BOOL_TO_BV; prec];
+                            if w > 1 {
+                                term![Op::BvUext(w - 1); bit]
+                            } else {
+                                bit
+                            }
+                        })
+                        .collect();
+                    let rank = if before.is_empty() {
+                        bv_lit(0, w)
+                    } else {
+                        term(BV_ADD, before)
+                    };
+                    term![EQ; rank, bv_lit(*i, w)]
+                };
+                let (last, init) = cs.split_last().unwrap();
+                let mut res = last.clone();
+                for j in (0..init.len()).rev() {
+                    let c = rank_is_i(self, j);
+                    res = term![ITE; c, cs[j].clone(), res];
+                }
+                res
+            }
+            Op::Lookup(id) => {
+                // The key must be in the table, so the result is one of its entries.
+                let table = lookup::table(*id);
+                let y = self.fresh("lookup", table.val_sort.clone());
+                let entries = table
+                    .entries
+                    .iter()
+                    .map(|(k, v)| {
+                        term![AND;
+                            term![EQ; cs[0].clone(), leaf_term(Op::Const(k.clone()))],
+                            term![EQ; y.clone(), leaf_term(Op::Const(v.clone()))]
+                        ]
+                    })
+                    .collect();
+                self.side_conditions.push(term(OR, entries));
+                y
+            }
+            _ => term(t.op.clone(), cs),
+        }
+    }
+
+    /// `a < b`, in the order of IR values.
+    fn lt(&mut self, a: &Term, b: &Term) -> Term {
+        match check(a) {
+            Sort::BitVector(_) => term![BV_ULT; a.clone(), b.clone()],
+            Sort::Bool => term![AND; term![NOT; a.clone()], b.clone()],
+            Sort::Field(f) => {
+                let n = f.modulus().significant_bits() as usize;
+                let a = self.pf_to_bv(a, n);
+                let b = self.pf_to_bv(b, n);
+                term![BV_ULT; a, b]
+            }
+            Sort::F32 | Sort::F64 => term![Op::FpBinPred(FpBinPred::Lt); a.clone(), b.clone()],
+            s => panic!("Cannot order {} in SMT", s),
+        }
+    }
+
+    /// The `w`-bit bit-vector equal to the integer representative of `x` (mod 2^w).
+    fn pf_to_bv(&mut self, x: &Term, w: usize) -> Term {
+        let f = match check(x) {
+            Sort::Field(f) => f,
+            s => panic!("pf2bv of non-field sort {}", s),
+        };
+        let n = f.modulus().significant_bits() as usize;
+        let bits = match self.pf_bits.get(x) {
+            Some(y) => y.clone(),
+            None => {
+                let y = self.fresh("pf2bv", Sort::BitVector(n));
+                self.side_conditions
+                    .push(term![BV_ULT; y.clone(), bv_lit(f.modulus().clone(), n)]);
+                self.side_conditions
+                    .push(term![EQ; term![Op::UbvToPf(f.clone()); y.clone()], x.clone()]);
+                self.pf_bits.insert(x.clone(), y.clone());
+                y
+            }
+        };
+        match w.cmp(&n) {
+            std::cmp::Ordering::Less => term![Op::BvExtract(w - 1, 0); bits],
+            std::cmp::Ordering::Equal => bits,
+            std::cmp::Ordering::Greater => term![Op::BvUext(w - n); bits],
+        }
+    }
+}
+
+/// An SMT-LIB encoding of a boolean term.
+struct Encoding {
+    /// The variables of the original term.
+    inputs: FxHashMap<String, Sort>,
+    /// Constants to declare: inputs, random values, and fresh variables.
+    consts: Vec<(String, Sort)>,
+    /// Uninterpreted functions to declare.
+    fns: Vec<(String, Vec<Sort>, Sort)>,
+    /// Named subterms, in dependency order.
+    defs: Vec<(String, Sort, Term)>,
+    /// Assertions, which refer to the named subterms.
+    assertions: Vec<Term>,
+}
+
+impl Encoding {
+    fn new(t: &Term) -> Self {
+        assert!(check(t) == Sort::Bool);
+        let mut inputs = FxHashMap::default();
+        for c in PostOrderIter::new(t.clone()) {
+            if let Op::Var(n, s) = &c.op {
+                inputs.insert(n.clone(), s.clone());
+            }
+        }
+        let mut lowerer = Lowerer::default();
+        let mut assertions = vec![lowerer.lower(t)];
+        assertions.extend(lowerer.side_conditions.drain(..));
+        let root = term(Op::Tuple, assertions);
+
+        // Count uses. Bit-vectors converted to fields are printed once per bit.
+        let mut uses: TermMap<usize> = TermMap::new();
+        for c in PostOrderIter::new(root.clone()) {
+            let n_uses = if let Op::UbvToPf(_) = &c.op { 2 } else { 1 };
+            for cc in &c.cs {
+                *uses.entry(cc.clone()).or_insert(0) += n_uses;
+            }
+        }
+
+        let mut consts = Vec::new();
+        let mut fns = Vec::new();
+        let mut defs = Vec::new();
+        let mut named: TermMap<Term> = TermMap::new();
+        for c in PostOrderIter::new(root.clone()) {
+            match &c.op {
+                Op::Var(n, s) | Op::Random(n, s) => consts.push((n.clone(), s.clone())),
+                Op::Call(n, args, ret) => {
+                    if !fns.iter().any(|(m, _, _)| m == n) {
+                        fns.push((n.clone(), args.clone(), ret.clone()));
+                    }
+                }
+                _ => {}
+            }
+            let new = term(
+                c.op.clone(),
+                c.cs.iter()
+                    .map(|cc| named.get(cc).unwrap().clone())
+                    .collect(),
+            );
+            let new = if !c.cs.is_empty() && uses.get(&c).map(|u| *u > 1).unwrap_or(false) {
+                let name = format!("__smt_d{}", defs.len());
+                let sort = check(&c);
+                defs.push((name.clone(), sort.clone(), new));
+                leaf_term(Op::Var(name, sort))
+            } else {
+                new
+            };
+            named.insert(c, new);
+        }
+        let assertions = named.get(&root).unwrap().cs.clone();
+        Encoding {
+            inputs,
+            consts,
+            fns,
+            defs,
+            assertions,
+        }
+    }
+
+    /// Declare everything and assert the term in `solver`.
+    fn assert_in<P>(&self, solver: &mut rsmt2::Solver<P>) {
+        for (n, args, ret) in &self.fns {
+            solver
+                .declare_fun(&SmtSymDisp(n), args.clone(), ret)
+                .unwrap();
+        }
+        for (n, s) in &self.consts {
+            solver.declare_const(&SmtSymDisp(n), s).unwrap();
+        }
+        for (n, s, body) in &self.defs {
+            solver.declare_const(&SmtSymDisp(n), s).unwrap();
+            let v = leaf_term(Op::Var(n.clone(), s.clone()));
+            solver.assert(&*term![EQ; v, body.clone()]).unwrap();
+        }
+        for a in &self.assertions {
+            solver.assert(&**a).unwrap();
+        }
+    }
+
+    /// Write SMT-LIB that declares everything and asserts the term.
+    fn write<W: Write>(&self, w: &mut W) {
+        for (n, args, ret) in &self.fns {
+            write!(w, "(declare-fun {} (", SmtSym(n)).unwrap();
+            for a in args {
+                write!(w, " {}", SmtSortDisp(a)).unwrap();
+            }
+            writeln!(w, ") {})", SmtSortDisp(ret)).unwrap();
+        }
+        for (n, s) in &self.consts {
+            writeln!(w, "(declare-const {} {})", SmtSym(n), SmtSortDisp(s)).unwrap();
+        }
+        for (n, s, body) in &self.defs {
+            writeln!(
+                w,
+                "(define-fun {} () {} {})",
+                SmtSym(n),
+                SmtSortDisp(s),
+                SmtDisp(&**body)
+            )
+            .unwrap();
+        }
+        for a in &self.assertions {
+            write!(w, "(assert\n\t").unwrap();
+            a.expr_to_smt2(w, ()).unwrap();
+            writeln!(w, "\n)").unwrap();
+        }
+    }
+
+    /// Restrict a model to the inputs, fixing up the parts of their values that SMT-LIB forgets.
+    fn model(
+        &self,
+        model: Vec<(String, Vec<(String, Sort)>, Sort, Value)>,
+    ) -> HashMap<String, Value> {
+        model
+            .into_iter()
+            .filter_map(|(id, _, _, v)| {
+                let s = self.inputs.get(&id)?;
+                Some((id, with_sort(v, s)))
+            })
+            .collect()
+    }
+}
+
+/// Give array values in `v` the sizes from `s`, dropping out-of-range entries.
+fn with_sort(v: Value, s: &Sort) -> Value {
+    match (v, s) {
+        (Value::Array(a), Sort::Array(_, vs, n)) => Value::Array(Array::new(
+            a.key_sort,
+            Box::new(with_sort(*a.default, vs)),
+            a.map
+                .into_iter()
+                .filter(|(k, _)| k.as_usize().map(|k| k < *n).unwrap_or(false))
+                .map(|(k, x)| (k, with_sort(x, vs)))
+                .collect(),
+            *n,
+        )),
+        (Value::Tuple(fs), Sort::Tuple(ss)) => Value::Tuple(
+            Vec::from(fs)
+                .into_iter()
+                .zip(ss.iter())
+                .map(|(f, s)| with_sort(f, s))
+                .collect(),
+        ),
+        (v, _) => v,
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Parser;
+
+fn parse_int<R: BufRead>(input: &mut SmtParser<R>) -> SmtRes<Integer> {
+    match input
+        .try_int(|s, pos| Integer::from_str_radix(s, 10).map(|i| if pos { i } else { -i }))?
+    {
+        Some(i) => Ok(i),
+        None => input.fail_with("an integer"),
+    }
+}
+
+fn parse_usize<R: BufRead>(input: &mut SmtParser<R>) -> SmtRes<usize> {
+    match parse_int(input)?.to_usize() {
+        Some(i) => Ok(i),
+        None => input.fail_with("a natural number"),
+    }
+}
+
+/// Parse a sort. Array sizes are unknown, so they are set to [usize::MAX].
+fn parse_sort<R: BufRead>(input: &mut SmtParser<R>) -> SmtRes<Sort> {
+    if input.try_tag("Bool")? {
+        Ok(Sort::Bool)
+    } else if input.try_tag("Int")? {
+        Ok(Sort::Int)
+    } else if input.try_tag("Float32")? {
+        Ok(Sort::F32)
+    } else if input.try_tag("Float64")? {
+        Ok(Sort::F64)
+    } else if input.try_tag("UnitTuple")? {
+        Ok(Sort::Tuple(Vec::new().into_boxed_slice()))
+    } else if input.try_tag("(_")? {
+        let s = if input.try_tag("BitVec")? {
+            Sort::BitVector(parse_usize(input)?)
+        } else if input.try_tag("FiniteField")? {
+            Sort::Field(circ_fields::FieldT::from(parse_int(input)?))
+        } else if input.try_tag("FloatingPoint")? {
+            match (parse_usize(input)?, parse_usize(input)?) {
+                (8, 24) => Sort::F32,
+                (11, 53) => Sort::F64,
+                _ => return input.fail_with("a 32 or 64-bit float sort"),
+            }
+        } else {
+            return input.fail_with("an indexed sort");
+        };
+        input.tag(")")?;
+        Ok(s)
+    } else if input.try_tag("(Array")? {
+        let k = parse_sort(input)?;
+        let v = parse_sort(input)?;
+        input.tag(")")?;
+        Ok(Sort::Array(Box::new(k), Box::new(v), usize::MAX))
+    } else if input.try_tag("(Tuple")? {
+        let mut fs = Vec::new();
+        while !input.try_tag(")")? {
+            fs.push(parse_sort(input)?);
+        }
+        Ok(Sort::Tuple(fs.into_boxed_slice()))
+    } else {
+        input.fail_with("a sort")
+    }
+}
+
+fn parse_bv<R: BufRead>(input: &mut SmtParser<R>, w: usize) -> SmtRes<BitVector> {
+    let i = if input.try_tag("#b")? {
+        Integer::from_str_radix(input.get_sexpr()?, 2).unwrap()
+    } else if input.try_tag("#x")? {
+        Integer::from_str_radix(input.get_sexpr()?, 16).unwrap()
+    } else if input.try_tag("(_")? {
+        input.tag("bv")?;
+        let i = parse_int(input)?;
+        parse_usize(input)?;
+        input.tag(")")?;
+        i
+    } else {
+        return input.fail_with("a bit-vector");
+    };
+    Ok(BitVector::new(i, w))
+}
+
+fn parse_fp<R: BufRead>(input: &mut SmtParser<R>, width: usize) -> SmtRes<Value> {
+    let bits = if input.try_tag("(fp")? {
+        let mut bits = String::new();
+        for _ in 0..3 {
+            input.tag("#b")?;
+            bits.push_str(input.get_sexpr()?);
+        }
+        input.tag(")")?;
+        u64::from_str_radix(&bits, 2).unwrap()
+    } else if input.try_tag("(_")? {
+        let f = if input.try_tag("+zero")? {
+            0.0
+        } else if input.try_tag("-zero")? {
+            -0.0
+        } else if input.try_tag("+oo")? {
+            f64::INFINITY
+        } else if input.try_tag("-oo")? {
+            f64::NEG_INFINITY
+        } else if input.try_tag("NaN")? {
+            f64::NAN
+        } else {
+            return input.fail_with("a floating-point constant");
+        };
+        parse_usize(input)?;
+        parse_usize(input)?;
+        input.tag(")")?;
+        return Ok(match width {
+            32 => Value::F32(f as f32),
+            _ => Value::F64(f),
+        });
+    } else {
+        return input.fail_with("a floating-point value");
+    };
+    Ok(match width {
+        32 => Value::F32(f32::from_bits(bits as u32)),
+        _ => Value::F64(f64::from_bits(bits)),
+    })
+}
+
+fn parse_array<R: BufRead>(input: &mut SmtParser<R>, k: &Sort, v: &Sort) -> SmtRes<Array> {
+    if input.try_tag("(store")? {
+        let mut a = parse_array(input, k, v)?;
+        let i = parse_value_of(input, k)?;
+        let x = parse_value_of(input, v)?;
+        input.tag(")")?;
+        a.map.insert(i, x);
+        Ok(a)
+    } else if input.try_tag("((as")? {
+        input.tag("const")?;
+        parse_sort(input)?;
+        input.tag(")")?;
+        let d = parse_value_of(input, v)?;
+        input.tag(")")?;
+        Ok(Array::new(
+            k.clone(),
+            Box::new(d),
+            BTreeMap::new(),
+            usize::MAX,
+        ))
+    } else {
+        input.fail_with("an array value")
+    }
+}
+
+/// Parse a value of sort `s`.
+fn parse_value_of<R: BufRead>(input: &mut SmtParser<R>, s: &Sort) -> SmtRes<Value> {
+    Ok(match s {
+        Sort::Bool => match input.try_bool()? {
+            Some(b) => Value::Bool(b),
+            None => return input.fail_with("a boolean"),
+        },
+        Sort::Int => Value::Int(parse_int(input)?),
+        Sort::BitVector(w) => Value::BitVector(parse_bv(input, *w)?),
+        Sort::Field(f) => {
+            let i = if input.try_tag("#f")? {
+                let lit = input.get_sexpr()?.to_owned();
+                let i = lit.split('m').next().unwrap();
+                Integer::from_str_radix(i, 10).unwrap()
+            } else {
+                parse_int(input)?
+            };
+            Value::Field(f.new_v(i))
+        }
+        Sort::F32 => parse_fp(input, 32)?,
+        Sort::F64 => parse_fp(input, 64)?,
+        Sort::Array(k, v, _) => Value::Array(parse_array(input, k, v)?),
+        Sort::Tuple(fs) => {
+            if input.try_tag("tuple.unit")? {
+                Value::Tuple(Vec::new().into_boxed_slice())
+            } else if input.try_tag("(mkTuple")? || input.try_tag("(tuple")? {
+                let vs = fs
+                    .iter()
+                    .map(|f| parse_value_of(input, f))
+                    .collect::<SmtRes<Vec<_>>>()?;
+                input.tag(")")?;
+                Value::Tuple(vs.into_boxed_slice())
+            } else {
+                return input.fail_with("a tuple value");
+            }
+        }
+    })
+}
+
+impl<'a, R: std::io::BufRead> IdentParser<String, Sort, &'a mut SmtParser<R>> for Parser {
+    fn parse_ident(self, input: &'a mut SmtParser<R>) -> SmtRes<String> {
+        let id = input
+            .try_sym(|a| -> Result<String, String> { Ok(a.to_owned()) })?
+            .expect("sym");
+        Ok(
+            match id.strip_prefix('|').and_then(|i| i.strip_suffix('|')) {
+                Some(i) => i.to_owned(),
+                None => id,
+            },
+        )
+    }
+    fn parse_type(self, input: &'a mut SmtParser<R>) -> SmtRes<Sort> {
+        parse_sort(input)
+    }
+}
+
+impl<'a, Br: ::std::io::BufRead> ModelParser<String, Sort, Value, &'a mut SmtParser<Br>>
+    for Parser
+{
+    fn parse_value(
+        self,
+        input: &'a mut SmtParser<Br>,
+        _: &String,
+        args: &[(String, Sort)],
+        s: &Sort,
+    ) -> SmtRes<Value> {
+        if !args.is_empty() {
+            // An uninterpreted function: we don't report it.
+            input.get_sexpr()?;
+            return Ok(s.default_value());
+        }
+        parse_value_of(input, s)
+    }
+}
+
+/// Create a solver, which can optionally parse models.
+///
+/// If [rsmt2::conf::CVC4_ENV_VAR] is set, uses that as the solver's invocation command.
+fn make_solver<P>(parser: P, models: bool, inc: bool) -> rsmt2::Solver<P> {
+    let mut conf = rsmt2::conf::SmtConf::default_cvc4();
+    if let Ok(val) = std::env::var(rsmt2::conf::CVC4_ENV_VAR) {
+        conf.cmd(val);
+    }
+    if models {
+        conf.models();
+    }
+    conf.set_incremental(inc);
+    rsmt2::Solver::new(conf, parser).expect("Error creating SMT solver")
+}
+
+/// Write SMT2 the encodes this terms satisfiability to a file
+pub fn write_smt2<W: Write>(mut w: W, t: &Term) {
+    Encoding::new(t).write(&mut w);
+    writeln!(w, "(check-sat)").unwrap();
+}
+
+/// Check whether some term is satisfiable.
+pub fn check_sat(t: &Term) -> bool {
+    let mut solver = make_solver((), false, false);
+    Encoding::new(t).assert_in(&mut solver);
+    solver.check_sat().unwrap()
+}
+
+fn get_model_solver(t: &Term, inc: bool) -> (rsmt2::Solver<Parser>, Encoding) {
+    let mut solver = make_solver(Parser, true, inc);
+    //solver.path_tee("solver_com").unwrap();
+    let enc = Encoding::new(t);
+    enc.assert_in(&mut solver);
+    (solver, enc)
+}
+
+/// Get a satisfying assignment for `t`, assuming it is SAT.
+pub fn find_model(t: &Term) -> Option<HashMap<String, Value>> {
+    let (mut solver, enc) = get_model_solver(t, false);
+    if solver.check_sat().unwrap() {
+        Some(enc.model(solver.get_model().unwrap()))
+    } else {
+        None
+    }
+}
+
+/// Get a unique satisfying assignment for `t`, assuming it is SAT.
+pub fn find_unique_model(t: &Term, uniqs: Vec<String>) -> Option<HashMap<String, Value>> {
+    let (mut solver, enc) = get_model_solver(t, true);
+    // first, get the result
+    let model: HashMap<String, Value> = if solver.check_sat().unwrap() {
+        enc.model(solver.get_model().unwrap())
+    } else {
+        return None;
+    };
+    // now, assert that any value in uniq is not the value assigned and check unsat
+    match uniqs
+        .into_iter()
+        .flat_map(|n| {
+            model
+                .get(&n)
+                .map(|v| term![EQ; term![Op::Var(n, v.sort())], term![Op::Const(v.clone())]])
+        })
+        .reduce(|l, r| term![AND; l, r])
+        .map(|t| term![NOT; t])
+    {
+        None => Some(model),
+        Some(ast) => {
+            solver.push(1).unwrap();
+            solver.assert(&*ast).unwrap();
+            match solver.check_sat().unwrap() {
+                true => None,
+                false => Some(model),
+            }
+        }
+    }
+}
+
+/// Find values for the inputs of `cs` that satisfy all of its outputs, which must be assertions.
+pub fn find_witness(cs: &Computation) -> Option<HashMap<String, Value>> {
+    find_model(&conjoin_assertions(cs))
+}
+
+/// Find values for the inputs of `cs` that violate one of its outputs, which must be assertions.
+///
+/// Variables with a precomputation (e.g., a Z# `return`) take their precomputed values, so only
+/// the other inputs are free.
+pub fn find_counterexample(cs: &Computation) -> Option<HashMap<String, Value>> {
+    let mut cs_terms: Vec<Term> = cs
+        .precomputes
+        .outputs()
+        .iter()
+        .map(|(name, t)| term![EQ; leaf_term(Op::Var(name.clone(), check(t))), t.clone()])
+        .collect();
+    cs_terms.push(term![NOT; conjoin_assertions(cs)]);
+    find_model(&term(AND, cs_terms))
+}
+
+fn conjoin_assertions(cs: &Computation) -> Term {
+    for o in &cs.outputs {
+        let s = check(o);
+        assert!(s == Sort::Bool, "Output {} is a {}, not an assertion", o, s);
+    }
+    term(AND, cs.outputs.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::term::dist::test::*;
+    use fxhash::FxHashMap as HashMap;
+    use quickcheck_macros::quickcheck;
+    use rug::Integer;
+
+    #[test]
+    fn var_is_sat() {
+        let t = leaf_term(Op::Var("a".into(), Sort::Bool));
+        assert!(check_sat(&t));
+    }
+
+    #[test]
+    fn var_is_sat_model() {
+        let t = leaf_term(Op::Var("a".into(), Sort::Bool));
+        assert!(
+            find_model(&t)
+                == Some(
+                    vec![("a".to_owned(), Value::Bool(true))]
+                        .into_iter()
+                        .collect()
+                )
+        );
+    }
+
+    #[test]
+    fn var_and_not_is_unsat() {
+        let v = leaf_term(Op::Var("a".into(), Sort::Bool));
+        let t = term![Op::BoolNaryOp(BoolNaryOp::And); v.clone(), term![Op::Not; v]];
+        assert!(!check_sat(&t));
+    }
+
+    #[test]
+    fn bv_is_sat() {
+        let t = term![Op::Eq; bv_lit(0,4), leaf_term(Op::Var("a".into(), Sort::BitVector(4)))];
+        assert!(check_sat(&t));
+    }
+
+    // ignored until FF support in cvc5 is upstreamed.
+    #[ignore]
+    #[test]
+    fn ff_is_sat() {
+        let t = text::parse_term(
+            b"
+        (declare ((a (mod 5)) (b (mod 5)))
+            (and
+                (= (* a a) a)
+                (= (* b b) b)
+                (= a b)
+                (= a #f1m5)
+            )
+        )
+        ",
+        );
+        assert!(check_sat(&t));
+    }
+
+    // ignored until FF support in cvc5 is upstreamed.
+    #[ignore]
+    #[test]
+    fn ff_model() {
+        let t = text::parse_term(
+            b"
+        (declare ((a (mod 5)) (b (mod 5)))
+            (and
+                (= (* a a) a)
+                (= (* b b) b)
+                (= a b)
+                (= a #f1m5)
+            )
+        )
+        ",
+        );
+        let field = circ_fields::FieldT::from(rug::Integer::from(5));
+        assert_eq!(
+            find_model(&t),
+            Some(
+                vec![
+                    ("a".to_owned(), Value::Field(field.new_v(1)),),
+                    ("b".to_owned(), Value::Field(field.new_v(1)),),
+                ]
+                .into_iter()
+                .collect()
+            )
+        )
+    }
+
+    #[test]
+    fn tuple_is_sat() {
+        let t = term![Op::Eq; term![Op::Field(0); term![Op::Tuple; bv_lit(0,4), bv_lit(5,6)]], leaf_term(Op::Var("a".into(), Sort::BitVector(4)))];
+        assert!(check_sat(&t));
+        let t = term![Op::Eq; term![Op::Tuple; bv_lit(0,4), bv_lit(5,6)], leaf_term(Op::Var("a".into(), Sort::Tuple(vec![Sort::BitVector(4), Sort::BitVector(6)].into_boxed_slice())))];
+        assert!(check_sat(&t));
+    }
+
+    #[test]
+    fn bv_is_sat_model() {
+        let t = term![Op::Eq; bv_lit(0,4), leaf_term(Op::Var("a".into(), Sort::BitVector(4)))];
+        assert!(
+            find_model(&t)
+                == Some(
+                    vec![(
+                        "a".to_owned(),
+                        Value::BitVector(BitVector::new(Integer::from(0), 4))
+                    ),]
+                    .into_iter()
+                    .collect()
+                )
+        );
+    }
+
+    #[test]
+    fn vars_are_sat_model() {
+        let t = term![Op::BoolNaryOp(BoolNaryOp::And);
+           leaf_term(Op::Var("a".into(), Sort::Bool)),
+           leaf_term(Op::Var("b".into(), Sort::Bool)),
+           leaf_term(Op::Var("c".into(), Sort::Bool))
+        ];
+        assert!(
+            find_model(&t)
+                == Some(
+                    vec![
+                        ("a".to_owned(), Value::Bool(true)),
+                        ("b".to_owned(), Value::Bool(true)),
+                        ("c".to_owned(), Value::Bool(true)),
+                    ]
+                    .into_iter()
+                    .collect()
+                )
+        );
+    }
+
+    #[test]
+    fn bv_ops_are_sat_model() {
+        let a = leaf_term(Op::Var("a".into(), Sort::BitVector(4)));
+        let t = term![AND;
+            term![Op::Eq;
+                term![BV_CONCAT; term![Op::BvExtract(1, 0); a.clone()], term![Op::BvUext(1); term![BOOL_TO_BV; term![Op::BvBit(3); a.clone()]]]],
+                bv_lit(0b1001, 4)
+            ],
+            term![NOT; term![Op::BvBit(2); a]]
+        ];
+        assert_eq!(
+            find_model(&t),
+            Some(
+                vec![(
+                    "a".to_owned(),
+                    Value::BitVector(BitVector::new(Integer::from(0b1010), 4))
+                )]
+                .into_iter()
+                .collect()
+            )
+        );
+    }
+
+    #[test]
+    fn fp_model() {
+        let a = leaf_term(Op::Var("a".into(), Sort::F64));
+        let t = term![Op::FpBinPred(FpBinPred::Eq);
+            term![Op::FpBinOp(FpBinOp::Add); a, leaf_term(Op::Const(Value::F64(0.5)))],
+            leaf_term(Op::Const(Value::F64(2.0)))
+        ];
+        assert_eq!(
+            find_model(&t),
+            Some(
+                vec![("a".to_owned(), Value::F64(1.5))]
+                    .into_iter()
+                    .collect()
+            )
+        );
+    }
+
+    #[test]
+    fn array_tuple_model() {
+        let a_sort = Sort::Array(
+            Box::new(Sort::BitVector(2)),
+            Box::new(Sort::Tuple(
+                vec![Sort::Bool, Sort::BitVector(4)].into_boxed_slice(),
+            )),
+            4,
+        );
+        let a = leaf_term(Op::Var("a".into(), a_sort));
+        let t = term![AND;
+            term![Op::Field(0); term![Op::Select; a.clone(), bv_lit(1, 2)]],
+            term![EQ; term![Op::Field(1); term![Op::Select; a.clone(), bv_lit(1, 2)]], bv_lit(7, 4)],
+            term![Op::Eq; term![Op::Update(1); term![Op::Select; a.clone(), bv_lit(3, 2)], bv_lit(7, 4)], term![Op::Select; a, bv_lit(1, 2)]]
+        ];
+        let model = find_model(&t).unwrap();
+        let a = model.get("a").unwrap().as_array();
+        assert_eq!(a.size, 4);
+        let elem = Value::Tuple(
+            vec![
+                Value::Bool(true),
+                Value::BitVector(BitVector::new(Integer::from(7), 4)),
+            ]
+            .into_boxed_slice(),
+        );
+        for i in &[1, 3] {
+            let i = Value::BitVector(BitVector::new(Integer::from(*i), 2));
+            assert_eq!(a.select(&i).as_tuple()[0], elem.as_tuple()[0]);
+        }
+        assert_eq!(
+            a.select(&Value::BitVector(BitVector::new(Integer::from(1), 2))),
+            elem
+        );
+    }
+
+    #[test]
+    fn nth_smallest_and_maj() {
+        let vs: Vec<Term> = [9, 2, 7, 2].iter().map(|i| bv_lit(*i, 4)).collect();
+        for (i, expected) in [2, 2, 7, 9].iter().enumerate() {
+            let t = term![EQ; term(Op::NthSmallest(i), vs.clone()), bv_lit(*expected, 4)];
+            assert!(check_sat(&t));
+            assert!(!check_sat(&term![NOT; t]));
+        }
+        let a = leaf_term(Op::Var("a".into(), Sort::Bool));
+        let t =
+            term![Op::BoolMaj; a.clone(), a.clone(), leaf_term(Op::Var("b".into(), Sort::Bool))];
+        assert!(!check_sat(&term![NOT; term![EQ; t, a]]));
+    }
+
+    // ignored until FF support in cvc5 is upstreamed.
+    #[ignore]
+    #[test]
+    fn ff_to_bv_and_recip() {
+        let t = text::parse_term(
+            b"
+        (declare ((a (mod 17)))
+            (and
+                (= ((pf2bv 3) a) #b110)
+                (= (pfrecip a) #f3m17)
+            )
+        )
+        ",
+        );
+        let field = circ_fields::FieldT::from(rug::Integer::from(17));
+        assert_eq!(
+            find_model(&t),
+            Some(
+                vec![("a".to_owned(), Value::Field(field.new_v(6)))]
+                    .into_iter()
+                    .collect()
+            )
+        )
+    }
+
+    #[test]
+    fn witness_and_counterexample() {
+        let mut cs = text::parse_computation(
+            b"
+            (computation
+                (metadata () ((a (bv 4)) (return (bv 4))) ())
+                (declare ((a (bv 4)) (return (bv 4)))
+                    (and
+                        (bvult a #x8)
+                        (= return (bvadd a a))
+                    )
+                )
+            )
+        ",
+        );
+        let a = leaf_term(Op::Var("a".into(), Sort::BitVector(4)));
+        cs.precomputes
+            .add_output("return".into(), term![BV_ADD; a.clone(), a.clone()]);
+        let witness = find_witness(&cs).unwrap();
+        assert!(eval(&cs.outputs[0], &witness.into_iter().collect()).as_bool());
+        let cex = find_counterexample(&cs).unwrap();
+        assert!(cex.get("a").unwrap().as_bv().uint() >= &8);
+
+        // the return value is defined by its precomputation
+        let ret = leaf_term(Op::Var("return".into(), Sort::BitVector(4)));
+        cs.outputs = vec![term![EQ; ret, term![BV_ADD; a.clone(), a]]];
+        assert_eq!(find_counterexample(&cs), None);
+    }
+
+    #[quickcheck]
+    fn eval_random_bool(ArbitraryBoolEnv(t, vs): ArbitraryBoolEnv) {
+        assert!(smt_eval_test(t.clone(), &vs));
+        assert!(!smt_eval_alternate_solution(t, &vs));
+    }
+
+    #[quickcheck]
+    fn eval_random_term(ArbitraryTermEnv(t, vs): ArbitraryTermEnv) {
+        assert!(smt_eval_test(t.clone(), &vs));
+        assert!(!smt_eval_alternate_solution(t, &vs));
+    }
+
+    /// Conjoin `t` with equalities between the variables and their values in `vs`.
+    fn with_values(t: Term, vs: &HashMap<String, Value>) -> Term {
+        let mut cs: Vec<Term> = vs
+            .iter()
+            .map(|(var, val)| term![Op::Eq; leaf_term(Op::Var(var.to_owned(), val.sort())), leaf_term(Op::Const(val.clone()))])
+            .collect();
+        cs.push(t);
+        term(AND, cs)
+    }
+
+    /// Check that `t` evaluates consistently within the SMT solver under `vs`.
+    pub fn smt_eval_test(t: Term, vs: &HashMap<String, Value>) -> bool {
+        let val = eval(&t, vs);
+        check_sat(&with_values(
+            term![Op::Eq; t, leaf_term(Op::Const(val))],
+            vs,
+        ))
+    }
+
+    /// Check that `t` evaluates consistently within the SMT solver under `vs`.
+    pub fn smt_eval_alternate_solution(t: Term, vs: &HashMap<String, Value>) -> bool {
+        let val = eval(&t, vs);
+        check_sat(&with_values(
+            term![Op::Not; term![Op::Eq; t, leaf_term(Op::Const(val))]],
+            vs,
+        ))
+    }
+}